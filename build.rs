@@ -0,0 +1,112 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Allow overriding the commit, e.g. when building from a source archive without .git
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(git_sha)
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // Honour reproducible builds, see https://reproducible-builds.org/specs/source-date-epoch/
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+    println!(
+        "cargo:rustc-env=BUILD_RUSTC={}",
+        rustc_version().unwrap_or_else(|| "unknown".to_string())
+    );
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+
+    // The locked dependencies, embedded for the software bill of materials
+    let out_dir = env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+    let dependencies = fs::read_to_string("Cargo.lock")
+        .map(|lock| locked_packages(&lock))
+        .unwrap_or_default();
+    fs::write(Path::new(&out_dir).join("dependencies.txt"), dependencies)
+        .expect("the dependency list should be writable");
+
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(head) = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        println!("cargo:rerun-if-changed=.git/{}", head);
+    }
+}
+
+fn git_sha() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|sha| sha.trim().to_string())
+}
+
+fn rustc_version() -> Option<String> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|version| version.trim().to_string())
+}
+
+/// The packages of the lock file, one `<name> <version> <source> <checksum>` line each
+///
+/// Missing sources and checksums (e.g. of the package itself) are written as `-`.
+fn locked_packages(lock: &str) -> String {
+    let mut packages = String::new();
+    for package in lock.split("[[package]]").skip(1) {
+        let field = |key: &str| {
+            package
+                .lines()
+                .filter_map(|line| line.split_once(" = "))
+                .find(|(k, _)| k.trim() == key)
+                .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        };
+        let (Some(name), Some(version)) = (field("name"), field("version")) else {
+            continue;
+        };
+
+        packages.push_str(&format!(
+            "{} {} {} {}\n",
+            name,
+            version,
+            field("source").unwrap_or_else(|| "-".to_string()),
+            field("checksum").unwrap_or_else(|| "-".to_string()),
+        ));
+    }
+    packages
+}
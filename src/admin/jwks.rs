@@ -0,0 +1,55 @@
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http::StatusCode;
+use serde::Serialize;
+use tracing::info;
+
+use crate::validators::jwks::JwksStatus;
+use crate::validators::ValidatorsState;
+
+#[derive(Debug, Serialize)]
+struct RefreshResult {
+    authority: String,
+    refreshed: bool,
+    #[serde(flatten)]
+    jwks: JwksStatus,
+}
+
+/// The loaded keys of every JWKS and the error of its last refresh, if it failed
+pub async fn list(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    Json(validators.jwks())
+}
+
+/// Refresh the JWKS of an authority right away, e.g. after the identity provider rotated its keys
+///
+/// A failed refresh is answered with 502, the previous keys are kept.
+pub async fn refresh(
+    State(validators): State<ValidatorsState>,
+    Path(authority): Path<String>,
+) -> Response {
+    let Some(found) = validators.authority(&authority) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Authority {} not found", authority),
+        )
+            .into_response();
+    };
+
+    info!("Refreshing the JWKS of authority {}", authority);
+    let refreshed = found.jwks().refresh().await.is_ok();
+    let status = match refreshed {
+        true => StatusCode::OK,
+        false => StatusCode::BAD_GATEWAY,
+    };
+
+    (
+        status,
+        Json(RefreshResult {
+            authority,
+            refreshed,
+            jwks: found.jwks().status(),
+        }),
+    )
+        .into_response()
+}
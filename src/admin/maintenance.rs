@@ -0,0 +1,58 @@
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http::StatusCode;
+use tracing::info;
+
+use crate::validators::ValidatorsState;
+
+pub async fn status(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    Json(validators.maintenance().status())
+}
+
+pub async fn enable_service(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    info!("Putting the service into maintenance mode");
+    validators.maintenance().set_service(true);
+
+    Json(validators.maintenance().status())
+}
+
+pub async fn disable_service(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    info!("Taking the service out of maintenance mode");
+    validators.maintenance().set_service(false);
+
+    Json(validators.maintenance().status())
+}
+
+pub async fn enable_validator(
+    State(validators): State<ValidatorsState>,
+    Path(validator): Path<String>,
+) -> Response {
+    set_validator(&validators, &validator, true)
+}
+
+pub async fn disable_validator(
+    State(validators): State<ValidatorsState>,
+    Path(validator): Path<String>,
+) -> Response {
+    set_validator(&validators, &validator, false)
+}
+
+fn set_validator(validators: &ValidatorsState, validator: &str, enabled: bool) -> Response {
+    if !validators.validators().contains(validator) {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Validator {} not found", validator),
+        )
+            .into_response();
+    }
+
+    info!(
+        "Turning maintenance mode of validator {} {}",
+        validator,
+        if enabled { "on" } else { "off" }
+    );
+    validators.maintenance().set_validator(validator, enabled);
+
+    Json(validators.maintenance().status()).into_response()
+}
@@ -0,0 +1,307 @@
+use axum::extract::{Request, State};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use http::{header, HeaderMap, Method, StatusCode};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::validators::ValidatorsState;
+
+mod jwks;
+mod maintenance;
+mod registry;
+mod sbom;
+mod slots;
+mod validators;
+
+/// What an admin credential grants access to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Introspection: the `GET` endpoints of the admin API and explain mode
+    Read,
+    /// Everything, including the endpoints changing the state of the service
+    Write,
+}
+
+/// The credentials granting access to the admin API and admin-only request features
+#[derive(Debug, Clone)]
+pub struct AdminAuth {
+    inner: Arc<AdminAuthInner>,
+}
+
+#[derive(Debug)]
+struct AdminAuthInner {
+    tokens: Vec<(Scope, Box<str>)>,
+    /// The validators accepting tokens of the identity provider for the read and write scope
+    read_validator: Option<String>,
+    write_validator: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AdminAuthError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// A line of the tokens file isn't `<scope> <token>`
+    InvalidLine { path: PathBuf, line: usize },
+}
+
+impl AdminAuth {
+    /// Returns `None` (disabling all admin features) if no credentials are configured
+    ///
+    /// The `token` is granted the write scope, the tokens file lists one `<scope> <token>` per
+    /// line, with `read` or `write` as scope.
+    pub fn new(
+        token: Option<String>,
+        tokens_file: Option<&Path>,
+        read_validator: Option<String>,
+        write_validator: Option<String>,
+    ) -> Result<Option<Self>, AdminAuthError> {
+        let mut tokens = Vec::new();
+        match token {
+            Some(token) if !token.is_empty() => tokens.push((Scope::Write, token.into())),
+            Some(_) => warn!("The admin token is empty, it is ignored"),
+            None => {}
+        }
+        if let Some(path) = tokens_file {
+            tokens.extend(read_tokens(path)?);
+        }
+
+        if tokens.is_empty() && read_validator.is_none() && write_validator.is_none() {
+            info!("No admin credentials configured, the admin API is disabled");
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            inner: Arc::new(AdminAuthInner {
+                tokens,
+                read_validator,
+                write_validator,
+            }),
+        }))
+    }
+
+    /// The scope of a static token, compared in constant time so tokens cannot be guessed from
+    /// response timings
+    pub fn verify(&self, provided: &[u8]) -> Option<Scope> {
+        self.inner
+            .tokens
+            .iter()
+            .filter(|(_, token)| constant_time_eq(token.as_bytes(), provided))
+            .map(|(scope, _)| *scope)
+            .max()
+    }
+
+    /// The scope granted to a request, by its bearer token or the admin validators
+    async fn authorize(&self, validators: &ValidatorsState, headers: &HeaderMap) -> Option<Scope> {
+        let provided = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.as_bytes().strip_prefix(b"Bearer "));
+        if let Some(scope) = provided.and_then(|provided| self.verify(provided)) {
+            return Some(scope);
+        }
+
+        let candidates = [
+            (Scope::Write, &self.inner.write_validator),
+            (Scope::Read, &self.inner.read_validator),
+        ];
+        for (scope, validator) in candidates {
+            let Some(validator) = validator else {
+                continue;
+            };
+            if crate::validators::accepts(validators, validator, headers).await {
+                return Some(scope);
+            }
+        }
+
+        None
+    }
+}
+
+fn read_tokens(path: &Path) -> Result<Vec<(Scope, Box<str>)>, AdminAuthError> {
+    let content = std::fs::read_to_string(path).map_err(|error| AdminAuthError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let invalid = || AdminAuthError::InvalidLine {
+                path: path.to_path_buf(),
+                line: i + 1,
+            };
+            let (scope, token) = line
+                .trim()
+                .split_once(char::is_whitespace)
+                .ok_or_else(invalid)?;
+            let scope = match scope {
+                "read" => Scope::Read,
+                "write" => Scope::Write,
+                _ => return Err(invalid()),
+            };
+            Ok((scope, token.trim().into()))
+        })
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Reading requests need the read scope, all others the write scope
+async fn authenticate(
+    State(validators): State<ValidatorsState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(auth) = validators.admin_auth() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let required = match *request.method() {
+        Method::GET | Method::HEAD => Scope::Read,
+        _ => Scope::Write,
+    };
+
+    match auth.authorize(&validators, request.headers()).await {
+        Some(scope) if scope >= required => next.run(request).await,
+        Some(_) => {
+            info!(
+                "Rejected admin request {} {} without the write scope",
+                request.method(),
+                request.uri()
+            );
+            (
+                StatusCode::FORBIDDEN,
+                "Admin credentials lack the write scope",
+            )
+                .into_response()
+        }
+        None => {
+            info!("Rejected admin request without valid credentials");
+            (
+                StatusCode::UNAUTHORIZED,
+                "Admin token is missing or invalid",
+            )
+                .into_response()
+        }
+    }
+}
+
+impl fmt::Display for AdminAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            Self::InvalidLine { path, line } => write!(
+                f,
+                "line {} of {} isn't `<read|write> <token>`",
+                line,
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdminAuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            Self::InvalidLine { .. } => None,
+        }
+    }
+}
+
+/// Reject the requests of the write scope, keeping the runtime configuration immutable
+async fn reject_mutations(request: Request, next: Next) -> Response {
+    match *request.method() {
+        Method::GET | Method::HEAD => next.run(request).await,
+        _ => {
+            info!(
+                "Rejected admin request {} {} in read-only mode",
+                request.method(),
+                request.uri()
+            );
+            (StatusCode::FORBIDDEN, "The service is read-only").into_response()
+        }
+    }
+}
+
+/// The admin API, guarded by the admin credentials
+///
+/// Returns no routes if no admin credentials are configured. In read-only mode only the
+/// introspection endpoints are available.
+pub fn routes<S>(validators: ValidatorsState, read_only: bool) -> axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if validators.admin_auth().is_none() {
+        return axum::Router::new();
+    }
+
+    let mut router = axum::Router::new()
+        .route(
+            "/admin/maintenance",
+            put(maintenance::enable_service)
+                .delete(maintenance::disable_service)
+                .get(maintenance::status),
+        )
+        .route(
+            "/admin/maintenance/:validator",
+            put(maintenance::enable_validator).delete(maintenance::disable_validator),
+        )
+        .route("/admin/validators", get(validators::list))
+        .route(
+            "/admin/validators/enable",
+            post(validators::enable_selected),
+        )
+        .route(
+            "/admin/validators/disable",
+            post(validators::disable_selected),
+        )
+        .route(
+            "/admin/validators/:validator/enable",
+            post(validators::enable),
+        )
+        .route(
+            "/admin/validators/:validator/disable",
+            post(validators::disable),
+        )
+        .route("/admin/jwks", get(jwks::list))
+        .route("/admin/authorities/:name/refresh", post(jwks::refresh))
+        .route("/admin/registry", get(registry::list))
+        .route(
+            "/admin/registry/authorities/:name",
+            put(registry::put_authority).delete(registry::delete_authority),
+        )
+        .route(
+            "/admin/registry/validators/:name",
+            put(registry::put_validator).delete(registry::delete_validator),
+        )
+        .route("/admin/sbom", get(sbom::get))
+        .route("/admin/slots", get(slots::status))
+        .route(
+            "/admin/slots/staged",
+            put(slots::stage).delete(slots::discard),
+        )
+        .route("/admin/slots/promote", post(slots::promote))
+        .route("/admin/slots/rollback", post(slots::rollback));
+    if read_only {
+        info!("Read-only mode, the admin API only allows introspection");
+        router = router.route_layer(middleware::from_fn(reject_mutations));
+    }
+
+    router
+        .route_layer(middleware::from_fn_with_state(
+            validators.clone(),
+            authenticate,
+        ))
+        .with_state(validators)
+}
@@ -0,0 +1,105 @@
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http::StatusCode;
+use tracing::info;
+
+use crate::validator_file::{JWTAuthority, PartialJWTValidator};
+use crate::validators::registry::RegistryError;
+use crate::validators::ValidatorsState;
+
+pub async fn list(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    Json(validators.dynamic())
+}
+
+pub async fn put_authority(
+    State(validators): State<ValidatorsState>,
+    Path(name): Path<String>,
+    Json(authority): Json<JWTAuthority>,
+) -> Response {
+    info!("Registering authority {}", name);
+
+    let res = validators
+        .update_dynamic(|file, dynamic| {
+            if file.authorities.contains_key(&name) {
+                return Err(RegistryError::Conflict(format!("Authority {}", name)));
+            }
+
+            dynamic.authorities.insert(name.clone(), authority);
+            Ok(())
+        })
+        .await;
+
+    respond(&validators, res)
+}
+
+pub async fn delete_authority(
+    State(validators): State<ValidatorsState>,
+    Path(name): Path<String>,
+) -> Response {
+    info!("Removing authority {}", name);
+
+    let res = validators
+        .update_dynamic(|_, dynamic| match dynamic.authorities.remove(&name) {
+            Some(_) => Ok(()),
+            None => Err(RegistryError::NotFound(format!("Authority {}", name))),
+        })
+        .await;
+
+    respond(&validators, res)
+}
+
+pub async fn put_validator(
+    State(validators): State<ValidatorsState>,
+    Path(name): Path<String>,
+    Json(validator): Json<PartialJWTValidator>,
+) -> Response {
+    info!("Registering validator {}", name);
+
+    let res = validators
+        .update_dynamic(|file, dynamic| {
+            if file.validators.contains_key(&name) {
+                return Err(RegistryError::Conflict(format!("Validator {}", name)));
+            }
+
+            dynamic.validators.insert(name.clone(), validator);
+            Ok(())
+        })
+        .await;
+
+    respond(&validators, res)
+}
+
+pub async fn delete_validator(
+    State(validators): State<ValidatorsState>,
+    Path(name): Path<String>,
+) -> Response {
+    info!("Removing validator {}", name);
+
+    let res = validators
+        .update_dynamic(|_, dynamic| match dynamic.validators.remove(&name) {
+            Some(_) => Ok(()),
+            None => Err(RegistryError::NotFound(format!("Validator {}", name))),
+        })
+        .await;
+
+    respond(&validators, res)
+}
+
+fn respond(validators: &ValidatorsState, res: Result<(), RegistryError>) -> Response {
+    let err = match res {
+        Ok(()) => return Json(validators.dynamic()).into_response(),
+        Err(err) => err,
+    };
+
+    info!("Rejected registry change: {}", err);
+    let status = match err {
+        RegistryError::NoConfig => StatusCode::SERVICE_UNAVAILABLE,
+        RegistryError::Conflict(_) => StatusCode::CONFLICT,
+        RegistryError::NotFound(_) => StatusCode::NOT_FOUND,
+        RegistryError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        RegistryError::Persist(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    (status, err.to_string()).into_response()
+}
@@ -0,0 +1,11 @@
+use axum::response::IntoResponse;
+use http::header;
+
+use crate::sbom;
+
+pub async fn get() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/vnd.cyclonedx+json")],
+        sbom::get().to_string(),
+    )
+}
@@ -0,0 +1,66 @@
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http::StatusCode;
+use tracing::info;
+
+use crate::validator_file::ConfigFile;
+use crate::validators::slots::SlotError;
+use crate::validators::ValidatorsState;
+
+pub async fn status(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    Json(validators.slots())
+}
+
+/// Stage a configuration file, sent as YAML or JSON
+pub async fn stage(State(validators): State<ValidatorsState>, body: Bytes) -> Response {
+    let file = match ConfigFile::from_slice(&body) {
+        Ok(file) => file,
+        Err(err) => {
+            info!("Rejected staged configuration: {}", err);
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid configuration file: {}", err),
+            )
+                .into_response();
+        }
+    };
+
+    info!("Staging configuration");
+    let res = validators.stage(file).await;
+    respond(&validators, res)
+}
+
+pub async fn discard(State(validators): State<ValidatorsState>) -> Response {
+    info!("Discarding the staged configuration");
+    let res = validators.discard_staged();
+    respond(&validators, res)
+}
+
+pub async fn promote(State(validators): State<ValidatorsState>) -> Response {
+    info!("Promoting the staged configuration");
+    let res = validators.promote();
+    respond(&validators, res)
+}
+
+pub async fn rollback(State(validators): State<ValidatorsState>) -> Response {
+    info!("Rolling back to the previous configuration");
+    let res = validators.rollback();
+    respond(&validators, res)
+}
+
+fn respond(validators: &ValidatorsState, res: Result<(), SlotError>) -> Response {
+    let err = match res {
+        Ok(()) => return Json(validators.slots()).into_response(),
+        Err(err) => err,
+    };
+
+    info!("Rejected slot change: {}", err);
+    let status = match err {
+        SlotError::NothingStaged | SlotError::NothingToRollBack => StatusCode::NOT_FOUND,
+        SlotError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+
+    (status, err.to_string()).into_response()
+}
@@ -0,0 +1,152 @@
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+use crate::validators::validator::LabelSelector;
+use crate::validators::ValidatorsState;
+
+#[derive(Debug, Serialize)]
+struct ValidatorStatus {
+    name: String,
+    enabled: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SelectorQuery {
+    /// e.g. `team=payments,env=prod`
+    selector: Option<String>,
+}
+
+pub async fn list(
+    State(validators): State<ValidatorsState>,
+    Query(query): Query<SelectorQuery>,
+) -> Response {
+    let selector = match query.selector.as_deref().map(str::parse::<LabelSelector>) {
+        Some(Ok(selector)) => Some(selector),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        None => None,
+    };
+
+    let mut statuses = validators
+        .validators()
+        .statuses()
+        .into_iter()
+        .filter(|(validator, _)| {
+            selector
+                .as_ref()
+                .is_none_or(|selector| selector.matches(validator.labels()))
+        })
+        .map(|(validator, enabled)| ValidatorStatus {
+            name: validator.name().to_string(),
+            enabled,
+            labels: validator.labels().clone(),
+        })
+        .collect::<Vec<_>>();
+    statuses.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    Json(statuses).into_response()
+}
+
+pub async fn enable_selected(
+    State(validators): State<ValidatorsState>,
+    Query(query): Query<SelectorQuery>,
+) -> Response {
+    set_enabled_selected(&validators, query, true)
+}
+
+pub async fn disable_selected(
+    State(validators): State<ValidatorsState>,
+    Query(query): Query<SelectorQuery>,
+) -> Response {
+    set_enabled_selected(&validators, query, false)
+}
+
+/// Enable or disable every validator matching the selector, which is required so a missing
+/// selector doesn't affect every validator
+fn set_enabled_selected(
+    validators: &ValidatorsState,
+    query: SelectorQuery,
+    enabled: bool,
+) -> Response {
+    let selector = match query.selector.as_deref().map(str::parse::<LabelSelector>) {
+        Some(Ok(selector)) => selector,
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "The selector parameter is required",
+            )
+                .into_response()
+        }
+    };
+
+    let mut names = validators.validators().select(&selector);
+    if names.is_empty() {
+        return (StatusCode::NOT_FOUND, "No validator matches the selector").into_response();
+    }
+    names.sort_unstable();
+
+    if enabled {
+        info!("Enabling validators {}", names.join(", "));
+    } else {
+        warn!("Disabling validators {}", names.join(", "));
+    }
+    validators.validators().set_enabled_all(&names, enabled);
+
+    let statuses = validators
+        .validators()
+        .statuses()
+        .into_iter()
+        .filter(|(validator, _)| names.iter().any(|name| name == validator.name()))
+        .map(|(validator, enabled)| ValidatorStatus {
+            name: validator.name().to_string(),
+            enabled,
+            labels: validator.labels().clone(),
+        })
+        .collect::<Vec<_>>();
+    Json(statuses).into_response()
+}
+
+pub async fn enable(
+    State(validators): State<ValidatorsState>,
+    Path(validator): Path<String>,
+) -> Response {
+    set_enabled(&validators, validator, true)
+}
+
+pub async fn disable(
+    State(validators): State<ValidatorsState>,
+    Path(validator): Path<String>,
+) -> Response {
+    set_enabled(&validators, validator, false)
+}
+
+fn set_enabled(validators: &ValidatorsState, validator: String, enabled: bool) -> Response {
+    let Some(configured) = validators.validators().get_configured(&validator) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Validator {} not found", validator),
+        )
+            .into_response();
+    };
+
+    if enabled {
+        info!("Enabling validator {}", validator);
+    } else {
+        warn!("Disabling validator {}", validator);
+    }
+    validators.validators().set_enabled(&validator, enabled);
+
+    Json(ValidatorStatus {
+        name: validator,
+        enabled,
+        labels: configured.labels().clone(),
+    })
+    .into_response()
+}
@@ -2,7 +2,12 @@ use std::env;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
+
+use crate::events::EventFormat;
+use crate::mirror::MirrorTokens;
+use crate::startup_report::StartupReport;
+use crate::tracing_cfg::LogOutput;
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -18,13 +23,178 @@ pub struct Args {
     #[clap(short, long, default_value = "config.yaml", env = "CONFIG")]
     pub config: PathBuf,
 
+    /// Reject configurations with lint findings (e.g. unused authorities) instead of only
+    /// logging them as warnings.
+    #[clap(long, env = "STRICT_CONFIG")]
+    pub strict_config: bool,
+
+    /// The maximum time in seconds a single validation request may take, including any
+    /// requests made to the identity provider.
+    #[clap(long, default_value = "10", env = "REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+
+    /// Seconds added to the system time wherever the service reads it (token lifetimes, JWKS
+    /// refreshes, caches), to correct a known drift of the host clock. Negative if the host
+    /// clock is ahead.
+    #[clap(
+        long,
+        default_value = "0",
+        env = "CLOCK_SKEW",
+        allow_negative_numbers = true
+    )]
+    pub clock_skew: i64,
+
+    /// The bearer token required for the admin API under /admin, granting the write scope. The
+    /// admin API is disabled if no admin credentials are configured.
+    #[clap(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Send the warm-up token through every validator this many times before listening, so the
+    /// first requests after a deployment aren't slower than the rest.
+    #[clap(long, env = "WARM_UP_REQUESTS", requires = "warm_up_token")]
+    pub warm_up_requests: Option<usize>,
+
+    /// The token used for warming up, e.g. a long-lived token of a test client. Validators
+    /// rejecting it are logged.
+    #[clap(long, env = "WARM_UP_TOKEN", hide_env_values = true)]
+    pub warm_up_token: Option<String>,
+
+    /// Only verify signatures with a FIPS 140 validated crypto backend, refusing to start if the
+    /// build doesn't have one.
+    #[clap(long, env = "FIPS")]
+    pub fips: bool,
+
+    /// Reject every mutating admin endpoint (maintenance mode, enabling and disabling validators,
+    /// registration, staged configurations, JWKS refreshes), keeping introspection available.
+    #[clap(long, env = "READ_ONLY")]
+    pub read_only: bool,
+
+    /// A file of further admin tokens, one `<scope> <token>` per line with `read` (the GET
+    /// endpoints and explain mode) or `write` (everything) as scope.
+    #[clap(long, env = "ADMIN_TOKENS_FILE")]
+    pub admin_tokens_file: Option<PathBuf>,
+
+    /// Grant the read scope of the admin API to requests this validator accepts, e.g. to use
+    /// tokens of the identity provider with a required admin role.
+    #[clap(long, env = "ADMIN_READ_VALIDATOR")]
+    pub admin_read_validator: Option<String>,
+
+    /// Grant the write scope of the admin API to requests this validator accepts.
+    #[clap(long, env = "ADMIN_WRITE_VALIDATOR")]
+    pub admin_write_validator: Option<String>,
+
+    /// The file the authorities and validators registered through the admin API are persisted
+    /// to and restored from on startup. Registered entries are lost on restart if not set.
+    #[clap(long, env = "STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Persist fetched JWKS documents (with their ETag and Last-Modified) to this directory, so
+    /// their keys are available right after a restart and the first refresh is a conditional
+    /// request.
+    #[clap(long, env = "JWKS_CACHE_DIR")]
+    pub jwks_cache_dir: Option<PathBuf>,
+
+    /// Publish every validation decision to this event stream, given as
+    /// `nats://<servers>/<subject>` or `kafka://<brokers>/<topic>`. Requires the nats or kafka
+    /// feature.
+    #[clap(long, env = "EVENTS_SINK")]
+    pub events_sink: Option<String>,
+
+    /// The format of the published decision events.
+    #[clap(long, value_enum, default_value = "json", env = "EVENTS_FORMAT")]
+    pub events_format: EventFormat,
+
+    /// Replay a sample of the validation requests to the validation endpoint of another instance
+    /// (e.g. "http://canary:8080/auth") and compare its decisions, to test new versions and
+    /// configurations against production traffic.
+    #[clap(long, env = "MIRROR_TO")]
+    pub mirror_to: Option<String>,
+
+    /// The share of the requests that are mirrored, between 0 and 1.
+    #[clap(long, default_value = "0.01", env = "MIRROR_SAMPLE_RATE")]
+    pub mirror_sample_rate: f64,
+
+    /// How the tokens of mirrored requests are passed on, `hash` replaces them with their SHA-256
+    /// hash (only generating load), `forward` passes them on so the decisions can be compared.
+    #[clap(long, value_enum, default_value = "hash", env = "MIRROR_TOKENS")]
+    pub mirror_tokens: MirrorTokens,
+
+    /// A Unix socket to upgrade the binary through without dropping connections, see the
+    /// `upgrade` command. Upgrades are disabled if not set.
+    #[clap(long, env = "CONTROL_SOCKET")]
+    pub control_socket: Option<PathBuf>,
+
     /// The log filter configuration (e.g. "info,my_crate=debug").
     #[clap(short, long, default_value = "info", env = "JWT_FWA_LOG")]
     pub log: String,
 
+    /// Where the log is written to.
+    #[clap(long, value_enum, default_value = "stdout", env = "LOG_OUTPUT")]
+    pub log_output: LogOutput,
+
+    /// Additionally export the log to this OTLP/HTTP logs endpoint
+    /// (e.g. "http://collector:4318/v1/logs"). Requires the otlp feature.
+    #[clap(long, env = "OTLP_LOGS_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Additionally push the metrics to `statsd://<host>:<port>` (DogStatsD tags) or to an
+    /// OTLP/HTTP metrics endpoint (e.g. "http://collector:4318/v1/metrics", requires the otlp
+    /// feature).
+    #[clap(long, env = "METRICS_PUSH")]
+    pub metrics_push: Option<String>,
+
+    /// The interval in seconds the metrics are pushed at.
+    #[clap(long, default_value = "15", env = "METRICS_PUSH_INTERVAL")]
+    pub metrics_push_interval: u64,
+
+    /// How the startup is reported, `json` prints a single JSON object summarizing the loaded
+    /// configuration to stdout once the service is listening (instead of the startup banner).
+    #[clap(long, value_enum, default_value = "log", env = "STARTUP_REPORT")]
+    pub startup_report: StartupReport,
+
     /// Whether to output the log using ansi colors. [env: JWT_FWA_PLAIN_LOG=] [default: true]
     #[clap(short, long, action = ArgAction::SetFalse)]
     pub ansi: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Upgrade a configuration file written for an older version to the current layout and
+    /// exit. The changes are printed to stderr, comments of the file are not preserved.
+    MigrateConfig {
+        /// The file to migrate, defaults to the configuration file.
+        input: Option<PathBuf>,
+
+        /// Where to write the migrated file, defaults to stdout.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Smoke test a running instance and exit: check its probes, list its validators and
+    /// optionally send a test token to every validator. Prints a table of the results and exits
+    /// with a failure if any check failed.
+    Selftest {
+        /// The base URL of the instance, e.g. http://localhost:8080
+        #[clap(long)]
+        url: String,
+
+        /// A token every validator has to accept, e.g. issued by a development identity
+        /// provider.
+        #[clap(long, env = "SELFTEST_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+
+        /// Read the test token from this file instead.
+        #[clap(long, conflicts_with = "token")]
+        token_file: Option<PathBuf>,
+    },
+
+    /// Upgrade the running instance behind the control socket to the binary at the path it was
+    /// started with and exit: the new process takes over the listening socket and the old one
+    /// shuts down once its open requests are answered. Prints the PID of the new process.
+    Upgrade,
 }
 
 impl Args {
@@ -0,0 +1,87 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::LazyLock;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// What was built, so operators can verify what is actually deployed
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// The build time in RFC 3339 format
+    pub build_timestamp: String,
+    /// The optional cargo features the binary was built with
+    pub features: Vec<&'static str>,
+    /// The library verifying the signatures of tokens
+    pub crypto_backend: &'static str,
+    /// Whether the crypto backend is a FIPS 140 validated module, required by `--fips`
+    pub fips_validated: bool,
+}
+
+/// FIPS mode was requested, but the build can't guarantee it
+#[derive(Debug)]
+pub struct FipsUnavailable {
+    backend: &'static str,
+}
+
+/// Signatures are verified by aliri, which is built on ring and has no other backend to select
+const CRYPTO_BACKEND: &str = "ring";
+/// ring isn't a FIPS 140 validated module
+const FIPS_VALIDATED: bool = false;
+
+static BUILD_INFO: LazyLock<BuildInfo> = LazyLock::new(|| BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_sha: env!("BUILD_GIT_SHA"),
+    build_timestamp: env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|epoch| OffsetDateTime::from_unix_timestamp(epoch).ok())
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| "unknown".to_string()),
+    features: [
+        ("cel", cfg!(feature = "cel")),
+        ("journald", cfg!(feature = "journald")),
+        ("kafka", cfg!(feature = "kafka")),
+        ("ldap", cfg!(feature = "ldap")),
+        ("nats", cfg!(feature = "nats")),
+        ("otlp", cfg!(feature = "otlp")),
+        ("redis", cfg!(feature = "redis")),
+        ("sql", cfg!(feature = "sql")),
+        ("syslog", cfg!(feature = "syslog")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect(),
+    crypto_backend: CRYPTO_BACKEND,
+    fips_validated: FIPS_VALIDATED,
+});
+
+pub fn get() -> &'static BuildInfo {
+    &BUILD_INFO
+}
+
+/// Check that the build can run in FIPS mode
+///
+/// Every algorithm the crypto backend supports (HMAC, RSA PKCS#1 v1.5 and PSS, ECDSA over the
+/// NIST curves) is FIPS approved, so the backend is all that has to be validated.
+pub fn require_fips() -> Result<(), FipsUnavailable> {
+    if !FIPS_VALIDATED {
+        return Err(FipsUnavailable {
+            backend: CRYPTO_BACKEND,
+        });
+    }
+    Ok(())
+}
+
+impl fmt::Display for FipsUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the crypto backend {} isn't a FIPS 140 validated module",
+            self.backend
+        )
+    }
+}
+
+impl std::error::Error for FipsUnavailable {}
@@ -0,0 +1,86 @@
+use futures_util::future::BoxFuture;
+use ldap3::{LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use std::time::Duration;
+
+use super::{BoxError, Source, Template};
+use crate::validator_file::LdapConfig;
+
+/// The pseudo attribute selecting the distinguished name of the found entries
+const DN_ATTRIBUTE: &str = "dn";
+/// Requests no attributes at all (RFC 4511, section 4.5.1.8)
+const NO_ATTRIBUTES: &str = "1.1";
+
+pub struct LdapSource {
+    config: LdapConfig,
+    filter: Template,
+    timeout: Duration,
+}
+
+impl LdapSource {
+    pub fn new(config: LdapConfig, filter: Template, timeout: Duration) -> Self {
+        Self {
+            config,
+            filter,
+            timeout,
+        }
+    }
+
+    /// Search the directory with a fresh connection
+    ///
+    /// Results are cached by the enrichment, so a connection per lookup is cheap enough and avoids
+    /// having to detect connections dropped by the server.
+    async fn search(&self, filter: &str) -> Result<Vec<String>, LdapError> {
+        let settings = LdapConnSettings::new().set_conn_timeout(self.timeout);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        ldap3::drive!(conn);
+
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(
+                bind_dn,
+                self.config.bind_password.as_deref().unwrap_or_default(),
+            )
+            .await?
+            .success()?;
+        }
+
+        let dn = self.config.attribute.eq_ignore_ascii_case(DN_ATTRIBUTE);
+        let attributes = match dn {
+            true => vec![NO_ATTRIBUTES],
+            false => vec![self.config.attribute.as_str()],
+        };
+        let (entries, _) = ldap
+            .search(&self.config.search_base, Scope::Subtree, filter, attributes)
+            .await?
+            .success()?;
+
+        let mut values = Vec::new();
+        for entry in entries {
+            let entry = SearchEntry::construct(entry);
+            if dn {
+                values.push(entry.dn);
+                continue;
+            }
+
+            // Attribute names are case-insensitive, the server may not use the configured case
+            values.extend(
+                entry
+                    .attrs
+                    .into_iter()
+                    .filter(|(name, _)| name.eq_ignore_ascii_case(&self.config.attribute))
+                    .flat_map(|(_, values)| values),
+            );
+        }
+
+        let _ = ldap.unbind().await;
+        Ok(values)
+    }
+}
+
+impl Source for LdapSource {
+    fn fetch<'a>(&'a self, args: &'a [String]) -> BoxFuture<'a, Result<Vec<String>, BoxError>> {
+        Box::pin(async move {
+            let filter = self.filter.render(args, ldap3::ldap_escape);
+            Ok(self.search(&filter).await?)
+        })
+    }
+}
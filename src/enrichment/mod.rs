@@ -0,0 +1,376 @@
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::metrics;
+use crate::validator_file::{EnrichmentConfig, EnrichmentSource};
+use crate::validators::claims::{Claim, JWTClaims};
+
+#[cfg(feature = "ldap")]
+mod ldap;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "sql")]
+mod sql;
+
+/// Looked up values are dropped after this long, unless configured otherwise
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Expired entries are purged once the cache grows beyond this many entries
+const CACHE_PURGE_SIZE: usize = 10_000;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Values of a token's identity looked up in an external directory or database, e.g. nested AD
+/// groups or entitlements
+///
+/// The values are added to the claims of valid tokens, so they can be required and mapped like any
+/// other claim. Lookups are cached per identity, so unchanged enrichments keep their cache across
+/// reloads.
+#[derive(Clone)]
+pub struct Enrichment {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    config: EnrichmentConfig,
+    template: Template,
+    source: Box<dyn Source>,
+    ttl: Duration,
+    timeout: Duration,
+    cache: DashMap<String, Cached>,
+}
+
+struct Cached {
+    values: Arc<[String]>,
+    expires: Instant,
+}
+
+/// A directory or database identities are looked up in
+trait Source: Send + Sync {
+    /// Look up the values for the claims used by the template, in the order of
+    /// [`Template::claims`]
+    fn fetch<'a>(&'a self, args: &'a [String]) -> BoxFuture<'a, Result<Vec<String>, BoxError>>;
+}
+
+/// A lookup key, filter or query with `{claim}` placeholders
+#[derive(Debug, Clone)]
+struct Template {
+    segments: Vec<Segment>,
+    /// The distinct claims of the placeholders, a placeholder refers to its claim by index
+    claims: Vec<Claim>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Arg(usize),
+}
+
+/// How the values of an enrichment were obtained for a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Cached,
+    Fetched,
+    /// The lookup failed, the claim is left out
+    Failed,
+    /// The token lacks a claim used by the lookup key
+    Skipped,
+}
+
+#[derive(Debug)]
+pub enum EnrichmentError {
+    /// The source was not enabled at compile time
+    #[cfg_attr(
+        all(feature = "ldap", feature = "redis", feature = "sql"),
+        allow(dead_code)
+    )]
+    Unsupported(&'static str),
+    /// The lookup key has an unterminated or empty placeholder
+    InvalidTemplate(String),
+    /// The URL of the source is invalid or uses an unsupported scheme
+    #[cfg_attr(not(any(feature = "redis", feature = "sql")), allow(dead_code))]
+    InvalidUrl(String),
+    /// The values would replace a registered claim like `sub`
+    ReservedClaim(String),
+}
+
+impl Enrichment {
+    pub fn new(name: &str, config: &EnrichmentConfig) -> Result<Self, EnrichmentError> {
+        if Claim::new(&config.claim).is_registered() {
+            return Err(EnrichmentError::ReservedClaim(config.claim.clone()));
+        }
+
+        let timeout = config
+            .timeout_seconds
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT);
+        let template = Template::parse(match &config.source {
+            EnrichmentSource::Ldap(ldap) => &ldap.filter,
+            EnrichmentSource::Redis(redis) => &redis.key,
+            EnrichmentSource::Sql(sql) => &sql.query,
+        })?;
+
+        let inner = Inner {
+            name: name.to_string(),
+            config: config.clone(),
+            source: source(&config.source, &template, timeout)?,
+            template,
+            ttl: config
+                .cache_ttl
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CACHE_TTL),
+            timeout,
+            cache: DashMap::new(),
+        };
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// The configuration the enrichment was built from
+    #[inline]
+    pub fn config(&self) -> &EnrichmentConfig {
+        &self.inner.config
+    }
+
+    /// Look up the values for the token and add them to its claims
+    ///
+    /// Failed lookups are logged and leave the claim out, so requirements on it reject the request.
+    pub async fn apply(&self, claims: &mut JWTClaims) -> Outcome {
+        let outcome = self.lookup(claims).await;
+        metrics::ENRICHMENT_LOOKUPS
+            .with_label_values(&[self.name(), outcome.as_str()])
+            .inc();
+        outcome
+    }
+
+    async fn lookup(&self, claims: &mut JWTClaims) -> Outcome {
+        let inner = &*self.inner;
+
+        let Some(args) = inner.template.args(claims) else {
+            debug!(
+                "Token lacks the claims used by enrichment {}, skipping it",
+                inner.name
+            );
+            return Outcome::Skipped;
+        };
+        // Claims cannot contain NUL, so the key is unambiguous
+        let key = args.join("\0");
+
+        let now = Instant::now();
+        if let Some(cached) = inner.cache.get(&key).filter(|cached| cached.expires > now) {
+            claims
+                .enriched
+                .insert(inner.config.claim.clone(), cached.values.clone());
+            return Outcome::Cached;
+        }
+
+        let values = match tokio::time::timeout(inner.timeout, inner.source.fetch(&args)).await {
+            Ok(Ok(values)) => Arc::<[String]>::from(values),
+            Ok(Err(e)) => {
+                warn!("Enrichment {} failed: {}", inner.name, e);
+                return Outcome::Failed;
+            }
+            Err(_) => {
+                warn!(
+                    "Enrichment {} did not finish within {:?}",
+                    inner.name, inner.timeout
+                );
+                return Outcome::Failed;
+            }
+        };
+
+        if inner.cache.len() >= CACHE_PURGE_SIZE {
+            inner.cache.retain(|_, cached| cached.expires > now);
+        }
+        inner.cache.insert(
+            key,
+            Cached {
+                values: values.clone(),
+                expires: now + inner.ttl,
+            },
+        );
+        claims.enriched.insert(inner.config.claim.clone(), values);
+
+        Outcome::Fetched
+    }
+}
+
+#[cfg_attr(
+    not(all(feature = "ldap", feature = "redis", feature = "sql")),
+    allow(unused_variables)
+)]
+fn source(
+    config: &EnrichmentSource,
+    template: &Template,
+    timeout: Duration,
+) -> Result<Box<dyn Source>, EnrichmentError> {
+    match config {
+        #[cfg(feature = "ldap")]
+        EnrichmentSource::Ldap(ldap) => Ok(Box::new(ldap::LdapSource::new(
+            ldap.clone(),
+            template.clone(),
+            timeout,
+        ))),
+        #[cfg(not(feature = "ldap"))]
+        EnrichmentSource::Ldap(_) => Err(EnrichmentError::Unsupported("ldap")),
+        #[cfg(feature = "redis")]
+        EnrichmentSource::Redis(redis) => Ok(Box::new(redis::RedisSource::new(
+            redis.clone(),
+            template.clone(),
+        )?)),
+        #[cfg(not(feature = "redis"))]
+        EnrichmentSource::Redis(_) => Err(EnrichmentError::Unsupported("redis")),
+        #[cfg(feature = "sql")]
+        EnrichmentSource::Sql(sql) => Ok(Box::new(sql::SqlSource::new(
+            sql.clone(),
+            template,
+            timeout,
+        )?)),
+        #[cfg(not(feature = "sql"))]
+        EnrichmentSource::Sql(_) => Err(EnrichmentError::Unsupported("sql")),
+    }
+}
+
+impl Template {
+    fn parse(template: &str) -> Result<Self, EnrichmentError> {
+        let invalid = || EnrichmentError::InvalidTemplate(template.to_string());
+
+        let mut segments = Vec::new();
+        let mut claims = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+
+            let end = rest[start..].find('}').ok_or_else(invalid)? + start;
+            let claim = rest[start + 1..end].trim();
+            if claim.is_empty() {
+                return Err(invalid());
+            }
+
+            let claim = Claim::new(claim);
+            let index = match claims.iter().position(|c| *c == claim) {
+                Some(index) => index,
+                None => {
+                    claims.push(claim);
+                    claims.len() - 1
+                }
+            };
+            segments.push(Segment::Arg(index));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Self { segments, claims })
+    }
+
+    /// The first value of each claim used by the template, `None` if a claim is missing
+    fn args(&self, claims: &JWTClaims) -> Option<Vec<String>> {
+        self.claims
+            .iter()
+            .map(|claim| {
+                let values = claims.values(claim).ok()?;
+                values.first().map(|value| value.to_string())
+            })
+            .collect()
+    }
+
+    /// Replace the placeholders with the (escaped) arguments
+    #[cfg_attr(not(any(feature = "ldap", feature = "redis")), allow(dead_code))]
+    fn render<'a, E>(&self, args: &'a [String], escape: E) -> String
+    where
+        E: Fn(&'a str) -> Cow<'a, str>,
+    {
+        let mut res = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => res.push_str(literal),
+                Segment::Arg(index) => res.push_str(&escape(&args[*index])),
+            }
+        }
+
+        res
+    }
+
+    /// Replace the placeholders with bind parameters, returning the argument of each parameter
+    ///
+    /// The marker is given the (one-based) position of the parameter, e.g. `$1` or `?`.
+    #[cfg_attr(not(feature = "sql"), allow(dead_code))]
+    fn bind<M>(&self, marker: M) -> (String, Vec<usize>)
+    where
+        M: Fn(usize) -> String,
+    {
+        let mut res = String::new();
+        let mut params = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => res.push_str(literal),
+                Segment::Arg(index) => {
+                    params.push(*index);
+                    res.push_str(&marker(params.len()));
+                }
+            }
+        }
+
+        (res, params)
+    }
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Cached => "cached",
+            Outcome::Fetched => "fetched",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+impl fmt::Debug for Enrichment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Enrichment")
+            .field("name", &self.inner.name)
+            .field("claim", &self.inner.config.claim)
+            .field("template", &self.inner.template)
+            .field("cached", &self.inner.cache.len())
+            .finish()
+    }
+}
+
+impl fmt::Display for EnrichmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnrichmentError::Unsupported(feature) => write!(
+                f,
+                "the {} source is not supported, it is enabled by the {} feature",
+                feature, feature
+            ),
+            EnrichmentError::InvalidTemplate(template) => {
+                write!(f, "invalid placeholder in {}", template)
+            }
+            EnrichmentError::InvalidUrl(url) => write!(f, "invalid or unsupported URL {}", url),
+            EnrichmentError::ReservedClaim(claim) => {
+                write!(f, "cannot replace the registered claim {}", claim)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnrichmentError {}
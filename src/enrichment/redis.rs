@@ -0,0 +1,62 @@
+use futures_util::future::BoxFuture;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client, RedisError};
+use tokio::sync::OnceCell;
+
+use super::{BoxError, EnrichmentError, Source, Template};
+use crate::validator_file::RedisConfig;
+
+pub struct RedisSource {
+    client: Client,
+    /// Connected on the first lookup, so loading the configuration doesn't wait for Redis
+    connection: OnceCell<ConnectionManager>,
+    key: Template,
+    field: Option<String>,
+}
+
+impl RedisSource {
+    pub fn new(config: RedisConfig, key: Template) -> Result<Self, EnrichmentError> {
+        let client = Client::open(config.url.as_str())
+            .map_err(|_| EnrichmentError::InvalidUrl(config.url.clone()))?;
+
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+            key,
+            field: config.field,
+        })
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<String>, RedisError> {
+        // The connection manager reconnects by itself, clones share the connection
+        let mut conn = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?
+            .clone();
+
+        if let Some(field) = &self.field {
+            let value: Option<String> = conn.hget(key, field).await?;
+            return Ok(value.into_iter().collect());
+        }
+
+        let kind: String = redis::cmd("TYPE").arg(key).query_async(&mut conn).await?;
+        match kind.as_str() {
+            "set" => conn.smembers(key).await,
+            "list" => conn.lrange(key, 0, -1).await,
+            "none" => Ok(Vec::new()),
+            // Anything else is rejected by GET with a descriptive error
+            _ => conn.get(key).await.map(|value: String| vec![value]),
+        }
+    }
+}
+
+impl Source for RedisSource {
+    fn fetch<'a>(&'a self, args: &'a [String]) -> BoxFuture<'a, Result<Vec<String>, BoxError>> {
+        Box::pin(async move {
+            // Keys are binary safe, so the claims are used as they are
+            let key = self.key.render(args, |arg| arg.into());
+            Ok(self.get(&key).await?)
+        })
+    }
+}
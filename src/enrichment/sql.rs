@@ -0,0 +1,68 @@
+use futures_util::future::BoxFuture;
+use sqlx::any::{install_default_drivers, AnyPoolOptions};
+use sqlx::{AnyPool, Row};
+use std::time::Duration;
+
+use super::{BoxError, EnrichmentError, Source, Template};
+use crate::validator_file::SqlConfig;
+
+const DEFAULT_MAX_CONNECTIONS: u32 = 4;
+
+pub struct SqlSource {
+    pool: AnyPool,
+    query: String,
+    /// The argument bound to each parameter of the query
+    params: Vec<usize>,
+}
+
+impl SqlSource {
+    pub fn new(
+        config: SqlConfig,
+        query: &Template,
+        timeout: Duration,
+    ) -> Result<Self, EnrichmentError> {
+        let invalid = || EnrichmentError::InvalidUrl(config.url.clone());
+
+        // The placeholders are passed as parameters, so claims can never change the query itself
+        let (query, params) = match config.url.split_once("://").map(|(scheme, _)| scheme) {
+            Some("postgres" | "postgresql") => query.bind(|position| format!("${}", position)),
+            Some("mysql" | "mariadb") => query.bind(|_| "?".to_string()),
+            _ => return Err(invalid()),
+        };
+
+        install_default_drivers();
+        // Connections are opened on the first lookup, so loading the configuration doesn't wait
+        // for the database
+        let pool = AnyPoolOptions::new()
+            .max_connections(config.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS))
+            .acquire_timeout(timeout)
+            .connect_lazy(&config.url)
+            .map_err(|_| invalid())?;
+
+        Ok(Self {
+            pool,
+            query,
+            params,
+        })
+    }
+
+    async fn select(&self, args: &[String]) -> Result<Vec<String>, sqlx::Error> {
+        let mut query = sqlx::query(&self.query);
+        for param in &self.params {
+            query = query.bind(args[*param].as_str());
+        }
+
+        query
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| row.try_get::<String, _>(0))
+            .collect()
+    }
+}
+
+impl Source for SqlSource {
+    fn fetch<'a>(&'a self, args: &'a [String]) -> BoxFuture<'a, Result<Vec<String>, BoxError>> {
+        Box::pin(async move { Ok(self.select(args).await?) })
+    }
+}
@@ -0,0 +1,41 @@
+use futures_util::future::BoxFuture;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use std::time::Duration;
+
+use super::{BoxError, Sink, SinkError};
+
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// Create a producer for the comma separated brokers, it connects lazily
+    pub fn connect(brokers: &str, topic: &str) -> Result<Box<dyn Sink>, SinkError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "5000")
+            .create()
+            .map_err(|e| SinkError::Connect(Box::new(e)))?;
+
+        Ok(Box::new(Self {
+            producer,
+            topic: topic.to_string(),
+        }))
+    }
+}
+
+impl Sink for KafkaSink {
+    fn send<'a>(&'a self, key: &'a str, payload: Vec<u8>) -> BoxFuture<'a, Result<(), BoxError>> {
+        Box::pin(async move {
+            let record = FutureRecord::to(&self.topic).key(key).payload(&payload);
+            // Fail instead of waiting if the producer queue is full
+            self.producer
+                .send(record, Duration::ZERO)
+                .await
+                .map_err(|(e, _)| e)?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,243 @@
+use axum::response::Response;
+use futures_util::future::BoxFuture;
+use http::HeaderMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::metrics;
+use crate::utils::clock;
+use crate::validators::privacy::Identity;
+use crate::validators::reason::{Reason, TokenSubject};
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+
+/// Events waiting to be published, further events are dropped while the sink is behind
+const QUEUE_SIZE: usize = 4096;
+/// The header identifying a request across the proxy and its services
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum EventFormat {
+    Json,
+    /// CloudEvents in structured JSON mode
+    Cloudevents,
+}
+
+/// Publishes every validation decision to an event stream
+#[derive(Debug, Clone)]
+pub struct Events {
+    queue: Option<mpsc::Sender<DecisionEvent>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecisionEvent {
+    id: String,
+    time: String,
+    validator: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    subject: Option<String>,
+    decision: Decision,
+    status: u16,
+    reason: Option<Reason>,
+    request_id: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Serialize)]
+struct CloudEvent<'a> {
+    specversion: &'static str,
+    id: &'a str,
+    source: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    time: &'a str,
+    subject: &'a str,
+    datacontenttype: &'static str,
+    data: &'a DecisionEvent,
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    InvalidUrl(String),
+    Unsupported(String),
+    #[cfg(any(feature = "kafka", feature = "nats"))]
+    Connect(BoxError),
+}
+
+/// A connection to an event stream
+trait Sink: Send + Sync {
+    /// Publish a serialized event, keyed by the validator
+    fn send<'a>(&'a self, key: &'a str, payload: Vec<u8>) -> BoxFuture<'a, Result<(), BoxError>>;
+}
+
+impl Events {
+    /// Connect to the sink given as `<scheme>://<servers>/<subject or topic>`, if any
+    pub async fn start(url: Option<&str>, format: EventFormat) -> Result<Self, SinkError> {
+        let Some(url) = url else {
+            return Ok(Self { queue: None });
+        };
+
+        let sink = connect(url).await?;
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(publish(sink, format, rx));
+
+        Ok(Self { queue: Some(tx) })
+    }
+
+    /// Queue a decision for publishing, the event is only built if a sink is configured
+    pub fn record(&self, event: impl FnOnce() -> DecisionEvent) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+
+        match queue.try_send(event()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("Decision event queue is full, dropping event");
+                metrics::DECISION_EVENTS
+                    .with_label_values(&["dropped"])
+                    .inc();
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+impl DecisionEvent {
+    pub fn new(
+        validator: &str,
+        labels: &BTreeMap<String, String>,
+        headers: &HeaderMap,
+        response: &Response,
+    ) -> Self {
+        let status = response.status();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            time: OffsetDateTime::from(clock::now())
+                .format(&Rfc3339)
+                .expect("the current time should be formattable"),
+            validator: validator.to_string(),
+            labels: labels.clone(),
+            subject: response
+                .extensions()
+                .get::<TokenSubject>()
+                .map(|subject| Identity::subject(&subject.0).to_string()),
+            decision: match status.is_success() {
+                true => Decision::Allow,
+                false => Decision::Deny,
+            },
+            status: status.as_u16(),
+            reason: response.extensions().get::<Reason>().copied(),
+            request_id: headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+
+    fn serialize(&self, format: EventFormat) -> Vec<u8> {
+        let res = match format {
+            EventFormat::Json => serde_json::to_vec(self),
+            EventFormat::Cloudevents => serde_json::to_vec(&CloudEvent {
+                specversion: "1.0",
+                id: &self.id,
+                source: "jwt-forward-auth",
+                kind: "jwt-forward-auth.decision",
+                time: &self.time,
+                subject: &self.validator,
+                datacontenttype: "application/json",
+                data: self,
+            }),
+        };
+
+        res.expect("decision events should be serializable")
+    }
+}
+
+async fn connect(url: &str) -> Result<Box<dyn Sink>, SinkError> {
+    let invalid = || SinkError::InvalidUrl(url.to_string());
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let (servers, target) = rest
+        .split_once('/')
+        .filter(|(servers, target)| !servers.is_empty() && !target.is_empty())
+        .ok_or_else(invalid)?;
+
+    info!(
+        "Publishing decision events to {} on {} ({})",
+        target, servers, scheme
+    );
+    match scheme {
+        #[cfg(feature = "nats")]
+        "nats" => nats::NatsSink::connect(servers, target).await,
+        #[cfg(feature = "kafka")]
+        "kafka" => kafka::KafkaSink::connect(servers, target),
+        scheme => Err(SinkError::Unsupported(scheme.to_string())),
+    }
+}
+
+async fn publish(
+    sink: Box<dyn Sink>,
+    format: EventFormat,
+    mut queue: mpsc::Receiver<DecisionEvent>,
+) {
+    while let Some(event) = queue.recv().await {
+        let outcome = match sink.send(&event.validator, event.serialize(format)).await {
+            Ok(()) => "published",
+            Err(e) => {
+                warn!("Failed to publish decision event: {}", e);
+                "failed"
+            }
+        };
+
+        metrics::DECISION_EVENTS.with_label_values(&[outcome]).inc();
+    }
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::InvalidUrl(url) => write!(
+                f,
+                "Invalid event sink {}, expected <scheme>://<servers>/<subject or topic>",
+                url
+            ),
+            SinkError::Unsupported(scheme) => write!(
+                f,
+                "Unsupported event sink {}, sinks are enabled by the kafka and nats features",
+                scheme
+            ),
+            #[cfg(any(feature = "kafka", feature = "nats"))]
+            SinkError::Connect(err) => write!(f, "Failed to connect to the event sink: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(any(feature = "kafka", feature = "nats"))]
+            SinkError::Connect(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use futures_util::future::BoxFuture;
+
+use super::{BoxError, Sink, SinkError};
+
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsSink {
+    /// Connect to the comma separated servers
+    pub async fn connect(servers: &str, subject: &str) -> Result<Box<dyn Sink>, SinkError> {
+        let client = async_nats::connect(servers)
+            .await
+            .map_err(|e| SinkError::Connect(e.into()))?;
+
+        Ok(Box::new(Self {
+            client,
+            subject: subject.to_string(),
+        }))
+    }
+}
+
+impl Sink for NatsSink {
+    fn send<'a>(&'a self, _: &'a str, payload: Vec<u8>) -> BoxFuture<'a, Result<(), BoxError>> {
+        Box::pin(async move {
+            self.client
+                .publish(self.subject.clone(), payload.into())
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -1,18 +1,31 @@
-use crate::utils::Shutdown;
+use crate::utils::{clock, Shutdown, ShutdownContext};
 use axum::body::Bytes;
 use axum::extract::Request;
 use axum::http::{HeaderMap, Response};
 use axum::{Router, ServiceExt};
+use std::path::Path;
 use std::time::Duration;
 use this_state::State as ThisState;
+use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::classify::ServerErrorsFailureClass;
 use tower_http::normalize_path::NormalizePathLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{debug, error, info, Span};
 
+mod admin;
 mod args;
+mod build_info;
+mod enrichment;
+mod events;
+mod metrics;
+mod mirror;
+mod openapi;
 mod probes;
+mod sbom;
+mod selftest;
+mod startup_report;
 mod tracing_cfg;
+mod upgrade;
 mod utils;
 mod validator_file;
 mod validators;
@@ -32,7 +45,36 @@ pub type State = ThisState<States>;
 fn main() {
     let args = args::Args::parse();
 
-    tracing_cfg::register_subscriber(args.ansi, &args.log);
+    if let Some(args::Command::MigrateConfig { input, output }) = &args.command {
+        migrate_config(input.as_ref().unwrap_or(&args.config), output.as_deref());
+        return;
+    }
+    if let Some(args::Command::Selftest {
+        url,
+        token,
+        token_file,
+    }) = &args.command
+    {
+        selftest(url, token.as_deref(), token_file.as_deref());
+        return;
+    }
+    if let Some(args::Command::Upgrade) = &args.command {
+        request_upgrade(args.control_socket.as_deref());
+        return;
+    }
+
+    // Installed before anything reads the time
+    clock::install(clock::SystemClock::with_skew(args.clock_skew));
+
+    // Kept until the end of main, so the exported logs are flushed on shutdown
+    let Ok(_logs) = tracing_cfg::register_subscriber(
+        args.ansi,
+        &args.log,
+        args.log_output,
+        args.otlp_endpoint.as_deref(),
+    ) else {
+        return;
+    };
 
     let runtime = match tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -52,16 +94,144 @@ fn main() {
     info!("Runtime shutdown complete");
 }
 
+/// Run the `migrate-config` command, the log isn't set up, as stdout may hold the migrated file
+fn migrate_config(input: &Path, output: Option<&Path>) {
+    match validator_file::migrate::migrate_file(input, output) {
+        Ok(changes) if changes.is_empty() => {
+            eprintln!("{} already uses the current layout", input.display())
+        }
+        Ok(changes) => {
+            for change in changes {
+                eprintln!("{}", change);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to migrate {}: {}", input.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run the `selftest` command, the report is the only output
+fn selftest(url: &str, token: Option<&str>, token_file: Option<&Path>) {
+    let token = match token_file {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(token) => Some(token.trim().to_string()),
+            Err(e) => {
+                eprintln!("Failed to read the token from {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => token.map(str::to_string),
+    };
+
+    let passed = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime.block_on(selftest::run(url, token.as_deref())),
+        Err(e) => {
+            eprintln!("Failed to create runtime: {}", e);
+            false
+        }
+    };
+    if !passed {
+        std::process::exit(1);
+    }
+}
+
+/// Run the `upgrade` command, the answer of the running instance is the only output
+fn request_upgrade(control_socket: Option<&Path>) {
+    let Some(path) = control_socket else {
+        eprintln!("The control socket of the running instance is required (--control-socket)");
+        std::process::exit(1);
+    };
+
+    let answer = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime.block_on(upgrade::request(path)),
+        Err(e) => {
+            eprintln!("Failed to create runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match answer {
+        Ok(answer) if answer.starts_with("ok") => println!("{}", answer),
+        Ok(answer) => {
+            eprintln!("{}", answer);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to reach {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
 async fn async_main(args: args::Args) -> Result<(), Shutdown> {
-    info!("Starting up");
+    args.startup_report.starting();
+    if args.fips {
+        build_info::require_fips().with_context(|| "Failed to enable FIPS mode")?;
+    }
 
     let state = State::new(States::Starting);
-    let validators = validators::Store::new(state.clone(), reqwest::Client::new());
-    validators.start_file_watcher(args.config).await?;
+    // Kept until the end, so the final values are pushed on shutdown
+    let _metrics_push = metrics::MetricsPush::start(
+        args.metrics_push.as_deref(),
+        Duration::from_secs(args.metrics_push_interval.max(1)),
+    )
+    .await
+    .with_context(|| "Failed to start pushing metrics")?;
+    let events = events::Events::start(args.events_sink.as_deref(), args.events_format)
+        .await
+        .with_context(|| "Failed to start publishing decision events")?;
+    let mirror = mirror::Mirror::start(
+        args.mirror_to.as_deref(),
+        args.mirror_sample_rate,
+        args.mirror_tokens,
+    )
+    .with_context(|| "Failed to start mirroring requests")?;
+    let jwks_cache = args
+        .jwks_cache_dir
+        .map(|dir| {
+            validators::jwks_cache::JwksCache::new(dir.clone())
+                .with_context(|| format!("Failed to create the JWKS cache {}", dir.display()))
+        })
+        .transpose()?;
+    let admin_auth = admin::AdminAuth::new(
+        args.admin_token,
+        args.admin_tokens_file.as_deref(),
+        args.admin_read_validator,
+        args.admin_write_validator,
+    )
+    .with_context(|| "Failed to load the admin credentials")?;
+    let validators = validators::Store::new(
+        state.clone(),
+        Duration::from_secs(args.request_timeout),
+        admin_auth,
+        args.state_file,
+        args.strict_config,
+        events,
+        mirror,
+        jwks_cache,
+    );
+    validators.start_file_watcher(args.config.clone()).await?;
+    if let (Some(requests), Some(token)) = (args.warm_up_requests, &args.warm_up_token) {
+        validators::warm_up(&validators.state(), requests, token).await;
+    }
 
     let app = Router::new()
-        .merge(probes::routes(state.clone()))
+        .merge(probes::routes(
+            state.clone(),
+            validators.state().connectivity().clone(),
+        ))
+        .merge(openapi::routes(validators.state()))
+        .merge(metrics::routes())
+        .merge(admin::routes(validators.state(), args.read_only))
         .nest("/auth", validators::routes(validators.state()))
+        .layer(CatchPanicLayer::custom(utils::panic::panic_response))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|request: &Request<_>| {
@@ -79,7 +249,7 @@ async fn async_main(args: args::Args) -> Result<(), Shutdown> {
                 .on_response(|res: &Response<_>, _: Duration, span: &Span| {
                     span.record(
                         "status_code",
-                        &tracing::field::display(res.status().as_u16()),
+                        tracing::field::display(res.status().as_u16()),
                     );
 
                     debug!("Finished processing request");
@@ -92,8 +262,25 @@ async fn async_main(args: args::Args) -> Result<(), Shutdown> {
         )
         .layer(NormalizePathLayer::trim_trailing_slash());
 
-    let listener = tokio::net::TcpListener::bind(args.listen_address).await?;
-    axum::serve(listener, ServiceExt::<Request>::into_make_service(app)).await?;
+    let inherited = upgrade::inherited_listener()
+        .with_context(|| "Failed to take over the listening socket")?;
+    let upgraded = inherited.is_some();
+    let listener = match inherited {
+        Some(listener) => tokio::net::TcpListener::from_std(listener)?,
+        None => tokio::net::TcpListener::bind(args.listen_address).await?,
+    };
+    args.startup_report.listening(
+        vec![listener.local_addr()?],
+        &args.config,
+        &state,
+        &validators.state(),
+    );
+    let control = upgrade::Control::start(args.control_socket.as_deref(), &listener, upgraded)
+        .await
+        .with_context(|| "Failed to open the control socket")?;
+    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
+        .with_graceful_shutdown(control.handed_over())
+        .await?;
 
     Ok(())
 }
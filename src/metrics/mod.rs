@@ -0,0 +1,348 @@
+use std::sync::LazyLock;
+
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use prometheus::core::Collector;
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tracing::{debug, warn};
+
+mod push;
+
+pub use push::MetricsPush;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// Requests per tenant, by outcome (`allowed` or `rate_limited`)
+pub static TENANT_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_tenant_requests_total",
+                "Validation requests per tenant, by outcome",
+            ),
+            &["tenant", "outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Number of authorities configured per tenant
+pub static TENANT_AUTHORITIES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "jwt_fwa_tenant_authorities",
+                "Number of authorities configured per tenant",
+            ),
+            &["tenant"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Requests evaluated by the staged configuration, by whether its decision matched the active one
+pub static SHADOW_DECISIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_shadow_decisions_total",
+                "Requests evaluated by the staged configuration, by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Decision events, by outcome (`published`, `failed` or `dropped`)
+pub static DECISION_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_decision_events_total",
+                "Decision events handed to the event sink, by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// The labels of the validators, always 1, for grouping the per-validator metrics by label
+pub static VALIDATOR_LABELS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "jwt_fwa_validator_labels",
+                "The labels of the configured validators, always 1",
+            ),
+            &["validator", "label", "value"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Whether the JWKS host of an authority answered the last connectivity check
+pub static IDP_REACHABLE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "jwt_fwa_idp_reachable",
+                "Whether the JWKS URL answered the last connectivity check",
+            ),
+            &["jwks_url"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Enrichment lookups, by outcome (`cached`, `fetched`, `failed` or `skipped`)
+pub static ENRICHMENT_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_enrichment_lookups_total",
+                "Identity enrichment lookups, by enrichment and outcome",
+            ),
+            &["enrichment", "outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// JWKS refreshes, by outcome (`updated`, `not_modified` or the reason the refresh failed)
+pub static JWKS_REFRESHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_jwks_refreshes_total",
+                "JWKS refreshes, by JWKS URL and outcome",
+            ),
+            &["jwks_url", "outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Number of keys loaded from a JWKS, a failed refresh keeps the previous keys
+pub static JWKS_KEYS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "jwt_fwa_jwks_keys",
+                "Number of keys loaded from the JWKS URL",
+            ),
+            &["jwks_url"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// The share of a validator's requests answered with a reason of an alert rule within the window
+pub static FAILURE_RATIO: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register(
+        GaugeVec::new(
+            Opts::new(
+                "jwt_fwa_failure_ratio",
+                "Share of the requests within the alert window answered with the reason",
+            ),
+            &["validator", "reason"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Whether the alert of a validator and reason is firing
+pub static ALERTS_FIRING: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "jwt_fwa_alert_firing",
+                "Whether the share of requests answered with the reason exceeds its threshold",
+            ),
+            &["validator", "reason"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Requests answered by the outage policy of a validator, by policy
+pub static OUTAGE_DECISIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_outage_decisions_total",
+                "Requests answered while the identity provider is unavailable, by validator and policy",
+            ),
+            &["validator", "policy"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Lookups of the decision cache, by outcome (`hit` or `miss`)
+pub static DECISION_CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_decision_cache_lookups_total",
+                "Lookups of the verified claims of tokens, by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Requests answered from the decision cache while the keys of the authority were refreshed or
+/// unavailable, by validator
+pub static STALE_DECISIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_stale_decisions_total",
+                "Requests answered from cached claims while the keys were revalidated, by validator",
+            ),
+            &["validator"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Requests mirrored to another instance, by outcome (`match`, `mismatch`, `sent`, `failed` or
+/// `dropped`)
+pub static MIRRORED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_mirrored_requests_total",
+                "Requests mirrored to another instance, by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Answered validation requests by validator and reason code, requests for unknown validators
+/// have an empty validator
+pub static DECISIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_decisions_total",
+                "Answered validation requests, by validator and reason code",
+            ),
+            &["validator", "reason"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Time spent in each phase of evaluating a validation request, by validator and phase
+///
+/// The buckets range from 10µs to about 650ms, the phases are fast unless something is off.
+pub static VALIDATION_PHASE_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "jwt_fwa_validation_phase_duration_seconds",
+                "Time spent in each phase of evaluating a validation request, by validator and phase",
+            )
+            .buckets(exponential_buckets(0.00001, 4.0, 9).expect("buckets should be valid")),
+            &["validator", "phase"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Failed required claims of `warn` severity, which don't reject the request
+pub static CLAIM_WARNINGS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_claim_warnings_total",
+                "Failed required claims of warn severity, by validator, claim and reason code",
+            ),
+            &["validator", "claim", "reason"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Decisions of a compared authority, by whether they matched the enforced authority
+pub static AUTHORITY_COMPARISONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "jwt_fwa_authority_comparisons_total",
+                "Tokens verified with a compared authority, by validator, authority and outcome",
+            ),
+            &["validator", "authority", "outcome"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Requests whose handler panicked, they are answered with 500
+pub static PANICS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "jwt_fwa_panics_total",
+            "Requests whose handler panicked and were answered with 500",
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+/// Uses of deprecated keys in the active configuration, by the old key name
+pub static CONFIG_DEPRECATED_KEYS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "jwt_fwa_config_deprecated_keys",
+                "Uses of deprecated keys in the active configuration",
+            ),
+            &["key"],
+        )
+        .expect("metric should be valid"),
+    )
+});
+
+fn register<C>(collector: C) -> C
+where
+    C: Collector + Clone + 'static,
+{
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("metric should only be registered once");
+    collector
+}
+
+async fn metrics() -> impl IntoResponse {
+    debug!("Serving metrics");
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        warn!("Failed to encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response()
+}
+
+pub fn routes<S>() -> axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    axum::Router::new().route("/metrics", get(metrics))
+}
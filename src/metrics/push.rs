@@ -0,0 +1,264 @@
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use super::REGISTRY;
+
+#[cfg(feature = "otlp")]
+mod otlp;
+
+/// Lines are batched into datagrams of at most this size, so they aren't fragmented on an
+/// Ethernet link
+const MAX_DATAGRAM_SIZE: usize = 1432;
+
+/// Pushes the metrics to a collector on an interval, for environments without a Prometheus scraper
+///
+/// The OTLP export is flushed when dropped, keep it alive until the application exits.
+pub struct MetricsPush {
+    _otlp: Option<otlp::OtlpMetrics>,
+}
+
+#[derive(Debug)]
+pub enum PushError {
+    InvalidUrl(String),
+    Unsupported(String),
+    Connect(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleKind {
+    Counter,
+    Gauge,
+}
+
+/// A single time series of the registry, histograms and summaries are pushed as their `_sum` and
+/// `_count` counters
+#[derive(Debug)]
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    kind: SampleKind,
+    value: f64,
+}
+
+impl MetricsPush {
+    /// Push to `statsd://<host>:<port>` or to an OTLP/HTTP metrics endpoint
+    /// (`http://collector:4318/v1/metrics`), if any
+    pub async fn start(url: Option<&str>, interval: Duration) -> Result<Self, PushError> {
+        let Some(url) = url else {
+            return Ok(Self { _otlp: None });
+        };
+
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| PushError::InvalidUrl(url.to_string()))?;
+        info!("Pushing metrics to {} every {:?}", url, interval);
+
+        match scheme {
+            "statsd" => {
+                let socket = UdpSocket::bind("0.0.0.0:0")
+                    .await
+                    .map_err(|e| PushError::Connect(e.to_string()))?;
+                socket
+                    .connect(rest.trim_end_matches('/'))
+                    .await
+                    .map_err(|e| PushError::Connect(e.to_string()))?;
+
+                tokio::spawn(statsd(socket, interval));
+                Ok(Self { _otlp: None })
+            }
+            "http" | "https" => Ok(Self {
+                _otlp: Some(otlp::OtlpMetrics::start(url, interval)?),
+            }),
+            scheme => Err(PushError::Unsupported(scheme.to_string())),
+        }
+    }
+}
+
+/// Push the samples as DogStatsD lines, counters are pushed as the increase since the last push
+async fn statsd(socket: UdpSocket, interval: Duration) {
+    let mut pushed = HashMap::<String, f64>::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let mut lines = Vec::new();
+        for sample in samples(&REGISTRY.gather()) {
+            let name = sanitize(&sample.name);
+            let tags = match sample.labels.is_empty() {
+                true => String::new(),
+                false => {
+                    let tags = sample
+                        .labels
+                        .iter()
+                        .map(|(name, value)| format!("{}:{}", sanitize(name), sanitize(value)))
+                        .collect::<Vec<_>>();
+                    format!("|#{}", tags.join(","))
+                }
+            };
+
+            let line = match sample.kind {
+                SampleKind::Gauge => format!("{}:{}|g{}", name, sample.value, tags),
+                SampleKind::Counter => {
+                    let series = format!("{}{}", name, tags);
+                    let previous = pushed.insert(series, sample.value).unwrap_or(0.0);
+                    // A counter can only decrease if it was reset
+                    let delta = match sample.value >= previous {
+                        true => sample.value - previous,
+                        false => sample.value,
+                    };
+                    if delta == 0.0 {
+                        continue;
+                    }
+
+                    format!("{}:{}|c{}", name, delta, tags)
+                }
+            };
+            lines.push(line);
+        }
+
+        for datagram in datagrams(&lines) {
+            if let Err(e) = socket.send(datagram.as_bytes()).await {
+                warn!("Failed to push metrics to StatsD: {}", e);
+                break;
+            }
+        }
+        debug!("Pushed {} metrics to StatsD", lines.len());
+    }
+}
+
+/// Join the lines into datagrams of at most [`MAX_DATAGRAM_SIZE`] bytes
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM_SIZE {
+            res.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        res.push(current);
+    }
+
+    res
+}
+
+/// Replace the characters with a meaning in the StatsD line format
+fn sanitize(value: &str) -> String {
+    value.replace([':', '|', ',', '#', '\n'], "_")
+}
+
+/// Flatten the metric families into their time series
+fn samples(families: &[MetricFamily]) -> Vec<Sample> {
+    let mut res = Vec::new();
+    for family in families {
+        for metric in family.get_metric() {
+            let labels = labels(metric);
+            let mut push = |name: String, kind, value| {
+                res.push(Sample {
+                    name,
+                    labels: labels.clone(),
+                    kind,
+                    value,
+                })
+            };
+
+            let name = family.get_name();
+            match family.get_field_type() {
+                MetricType::COUNTER => push(
+                    name.to_string(),
+                    SampleKind::Counter,
+                    metric.get_counter().get_value(),
+                ),
+                MetricType::GAUGE => push(
+                    name.to_string(),
+                    SampleKind::Gauge,
+                    metric.get_gauge().get_value(),
+                ),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    push(
+                        format!("{}_sum", name),
+                        SampleKind::Counter,
+                        histogram.get_sample_sum(),
+                    );
+                    push(
+                        format!("{}_count", name),
+                        SampleKind::Counter,
+                        histogram.get_sample_count() as f64,
+                    );
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    push(
+                        format!("{}_sum", name),
+                        SampleKind::Counter,
+                        summary.get_sample_sum(),
+                    );
+                    push(
+                        format!("{}_count", name),
+                        SampleKind::Counter,
+                        summary.get_sample_count() as f64,
+                    );
+                }
+                // Only produced by parsing the exposition format, never by the registry
+                MetricType::UNTYPED => {}
+            }
+        }
+    }
+
+    res
+}
+
+fn labels(metric: &Metric) -> Vec<(String, String)> {
+    metric
+        .get_label()
+        .iter()
+        .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+        .collect()
+}
+
+#[cfg(not(feature = "otlp"))]
+mod otlp {
+    use std::time::Duration;
+
+    use super::PushError;
+
+    pub enum OtlpMetrics {}
+
+    impl OtlpMetrics {
+        pub fn start(_: &str, _: Duration) -> Result<Self, PushError> {
+            Err(PushError::Unsupported(
+                "OTLP (requires the otlp feature)".to_string(),
+            ))
+        }
+    }
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushError::InvalidUrl(url) => write!(
+                f,
+                "Invalid metrics push target {}, expected statsd://<host>:<port> or an OTLP/HTTP URL",
+                url
+            ),
+            PushError::Unsupported(scheme) => {
+                write!(f, "Unsupported metrics push target {}", scheme)
+            }
+            PushError::Connect(err) => {
+                write!(f, "Failed to set up the metrics push: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PushError {}
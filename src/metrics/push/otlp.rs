@@ -0,0 +1,117 @@
+use opentelemetry::metrics::{Meter, MeterProvider};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::Resource;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use super::{samples, PushError, SampleKind, REGISTRY};
+
+/// Exports the metrics of the registry to an OTLP/HTTP endpoint
+pub struct OtlpMetrics {
+    provider: SdkMeterProvider,
+    instruments: JoinHandle<()>,
+}
+
+impl OtlpMetrics {
+    /// Export to the metrics endpoint (e.g. `http://collector:4318/v1/metrics`), additional
+    /// resource attributes are read from `OTEL_RESOURCE_ATTRIBUTES`
+    pub fn start(endpoint: &str, interval: Duration) -> Result<Self, PushError> {
+        let exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| PushError::Connect(e.to_string()))?;
+
+        let resource = Resource::builder()
+            .with_service_name(env!("CARGO_PKG_NAME"))
+            .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+            .build();
+
+        let provider = SdkMeterProvider::builder()
+            .with_resource(resource)
+            .with_reader(
+                PeriodicReader::builder(exporter)
+                    .with_interval(interval)
+                    .build(),
+            )
+            .build();
+
+        let meter = provider.meter(env!("CARGO_PKG_NAME"));
+        let instruments = tokio::spawn(register(meter, interval));
+
+        Ok(Self {
+            provider,
+            instruments,
+        })
+    }
+}
+
+/// Register an observable instrument for every time series of the registry
+///
+/// Metrics are registered with the registry when they are first used, so the registry is checked
+/// for new ones on every interval.
+async fn register(meter: Meter, interval: Duration) {
+    let mut registered = HashSet::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        for sample in samples(&REGISTRY.gather()) {
+            if !registered.insert(sample.name.clone()) {
+                continue;
+            }
+
+            let name = sample.name.clone();
+            let observe = move |kind: SampleKind, observe: &dyn Fn(f64, &[KeyValue])| {
+                for sample in samples(&REGISTRY.gather()) {
+                    if sample.name != name || sample.kind != kind {
+                        continue;
+                    }
+
+                    let attributes = sample
+                        .labels
+                        .into_iter()
+                        .map(|(name, value)| KeyValue::new(name, value))
+                        .collect::<Vec<_>>();
+                    observe(sample.value, &attributes);
+                }
+            };
+
+            // The instruments stay registered with the provider, the handles aren't needed
+            match sample.kind {
+                SampleKind::Counter => {
+                    meter
+                        .f64_observable_counter(sample.name)
+                        .with_callback(move |observer| {
+                            observe(SampleKind::Counter, &|value, attributes| {
+                                observer.observe(value, attributes)
+                            })
+                        })
+                        .build();
+                }
+                SampleKind::Gauge => {
+                    meter
+                        .f64_observable_gauge(sample.name)
+                        .with_callback(move |observer| {
+                            observe(SampleKind::Gauge, &|value, attributes| {
+                                observer.observe(value, attributes)
+                            })
+                        })
+                        .build();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for OtlpMetrics {
+    fn drop(&mut self) {
+        self.instruments.abort();
+        // Export the final values
+        let _ = self.provider.shutdown();
+    }
+}
@@ -0,0 +1,286 @@
+use http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::metrics;
+use crate::validators::explain::{ADMIN_TOKEN_HEADER, EXPLAIN_HEADER};
+use crate::validators::request::ForwardedRequest;
+use crate::validators::token_source::TokenSource;
+
+/// Requests waiting to be mirrored, further requests are dropped while the target is behind
+const QUEUE_SIZE: usize = 1024;
+/// The number of mirrored requests in flight at once
+const CONCURRENCY: usize = 64;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const FORWARDED_URI: HeaderName = HeaderName::from_static("x-forwarded-uri");
+
+/// Headers describing the connection to this instance, not the original request
+const HOP_BY_HOP: [HeaderName; 4] = [
+    header::HOST,
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::TRANSFER_ENCODING,
+];
+
+/// How the tokens of mirrored requests are passed on
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum MirrorTokens {
+    /// Replace the token with its SHA-256 hash, the target only receives the load
+    Hash,
+    /// Pass the token on unchanged, so the decisions of the target can be compared
+    Forward,
+}
+
+/// Replays a sample of the validation requests to another instance
+///
+/// Used to test new versions and configurations against production traffic. Only the headers
+/// are replayed, the decision of this instance is never affected by the target.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    inner: Option<Arc<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    queue: mpsc::Sender<Mirrored>,
+    sample_rate: f64,
+    tokens: MirrorTokens,
+    requests: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Mirrored {
+    validator: String,
+    headers: HeaderMap,
+    status: StatusCode,
+}
+
+#[derive(Debug)]
+pub enum MirrorError {
+    InvalidUrl(String),
+    InvalidSampleRate(f64),
+    Client(reqwest::Error),
+}
+
+impl Mirror {
+    /// Start mirroring to the validation endpoint of another instance (e.g.
+    /// `http://canary:8080/auth`), if given
+    pub fn start(
+        url: Option<&str>,
+        sample_rate: f64,
+        tokens: MirrorTokens,
+    ) -> Result<Self, MirrorError> {
+        let Some(url) = url else {
+            return Ok(Self { inner: None });
+        };
+
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(MirrorError::InvalidUrl(url.to_string()));
+        }
+        if !(0.0..=1.0).contains(&sample_rate) {
+            return Err(MirrorError::InvalidSampleRate(sample_rate));
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(MirrorError::Client)?;
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(replay(
+            client,
+            url.trim_end_matches('/').to_string(),
+            tokens,
+            rx,
+        ));
+
+        info!(
+            "Mirroring {:.1}% of the requests to {}",
+            sample_rate * 100.0,
+            url
+        );
+        Ok(Self {
+            inner: Some(Arc::new(Inner {
+                queue: tx,
+                sample_rate,
+                tokens,
+                requests: AtomicU64::new(0),
+            })),
+        })
+    }
+
+    /// Mirror the request if it is part of the sample
+    ///
+    /// The token source of the validator is used to find the token to hash, if tokens are hashed.
+    /// Without one, the authorization, the cookies and the query are stripped instead.
+    pub fn record(
+        &self,
+        validator: &str,
+        headers: &HeaderMap,
+        status: StatusCode,
+        source: Option<&TokenSource>,
+    ) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+
+        // Spread the sample evenly, without depending on a random number generator
+        let n = inner.requests.fetch_add(1, Ordering::Relaxed) as f64;
+        if (n * inner.sample_rate).floor() == ((n + 1.0) * inner.sample_rate).floor() {
+            return;
+        }
+
+        let mut headers = headers.clone();
+        for name in HOP_BY_HOP {
+            headers.remove(name);
+        }
+        // Admin features of this instance are not passed on
+        headers.remove(ADMIN_TOKEN_HEADER);
+        headers.remove(EXPLAIN_HEADER);
+
+        if inner.tokens == MirrorTokens::Hash {
+            match source {
+                Some(source) => source.replace_token(&mut headers, hash),
+                // The validator doesn't read a token, so every place one may be in is stripped
+                None => strip_credentials(&mut headers),
+            }
+        }
+
+        let mirrored = Mirrored {
+            validator: validator.to_string(),
+            headers,
+            status,
+        };
+        match inner.queue.try_send(mirrored) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("Mirror queue is full, dropping request");
+                metrics::MIRRORED_REQUESTS
+                    .with_label_values(&["dropped"])
+                    .inc();
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+/// Remove the authorization, the cookies and the query of the forwarded URI
+fn strip_credentials(headers: &mut HeaderMap) {
+    headers.remove(header::AUTHORIZATION);
+    headers.remove(header::COOKIE);
+
+    let path = ForwardedRequest::new(headers)
+        .uri()
+        .and_then(|uri| uri.split_once('?'))
+        .map(|(path, _)| path.to_string());
+    if let Some(path) = path.and_then(|path| HeaderValue::try_from(path).ok()) {
+        headers.insert(FORWARDED_URI, path);
+    }
+}
+
+fn hash(token: &[u8]) -> String {
+    let digest = Sha256::digest(token);
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn replay(
+    client: reqwest::Client,
+    url: String,
+    tokens: MirrorTokens,
+    mut queue: mpsc::Receiver<Mirrored>,
+) {
+    let permits = Arc::new(Semaphore::new(CONCURRENCY));
+    while let Some(mirrored) = queue.recv().await {
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            return;
+        };
+
+        let request = client
+            .get(format!("{}/{}", url, mirrored.validator))
+            .headers(mirrored.headers);
+        tokio::spawn(async move {
+            let outcome = match request.send().await {
+                // Hashed tokens never verify, so the decisions cannot be compared
+                Ok(_) if tokens == MirrorTokens::Hash => "sent",
+                Ok(res) if res.status() == mirrored.status => "match",
+                Ok(res) => {
+                    info!(
+                        "Mirrored request for validator {} was answered with {} instead of {}",
+                        mirrored.validator,
+                        res.status(),
+                        mirrored.status
+                    );
+                    "mismatch"
+                }
+                Err(e) => {
+                    warn!("Failed to mirror request: {}", e);
+                    "failed"
+                }
+            };
+
+            metrics::MIRRORED_REQUESTS
+                .with_label_values(&[outcome])
+                .inc();
+            drop(permit);
+        });
+    }
+}
+
+impl fmt::Display for MirrorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MirrorError::InvalidUrl(url) => write!(
+                f,
+                "Invalid mirror target {}, expected an http:// or https:// URL",
+                url
+            ),
+            MirrorError::InvalidSampleRate(rate) => write!(
+                f,
+                "Invalid mirror sample rate {}, expected a value between 0 and 1",
+                rate
+            ),
+            MirrorError::Client(e) => write!(f, "Failed to create the mirror client: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MirrorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MirrorError::Client(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_credentials_without_token_source() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer token"),
+        );
+        headers.insert(header::COOKIE, HeaderValue::from_static("session=token"));
+        headers.insert(
+            FORWARDED_URI,
+            HeaderValue::from_static("/api?access_token=token"),
+        );
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+
+        strip_credentials(&mut headers);
+
+        assert!(!headers.contains_key(header::AUTHORIZATION));
+        assert!(!headers.contains_key(header::COOKIE));
+        assert_eq!(headers[FORWARDED_URI], "/api");
+        assert_eq!(headers[header::ACCEPT], "application/json");
+    }
+}
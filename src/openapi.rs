@@ -0,0 +1,869 @@
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Json;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::validators::ValidatorsState;
+
+/// Build the OpenAPI document describing the service.
+///
+/// The document is generated on every request, so the `validator` path parameter always
+/// reflects the currently loaded validators.
+fn document(validators: &ValidatorsState) -> Value {
+    let names = validators.list();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "jwt-forward-auth",
+            "description": "Forward authentication service validating JWTs",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/healthz": {
+                "get": {
+                    "tags": ["probes"],
+                    "operationId": "healthz",
+                    "summary": "Liveness probe",
+                    "responses": {
+                        "200": text_response("The service is alive"),
+                    },
+                },
+            },
+            "/readyz": {
+                "get": {
+                    "tags": ["probes"],
+                    "operationId": "readyz",
+                    "summary": "Readiness probe",
+                    "responses": {
+                        "200": text_response("The service is ready to validate tokens"),
+                        "500": text_response("Only a faulty configuration is available"),
+                        "503": text_response("The service is still starting"),
+                    },
+                },
+            },
+            "/version": {
+                "get": {
+                    "tags": ["probes"],
+                    "operationId": "version",
+                    "summary": "Build information of the running binary",
+                    "responses": {
+                        "200": {
+                            "description": "The version, commit, build time and enabled features",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/BuildInfo" },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "/metrics": {
+                "get": {
+                    "tags": ["probes"],
+                    "operationId": "metrics",
+                    "summary": "Prometheus metrics",
+                    "responses": {
+                        "200": text_response("The metrics in the Prometheus text format"),
+                    },
+                },
+            },
+            "/auth": {
+                "get": {
+                    "tags": ["auth"],
+                    "operationId": "listValidators",
+                    "summary": "List the available validators",
+                    "parameters": [{
+                        "name": "prefix",
+                        "in": "query",
+                        "description": "Only list validators whose name starts with the prefix",
+                        "schema": { "type": "string" },
+                    }, {
+                        "name": "selector",
+                        "in": "query",
+                        "description": "Only list validators with the labels, e.g. team=payments,env=prod",
+                        "schema": { "type": "string" },
+                    }, {
+                        "name": "after",
+                        "in": "query",
+                        "description": "Only list validators whose name sorts after this one",
+                        "schema": { "type": "string" },
+                    }, {
+                        "name": "limit",
+                        "in": "query",
+                        "description": "The maximum number of validators listed, all of them if not set",
+                        "schema": { "type": "integer", "minimum": 1, "maximum": 1000 },
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "The available validators, sorted by name",
+                            "headers": {
+                                "X-Total-Count": {
+                                    "description": "The number of validators matching the filters",
+                                    "schema": { "type": "integer" },
+                                },
+                                "Link": {
+                                    "description": "The next page, if there are more validators",
+                                    "schema": { "type": "string" },
+                                },
+                            },
+                            "content": {
+                                "text/plain": {
+                                    "schema": { "type": "string" },
+                                },
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": { "$ref": "#/components/schemas/ValidatorInfo" },
+                                    },
+                                },
+                            },
+                        },
+                        "400": {
+                            "description": "The selector or limit is invalid",
+                            "content": {
+                                "text/plain": {
+                                    "schema": { "type": "string" },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "/auth/{validator}": {
+                "parameters": [{
+                    "name": "validator",
+                    "in": "path",
+                    "required": true,
+                    "description": "The name of the validator to validate the token with",
+                    "schema": {
+                        "type": "string",
+                        "enum": names,
+                    },
+                }, {
+                    "name": "X-Auth-Explain",
+                    "in": "header",
+                    "description": "Replace the response body with a trace of every check performed, \
+                        requires a valid X-Admin-Token",
+                    "schema": { "type": "boolean" },
+                }, {
+                    "name": "X-Admin-Token",
+                    "in": "header",
+                    "description": "The admin token, required for explain mode",
+                    "schema": { "type": "string" },
+                }],
+                "get": {
+                    "tags": ["auth"],
+                    "operationId": "validate",
+                    "summary": "Validate a token",
+                    "description": "Validates the token of the request using the given validator. \
+                        Every HTTP method is accepted. On success the mapped claims are returned \
+                        as response headers. The reason code of the decision (e.g. valid, \
+                        missing_token or unknown_kid) is returned in the X-Auth-Reason header.",
+                    "responses": {
+                        "200": {
+                            "description": "The token is valid, the expiry of tokens with an exp claim \
+                                is returned in X-Auth-Expires-At (Unix timestamp) and X-Auth-Expires-In (seconds)",
+                        },
+                        "2XX": {
+                            "description": "The token is valid, answered with the success response \
+                                (status, headers and body) configured for the validator",
+                        },
+                        "302": {
+                            "description": "The token is missing or invalid and the request accepts \
+                                text/html, redirecting to the login page of the validator (in Location)",
+                        },
+                        "401": text_response(
+                            "The token is missing or invalid, with an RFC 6750 challenge in WWW-Authenticate"
+                        ),
+                        "4XX": text_response("The validator denies every request with a fixed status"),
+                        "429": text_response("The tenant of the validator exceeded its request rate"),
+                        "503": text_response("The service or the validator is in maintenance mode"),
+                        "504": text_response("The validation did not finish within the request timeout"),
+                    },
+                },
+            },
+            "/admin/maintenance": {
+                "get": {
+                    "tags": ["admin"],
+                    "operationId": "maintenanceStatus",
+                    "summary": "Get the maintenance mode of the service and its validators",
+                    "security": [{ "adminToken": [] }],
+                    "responses": maintenance_responses(),
+                },
+                "put": {
+                    "tags": ["admin"],
+                    "operationId": "enableMaintenance",
+                    "summary": "Put the whole service into maintenance mode",
+                    "security": [{ "adminToken": [] }],
+                    "responses": maintenance_responses(),
+                },
+                "delete": {
+                    "tags": ["admin"],
+                    "operationId": "disableMaintenance",
+                    "summary": "Take the whole service out of maintenance mode",
+                    "security": [{ "adminToken": [] }],
+                    "responses": maintenance_responses(),
+                },
+            },
+            "/admin/maintenance/{validator}": {
+                "parameters": [{
+                    "name": "validator",
+                    "in": "path",
+                    "required": true,
+                    "schema": {
+                        "type": "string",
+                        "enum": names,
+                    },
+                }],
+                "put": {
+                    "tags": ["admin"],
+                    "operationId": "enableValidatorMaintenance",
+                    "summary": "Put a validator into maintenance mode",
+                    "security": [{ "adminToken": [] }],
+                    "responses": maintenance_responses(),
+                },
+                "delete": {
+                    "tags": ["admin"],
+                    "operationId": "disableValidatorMaintenance",
+                    "summary": "Take a validator out of maintenance mode",
+                    "security": [{ "adminToken": [] }],
+                    "responses": maintenance_responses(),
+                },
+            },
+            "/admin/validators": {
+                "get": {
+                    "tags": ["admin"],
+                    "operationId": "listAllValidators",
+                    "summary": "List all configured validators, including disabled ones",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [selector_parameter(false)],
+                    "responses": {
+                        "200": {
+                            "description": "The configured validators",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": { "$ref": "#/components/schemas/ValidatorStatus" },
+                                    },
+                                },
+                            },
+                        },
+                        "401": text_response("The admin token is missing or invalid"),
+                    },
+                },
+            },
+            "/admin/validators/enable": {
+                "parameters": [selector_parameter(true)],
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "enableSelectedValidators",
+                    "summary": "Enable every validator matching the label selector",
+                    "security": [{ "adminToken": [] }],
+                    "responses": selected_validators_responses(),
+                },
+            },
+            "/admin/validators/disable": {
+                "parameters": [selector_parameter(true)],
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "disableSelectedValidators",
+                    "summary": "Disable every validator matching the label selector",
+                    "security": [{ "adminToken": [] }],
+                    "responses": selected_validators_responses(),
+                },
+            },
+            "/admin/validators/{validator}/enable": {
+                "parameters": [admin_validator_parameter()],
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "enableValidator",
+                    "summary": "Enable a disabled validator",
+                    "security": [{ "adminToken": [] }],
+                    "responses": validator_status_responses(),
+                },
+            },
+            "/admin/validators/{validator}/disable": {
+                "parameters": [admin_validator_parameter()],
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "disableValidator",
+                    "summary": "Disable a validator until it is enabled again",
+                    "description": "Requests for a disabled validator are rejected as if it didn't exist. \
+                        The validator stays disabled across configuration reloads.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": validator_status_responses(),
+                },
+            },
+            "/admin/jwks": {
+                "get": {
+                    "tags": ["admin"],
+                    "operationId": "listJwks",
+                    "summary": "List the loaded keys of every JWKS and the outcome of its last refresh",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": {
+                            "description": "The JWKS, sorted by URL",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": { "$ref": "#/components/schemas/JwksStatus" },
+                                    },
+                                },
+                            },
+                        },
+                        "401": text_response("The admin token is missing or invalid"),
+                    },
+                },
+            },
+            "/admin/authorities/{name}/refresh": {
+                "parameters": [{
+                    "name": "name",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "refreshAuthority",
+                    "summary": "Refresh the JWKS of an authority right away",
+                    "description": "E.g. after the identity provider rotated its keys. A failed refresh \
+                        keeps the previous keys.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": refresh_response("The JWKS was refreshed"),
+                        "401": text_response("The admin token is missing or invalid"),
+                        "404": text_response("The authority doesn't exist"),
+                        "502": refresh_response("The JWKS could not be fetched, the previous keys are kept"),
+                    },
+                },
+            },
+            "/admin/registry": {
+                "get": {
+                    "tags": ["admin"],
+                    "operationId": "listRegistered",
+                    "summary": "List the authorities and validators registered through the admin API",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": registry_response(),
+                        "401": text_response("The admin token is missing or invalid"),
+                    },
+                },
+            },
+            "/admin/registry/authorities/{name}": registry_path(
+                "Authority",
+                "An authority, using the same schema as the configuration file",
+            ),
+            "/admin/registry/validators/{name}": registry_path(
+                "Validator",
+                "A validator, using the same schema as the configuration file",
+            ),
+            "/admin/sbom": {
+                "get": {
+                    "tags": ["admin"],
+                    "operationId": "sbom",
+                    "summary": "Get the software bill of materials of the running binary",
+                    "description": "A CycloneDX document of the locked dependencies, embedded at build \
+                        time, with the build provenance (commit, features, compiler, target) as \
+                        metadata properties.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": {
+                            "description": "The CycloneDX document",
+                            "content": {
+                                "application/vnd.cyclonedx+json": {
+                                    "schema": { "type": "object" },
+                                },
+                            },
+                        },
+                        "401": text_response("The admin token is missing or invalid"),
+                    },
+                },
+            },
+            "/admin/slots": {
+                "get": {
+                    "tags": ["admin"],
+                    "operationId": "slotStatus",
+                    "summary": "Get the staged configuration and whether a rollback is possible",
+                    "security": [{ "adminToken": [] }],
+                    "responses": slot_responses(),
+                },
+            },
+            "/admin/slots/staged": {
+                "put": {
+                    "tags": ["admin"],
+                    "operationId": "stageConfiguration",
+                    "summary": "Stage a configuration file, evaluating it in shadow mode",
+                    "security": [{ "adminToken": [] }],
+                    "requestBody": {
+                        "required": true,
+                        "description": "A configuration file, as YAML or JSON",
+                        "content": {
+                            "application/yaml": { "schema": { "type": "string" } },
+                            "application/json": { "schema": { "type": "object" } },
+                        },
+                    },
+                    "responses": slot_responses(),
+                },
+                "delete": {
+                    "tags": ["admin"],
+                    "operationId": "discardStagedConfiguration",
+                    "summary": "Discard the staged configuration",
+                    "security": [{ "adminToken": [] }],
+                    "responses": slot_responses(),
+                },
+            },
+            "/admin/slots/promote": {
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "promoteConfiguration",
+                    "summary": "Activate the staged configuration",
+                    "security": [{ "adminToken": [] }],
+                    "responses": slot_responses(),
+                },
+            },
+            "/admin/slots/rollback": {
+                "post": {
+                    "tags": ["admin"],
+                    "operationId": "rollbackConfiguration",
+                    "summary": "Reactivate the configuration replaced by the last promotion or rollback",
+                    "security": [{ "adminToken": [] }],
+                    "responses": slot_responses(),
+                },
+            },
+        },
+        "components": {
+            "securitySchemes": {
+                "adminToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "An admin token (or a token accepted by an admin validator), the admin \
+                        API is only available if admin credentials are configured. GET operations \
+                        require the read scope, all others the write scope (403 otherwise).",
+                },
+            },
+            "schemas": {
+                "ValidatorInfo": {
+                    "type": "object",
+                    "required": ["name", "action", "required_claims", "mapped_claims"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "labels": labels_schema(),
+                        "action": { "type": "string", "enum": ["validate", "deny"] },
+                        "authority": {
+                            "type": "string",
+                            "description": "Only present for validators that validate tokens with a single authority",
+                        },
+                        "issuers": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "Only present for validators that select the authority by issuer",
+                        },
+                        "token_source": {
+                            "description": "Only present for validators that validate tokens",
+                            "oneOf": [
+                                {
+                                    "type": "object",
+                                    "required": ["header", "require_prefix"],
+                                    "properties": {
+                                        "header": { "type": "string" },
+                                        "prefixes": {
+                                            "type": "array",
+                                            "description": "The accepted prefixes, matched case-insensitively",
+                                            "items": { "type": "string" },
+                                        },
+                                        "require_prefix": { "type": "boolean" },
+                                    },
+                                },
+                                {
+                                    "type": "object",
+                                    "description": "A query parameter of the forwarded URI",
+                                    "required": ["query"],
+                                    "properties": {
+                                        "query": { "type": "string" },
+                                    },
+                                },
+                                {
+                                    "type": "object",
+                                    "description": "A cookie, reassembled from its chunks",
+                                    "required": ["cookie"],
+                                    "properties": {
+                                        "cookie": { "type": "string" },
+                                    },
+                                },
+                                {
+                                    "type": "object",
+                                    "description": "Token sources tried in order",
+                                    "required": ["sources"],
+                                    "properties": {
+                                        "sources": {
+                                            "type": "array",
+                                            "items": { "type": "object" },
+                                        },
+                                    },
+                                },
+                            ],
+                        },
+                        "required_claims": { "type": "integer", "minimum": 0 },
+                        "mapped_claims": { "type": "integer", "minimum": 0 },
+                        "deny_status": {
+                            "type": "integer",
+                            "description": "The status returned by validators that deny every request",
+                        },
+                    },
+                },
+                "BuildInfo": {
+                    "type": "object",
+                    "required": [
+                        "version",
+                        "git_sha",
+                        "build_timestamp",
+                        "features",
+                        "crypto_backend",
+                        "fips_validated",
+                    ],
+                    "properties": {
+                        "version": { "type": "string" },
+                        "git_sha": { "type": "string" },
+                        "build_timestamp": { "type": "string", "format": "date-time" },
+                        "features": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "The optional cargo features the binary was built with",
+                        },
+                        "crypto_backend": {
+                            "type": "string",
+                            "description": "The library verifying the signatures of tokens",
+                        },
+                        "fips_validated": {
+                            "type": "boolean",
+                            "description": "Whether the crypto backend is a FIPS 140 validated module",
+                        },
+                    },
+                },
+                "ValidatorStatus": {
+                    "type": "object",
+                    "required": ["name", "enabled"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "enabled": { "type": "boolean" },
+                        "labels": labels_schema(),
+                    },
+                },
+                "MaintenanceStatus": {
+                    "type": "object",
+                    "required": ["service", "validators", "retry_after"],
+                    "properties": {
+                        "service": { "type": "boolean" },
+                        "validators": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                        },
+                        "retry_after": { "type": "integer", "minimum": 0 },
+                    },
+                },
+                "JwksStatus": {
+                    "type": "object",
+                    "required": [
+                        "url",
+                        "filter",
+                        "keys",
+                        "etag",
+                        "last_refresh",
+                        "last_error",
+                        "last_error_at",
+                        "consecutive_failures",
+                        "circuit_open",
+                        "requests",
+                    ],
+                    "properties": {
+                        "url": { "type": "string" },
+                        "filter": {
+                            "type": "object",
+                            "description": "The key filter of the authorities using the JWKS, \
+                                authorities with different filters have separate key sets",
+                            "required": ["use"],
+                            "properties": {
+                                "use": { "type": "string" },
+                                "key_ops": {
+                                    "type": "array",
+                                    "nullable": true,
+                                    "items": { "type": "string" },
+                                },
+                                "max_document_bytes": { "type": "integer", "nullable": true },
+                                "max_keys": { "type": "integer", "nullable": true },
+                                "certificates": {
+                                    "type": "object",
+                                    "nullable": true,
+                                    "required": ["ca_file", "require"],
+                                    "properties": {
+                                        "ca_file": { "type": "string" },
+                                        "require": { "type": "boolean" },
+                                    },
+                                },
+                            },
+                        },
+                        "keys": {
+                            "type": "integer",
+                            "minimum": 0,
+                            "description": "The keys loaded after filtering",
+                        },
+                        "etag": { "type": "string", "nullable": true },
+                        "last_refresh": {
+                            "type": "string",
+                            "format": "date-time",
+                            "nullable": true,
+                        },
+                        "last_error": {
+                            "type": "string",
+                            "nullable": true,
+                            "description": "The error of the last refresh, cleared by a successful refresh",
+                        },
+                        "last_error_at": {
+                            "type": "string",
+                            "format": "date-time",
+                            "nullable": true,
+                        },
+                        "consecutive_failures": { "type": "integer", "minimum": 0 },
+                        "circuit_open": {
+                            "type": "boolean",
+                            "description": "Whether refreshes are paused after repeated failures",
+                        },
+                        "requests": {
+                            "type": "integer",
+                            "minimum": 0,
+                            "description": "The requests verified with the keys since they were first loaded",
+                        },
+                    },
+                },
+                "RefreshResult": {
+                    "allOf": [
+                        {
+                            "type": "object",
+                            "required": ["authority", "refreshed"],
+                            "properties": {
+                                "authority": { "type": "string" },
+                                "refreshed": { "type": "boolean" },
+                            },
+                        },
+                        { "$ref": "#/components/schemas/JwksStatus" },
+                    ],
+                },
+                "SlotStatus": {
+                    "type": "object",
+                    "required": ["staged", "rollback_available"],
+                    "properties": {
+                        "staged": {
+                            "type": "object",
+                            "nullable": true,
+                            "required": ["validators", "matches", "mismatches"],
+                            "properties": {
+                                "validators": {
+                                    "type": "array",
+                                    "items": { "type": "string" },
+                                },
+                                "matches": { "type": "integer", "minimum": 0 },
+                                "mismatches": { "type": "integer", "minimum": 0 },
+                            },
+                        },
+                        "rollback_available": { "type": "boolean" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn maintenance_responses() -> Value {
+    json!({
+        "200": {
+            "description": "The current maintenance mode",
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": "#/components/schemas/MaintenanceStatus" },
+                },
+            },
+        },
+        "401": text_response("The admin token is missing or invalid"),
+        "404": text_response("The validator doesn't exist"),
+    })
+}
+
+fn slot_responses() -> Value {
+    json!({
+        "200": {
+            "description": "The current state of the slots",
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": "#/components/schemas/SlotStatus" },
+                },
+            },
+        },
+        "400": text_response("The configuration file cannot be parsed"),
+        "401": text_response("The admin token is missing or invalid"),
+        "404": text_response("Nothing is staged or there is nothing to roll back to"),
+        "422": text_response("The configuration is invalid"),
+    })
+}
+
+/// The validator path parameter of admin endpoints, which also accept disabled validators
+fn admin_validator_parameter() -> Value {
+    json!({
+        "name": "validator",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    })
+}
+
+fn selector_parameter(required: bool) -> Value {
+    json!({
+        "name": "selector",
+        "in": "query",
+        "required": required,
+        "description": "Comma separated label requirements, e.g. team=payments,env=prod",
+        "schema": { "type": "string" },
+    })
+}
+
+fn labels_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": { "type": "string" },
+        "description": "Only present for validators with labels",
+    })
+}
+
+fn selected_validators_responses() -> Value {
+    json!({
+        "200": {
+            "description": "The new status of the selected validators",
+            "content": {
+                "application/json": {
+                    "schema": {
+                        "type": "array",
+                        "items": { "$ref": "#/components/schemas/ValidatorStatus" },
+                    },
+                },
+            },
+        },
+        "400": text_response("The selector is missing or invalid"),
+        "401": text_response("The admin token is missing or invalid"),
+        "404": text_response("No validator matches the selector"),
+    })
+}
+
+fn validator_status_responses() -> Value {
+    json!({
+        "200": {
+            "description": "The new status of the validator",
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": "#/components/schemas/ValidatorStatus" },
+                },
+            },
+        },
+        "401": text_response("The admin token is missing or invalid"),
+        "404": text_response("The validator doesn't exist"),
+    })
+}
+
+fn registry_response() -> Value {
+    json!({
+        "description": "The registered authorities and validators",
+        "content": {
+            "application/json": {
+                "schema": {
+                    "type": "object",
+                    "properties": {
+                        "authorities": { "type": "object", "additionalProperties": { "type": "object" } },
+                        "validators": { "type": "object", "additionalProperties": { "type": "object" } },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// The admin endpoints registering and removing an authority or validator
+fn refresh_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/RefreshResult" },
+            },
+        },
+    })
+}
+
+fn registry_path(kind: &str, schema_description: &str) -> Value {
+    let responses = json!({
+        "200": registry_response(),
+        "401": text_response("The admin token is missing or invalid"),
+        "404": text_response("The entry isn't registered"),
+        "409": text_response("The name is already used by the configuration file"),
+        "422": text_response("The resulting configuration is invalid"),
+        "500": text_response("The change could not be written to the state file"),
+        "503": text_response("No valid configuration file is loaded"),
+    });
+
+    json!({
+        "parameters": [{
+            "name": "name",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        }],
+        "put": {
+            "tags": ["admin"],
+            "operationId": format!("register{}", kind),
+            "summary": format!("Create or replace a dynamically registered {}", kind.to_lowercase()),
+            "security": [{ "adminToken": [] }],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": { "type": "object", "description": schema_description },
+                    },
+                },
+            },
+            "responses": responses,
+        },
+        "delete": {
+            "tags": ["admin"],
+            "operationId": format!("remove{}", kind),
+            "summary": format!("Remove a dynamically registered {}", kind.to_lowercase()),
+            "security": [{ "adminToken": [] }],
+            "responses": responses,
+        },
+    })
+}
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "text/plain": {
+                "schema": { "type": "string" },
+            },
+        },
+    })
+}
+
+async fn openapi(State(validators): State<ValidatorsState>) -> impl IntoResponse {
+    debug!("Serving OpenAPI document");
+
+    Json(document(&validators))
+}
+
+pub fn routes<S>(validators: ValidatorsState) -> axum::Router<S> {
+    axum::Router::new()
+        .route("/openapi.json", get(openapi))
+        .with_state(validators)
+}
@@ -1,8 +1,11 @@
+use crate::build_info;
+use crate::validators::connectivity::Connectivity;
 use crate::{State, States};
 use axum::extract;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::get;
+use axum::Json;
 use tracing::{debug, info};
 
 async fn healthz() -> impl IntoResponse {
@@ -11,13 +14,27 @@ async fn healthz() -> impl IntoResponse {
     "OK"
 }
 
-async fn readyz(extract::State(state): extract::State<State>) -> impl IntoResponse {
+async fn readyz(
+    extract::State((state, connectivity)): extract::State<(State, Connectivity)>,
+) -> impl IntoResponse {
     match state.get() {
         States::Starting => {
             info!("Ready check: Not ready (starting)");
             (StatusCode::SERVICE_UNAVAILABLE, "Starting")
         }
         States::Running => {
+            let unreachable = connectivity.unreachable();
+            if !unreachable.is_empty() {
+                info!(
+                    "Ready check: Not ready (unreachable identity providers: {})",
+                    unreachable.join(", ")
+                );
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Identity provider unreachable",
+                );
+            }
+
             debug!("Ready check: Ready");
             (StatusCode::OK, "OK")
         }
@@ -28,9 +45,16 @@ async fn readyz(extract::State(state): extract::State<State>) -> impl IntoRespon
     }
 }
 
-pub fn routes<S>(state: State) -> axum::Router<S> {
+async fn version() -> impl IntoResponse {
+    debug!("Version check");
+
+    Json(build_info::get())
+}
+
+pub fn routes<S>(state: State, connectivity: Connectivity) -> axum::Router<S> {
     axum::Router::new()
         .route("/healthz", get(healthz))
         .route("/readyz", get(readyz))
-        .with_state(state)
+        .route("/version", get(version))
+        .with_state((state, connectivity))
 }
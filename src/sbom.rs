@@ -0,0 +1,75 @@
+use serde_json::{json, Value};
+use std::sync::LazyLock;
+
+use crate::build_info;
+
+/// The locked dependencies, one `<name> <version> <source> <checksum>` line each, see `build.rs`
+const DEPENDENCIES: &str = include_str!(concat!(env!("OUT_DIR"), "/dependencies.txt"));
+
+const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+static SBOM: LazyLock<Value> = LazyLock::new(|| {
+    let build = build_info::get();
+    let components = DEPENDENCIES
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let (name, version) = (fields.next()?, fields.next()?);
+            let source = fields.next().filter(|source| *source != "-");
+            let checksum = fields.next().filter(|checksum| *checksum != "-");
+            // The package itself is described by the metadata
+            if source.is_none() && name == env!("CARGO_PKG_NAME") {
+                return None;
+            }
+
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": format!("{}@{}", name, version),
+                "name": name,
+                "version": version,
+            });
+            if source == Some(CRATES_IO) {
+                component["purl"] = json!(format!("pkg:cargo/{}@{}", name, version));
+            } else if let Some(source) = source {
+                component["externalReferences"] = json!([{ "type": "vcs", "url": source }]);
+            }
+            if let Some(checksum) = checksum {
+                component["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+            }
+            Some(component)
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": build.build_timestamp,
+            "component": {
+                "type": "application",
+                "bom-ref": format!("{}@{}", env!("CARGO_PKG_NAME"), build.version),
+                "name": env!("CARGO_PKG_NAME"),
+                "version": build.version,
+                "purl": format!("pkg:cargo/{}@{}", env!("CARGO_PKG_NAME"), build.version),
+            },
+            "properties": [
+                { "name": "git_sha", "value": build.git_sha },
+                { "name": "features", "value": build.features.join(",") },
+                { "name": "crypto_backend", "value": build.crypto_backend },
+                { "name": "rustc", "value": env!("BUILD_RUSTC") },
+                { "name": "target", "value": env!("BUILD_TARGET") },
+                { "name": "profile", "value": env!("BUILD_PROFILE") },
+            ],
+        },
+        "components": components,
+    })
+});
+
+/// The software bill of materials of the binary as a CycloneDX document, embedded at build time
+///
+/// Lists every package of the lock file, including the dependencies of optional features the
+/// binary wasn't built with (see the `features` property).
+pub fn get() -> &'static Value {
+    &SBOM
+}
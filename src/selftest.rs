@@ -0,0 +1,205 @@
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::validators::reason::REASON_HEADER;
+
+/// The time a single check may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A validator, as returned by the validator listing
+#[derive(Debug, Deserialize)]
+struct Listed {
+    name: String,
+    action: String,
+    token_source: Option<Value>,
+}
+
+/// The outcome of a single check, a row of the report
+struct Check {
+    name: String,
+    status: Option<StatusCode>,
+    detail: String,
+    passed: bool,
+}
+
+/// Run the `selftest` command against a running instance, returns whether every check passed
+///
+/// Checks the probes and the validator listing. With a token (e.g. issued by the identity provider
+/// of a development environment), the token is sent to every validator, placed where the
+/// validator reads it from, and has to be accepted.
+pub async fn run(url: &str, token: Option<&str>) -> bool {
+    let url = url.trim_end_matches('/');
+    let client = match Client::builder().timeout(TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to create the HTTP client: {}", e);
+            return false;
+        }
+    };
+
+    let mut checks = Vec::new();
+    for probe in ["healthz", "readyz"] {
+        checks.push(probe_check(&client, url, probe).await);
+    }
+
+    let validators = match list(&client, url).await {
+        Ok(validators) => {
+            checks.push(Check {
+                name: "validators".to_string(),
+                status: Some(StatusCode::OK),
+                detail: format!("{} available", validators.len()),
+                passed: true,
+            });
+            validators
+        }
+        Err(detail) => {
+            checks.push(Check {
+                name: "validators".to_string(),
+                status: None,
+                detail,
+                passed: false,
+            });
+            Vec::new()
+        }
+    };
+
+    if let Some(token) = token {
+        for validator in &validators {
+            checks.push(validate(&client, url, validator, token).await);
+        }
+    }
+
+    report(&checks);
+    checks.iter().all(|check| check.passed)
+}
+
+async fn probe_check(client: &Client, url: &str, probe: &str) -> Check {
+    let (status, detail) = match client.get(format!("{}/{}", url, probe)).send().await {
+        Ok(res) => (
+            Some(res.status()),
+            res.text().await.unwrap_or_default().trim().to_string(),
+        ),
+        Err(e) => (None, e.to_string()),
+    };
+
+    Check {
+        name: probe.to_string(),
+        passed: status.is_some_and(|s| s.is_success()),
+        status,
+        detail,
+    }
+}
+
+async fn list(client: &Client, url: &str) -> Result<Vec<Listed>, String> {
+    let res = client
+        .get(format!("{}/auth", url))
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+
+    res.json().await.map_err(|e| e.to_string())
+}
+
+async fn validate(client: &Client, url: &str, validator: &Listed, token: &str) -> Check {
+    let name = format!("validate {}", validator.name);
+    if validator.action != "validate" {
+        return Check {
+            name,
+            status: None,
+            detail: format!("skipped ({})", validator.action),
+            passed: true,
+        };
+    }
+
+    let request = client.get(format!("{}/auth/{}", url, validator.name));
+    let request = match validator.token_source.as_ref() {
+        Some(source) => place_token(request, source, token),
+        None => request.bearer_auth(token),
+    };
+
+    match request.send().await {
+        Ok(res) => {
+            let status = res.status();
+            let reason = res
+                .headers()
+                .get(REASON_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            // Responses without a reason code (e.g. internal errors) are described by their body
+            let detail = match reason {
+                Some(reason) => reason,
+                None => res.text().await.unwrap_or_default().trim().to_string(),
+            };
+            Check {
+                name,
+                passed: status.is_success(),
+                status: Some(status),
+                detail,
+            }
+        }
+        Err(e) => Check {
+            name,
+            status: None,
+            detail: e.to_string(),
+            passed: false,
+        },
+    }
+}
+
+/// Add the token to the request where the token source of the listing reads it from
+///
+/// Of ordered token sources, the first one is used.
+fn place_token(request: RequestBuilder, source: &Value, token: &str) -> RequestBuilder {
+    if let Some(first) = source
+        .get("sources")
+        .and_then(Value::as_array)
+        .and_then(|sources| sources.first())
+    {
+        return place_token(request, first, token);
+    }
+
+    let field = |name| source.get(name).and_then(Value::as_str);
+    match (field("header"), field("cookie"), field("query")) {
+        (Some(header), _, _) => {
+            let prefix = source
+                .get("prefixes")
+                .and_then(Value::as_array)
+                .and_then(|prefixes| prefixes.first())
+                .and_then(Value::as_str);
+            let value = match prefix {
+                Some(prefix) => format!("{} {}", prefix.trim(), token),
+                None => token.to_string(),
+            };
+            request.header(header, value)
+        }
+        (None, Some(cookie), _) => {
+            request.header(reqwest::header::COOKIE, format!("{}={}", cookie, token))
+        }
+        (None, None, Some(param)) => {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .append_pair(param, token)
+                .finish();
+            request.header("x-forwarded-uri", format!("/?{}", query))
+        }
+        (None, None, None) => request.bearer_auth(token),
+    }
+}
+
+fn report(checks: &[Check]) {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    println!("{:width$}  {:6}  {:6}  DETAIL", "CHECK", "STATUS", "RESULT");
+    for check in checks {
+        let status = check
+            .status
+            .map_or_else(|| "-".to_string(), |s| s.as_u16().to_string());
+        let result = if check.passed { "ok" } else { "FAILED" };
+        println!(
+            "{:width$}  {:6}  {:6}  {}",
+            check.name, status, result, check.detail
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::path::Path;
+use tracing::info;
+
+use crate::build_info::{self, BuildInfo};
+use crate::validators::ValidatorsState;
+use crate::{State, States};
+
+/// How the startup is reported
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum StartupReport {
+    /// Log a startup banner
+    Log,
+    /// Print a single JSON object to stdout once the service is listening, instead of the banner
+    Json,
+}
+
+/// What the service started with, for deployment tooling to verify a rollout
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    build: &'static BuildInfo,
+    listeners: Vec<SocketAddr>,
+    config: &'a Path,
+    /// Whether the configuration could be loaded
+    config_valid: bool,
+    authorities: Vec<String>,
+    validators: Vec<String>,
+}
+
+impl StartupReport {
+    /// Report that the service is starting up
+    pub fn starting(self) {
+        if self == StartupReport::Log {
+            let build = build_info::get();
+            info!(
+                "Starting up (version {}, commit {}, crypto backend {})",
+                build.version, build.git_sha, build.crypto_backend
+            );
+        }
+    }
+
+    /// Report what was loaded, once the service is listening
+    pub fn listening(
+        self,
+        listeners: Vec<SocketAddr>,
+        config: &Path,
+        state: &State,
+        validators: &ValidatorsState,
+    ) {
+        if self != StartupReport::Json {
+            return;
+        }
+
+        let mut authorities = validators.authorities();
+        authorities.sort_unstable();
+        let names = validators.list();
+
+        let report = Report {
+            build: build_info::get(),
+            listeners,
+            config,
+            config_valid: state.get() == States::Running,
+            authorities,
+            validators: names,
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&report).expect("startup report should be serializable")
+        );
+    }
+}
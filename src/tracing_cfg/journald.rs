@@ -0,0 +1,12 @@
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+pub fn layer<S>() -> Result<impl Layer<S>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_journald::layer()
+        .map(|layer| layer.with_syslog_identifier(env!("CARGO_PKG_NAME").to_string()))
+        .map_err(|e| format!("Failed to connect to journald: {}", e))
+}
@@ -0,0 +1,111 @@
+use tracing::{error, Level};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::utils::Shutdown;
+
+#[cfg(feature = "journald")]
+mod journald;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "syslog")]
+mod syslog;
+
+/// Where the log is written to
+#[derive(Debug, Copy, Clone, clap::ValueEnum)]
+pub enum LogOutput {
+    Stdout,
+    /// The local syslog daemon, using RFC 5424
+    Syslog,
+    Journald,
+}
+
+/// Flushes the exported logs when dropped, keep it alive until the application exits
+pub struct LogGuard {
+    _otlp: Option<otlp::OtlpLogs>,
+}
+
+pub fn register_subscriber(
+    use_ansi: bool,
+    env_filter: &str,
+    output: LogOutput,
+    otlp_endpoint: Option<&str>,
+) -> Result<LogGuard, Shutdown> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(Level::INFO.into())
+        .parse_lossy(env_filter);
+
+    // Errors can only be reported once the subscriber is registered
+    let mut errors = Vec::new();
+
+    let syslog = match output {
+        LogOutput::Syslog => syslog::layer().map_err(|e| errors.push(e)).ok(),
+        _ => None,
+    };
+    let journald = match output {
+        LogOutput::Journald => journald::layer().map_err(|e| errors.push(e)).ok(),
+        _ => None,
+    };
+    // Stdout is also the fallback, so the errors can be reported somewhere
+    let stdout = (syslog.is_none() && journald.is_none())
+        .then(|| tracing_subscriber::fmt::layer().with_ansi(use_ansi));
+
+    let otlp = otlp_endpoint.and_then(|endpoint| {
+        otlp::OtlpLogs::new(endpoint)
+            .map_err(|e| errors.push(e))
+            .ok()
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout)
+        .with(syslog)
+        .with(journald)
+        .with(otlp.as_ref().map(|otlp| otlp.layer()))
+        .init();
+
+    if !errors.is_empty() {
+        for e in errors {
+            error!("{}", e);
+        }
+        return Err(Shutdown);
+    }
+
+    Ok(LogGuard { _otlp: otlp })
+}
+
+#[cfg(not(feature = "journald"))]
+mod journald {
+    use tracing_subscriber::layer::Identity;
+
+    pub fn layer() -> Result<Identity, String> {
+        Err("The journald output requires the journald feature".to_string())
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+mod otlp {
+    use tracing_subscriber::layer::Identity;
+
+    pub enum OtlpLogs {}
+
+    impl OtlpLogs {
+        pub fn new(_: &str) -> Result<Self, String> {
+            Err("OTLP log export requires the otlp feature".to_string())
+        }
+
+        pub fn layer(&self) -> Identity {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(not(feature = "syslog"))]
+mod syslog {
+    use tracing_subscriber::layer::Identity;
+
+    pub fn layer() -> Result<Identity, String> {
+        Err("The syslog output requires the syslog feature".to_string())
+    }
+}
@@ -0,0 +1,59 @@
+use opentelemetry::KeyValue;
+use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
+use opentelemetry_otlp::{LogExporter, WithExportConfig};
+use opentelemetry_sdk::logs::SdkLoggerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Crates used by the exporter itself, their logs would be exported in an endless loop
+const EXPORTER_TARGETS: &[&str] = &["opentelemetry", "hyper", "reqwest", "h2"];
+
+/// Exports logs to an OTLP/HTTP endpoint in batches
+pub struct OtlpLogs {
+    provider: SdkLoggerProvider,
+}
+
+impl OtlpLogs {
+    /// Export to the logs endpoint (e.g. `http://collector:4318/v1/logs`), additional resource
+    /// attributes are read from `OTEL_RESOURCE_ATTRIBUTES`
+    pub fn new(endpoint: &str) -> Result<Self, String> {
+        let exporter = LogExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| format!("Failed to set up the OTLP log export: {}", e))?;
+
+        let resource = Resource::builder()
+            .with_service_name(env!("CARGO_PKG_NAME"))
+            .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+            .build();
+
+        let provider = SdkLoggerProvider::builder()
+            .with_resource(resource)
+            .with_batch_exporter(exporter)
+            .build();
+
+        Ok(Self { provider })
+    }
+
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        OpenTelemetryTracingBridge::new(&self.provider).with_filter(filter_fn(|meta| {
+            !EXPORTER_TARGETS
+                .iter()
+                .any(|target| meta.target().starts_with(target))
+        }))
+    }
+}
+
+impl Drop for OtlpLogs {
+    fn drop(&mut self) {
+        // Flush the logs that are still buffered
+        let _ = self.provider.shutdown();
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use syslog::{Facility, Formatter5424, Logger, LoggerBackend};
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+type Syslog = Logger<LoggerBackend, Formatter5424>;
+
+/// Sends every formatted event as one RFC 5424 message to the local syslog daemon
+#[derive(Clone)]
+struct SyslogWriter {
+    logger: Arc<Mutex<Syslog>>,
+}
+
+/// A single message, sent once the event is formatted
+struct Message<'a> {
+    logger: &'a Mutex<Syslog>,
+    level: Level,
+    buf: Vec<u8>,
+}
+
+pub fn layer<S>() -> Result<impl Layer<S>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let formatter = Formatter5424 {
+        facility: Facility::LOG_DAEMON,
+        hostname: None,
+        process: env!("CARGO_PKG_NAME").to_string(),
+        pid: std::process::id(),
+    };
+    let logger =
+        syslog::unix(formatter).map_err(|e| format!("Failed to connect to syslog: {}", e))?;
+
+    // Syslog records the time and severity itself
+    Ok(tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .without_time()
+        .with_level(false)
+        .with_writer(SyslogWriter {
+            logger: Arc::new(Mutex::new(logger)),
+        }))
+}
+
+impl<'a> MakeWriter<'a> for SyslogWriter {
+    type Writer = Message<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.message(Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.message(*meta.level())
+    }
+}
+
+impl SyslogWriter {
+    fn message(&self, level: Level) -> Message<'_> {
+        Message {
+            logger: &self.logger,
+            level,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl io::Write for Message<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message<'_> {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.buf);
+        let message = message.trim_end();
+        if message.is_empty() {
+            return;
+        }
+
+        let mut logger = self.logger.lock().unwrap_or_else(|e| e.into_inner());
+        let message = (0, HashMap::new(), message);
+        // There is nowhere left to report a failure to
+        let _ = match self.level {
+            Level::ERROR => logger.err(message),
+            Level::WARN => logger.warning(message),
+            Level::INFO => logger.info(message),
+            Level::DEBUG | Level::TRACE => logger.debug(message),
+        };
+    }
+}
@@ -0,0 +1,292 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::process::{Child, Command};
+use tokio::sync::{oneshot, watch};
+use tracing::{info, warn};
+
+/// The environment variable passing the listening socket on to the new process
+const LISTEN_FD_ENV: &str = "JWT_FWA_LISTEN_FD";
+/// How long the new process may take until it accepts connections, e.g. to load the
+/// configuration and fetch the JWKS
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The control socket upgrades of the binary are coordinated through
+///
+/// On `upgrade` the binary is started again with the same arguments, inheriting the listening
+/// socket. Once the new process is listening it announces itself with `ready <pid>`, this process
+/// stops accepting connections and shuts down after answering the open requests. Connections
+/// waiting to be accepted are accepted by the new process, so none are dropped.
+#[derive(Debug)]
+pub struct Control {
+    handed_over: Option<watch::Receiver<bool>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    /// The listening socket passed on to the new process
+    listener: RawFd,
+    /// The upgrade waiting for the new process to announce itself
+    pending: Mutex<Option<Pending>>,
+    handed_over: watch::Sender<bool>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    pid: u32,
+    ready: oneshot::Sender<OwnedWriteHalf>,
+}
+
+/// Take over the listening socket of the process being upgraded, if this is the new process
+pub fn inherited_listener() -> io::Result<Option<TcpListener>> {
+    let Some(fd) = env::var(LISTEN_FD_ENV)
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+    else {
+        return Ok(None);
+    };
+
+    // SAFETY: the previous process passed the listening socket as this descriptor, nothing else
+    // in this process owns it
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    set_inherited(fd, false)?;
+    listener.set_nonblocking(true)?;
+    info!("Took over the listening socket of the previous process");
+    Ok(Some(listener))
+}
+
+/// Ask the instance behind the control socket to upgrade, its answer once the new process took
+/// over or the upgrade failed
+pub async fn request(path: &Path) -> io::Result<String> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(b"upgrade\n").await?;
+
+    let mut answer = String::new();
+    BufReader::new(reader).read_line(&mut answer).await?;
+    Ok(answer.trim_end().to_string())
+}
+
+impl Control {
+    /// Open the control socket, after handing over from the previous process if this is the new
+    /// process of an upgrade
+    ///
+    /// Without a path upgrades are disabled.
+    pub async fn start(
+        path: Option<&Path>,
+        listener: &tokio::net::TcpListener,
+        inherited: bool,
+    ) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self { handed_over: None });
+        };
+
+        if inherited {
+            announce(path).await?;
+        }
+        let socket = bind(path).await?;
+
+        let (handed_over, receiver) = watch::channel(false);
+        let inner = Arc::new(Inner {
+            path: path.to_path_buf(),
+            listener: listener.as_raw_fd(),
+            pending: Mutex::new(None),
+            handed_over,
+        });
+        tokio::spawn(accept(inner, socket));
+
+        Ok(Self {
+            handed_over: Some(receiver),
+        })
+    }
+
+    /// Resolves once a new process took over the listening socket, to stop accepting connections
+    pub async fn handed_over(self) {
+        if let Some(mut handed_over) = self.handed_over {
+            if handed_over
+                .wait_for(|handed_over| *handed_over)
+                .await
+                .is_ok()
+            {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// Tell the previous process this one is listening and wait until it stopped accepting
+/// connections and released the control socket
+async fn announce(path: &Path) -> io::Result<()> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("ready {}\n", std::process::id()).as_bytes())
+        .await?;
+
+    let mut answer = String::new();
+    BufReader::new(reader).read_line(&mut answer).await?;
+    match answer.trim_end() {
+        "ok" => {
+            info!("The previous process handed over, it shuts down once its requests are answered");
+            Ok(())
+        }
+        answer => Err(io::Error::other(format!(
+            "the previous process refused the hand-over: {}",
+            answer
+        ))),
+    }
+}
+
+/// Bind the control socket, replacing the socket file of an instance that didn't shut down
+/// cleanly
+async fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another instance listens on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+async fn accept(inner: Arc<Inner>, socket: UnixListener) {
+    let mut handed_over = inner.handed_over.subscribe();
+    loop {
+        tokio::select! {
+            accepted = socket.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle(inner.clone(), stream));
+                }
+                Err(e) => warn!("Failed to accept a control connection: {}", e),
+            },
+            _ = handed_over.wait_for(|handed_over| *handed_over) => break,
+        }
+    }
+}
+
+async fn handle(inner: Arc<Inner>, stream: UnixStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut command = String::new();
+    if let Err(e) = BufReader::new(reader).read_line(&mut command).await {
+        warn!("Failed to read a control command: {}", e);
+        return;
+    }
+
+    let answer = match command.split_whitespace().collect::<Vec<_>>()[..] {
+        ["upgrade"] => match upgrade(&inner).await {
+            Ok(pid) => format!("ok {}", pid),
+            Err(e) => format!("error: {}", e),
+        },
+        ["ready", pid] => {
+            let pending = inner.pending.lock().expect("lock poisoned").take();
+            match pending {
+                Some(pending) if pid.parse() == Ok(pending.pid) => {
+                    // The upgrade answers the new process, once this one stopped accepting
+                    let _ = pending.ready.send(writer);
+                    return;
+                }
+                pending => {
+                    *inner.pending.lock().expect("lock poisoned") = pending;
+                    "error: no upgrade to this process is in progress".to_string()
+                }
+            }
+        }
+        _ => "error: unknown command".to_string(),
+    };
+    let _ = writer.write_all(format!("{}\n", answer).as_bytes()).await;
+}
+
+/// Start the new process and hand the listening socket over once it is ready, its PID
+async fn upgrade(inner: &Inner) -> io::Result<u32> {
+    let (ready, announced) = oneshot::channel();
+    let mut child = {
+        let mut pending = inner.pending.lock().expect("lock poisoned");
+        if pending.is_some() {
+            return Err(io::Error::other("an upgrade is already in progress"));
+        }
+
+        let child = spawn(inner.listener)?;
+        let pid = child
+            .id()
+            .ok_or_else(|| io::Error::other("the new process exited right away"))?;
+        *pending = Some(Pending { pid, ready });
+        child
+    };
+    let pid = child.id().unwrap_or_default();
+    info!("Started process {} to take over the listening socket", pid);
+
+    let announced = tokio::select! {
+        announced = tokio::time::timeout(READY_TIMEOUT, announced) => announced,
+        status = child.wait() => {
+            inner.pending.lock().expect("lock poisoned").take();
+            return Err(io::Error::other(match status {
+                Ok(status) => format!("the new process exited with {}", status),
+                Err(e) => format!("the new process couldn't be awaited: {}", e),
+            }));
+        }
+    };
+    let mut new_process = match announced {
+        Ok(Ok(new_process)) => new_process,
+        _ => {
+            inner.pending.lock().expect("lock poisoned").take();
+            let _ = child.kill().await;
+            return Err(io::Error::other(format!(
+                "the new process wasn't ready within {} seconds",
+                READY_TIMEOUT.as_secs()
+            )));
+        }
+    };
+
+    // Released before answering, so the new process can bind the control socket
+    if let Err(e) = std::fs::remove_file(&inner.path) {
+        warn!("Failed to remove the control socket: {}", e);
+    }
+    inner.handed_over.send_replace(true);
+    new_process.write_all(b"ok\n").await?;
+    info!(
+        "Handed the listening socket over to process {}, shutting down once the open requests are answered",
+        pid
+    );
+
+    Ok(pid)
+}
+
+/// Start the binary again with the same arguments, inheriting the listening socket
+///
+/// The binary is started by the path it was started with, so a replaced binary is picked up.
+fn spawn(listener: RawFd) -> io::Result<Child> {
+    let mut args = env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::other("the path of the binary is unknown"))?;
+
+    let mut command = Command::new(program);
+    command.args(args).env(LISTEN_FD_ENV, listener.to_string());
+    // SAFETY: only calls fcntl, which is async-signal-safe
+    unsafe {
+        command.pre_exec(move || set_inherited(listener, true));
+    }
+    command.spawn()
+}
+
+/// Whether the descriptor is inherited by started processes (sockets are opened close-on-exec)
+fn set_inherited(fd: RawFd, inherited: bool) -> io::Result<()> {
+    let flags = if inherited { 0 } else { libc::FD_CLOEXEC };
+    // SAFETY: fcntl doesn't access memory, an invalid descriptor is reported as an error
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
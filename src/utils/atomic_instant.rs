@@ -1,6 +1,11 @@
 use std::sync::atomic::AtomicU64;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::utils::clock;
+
+/// A point in time that can be updated atomically, in microseconds since the epoch
+///
+/// A system clock before the epoch is treated as the epoch, so reading the time never panics.
 #[derive(Debug)]
 pub struct AtomicInstant {
     inner: AtomicU64,
@@ -17,21 +22,9 @@ impl AtomicInstant {
         Self::new(0)
     }
 
-    pub fn now() -> Self {
-        Self::new(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_micros() as u64,
-        )
-    }
-
     pub fn to_now(&self) {
         self.inner.store(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_micros() as u64,
+            clock::unix_now().as_micros() as u64,
             std::sync::atomic::Ordering::Relaxed,
         );
     }
@@ -40,13 +33,13 @@ impl AtomicInstant {
         let micros = self.inner.load(std::sync::atomic::Ordering::Relaxed);
         SystemTime::UNIX_EPOCH
             .checked_add(Duration::from_micros(micros))
-            .expect("Time went backwards")
+            .unwrap_or(SystemTime::UNIX_EPOCH)
     }
 
     pub fn duration_until(&self, later: SystemTime) -> Result<Duration, Duration> {
         let later = later
             .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
+            .unwrap_or_default()
             .as_micros() as u64;
 
         let now = self.inner.load(std::sync::atomic::Ordering::Relaxed);
@@ -57,42 +50,8 @@ impl AtomicInstant {
         }
     }
 
-    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, Duration> {
-        let earlier = earlier
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_micros() as u64;
-
-        let now = self.inner.load(std::sync::atomic::Ordering::Relaxed);
-        if now < earlier {
-            Err(Duration::from_micros(earlier - now))
-        } else {
-            Ok(Duration::from_micros(now - earlier))
-        }
-    }
-
     pub fn elapsed(&self) -> Result<Duration, Duration> {
-        self.duration_until(SystemTime::now())
-    }
-
-    /// Adds the duration to the current instant.
-    ///
-    /// Note: This is operation can overflow.
-    pub fn add(&self, duration: Duration) {
-        self.inner.fetch_add(
-            duration.as_micros() as u64,
-            std::sync::atomic::Ordering::Relaxed,
-        );
-    }
-
-    /// Subtracts the duration from the current instant.
-    ///
-    /// Note: This is operation can underflow.
-    pub fn sub(&self, duration: Duration) {
-        self.inner.fetch_sub(
-            duration.as_micros() as u64,
-            std::sync::atomic::Ordering::Relaxed,
-        );
+        self.duration_until(clock::now())
     }
 }
 
@@ -0,0 +1,147 @@
+#[cfg(test)]
+use std::cell::RefCell;
+use std::fmt;
+#[cfg(not(test))]
+use std::sync::OnceLock;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
+
+/// The clock of the service, installed once on startup
+#[cfg(not(test))]
+static CLOCK: OnceLock<Box<dyn Clock>> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// The clock installed by the running test, so tests running in parallel don't share it
+    static CLOCK: RefCell<Option<Box<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// A source of the current time
+///
+/// Everything comparing against the wall clock (token lifetimes, JWKS refresh scheduling, cache
+/// expiry) reads the time through the installed clock, so it can be corrected (and frozen in
+/// tests).
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system clock, corrected by a known drift
+#[derive(Debug, Default)]
+pub struct SystemClock {
+    /// Seconds added to the system time, negative if the system clock is ahead
+    skew: i64,
+}
+
+/// A clock standing still until it is advanced, for deterministic tests
+///
+/// Clones share the time, so a test can install one and advance the time through another.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct FixedClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl SystemClock {
+    pub fn with_skew(skew: i64) -> Self {
+        Self { skew }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        let now = SystemTime::now();
+        let skew = Duration::from_secs(self.skew.unsigned_abs());
+        let corrected = match self.skew >= 0 {
+            true => now.checked_add(skew),
+            false => now.checked_sub(skew),
+        };
+        corrected.unwrap_or(now)
+    }
+}
+
+#[cfg(test)]
+impl FixedClock {
+    /// A clock standing at the given seconds since the epoch
+    pub fn at(secs: u64) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(secs))),
+        }
+    }
+
+    /// Move the time forward
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("lock poisoned") += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().expect("lock poisoned")
+    }
+}
+
+/// Install the clock of the service, ignored if the time was already read
+///
+/// In tests the clock is installed for the current thread and replaces the previous one.
+pub fn install(clock: impl Clock + 'static) {
+    #[cfg(not(test))]
+    let _ = CLOCK.set(Box::new(clock));
+    #[cfg(test)]
+    CLOCK.set(Some(Box::new(clock)));
+}
+
+/// The current time according to the installed clock, the system clock if none is installed
+#[cfg(not(test))]
+pub fn now() -> SystemTime {
+    CLOCK.get_or_init(|| Box::new(SystemClock::default())).now()
+}
+
+/// The current time according to the installed clock, the system clock if none is installed
+#[cfg(test)]
+pub fn now() -> SystemTime {
+    CLOCK.with_borrow(|clock| match clock {
+        Some(clock) => clock.now(),
+        None => SystemClock::default().now(),
+    })
+}
+
+/// The time passed since `earlier` according to the installed clock, like [`SystemTime::elapsed`]
+pub fn elapsed(earlier: SystemTime) -> Result<Duration, SystemTimeError> {
+    now().duration_since(earlier)
+}
+
+/// The current time since the epoch
+///
+/// A clock before the epoch is treated as the epoch instead of panicking.
+pub fn unix_now() -> Duration {
+    now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_installed_clock() {
+        let time = FixedClock::at(1_700_000_000);
+        install(time.clone());
+        assert_eq!(unix_now(), Duration::from_secs(1_700_000_000));
+
+        time.advance(Duration::from_secs(5));
+        assert_eq!(
+            elapsed(UNIX_EPOCH + Duration::from_secs(1_700_000_000)).unwrap(),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn corrects_the_system_clock_by_its_skew() {
+        let ahead = SystemClock::with_skew(3600).now();
+        let behind = SystemClock::with_skew(-3600).now();
+
+        let skew = ahead.duration_since(behind).unwrap();
+        assert!(skew.abs_diff(Duration::from_secs(7200)) < Duration::from_secs(1));
+    }
+}
@@ -5,18 +5,25 @@ fn is_valid(b: u8) -> bool {
     b >= 32 && b != 127 || b == b'\t'
 }
 
-pub fn header_val_lossy(val: impl AsRef<[u8]>) -> HeaderValue {
-    let mut bytes = Vec::with_capacity(val.as_ref().len());
-    for &b in val.as_ref() {
-        if is_valid(b) {
-            bytes.push(b);
-        } else {
-            bytes.push(b'?');
+#[inline]
+fn push_lossy(bytes: &mut Vec<u8>, val: &[u8]) {
+    bytes.extend(val.iter().map(|&b| if is_valid(b) { b } else { b'?' }));
+}
+
+/// Join the values with the separator into a single header value, without an intermediate string
+pub fn header_val_lossy_joined<T: AsRef<str>>(vals: &[T], separator: u8) -> HeaderValue {
+    let len = vals.iter().map(|v| v.as_ref().len() + 1).sum::<usize>();
+    let mut bytes = Vec::with_capacity(len.saturating_sub(1));
+    for (i, val) in vals.iter().enumerate() {
+        if i > 0 {
+            bytes.push(separator);
         }
+        push_lossy(&mut bytes, val.as_ref().as_bytes());
     }
 
+    debug_assert!(is_valid(separator));
     unsafe {
-        // SAFETY: we removed all invalid bytes
+        // SAFETY: we removed all invalid bytes and the separator is valid
         HeaderValue::from_maybe_shared_unchecked(bytes)
     }
 }
@@ -1,5 +1,8 @@
 pub mod atomic_instant;
+pub mod clock;
 pub mod header_val;
+pub mod panic;
+pub mod rate_limiter;
 pub mod reloadable;
 mod shutdown;
 
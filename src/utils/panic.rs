@@ -0,0 +1,29 @@
+use axum::response::{IntoResponse, Response};
+use http::StatusCode;
+use std::any::Any;
+use tracing::error;
+
+use crate::metrics;
+
+/// Answer a request whose handler panicked with 500, reporting the panic
+///
+/// Only the request that panicked fails, the connection and the other requests are unaffected.
+pub fn panic_response(err: Box<dyn Any + Send + 'static>) -> Response {
+    report(err);
+
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+}
+
+/// Log and count a panic of a request handler
+pub fn report(err: Box<dyn Any + Send + 'static>) {
+    let message = match err.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match err.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic payload".to_string(),
+        },
+    };
+
+    error!(panic = message, "Request handler panicked");
+    metrics::PANICS.inc();
+}
@@ -1,17 +1,24 @@
 use arc_swap::{ArcSwap, Guard};
 use notify::event::ModifyKind;
 use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
 use std::fmt;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::Notify;
 
 #[derive(Clone, Debug)]
 pub struct Reloadable<T> {
     inner: Arc<ReloadableInner<T>>,
-    #[allow(unused)] // The watcher needs to be kept alive but isn't used otherwise
-    watcher: Arc<notify::RecommendedWatcher>,
+    watcher: Arc<Mutex<Watched>>,
+}
+
+#[derive(Debug)]
+struct Watched {
+    watcher: notify::RecommendedWatcher,
+    /// The paths watched in addition to the main path
+    paths: HashSet<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -68,10 +75,46 @@ where
 
         Ok(Self {
             inner,
-            watcher: Arc::new(watcher),
+            watcher: Arc::new(Mutex::new(Watched {
+                watcher,
+                paths: HashSet::new(),
+            })),
         })
     }
 
+    /// Watch these paths in addition to the main path, replacing the previously added paths
+    ///
+    /// Paths that can't be watched (e.g. because they don't exist) are skipped and returned
+    /// together with the error.
+    pub fn watch_also(&self, paths: &[PathBuf]) -> Vec<(PathBuf, notify::Error)> {
+        let mut watched = self.watcher.lock().expect("watcher lock poisoned");
+        let paths = paths.iter().cloned().collect::<HashSet<_>>();
+
+        let removed = watched
+            .paths
+            .difference(&paths)
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in removed {
+            let _ = watched.watcher.unwatch(&path);
+            watched.paths.remove(&path);
+        }
+
+        let mut errors = Vec::new();
+        for path in paths {
+            if watched.paths.contains(&path) {
+                continue;
+            }
+            match watched.watcher.watch(&path, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    watched.paths.insert(path);
+                }
+                Err(e) => errors.push((path, e)),
+            }
+        }
+        errors
+    }
+
     pub fn get(&self) -> ReloadableGuard<T> {
         ReloadableGuard {
             inner: self.inner.data.load(),
@@ -1,11 +1,31 @@
 use std::fmt;
+use std::path::PathBuf;
+
+use super::Lint;
+use crate::enrichment::EnrichmentError;
+use crate::validators::certificates::CertificateError;
+use crate::validators::static_keys::StaticKeyError;
 
 #[derive(Debug)]
 pub enum ValidationFileError {
     IoError(std::io::Error),
     SerdeError(serde_yaml::Error),
     IsMissingAuthority(String),
+    AuthorityAndIssuers(String),
     IsMissingHeader(String),
+    InvalidTokenHeader {
+        validator: String,
+        header: String,
+    },
+    InvalidTokenCookie {
+        validator: String,
+        cookie: String,
+    },
+    InvalidTokenSource {
+        validator: String,
+        index: usize,
+        reason: &'static str,
+    },
     MissingAuthority {
         validator: String,
         authority: String,
@@ -14,12 +34,186 @@ pub enum ValidationFileError {
         validator: String,
         template: String,
     },
+    MissingEnrichment {
+        validator: String,
+        enrichment: String,
+    },
+    InvalidEnrichment {
+        enrichment: String,
+        error: EnrichmentError,
+    },
     CircularTemplate(String),
+    UnknownMatcher {
+        validator: String,
+        claim: String,
+        matcher: String,
+    },
+    InvalidRequirementGroup {
+        validator: String,
+        reason: &'static str,
+    },
+    InvalidMatcher {
+        validator: String,
+        claim: String,
+        matcher: String,
+        error: serde_yaml::Error,
+    },
     InvalidHeaderName {
         validator: String,
         claim: String,
         header: String,
     },
+    InvalidCookie {
+        validator: String,
+        claim: String,
+        reason: &'static str,
+    },
+    InvalidLoginRedirect {
+        validator: String,
+        reason: &'static str,
+    },
+    InvalidRealm {
+        validator: String,
+        realm: String,
+    },
+    InvalidSuccessResponse {
+        validator: String,
+        reason: String,
+    },
+    InvalidBodyTemplate {
+        validator: String,
+        reason: String,
+    },
+    InvalidEchoHeader {
+        validator: String,
+        header: String,
+    },
+    ReservedHeaderName {
+        validator: String,
+        claim: String,
+        header: String,
+    },
+    InvalidHeaderProjection {
+        validator: String,
+        reason: &'static str,
+    },
+    InvalidPolicy {
+        validator: String,
+        reason: String,
+    },
+    InvalidOpaUrl {
+        validator: String,
+        url: String,
+    },
+    UnsupportedByProfile {
+        validator: String,
+        profile: &'static str,
+        option: &'static str,
+    },
+    InvalidStatus {
+        validator: String,
+        option: &'static str,
+        status: u16,
+    },
+    SubjectFile {
+        validator: String,
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    MessageCatalog {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    UnknownLanguage {
+        validator: String,
+        language: String,
+    },
+    InvalidHttpClient {
+        authority: Option<String>,
+        error: HttpClientError,
+    },
+    InvalidCertificates {
+        authority: String,
+        error: CertificateError,
+    },
+    /// The authority has neither or both a JWKS URL and static keys
+    KeySource(String),
+    InvalidStaticKeys {
+        authority: String,
+        error: StaticKeyError,
+    },
+    MissingTenant {
+        authority: String,
+        tenant: String,
+    },
+    TenantQuotaExceeded {
+        tenant: String,
+        authorities: usize,
+        max_authorities: usize,
+    },
+    InvalidAlertThreshold {
+        reason: String,
+        threshold: f64,
+    },
+    /// A deprecated key is used that isn't accepted by this release anymore
+    RemovedKey {
+        path: String,
+        key: &'static str,
+        replacement: &'static str,
+    },
+    /// A `${name}` reference to a variable that isn't defined
+    UnknownVariable {
+        path: String,
+        name: String,
+    },
+    InvalidVariable {
+        name: String,
+        reason: &'static str,
+    },
+    CircularVariable(String),
+    InvalidInclude {
+        path: PathBuf,
+        reason: String,
+    },
+    /// An included file can't be read or parsed
+    IncludedFile {
+        path: PathBuf,
+        error: Box<ValidationFileError>,
+    },
+    CircularInclude(PathBuf),
+    /// An entry is defined by more than one of the included files
+    DuplicateKey {
+        key: String,
+        path: PathBuf,
+    },
+    /// Only the configuration file itself can include files, not staged configurations
+    IncludeNotAllowed,
+    InvalidIdentityHashing(&'static str),
+    /// The file is written for a schema version this release doesn't know
+    UnsupportedVersion {
+        version: String,
+        supported: u64,
+    },
+    /// The configuration has lint findings and strict mode is enabled
+    Lint(Vec<Lint>),
+}
+
+#[derive(Debug)]
+pub enum HttpClientError {
+    ReadCaFile(std::io::Error),
+    Reqwest(reqwest::Error),
+}
+
+impl From<std::io::Error> for HttpClientError {
+    fn from(e: std::io::Error) -> Self {
+        HttpClientError::ReadCaFile(e)
+    }
+}
+
+impl From<reqwest::Error> for HttpClientError {
+    fn from(e: reqwest::Error) -> Self {
+        HttpClientError::Reqwest(e)
+    }
 }
 
 impl From<std::io::Error> for ValidationFileError {
@@ -44,9 +238,37 @@ impl fmt::Display for ValidationFileError {
             ValidationFileError::IsMissingAuthority(name) => {
                 write!(f, "Validator {} is missing an authority", name)
             }
+            ValidationFileError::AuthorityAndIssuers(name) => write!(
+                f,
+                "Validator {} sets both an authority and issuers, only one is allowed",
+                name
+            ),
             ValidationFileError::IsMissingHeader(name) => {
-                write!(f, "Validator {} is missing the jwt header", name)
+                write!(
+                    f,
+                    "Validator {} is missing the jwt header (or query parameter or cookie)",
+                    name
+                )
             }
+            ValidationFileError::InvalidTokenHeader { validator, header } => write!(
+                f,
+                "Validator {} reads the token from invalid header name {}",
+                validator, header
+            ),
+            ValidationFileError::InvalidTokenCookie { validator, cookie } => write!(
+                f,
+                "Validator {} reads the token from invalid cookie name {}",
+                validator, cookie
+            ),
+            ValidationFileError::InvalidTokenSource {
+                validator,
+                index,
+                reason,
+            } => write!(
+                f,
+                "Validator {} has an invalid token source at index {}: {}",
+                validator, index, reason
+            ),
             ValidationFileError::MissingAuthority {
                 validator,
                 authority,
@@ -63,9 +285,44 @@ impl fmt::Display for ValidationFileError {
                 "Validator {} references missing template {}",
                 validator, template
             ),
+            ValidationFileError::MissingEnrichment {
+                validator,
+                enrichment,
+            } => write!(
+                f,
+                "Validator {} references missing enrichment {}",
+                validator, enrichment
+            ),
+            ValidationFileError::InvalidEnrichment { enrichment, error } => {
+                write!(f, "Enrichment {} is invalid: {}", enrichment, error)
+            }
             ValidationFileError::CircularTemplate(name) => {
                 write!(f, "Circular template reference in template {}", name)
             }
+            ValidationFileError::InvalidRequirementGroup { validator, reason } => write!(
+                f,
+                "Validator {} has an invalid any_of or all_of group: {}",
+                validator, reason
+            ),
+            ValidationFileError::UnknownMatcher {
+                validator,
+                claim,
+                matcher,
+            } => write!(
+                f,
+                "Validator {} uses unknown matcher {} for claim {}",
+                validator, matcher, claim
+            ),
+            ValidationFileError::InvalidMatcher {
+                validator,
+                claim,
+                matcher,
+                error,
+            } => write!(
+                f,
+                "Validator {} has an invalid matcher {} for claim {}: {}",
+                validator, matcher, claim, error
+            ),
             ValidationFileError::InvalidHeaderName {
                 validator,
                 claim,
@@ -75,6 +332,205 @@ impl fmt::Display for ValidationFileError {
                 "Validator {} references invalid header name {} for claim {}",
                 validator, header, claim
             ),
+            ValidationFileError::InvalidCookie {
+                validator,
+                claim,
+                reason,
+            } => write!(
+                f,
+                "Validator {} has an invalid cookie for claim {}: {}",
+                validator, claim, reason
+            ),
+            ValidationFileError::InvalidLoginRedirect { validator, reason } => write!(
+                f,
+                "Validator {} has an invalid login redirect: {}",
+                validator, reason
+            ),
+            ValidationFileError::InvalidRealm { validator, realm } => write!(
+                f,
+                "Validator {} has an invalid realm {:?}, quotes, backslashes and non-ASCII characters aren't allowed",
+                validator, realm
+            ),
+            ValidationFileError::InvalidSuccessResponse { validator, reason } => write!(
+                f,
+                "Validator {} has an invalid success response: {}",
+                validator, reason
+            ),
+            ValidationFileError::InvalidBodyTemplate { validator, reason } => write!(
+                f,
+                "Validator {} has an invalid body template: {}",
+                validator, reason
+            ),
+            ValidationFileError::InvalidEchoHeader { validator, header } => write!(
+                f,
+                "Validator {} echoes invalid header name {}",
+                validator, header
+            ),
+            ValidationFileError::ReservedHeaderName {
+                validator,
+                claim,
+                header,
+            } => write!(
+                f,
+                "Validator {} maps claim {} to header {}, which is reserved for the proxy or removed by intermediaries",
+                validator, claim, header
+            ),
+            ValidationFileError::InvalidHeaderProjection { validator, reason } => write!(
+                f,
+                "Validator {} has an invalid header projection: {}",
+                validator, reason
+            ),
+            ValidationFileError::InvalidPolicy { validator, reason } => {
+                write!(f, "Validator {} has an invalid policy: {}", validator, reason)
+            }
+            ValidationFileError::InvalidOpaUrl { validator, url } => write!(
+                f,
+                "Validator {} has an invalid Open Policy Agent URL {}, it has to be an http or https URL",
+                validator, url
+            ),
+            ValidationFileError::UnsupportedByProfile {
+                validator,
+                profile,
+                option,
+            } => write!(
+                f,
+                "Validator {} uses the {} profile, whose proxy doesn't support {}",
+                validator, profile, option
+            ),
+            ValidationFileError::InvalidStatus {
+                validator,
+                option,
+                status,
+            } => write!(
+                f,
+                "Validator {} has an invalid {} {}, expected a 4xx or 5xx status",
+                validator, option, status
+            ),
+            ValidationFileError::SubjectFile {
+                validator,
+                path,
+                error,
+            } => write!(
+                f,
+                "Validator {} references unreadable subject file {}: {}",
+                validator,
+                path.display(),
+                error
+            ),
+            ValidationFileError::MessageCatalog { path, error } => write!(
+                f,
+                "Unreadable message catalog {}: {}",
+                path.display(),
+                error
+            ),
+            ValidationFileError::UnknownLanguage {
+                validator,
+                language,
+            } => write!(
+                f,
+                "Validator {} uses language {}, which isn't in the message catalog",
+                validator, language
+            ),
+            ValidationFileError::InvalidHttpClient {
+                authority: Some(authority),
+                error,
+            } => write!(
+                f,
+                "Authority {} has invalid HTTP client settings: {}",
+                authority, error
+            ),
+            ValidationFileError::InvalidHttpClient {
+                authority: None,
+                error,
+            } => write!(f, "Invalid global HTTP client settings: {}", error),
+            ValidationFileError::InvalidCertificates { authority, error } => write!(
+                f,
+                "Authority {} has invalid certificate authorities: {}",
+                authority, error
+            ),
+            ValidationFileError::KeySource(name) => write!(
+                f,
+                "Authority {} needs either a jwks_url or static_keys",
+                name
+            ),
+            ValidationFileError::InvalidStaticKeys { authority, error } => {
+                write!(
+                    f,
+                    "Authority {} has invalid static keys: {}",
+                    authority, error
+                )
+            }
+            ValidationFileError::MissingTenant { authority, tenant } => write!(
+                f,
+                "Authority {} references missing tenant {}",
+                authority, tenant
+            ),
+            ValidationFileError::TenantQuotaExceeded {
+                tenant,
+                authorities,
+                max_authorities,
+            } => write!(
+                f,
+                "Tenant {} has {} authorities, but is limited to {}",
+                tenant, authorities, max_authorities
+            ),
+            ValidationFileError::InvalidAlertThreshold { reason, threshold } => write!(
+                f,
+                "Alert on {} has an invalid threshold {}, expected a share between 0 and 1",
+                reason, threshold
+            ),
+            ValidationFileError::RemovedKey {
+                path,
+                key,
+                replacement,
+            } => write!(
+                f,
+                "{}: {} was removed, use {} instead (see migrate-config)",
+                path, key, replacement
+            ),
+            ValidationFileError::UnknownVariable { path, name } => {
+                write!(f, "{}: references the undefined variable {}", path, name)
+            }
+            ValidationFileError::InvalidVariable { name, reason } => {
+                write!(f, "Variable {} is invalid: {}", name, reason)
+            }
+            ValidationFileError::CircularVariable(name) => {
+                write!(
+                    f,
+                    "Variable {} references itself through other variables",
+                    name
+                )
+            }
+            ValidationFileError::InvalidInclude { path, reason } => {
+                write!(f, "Invalid include in {}: {}", path.display(), reason)
+            }
+            ValidationFileError::IncludedFile { path, error } => {
+                write!(f, "Included file {}: {}", path.display(), error)
+            }
+            ValidationFileError::CircularInclude(path) => {
+                write!(f, "Circular include of file {}", path.display())
+            }
+            ValidationFileError::DuplicateKey { key, path } => write!(
+                f,
+                "{} in {} is already defined by another file",
+                key,
+                path.display()
+            ),
+            ValidationFileError::IncludeNotAllowed => {
+                write!(f, "Only the configuration file can include other files")
+            }
+            ValidationFileError::InvalidIdentityHashing(reason) => {
+                write!(f, "Invalid identity hashing: {}", reason)
+            }
+            ValidationFileError::UnsupportedVersion { version, supported } => write!(
+                f,
+                "The configuration file is written for schema version {}, this release supports versions up to {}",
+                version, supported
+            ),
+            ValidationFileError::Lint(lints) => {
+                let lints = lints.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+                write!(f, "Configuration has lint findings: {}", lints.join("; "))
+            }
         }
     }
 }
@@ -84,7 +540,33 @@ impl std::error::Error for ValidationFileError {
         match self {
             ValidationFileError::IoError(e) => Some(e),
             ValidationFileError::SerdeError(e) => Some(e),
+            ValidationFileError::InvalidMatcher { error, .. } => Some(error),
+            ValidationFileError::SubjectFile { error, .. } => Some(error),
+            ValidationFileError::MessageCatalog { error, .. } => Some(error),
+            ValidationFileError::InvalidHttpClient { error, .. } => Some(error),
+            ValidationFileError::InvalidCertificates { error, .. } => Some(error),
+            ValidationFileError::InvalidStaticKeys { error, .. } => Some(error),
+            ValidationFileError::InvalidEnrichment { error, .. } => Some(error),
+            ValidationFileError::IncludedFile { error, .. } => Some(error.as_ref()),
             _ => None,
         }
     }
 }
+
+impl fmt::Display for HttpClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpClientError::ReadCaFile(e) => write!(f, "failed to read CA file: {}", e),
+            HttpClientError::Reqwest(e) => write!(f, "failed to build HTTP client: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HttpClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpClientError::ReadCaFile(e) => Some(e),
+            HttpClientError::Reqwest(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use super::migrate::key_name;
+use super::ValidationFileError;
+
+/// The top level key listing the files included by a configuration file
+pub const INCLUDE_KEY: &str = "include";
+
+/// Merge the files listed in the `include` section of a configuration file into it
+///
+/// Entries are paths or glob patterns, relative to the directory of the including file. Matches
+/// are merged in alphabetical order and may include further files. Every entry of a section (an
+/// authority, a validator, an HTTP client setting...) can only be defined by one of the files.
+///
+/// Returns the included files and the directories of the patterns, to be watched for changes.
+pub fn resolve(document: &mut Value, path: &Path) -> Result<Vec<PathBuf>, ValidationFileError> {
+    let mut watched = Vec::new();
+    resolve_file(document, path, &mut vec![canonical(path)], &mut watched)?;
+    watched.sort();
+    watched.dedup();
+    Ok(watched)
+}
+
+fn resolve_file(
+    document: &mut Value,
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    watched: &mut Vec<PathBuf>,
+) -> Result<(), ValidationFileError> {
+    let Some(root) = document.as_mapping_mut() else {
+        return Ok(());
+    };
+    let Some(patterns) = root.remove(INCLUDE_KEY) else {
+        return Ok(());
+    };
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    for pattern in patterns_of(patterns, path)? {
+        let full = base.join(&pattern);
+        let full = full.to_string_lossy();
+        let matches = glob::glob(&full).map_err(|e| ValidationFileError::InvalidInclude {
+            path: path.to_path_buf(),
+            reason: format!("{}: {}", pattern, e.msg),
+        })?;
+        watched.push(base.join(literal_prefix(&pattern)));
+
+        let mut files = matches
+            .filter_map(Result::ok)
+            .filter(|file| file.is_file())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        for file in files {
+            let canonical = canonical(&file);
+            if stack.contains(&canonical) {
+                return Err(ValidationFileError::CircularInclude(file));
+            }
+            watched.push(file.clone());
+
+            let mut included = load(&file)?;
+            stack.push(canonical);
+            resolve_file(&mut included, &file, stack, watched)?;
+            stack.pop();
+
+            merge(root, included, &file)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn patterns_of(patterns: Value, path: &Path) -> Result<Vec<String>, ValidationFileError> {
+    let invalid = || ValidationFileError::InvalidInclude {
+        path: path.to_path_buf(),
+        reason: "has to be a path or a list of paths".to_string(),
+    };
+
+    let patterns = match patterns {
+        Value::String(pattern) => return Ok(vec![pattern]),
+        Value::Sequence(patterns) => patterns,
+        _ => return Err(invalid()),
+    };
+    patterns
+        .into_iter()
+        .map(|pattern| match pattern {
+            Value::String(pattern) => Ok(pattern),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+fn load(file: &Path) -> Result<Value, ValidationFileError> {
+    let included = |error| ValidationFileError::IncludedFile {
+        path: file.to_path_buf(),
+        error: Box::new(error),
+    };
+
+    let content = fs::read(file).map_err(|e| included(e.into()))?;
+    serde_yaml::from_slice(&content).map_err(|e| included(e.into()))
+}
+
+/// Merge the sections of an included file, failing on entries defined by both
+fn merge(root: &mut Mapping, included: Value, file: &Path) -> Result<(), ValidationFileError> {
+    let duplicate = |key: String| ValidationFileError::DuplicateKey {
+        key,
+        path: file.to_path_buf(),
+    };
+
+    let Value::Mapping(included) = included else {
+        return match included {
+            Value::Null => Ok(()),
+            _ => Err(ValidationFileError::InvalidInclude {
+                path: file.to_path_buf(),
+                reason: "the included file isn't a mapping".to_string(),
+            }),
+        };
+    };
+
+    for (section, entries) in included {
+        let Some(existing) = root.get_mut(&section) else {
+            root.insert(section, entries);
+            continue;
+        };
+
+        let (Value::Mapping(existing), Value::Mapping(entries)) = (existing, entries) else {
+            return Err(duplicate(key_name(&section)));
+        };
+        for (key, value) in entries {
+            if existing.contains_key(&key) {
+                return Err(duplicate(format!(
+                    "{}.{}",
+                    key_name(&section),
+                    key_name(&key)
+                )));
+            }
+            existing.insert(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// The leading components of a pattern without glob characters, the directory new matches appear
+/// in (or the file itself, if the pattern has no glob characters)
+fn literal_prefix(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| match component {
+            Component::Normal(part) => !part.to_string_lossy().contains(['*', '?', '[']),
+            _ => true,
+        })
+        .collect()
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
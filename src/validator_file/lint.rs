@@ -0,0 +1,188 @@
+use http::HeaderName;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use super::migrate::DeprecatedKey;
+use super::{ConfigFile, JWTAuthority, JWTValidator, ValidatorAction};
+use crate::validators::reason::REASON_HEADER;
+use crate::validators::{EXPIRES_AT_HEADER, EXPIRES_IN_HEADER};
+
+/// A likely mistake in the configuration that doesn't prevent it from being loaded
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    /// The authority isn't used by any validator
+    UnusedAuthority(String),
+    /// The template isn't used by any validator or other template
+    UnusedTemplate(String),
+    /// A claim is mapped onto the header the validator reads the token from
+    TokenHeaderMapped {
+        validator: String,
+        claim: String,
+        header: String,
+    },
+    /// A claim is mapped onto a header the reverse proxy or this service sets as well
+    ProxyHeaderMapped {
+        validator: String,
+        claim: String,
+        header: String,
+    },
+    /// Multiple claims are mapped onto the same header, only one of them is forwarded
+    DuplicateHeader {
+        validator: String,
+        header: String,
+        claims: Vec<String>,
+    },
+    /// The validator serves stale decisions, but there is no decision cache to serve them from
+    StaleWithoutDecisionCache(String),
+    /// A key was renamed, the old name is only accepted until it is removed
+    DeprecatedKey(DeprecatedKey),
+}
+
+/// Find the templates that are never inherited from
+pub fn templates(file: &ConfigFile) -> Vec<Lint> {
+    let used = file
+        .validators
+        .values()
+        .chain(file.validator_templates.values())
+        .filter_map(|v| v.template.as_deref())
+        .collect::<HashSet<_>>();
+
+    file.validator_templates
+        .keys()
+        .filter(|name| !used.contains(name.as_str()))
+        .map(|name| Lint::UnusedTemplate(name.clone()))
+        .collect()
+}
+
+/// Find unused authorities, conflicting claim mappings and ineffective options of the resolved
+/// validators
+pub fn validators(
+    authorities: &HashMap<String, JWTAuthority>,
+    validators: &HashMap<String, JWTValidator>,
+    decision_cache: bool,
+) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    let mut used_authorities = HashSet::new();
+    for (name, validator) in validators {
+        let ValidatorAction::Validate(validation) = &validator.action else {
+            continue;
+        };
+        used_authorities.extend(validation.authorities.iter().map(String::as_str));
+        if validation.stale_while_revalidate.is_some() && !decision_cache {
+            lints.push(Lint::StaleWithoutDecisionCache(name.clone()));
+        }
+
+        let mut headers = BTreeMap::<&str, Vec<String>>::new();
+        for mapping in validation.plan.mappings() {
+            let claim = mapping.claim.to_string();
+            headers
+                .entry(mapping.header.as_str())
+                .or_default()
+                .push(claim.clone());
+
+            if validation.source.reads_header(&mapping.header) {
+                lints.push(Lint::TokenHeaderMapped {
+                    validator: name.clone(),
+                    claim,
+                    header: mapping.header.to_string(),
+                });
+            } else if is_proxy_header(&mapping.header) {
+                lints.push(Lint::ProxyHeaderMapped {
+                    validator: name.clone(),
+                    claim,
+                    header: mapping.header.to_string(),
+                });
+            }
+        }
+
+        for (header, mut claims) in headers {
+            if claims.len() > 1 {
+                claims.sort();
+                lints.push(Lint::DuplicateHeader {
+                    validator: name.clone(),
+                    header: header.to_string(),
+                    claims,
+                });
+            }
+        }
+    }
+    lints.extend(
+        authorities
+            .keys()
+            .filter(|name| !used_authorities.contains(name.as_str()))
+            .map(|name| Lint::UnusedAuthority(name.clone())),
+    );
+
+    lints
+}
+
+/// Whether the reverse proxy (`X-Forwarded-*`) or this service sets the header as well
+///
+/// The headers the proxy always sets itself (e.g. `Host`) can't be mapped at all.
+fn is_proxy_header(header: &HeaderName) -> bool {
+    header.as_str().starts_with("x-forwarded-")
+        || [
+            REASON_HEADER,
+            EXPIRES_AT_HEADER.as_str(),
+            EXPIRES_IN_HEADER.as_str(),
+        ]
+        .contains(&header.as_str())
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::UnusedAuthority(name) => {
+                write!(f, "Authority {} is not used by any validator", name)
+            }
+            Lint::UnusedTemplate(name) => write!(
+                f,
+                "Template {} is not used by any validator or template",
+                name
+            ),
+            Lint::TokenHeaderMapped {
+                validator,
+                claim,
+                header,
+            } => write!(
+                f,
+                "Validator {} maps claim {} onto its token header {}",
+                validator, claim, header
+            ),
+            Lint::ProxyHeaderMapped {
+                validator,
+                claim,
+                header,
+            } => write!(
+                f,
+                "Validator {} maps claim {} onto header {}, which is set by the reverse proxy or this service as well",
+                validator, claim, header
+            ),
+            Lint::DuplicateHeader {
+                validator,
+                header,
+                claims,
+            } => write!(
+                f,
+                "Validator {} maps claims {} onto the same header {}",
+                validator,
+                claims.join(", "),
+                header
+            ),
+            Lint::StaleWithoutDecisionCache(validator) => write!(
+                f,
+                "Validator {} sets stale_while_revalidate_seconds, which has no effect without decision_cache",
+                validator
+            ),
+            Lint::DeprecatedKey(used) => write!(
+                f,
+                "{}: {} is deprecated and will be removed in {}, use {} instead (see migrate-config)",
+                used.path,
+                used.deprecation.key,
+                used.deprecation.removed_in,
+                used.deprecation.replacement
+            ),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use serde_yaml::{Mapping, Value};
+
+use super::{ConfigFile, ValidationFileError};
+
+/// The schema version of configuration files written for this release
+pub const SCHEMA_VERSION: u64 = 2;
+/// The schema of files without a `version`, which may still use the deprecated keys
+const LEGACY_SCHEMA_VERSION: u64 = 1;
+const VERSION_KEY: &str = "version";
+
+/// Keys of validators and templates that were renamed
+///
+/// The old names are still accepted when loading a configuration until the release they are
+/// removed in, the migration always replaces them.
+const DEPRECATED_VALIDATOR_KEYS: &[Deprecation] = &[Deprecation {
+    key: "prefix",
+    replacement: "header_prefix",
+    removed_in: "0.3.0",
+}];
+
+/// Reason codes of alert rules that were split into more specific codes
+const DEPRECATED_REASONS: &[Deprecation] = &[
+    Deprecation {
+        key: "invalid_token",
+        replacement: "signature",
+        removed_in: "0.3.0",
+    },
+    Deprecation {
+        key: "claims",
+        replacement: "claim_mismatch",
+        removed_in: "0.3.0",
+    },
+];
+
+/// The sections of the configuration file holding validator definitions
+const VALIDATOR_SECTIONS: [&str; 2] = ["validator_templates", "validators"];
+
+/// A key or value that was renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deprecation {
+    pub key: &'static str,
+    pub replacement: &'static str,
+    /// The first release that doesn't accept the key anymore
+    pub removed_in: &'static str,
+}
+
+/// A use of a deprecated key in a loaded configuration file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeprecatedKey {
+    /// Where the key is used, e.g. `validators.api.prefix`
+    pub path: String,
+    pub deprecation: Deprecation,
+}
+
+/// A change made to upgrade a configuration file to the current layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Where the change was made, e.g. `validators.api.prefix`
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key was renamed
+    Renamed { to: &'static str },
+    /// Both the old and the current key are set, the old one was dropped
+    Conflict { current: &'static str },
+    /// A required claim without matchers was shortened to its name
+    SimplifiedClaim,
+    /// The schema version was set to the current one
+    Versioned { to: u64 },
+}
+
+/// The schema version a configuration file is written for, files without one are taken as the
+/// legacy schema
+///
+/// Fails for versions this release doesn't know, e.g. of files written for a newer release.
+pub fn schema_version(document: &Value) -> Result<u64, ValidationFileError> {
+    let Some(version) = document.get(VERSION_KEY) else {
+        return Ok(LEGACY_SCHEMA_VERSION);
+    };
+
+    match version.as_u64() {
+        Some(version) if (LEGACY_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&version) => Ok(version),
+        _ => Err(ValidationFileError::UnsupportedVersion {
+            version: key_name(version),
+            supported: SCHEMA_VERSION,
+        }),
+    }
+}
+
+/// Upgrade a configuration file written for an older version to the current layout
+///
+/// Works on the YAML document, so keys unknown to this version are kept as they are.
+pub fn migrate(document: &mut Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let Some(root) = document.as_mapping_mut() else {
+        return changes;
+    };
+
+    if root.get(VERSION_KEY).and_then(Value::as_u64) != Some(SCHEMA_VERSION) {
+        // The version goes first, so it is seen before anything depending on it
+        let mut versioned = Mapping::with_capacity(root.len() + 1);
+        versioned.insert(Value::from(VERSION_KEY), Value::from(SCHEMA_VERSION));
+        versioned.extend(
+            std::mem::take(root)
+                .into_iter()
+                .filter(|(key, _)| key.as_str() != Some(VERSION_KEY)),
+        );
+        *root = versioned;
+        changes.push(Change {
+            path: VERSION_KEY.to_string(),
+            kind: ChangeKind::Versioned { to: SCHEMA_VERSION },
+        });
+    }
+
+    for section in VALIDATOR_SECTIONS {
+        let Some(validators) = root.get_mut(section).and_then(Value::as_mapping_mut) else {
+            continue;
+        };
+
+        for (name, validator) in validators.iter_mut() {
+            let Some(validator) = validator.as_mapping_mut() else {
+                continue;
+            };
+            let path = format!("{}.{}", section, key_name(name));
+
+            rename_keys(validator, &path, DEPRECATED_VALIDATOR_KEYS, &mut changes);
+            simplify_claims(validator, &path, &mut changes);
+        }
+    }
+
+    for (path, deprecation) in rename_reasons(root) {
+        changes.push(Change {
+            path,
+            kind: ChangeKind::Renamed {
+                to: deprecation.replacement,
+            },
+        });
+    }
+
+    changes
+}
+
+/// Migrate a configuration file, writing the result to `output` or stdout
+///
+/// Fails without writing anything if the migrated file still isn't a valid configuration file.
+pub fn migrate_file(
+    input: &Path,
+    output: Option<&Path>,
+) -> Result<Vec<Change>, ValidationFileError> {
+    let mut document: Value = serde_yaml::from_reader(fs::File::open(input)?)?;
+    schema_version(&document)?;
+    let changes = migrate(&mut document);
+    serde_yaml::from_value::<ConfigFile>(document.clone())?;
+
+    let migrated = serde_yaml::to_string(&document)?;
+    match output {
+        Some(output) => fs::write(output, migrated)?,
+        None => std::io::stdout().write_all(migrated.as_bytes())?,
+    }
+
+    Ok(changes)
+}
+
+/// Accept the deprecated keys of a configuration file being loaded, renaming them
+///
+/// Fails if a key is used that isn't accepted by this release anymore, or that isn't part of the
+/// schema version of the file.
+pub fn accept_deprecated(
+    document: &mut Value,
+    version: u64,
+) -> Result<Vec<DeprecatedKey>, ValidationFileError> {
+    let mut used = Vec::new();
+    let Some(root) = document.as_mapping_mut() else {
+        return Ok(used);
+    };
+
+    for section in VALIDATOR_SECTIONS {
+        let Some(validators) = root.get_mut(section).and_then(Value::as_mapping_mut) else {
+            continue;
+        };
+
+        for (name, validator) in validators.iter_mut() {
+            let Some(validator) = validator.as_mapping_mut() else {
+                continue;
+            };
+            let path = format!("{}.{}", section, key_name(name));
+
+            for deprecation in DEPRECATED_VALIDATOR_KEYS {
+                if !validator.contains_key(deprecation.key) {
+                    continue;
+                }
+
+                used.push(DeprecatedKey {
+                    path: format!("{}.{}", path, deprecation.key),
+                    deprecation: *deprecation,
+                });
+            }
+            rename_keys(validator, &path, DEPRECATED_VALIDATOR_KEYS, &mut Vec::new());
+        }
+    }
+
+    for (path, deprecation) in rename_reasons(root) {
+        used.push(DeprecatedKey { path, deprecation });
+    }
+
+    // Files of the current schema are written with the new names, so an old one is a mistake
+    // rather than a file that wasn't upgraded yet
+    let removed = used
+        .iter()
+        .find(|used| version >= SCHEMA_VERSION || used.deprecation.is_removed());
+    if let Some(removed) = removed {
+        return Err(ValidationFileError::RemovedKey {
+            path: removed.path.clone(),
+            key: removed.deprecation.key,
+            replacement: removed.deprecation.replacement,
+        });
+    }
+    used.sort();
+    Ok(used)
+}
+
+fn rename_keys(
+    mapping: &mut Mapping,
+    path: &str,
+    deprecations: &[Deprecation],
+    changes: &mut Vec<Change>,
+) {
+    for deprecation in deprecations {
+        let Some(value) = mapping.remove(deprecation.key) else {
+            continue;
+        };
+
+        let path = format!("{}.{}", path, deprecation.key);
+        let replacement = deprecation.replacement;
+        if mapping.contains_key(replacement) {
+            changes.push(Change {
+                path,
+                kind: ChangeKind::Conflict {
+                    current: replacement,
+                },
+            });
+            continue;
+        }
+
+        mapping.insert(Value::from(replacement), value);
+        changes.push(Change {
+            path,
+            kind: ChangeKind::Renamed { to: replacement },
+        });
+    }
+}
+
+/// Replace the deprecated reason codes of the alert rules, returning where they were used
+fn rename_reasons(root: &mut Mapping) -> Vec<(String, Deprecation)> {
+    let mut renamed = Vec::new();
+    let Some(rules) = root
+        .get_mut("alerts")
+        .and_then(|alerts| alerts.get_mut("rules"))
+        .and_then(Value::as_sequence_mut)
+    else {
+        return renamed;
+    };
+
+    for (i, rule) in rules.iter_mut().enumerate() {
+        let Some(reason) = rule.get_mut("reason") else {
+            continue;
+        };
+        let Some(deprecation) = DEPRECATED_REASONS
+            .iter()
+            .find(|deprecation| reason.as_str() == Some(deprecation.key))
+        else {
+            continue;
+        };
+
+        *reason = Value::from(deprecation.replacement);
+        renamed.push((format!("alerts.rules[{}].reason", i), *deprecation));
+    }
+
+    renamed
+}
+
+/// Replace `{name: sub}` and `{name: sub, value: null}` with `sub`, as older versions required
+/// a `value` key (that could be empty) for every complex claim
+fn simplify_claims(validator: &mut Mapping, path: &str, changes: &mut Vec<Change>) {
+    let Some(claims) = validator
+        .get_mut("required_claims")
+        .and_then(Value::as_sequence_mut)
+    else {
+        return;
+    };
+
+    for (i, claim) in claims.iter_mut().enumerate() {
+        let Some(mapping) = claim.as_mapping() else {
+            continue;
+        };
+        let Some(name) = mapping.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        let only_name = mapping
+            .iter()
+            .all(|(key, value)| key.as_str() == Some("name") || value.is_null());
+        if !only_name {
+            continue;
+        }
+
+        *claim = Value::from(name);
+        changes.push(Change {
+            path: format!("{}.required_claims[{}]", path, i),
+            kind: ChangeKind::SimplifiedClaim,
+        });
+    }
+}
+
+pub(super) fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+impl Deprecation {
+    /// Whether this release doesn't accept the key anymore
+    fn is_removed(&self) -> bool {
+        version(env!("CARGO_PKG_VERSION")) >= version(self.removed_in)
+    }
+}
+
+/// The numeric components of a version, ignoring pre-release and build metadata
+fn version(version: &str) -> Vec<u64> {
+    version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ChangeKind::Renamed { to } => write!(f, "{}: renamed to {}", self.path, to),
+            ChangeKind::Conflict { current } => write!(
+                f,
+                "{}: dropped, as {} is set as well and takes precedence",
+                self.path, current
+            ),
+            ChangeKind::SimplifiedClaim => {
+                write!(f, "{}: replaced with the name of the claim", self.path)
+            }
+            ChangeKind::Versioned { to } => write!(f, "{}: set to {}", self.path, to),
+        }
+    }
+}
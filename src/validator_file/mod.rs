@@ -1,55 +1,249 @@
 use aliri::jwt::CoreValidator;
-use http::HeaderName;
-use std::collections::HashMap;
-use std::path::Path;
+use http::{HeaderName, StatusCode};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fmt, fs};
+use std::time::Duration;
+use tracing::warn;
 
 mod error;
 mod file;
+mod include;
+mod lint;
+pub mod migrate;
+mod vars;
 
-pub use error::ValidationFileError;
-use file::{JWTAuthority, PartialJWTValidator};
+pub use error::{HttpClientError, ValidationFileError};
+#[cfg(feature = "ldap")]
+pub use file::LdapConfig;
+#[cfg(feature = "redis")]
+pub use file::RedisConfig;
+#[cfg(feature = "sql")]
+pub use file::SqlConfig;
+use file::{Action, TransportConfig};
+pub use file::{
+    AlertRule, AlertsConfig, BodyTemplateConfig, ConfigFile, ConnectivityCheckConfig, CookieConfig,
+    DecisionCacheConfig, DynamicConfig, EnrichmentConfig, EnrichmentSource, HeaderLimitsConfig,
+    HeaderProjectionConfig, HttpClientConfig, IdentityHashingConfig, JWTAuthority, KeyFilter,
+    KeyMaterial, LimitPolicy, LoginRedirectConfig, MaintenanceConfig, OpaConfig, OutagePolicy,
+    PartialJWTValidator, Prefixes, SameSite, Severity, StaticKeys, SuccessResponseConfig,
+    TenantConfig, TokenLimitsConfig, TokenSourceConfig,
+};
+pub use lint::Lint;
+
+use crate::enrichment::Enrichment;
+use crate::validators::authority::{Lifetime, Verifier};
+use crate::validators::body_template::BodyTemplate;
+use crate::validators::certificates::TrustAnchors;
+use crate::validators::checks::{
+    self, ClaimMapping, ClaimRequirement, CompileError, Operator, Plan, Requirement,
+    RequirementGroup, SubjectFilter,
+};
+use crate::validators::claims::Claim;
+use crate::validators::cookies::{self, CookieMapping};
+use crate::validators::header_limits::HeaderLimits;
+use crate::validators::login::LoginRedirect;
+use crate::validators::messages::Messages;
+use crate::validators::policy::Policy;
+use crate::validators::privacy::IdentityHashing;
+use crate::validators::profile::Profile;
+use crate::validators::projection::{self, HeaderProjection};
+use crate::validators::reason::Reason;
+use crate::validators::static_keys;
+use crate::validators::success::SuccessResponse;
+use crate::validators::token_source::TokenSource;
+use crate::validators::validator::Authorities;
+
+/// The claim with the space-delimited OAuth 2.0 scopes of an access token (RFC 9068)
+const SCOPE_CLAIM: &str = "scope";
+/// The claim Keycloak lists the realm roles of a token in
+const REALM_ROLES_CLAIM: &str = "realm_access.roles";
 
 #[derive(Debug)]
 pub struct Config {
+    pub http: HttpClientConfig,
+    /// The HTTP clients for every distinct set of client settings used by the authorities
+    pub http_clients: HashMap<HttpClientConfig, reqwest::Client>,
+    pub maintenance: MaintenanceConfig,
+    pub connectivity_check: Option<ConnectivityCheckConfig>,
+    pub alerts: Option<AlertsConfig>,
+    pub decision_cache: Option<DecisionCacheConfig>,
+    pub token_limits: TokenLimitsConfig,
+    pub identity_hashing: Option<IdentityHashing>,
+    pub tenants: HashMap<String, TenantConfig>,
     pub authorities: HashMap<String, JWTAuthority>,
+    pub enrichments: HashMap<String, Enrichment>,
     pub validators: HashMap<String, JWTValidator>,
+    /// Likely mistakes found while loading the configuration
+    pub lints: Vec<Lint>,
 }
 
 #[derive(Debug)]
 pub struct JWTValidator {
-    pub authority: String,
+    pub action: ValidatorAction,
+    pub labels: BTreeMap<String, String>,
+    /// Whether the validator answers every request with 503
+    pub maintenance: bool,
+    /// The rejection messages, with the message catalog applied
+    pub messages: Messages,
+    pub profile: Profile,
+    /// The definition with its templates applied, `None` if it depends on other files
+    ///
+    /// Used to detect unchanged validators on reload.
+    pub definition: Option<PartialJWTValidator>,
+}
+
+#[derive(Debug)]
+pub enum ValidatorAction {
+    Validate(Box<TokenValidation>),
+    Deny(DenyResponse),
+}
+
+#[derive(Debug)]
+pub struct TokenValidation {
+    pub authorities: Authorities<String>,
 
-    pub header: String,
-    pub header_prefix: Option<String>,
+    pub source: TokenSource,
 
-    pub required_claims: Vec<RequiredClaim>,
-    pub map_claims: HashMap<String, HeaderName>,
+    /// The enrichments applied to valid tokens, in order
+    pub enrich: Vec<String>,
+    /// The required claims and the claims mapped to headers
+    pub plan: Plan,
+    /// The CEL expression the claims have to satisfy after the required claims
+    pub policy: Option<Policy>,
+    /// The Open Policy Agent deciding about tokens after the policy
+    pub opa: Option<OpaConfig>,
+    pub subjects: SubjectFilter,
+    pub transport: Option<TransportPolicy>,
+    pub outage: OutagePolicy,
+    pub cache_max_age: Option<u64>,
+    pub stale_while_revalidate: Option<Duration>,
+    pub header_limits: Option<HeaderLimits>,
+    pub header_projection: Option<HeaderProjection>,
+    /// The authority to compare decisions with and the claims to compare
+    pub compare: Option<(String, Vec<Claim>)>,
+    pub echo_headers: Vec<HeaderName>,
+    pub success: Option<SuccessResponse>,
+    pub body_template: Option<BodyTemplate>,
+    pub realm: String,
+    pub login_redirect: Option<LoginRedirect>,
 }
 
+/// Requirements on how the original request reached the reverse proxy
 #[derive(Debug, Clone)]
-pub struct RequiredClaim {
-    pub name: String,
-    pub value: RequiredClaimValue,
+pub struct TransportPolicy {
+    pub require_https: bool,
+    pub allowed_ports: Option<Vec<u16>>,
+    pub status: StatusCode,
 }
 
+/// The fixed response of a validator that denies every request
 #[derive(Debug, Clone)]
-pub enum RequiredClaimValue {
-    None,
-    Single(String),
-    Multiple(Vec<String>),
+pub struct DenyResponse {
+    pub status: StatusCode,
+    pub message: String,
 }
 
-impl Config {
+impl ConfigFile {
+    /// Load the configuration file, merging the files it includes
+    pub fn load(path: &Path) -> Result<Self, ValidationFileError> {
+        let mut document: serde_yaml::Value = serde_yaml::from_slice(&fs::read(path)?)?;
+        let watched = include::resolve(&mut document, path)?;
+
+        let mut file = Self::from_document(document)?;
+        file.watched = watched;
+        Ok(file)
+    }
+
+    /// Parse a configuration file sent as YAML or JSON, accepting deprecated keys and replacing
+    /// variables
+    pub fn from_slice(content: &[u8]) -> Result<Self, ValidationFileError> {
+        let document: serde_yaml::Value = serde_yaml::from_slice(content)?;
+        if document.get(include::INCLUDE_KEY).is_some() {
+            return Err(ValidationFileError::IncludeNotAllowed);
+        }
+
+        Self::from_document(document)
+    }
+
+    fn from_document(mut document: serde_yaml::Value) -> Result<Self, ValidationFileError> {
+        vars::substitute(&mut document)?;
+        let version = migrate::schema_version(&document)?;
+        let deprecated = migrate::accept_deprecated(&mut document, version)?;
+
+        let mut file: Self = serde_yaml::from_value(document)?;
+        file.deprecated = deprecated;
+        Ok(file)
+    }
+
+    /// Add the dynamically registered authorities and validators
+    ///
+    /// Entries of the configuration file take precedence over dynamic entries with the same name.
+    pub fn with_dynamic(&self, dynamic: &DynamicConfig) -> Self {
+        let mut res = self.clone();
+
+        for (name, authority) in &dynamic.authorities {
+            if res.authorities.contains_key(name) {
+                warn!(
+                    "Dynamic authority {} is shadowed by the configuration file",
+                    name
+                );
+                continue;
+            }
+            res.authorities.insert(name.clone(), authority.clone());
+        }
+
+        for (name, validator) in &dynamic.validators {
+            if res.validators.contains_key(name) {
+                warn!(
+                    "Dynamic validator {} is shadowed by the configuration file",
+                    name
+                );
+                continue;
+            }
+            res.validators.insert(name.clone(), validator.clone());
+        }
+
+        res
+    }
+}
+
+impl DynamicConfig {
+    pub fn is_empty(&self) -> bool {
+        self.authorities.is_empty() && self.validators.is_empty()
+    }
+
+    /// Load the persisted entries, a missing file is treated as no entries
     pub fn load(path: &Path) -> Result<Self, ValidationFileError> {
-        let file = fs::File::open(path)?;
-        let config_file: file::ConfigFile = serde_yaml::from_reader(file)?;
-        Self::from_file(config_file)
+        match fs::File::open(path) {
+            Ok(file) => Ok(serde_yaml::from_reader(file)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    fn from_file(file: file::ConfigFile) -> Result<Self, ValidationFileError> {
+    /// Persist the entries, replacing the file atomically so a crash never leaves a partial file
+    pub fn save(&self, path: &Path) -> Result<(), ValidationFileError> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        fs::write(&tmp, serde_yaml::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn from_file(file: ConfigFile) -> Result<Self, ValidationFileError> {
         let mut validators = HashMap::new();
+        let mut lints = lint::templates(&file);
+        lints.extend(file.deprecated.iter().cloned().map(Lint::DeprecatedKey));
+        let catalog = match &file.message_catalog {
+            Some(path) => load_catalog(path)?,
+            None => HashMap::new(),
+        };
 
         for (name, mut partial) in file.validators {
             let mut visited = std::collections::HashSet::new();
@@ -68,24 +262,184 @@ impl Config {
             };
 
             while let Some(temp) = template {
-                if partial.authority.is_none() {
+                if partial.action.is_none() {
+                    partial.action = temp.action;
+                }
+
+                for (label, value) in &temp.labels {
+                    partial
+                        .labels
+                        .entry(label.clone())
+                        .or_insert_with(|| value.clone());
+                }
+
+                // A validator's own choice between a single authority and issuers takes precedence
+                if partial.authority.is_none() && partial.issuers.is_empty() {
                     partial.authority = temp.authority.clone();
                 }
 
+                if partial.issuers.is_empty() && partial.authority.is_none() {
+                    partial.issuers = temp.issuers.clone();
+                }
+
                 if partial.header.is_none() {
                     partial.header = temp.header.clone();
                 }
 
+                if partial.query_param.is_none() {
+                    partial.query_param = temp.query_param.clone();
+                }
+
+                if partial.cookie.is_none() {
+                    partial.cookie = temp.cookie.clone();
+                }
+
+                if partial.token_sources.is_none() {
+                    partial.token_sources = temp.token_sources.clone();
+                }
+
+                if partial.enrich.is_none() {
+                    partial.enrich = temp.enrich.clone();
+                }
+
                 if partial.header_prefix.is_none() {
                     partial.header_prefix = temp.header_prefix.clone();
                 }
 
+                if partial.require_prefix.is_none() {
+                    partial.require_prefix = temp.require_prefix;
+                }
+
                 partial
                     .required_claims
                     .extend(temp.required_claims.iter().cloned());
                 partial
                     .map_claims
                     .extend(temp.map_claims.iter().map(|(k, v)| (k.clone(), v.clone())));
+                for (claim, cookie) in &temp.map_cookies {
+                    partial
+                        .map_cookies
+                        .entry(claim.clone())
+                        .or_insert_with(|| cookie.clone());
+                }
+
+                if partial.required_scopes.is_none() {
+                    partial.required_scopes = temp.required_scopes.clone();
+                }
+
+                if partial.map_scopes.is_none() {
+                    partial.map_scopes = temp.map_scopes.clone();
+                }
+
+                if partial.required_realm_roles.is_none() {
+                    partial.required_realm_roles = temp.required_realm_roles.clone();
+                }
+                if partial.required_client_roles.is_none() {
+                    partial.required_client_roles = temp.required_client_roles.clone();
+                }
+                if partial.map_realm_roles.is_none() {
+                    partial.map_realm_roles = temp.map_realm_roles.clone();
+                }
+                if partial.map_client_roles.is_none() {
+                    partial.map_client_roles = temp.map_client_roles.clone();
+                }
+
+                if partial.policy.is_none() {
+                    partial.policy = temp.policy.clone();
+                }
+
+                if partial.opa.is_none() {
+                    partial.opa = temp.opa.clone();
+                }
+
+                if partial.deny_status.is_none() {
+                    partial.deny_status = temp.deny_status;
+                }
+
+                if partial.deny_message.is_none() {
+                    partial.deny_message = temp.deny_message.clone();
+                }
+
+                if partial.maintenance.is_none() {
+                    partial.maintenance = temp.maintenance;
+                }
+
+                if partial.allowed_subjects.is_none() {
+                    partial.allowed_subjects = temp.allowed_subjects.clone();
+                }
+
+                if partial.allowed_subjects_file.is_none() {
+                    partial.allowed_subjects_file = temp.allowed_subjects_file.clone();
+                }
+
+                if partial.denied_subjects.is_none() {
+                    partial.denied_subjects = temp.denied_subjects.clone();
+                }
+
+                if partial.denied_subjects_file.is_none() {
+                    partial.denied_subjects_file = temp.denied_subjects_file.clone();
+                }
+
+                if partial.transport.is_none() {
+                    partial.transport = temp.transport.clone();
+                }
+
+                if partial.outage_policy.is_none() {
+                    partial.outage_policy = temp.outage_policy;
+                }
+
+                if partial.compare_with.is_none() {
+                    partial.compare_with = temp.compare_with.clone();
+                }
+
+                if partial.cache_max_age.is_none() {
+                    partial.cache_max_age = temp.cache_max_age;
+                }
+                if partial.stale_while_revalidate_seconds.is_none() {
+                    partial.stale_while_revalidate_seconds = temp.stale_while_revalidate_seconds;
+                }
+
+                if partial.header_limits.is_none() {
+                    partial.header_limits = temp.header_limits.clone();
+                }
+                if partial.header_projection.is_none() {
+                    partial.header_projection = temp.header_projection.clone();
+                }
+
+                if partial.profile.is_none() {
+                    partial.profile = temp.profile;
+                }
+
+                if partial.echo_headers.is_none() {
+                    partial.echo_headers = temp.echo_headers.clone();
+                }
+
+                if partial.success.is_none() {
+                    partial.success = temp.success.clone();
+                }
+
+                if partial.body_template.is_none() {
+                    partial.body_template = temp.body_template.clone();
+                }
+
+                if partial.realm.is_none() {
+                    partial.realm = temp.realm.clone();
+                }
+
+                if partial.login_redirect.is_none() {
+                    partial.login_redirect = temp.login_redirect.clone();
+                }
+
+                if partial.language.is_none() {
+                    partial.language = temp.language.clone();
+                }
+
+                for (reason, message) in &temp.messages {
+                    partial
+                        .messages
+                        .entry(*reason)
+                        .or_insert_with(|| message.clone());
+                }
 
                 template = match &temp.template {
                     Some(template_name) => {
@@ -106,127 +460,905 @@ impl Config {
                 };
             }
 
-            let val = JWTValidator::from_partial(&name, partial)?;
-            if file.authorities.get(&val.authority).is_none() {
-                return Err(ValidationFileError::MissingAuthority {
-                    validator: name,
-                    authority: val.authority.clone(),
-                });
+            let val = JWTValidator::from_partial(&name, partial, &catalog)?;
+            if let ValidatorAction::Validate(validation) = &val.action {
+                if let Some(authority) = validation
+                    .authorities
+                    .iter()
+                    .chain(validation.compare.as_ref().map(|(authority, _)| authority))
+                    .find(|authority| !file.authorities.contains_key(*authority))
+                {
+                    return Err(ValidationFileError::MissingAuthority {
+                        validator: name,
+                        authority: authority.clone(),
+                    });
+                }
+                if let Some(enrichment) = validation
+                    .enrich
+                    .iter()
+                    .find(|enrichment| !file.enrichments.contains_key(*enrichment))
+                {
+                    return Err(ValidationFileError::MissingEnrichment {
+                        validator: name,
+                        enrichment: enrichment.clone(),
+                    });
+                }
             }
             validators.insert(name, val);
         }
 
+        lints.extend(lint::validators(
+            &file.authorities,
+            &validators,
+            file.decision_cache.is_some(),
+        ));
+        // Sorted, so repeated loads report the findings in the same order
+        lints.sort();
+
+        let mut tenant_authorities = HashMap::new();
+        for (name, authority) in &file.authorities {
+            if let Some(tenant) = &authority.tenant {
+                if !file.tenants.contains_key(tenant) {
+                    return Err(ValidationFileError::MissingTenant {
+                        authority: name.clone(),
+                        tenant: tenant.clone(),
+                    });
+                }
+
+                *tenant_authorities.entry(tenant.as_str()).or_insert(0) += 1;
+            }
+        }
+
+        for (tenant, authorities) in tenant_authorities {
+            match file.tenants[tenant].max_authorities {
+                Some(max_authorities) if authorities > max_authorities => {
+                    return Err(ValidationFileError::TenantQuotaExceeded {
+                        tenant: tenant.to_string(),
+                        authorities,
+                        max_authorities,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        for rule in file.alerts.iter().flat_map(|alerts| &alerts.rules) {
+            if !(0.0..1.0).contains(&rule.threshold) {
+                return Err(ValidationFileError::InvalidAlertThreshold {
+                    reason: rule.reason.as_str().to_string(),
+                    threshold: rule.threshold,
+                });
+            }
+        }
+
+        let mut http_clients = HashMap::new();
+        let global_client =
+            file.http
+                .build()
+                .map_err(|error| ValidationFileError::InvalidHttpClient {
+                    authority: None,
+                    error,
+                })?;
+        http_clients.insert(file.http.clone(), global_client);
+
+        for (name, authority) in &file.authorities {
+            let http = authority.http_config(&file.http);
+            if http_clients.contains_key(&http) {
+                continue;
+            }
+
+            let client = http
+                .build()
+                .map_err(|error| ValidationFileError::InvalidHttpClient {
+                    authority: Some(name.clone()),
+                    error,
+                })?;
+            http_clients.insert(http, client);
+        }
+
+        for (name, authority) in &file.authorities {
+            match (&authority.jwks_url, &authority.static_keys) {
+                (Some(_), None) => {}
+                (None, Some(keys)) => {
+                    static_keys::document(keys).map_err(|error| {
+                        ValidationFileError::InvalidStaticKeys {
+                            authority: name.clone(),
+                            error,
+                        }
+                    })?;
+                }
+                _ => return Err(ValidationFileError::KeySource(name.clone())),
+            }
+
+            if let Some(certificates) = authority
+                .keys
+                .as_ref()
+                .and_then(|keys| keys.certificates.as_ref())
+            {
+                TrustAnchors::load(&certificates.ca_file, certificates.require).map_err(
+                    |error| ValidationFileError::InvalidCertificates {
+                        authority: name.clone(),
+                        error,
+                    },
+                )?;
+            }
+        }
+
+        let enrichments = file
+            .enrichments
+            .iter()
+            .map(|(name, config)| match Enrichment::new(name, config) {
+                Ok(enrichment) => Ok((name.clone(), enrichment)),
+                Err(error) => Err(ValidationFileError::InvalidEnrichment {
+                    enrichment: name.clone(),
+                    error,
+                }),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
         Ok(Self {
+            http: file.http,
+            http_clients,
+            maintenance: file.maintenance,
+            connectivity_check: file.connectivity_check,
+            alerts: file.alerts,
+            decision_cache: file.decision_cache,
+            token_limits: file.token_limits,
+            identity_hashing: file
+                .identity_hashing
+                .as_ref()
+                .map(IdentityHashing::compile)
+                .transpose()
+                .map_err(ValidationFileError::InvalidIdentityHashing)?,
+            tenants: file.tenants,
             authorities: file.authorities,
+            enrichments,
             validators,
+            lints,
         })
     }
 }
 
 impl JWTValidator {
+    fn from_partial(
+        name: &str,
+        partial: PartialJWTValidator,
+        catalog: &MessageCatalog,
+    ) -> Result<Self, ValidationFileError> {
+        let maintenance = partial.maintenance.unwrap_or(false);
+        let labels = partial.labels.clone();
+        let messages = messages(name, &partial, catalog)?;
+        let profile = partial.profile.unwrap_or_default();
+        // The subject and template files may have changed even if the definition didn't
+        let definition = (partial.allowed_subjects_file.is_none()
+            && partial.denied_subjects_file.is_none()
+            && partial
+                .body_template
+                .as_ref()
+                .is_none_or(|template| template.template_file.is_none()))
+        .then(|| partial.clone());
+        let action = match partial.action.unwrap_or_default() {
+            Action::Validate => {
+                let validation = TokenValidation::from_partial(name, partial)?;
+                validation.check_profile(name, profile)?;
+                ValidatorAction::Validate(Box::new(validation))
+            }
+            Action::Deny => ValidatorAction::Deny(DenyResponse::from_partial(name, partial)?),
+        };
+
+        Ok(Self {
+            action,
+            labels,
+            maintenance,
+            messages,
+            profile,
+            definition,
+        })
+    }
+}
+
+impl TokenValidation {
     fn from_partial(name: &str, partial: PartialJWTValidator) -> Result<Self, ValidationFileError> {
-        use file::RequiredClaim as PartialRequiredClaim;
+        let allowed_subjects = load_subjects(
+            name,
+            partial.allowed_subjects,
+            partial.allowed_subjects_file,
+        )?;
+        let denied_subjects =
+            load_subjects(name, partial.denied_subjects, partial.denied_subjects_file)?;
+
+        let header = match partial.header {
+            Some(header) => Some(token_header(
+                name,
+                header,
+                partial.header_prefix,
+                partial.require_prefix,
+            )?),
+            None => None,
+        };
+        // Explicit token sources are tried in their order. Without them a query parameter takes
+        // precedence over the header, so it can be set on a validator using a template that reads
+        // the token from a header.
+        let source = match (partial.token_sources, partial.query_param, partial.cookie) {
+            (Some(sources), _, _) if !sources.is_empty() => TokenSource::Ordered(
+                sources
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, source)| token_source(name, index, source))
+                    .collect::<Result<_, _>>()?,
+            ),
+            (_, Some(param), _) => TokenSource::Query { param },
+            // The header is read if the request has no such cookie
+            (_, None, Some(cookie)) => match header {
+                Some(header) => TokenSource::Ordered(vec![token_cookie(name, cookie)?, header]),
+                None => token_cookie(name, cookie)?,
+            },
+            (_, None, None) => {
+                header.ok_or_else(|| ValidationFileError::IsMissingHeader(name.to_string()))?
+            }
+        };
+
+        let header_projection = partial
+            .header_projection
+            .map(|projection| {
+                HeaderProjection::compile(projection).map_err(|reason| {
+                    ValidationFileError::InvalidHeaderProjection {
+                        validator: name.to_string(),
+                        reason,
+                    }
+                })
+            })
+            .transpose()?;
 
         Ok(Self {
-            header: partial
-                .header
-                .ok_or_else(|| ValidationFileError::IsMissingHeader(name.to_string()))?,
-            header_prefix: partial.header_prefix.filter(|s| !s.is_empty()),
-            authority: partial
-                .authority
-                .ok_or_else(|| ValidationFileError::IsMissingAuthority(name.to_string()))?,
-            required_claims: partial
-                .required_claims
-                .iter()
-                .map(|rc| match rc {
-                    PartialRequiredClaim::Complex { name, value } => RequiredClaim {
-                        name: name.clone(),
-                        value: match value {
-                            Some(value) => RequiredClaimValue::Single(value.clone()),
-                            None => RequiredClaimValue::None,
-                        },
-                    },
-                    PartialRequiredClaim::ComplexMultiple { name, values } => RequiredClaim {
-                        name: name.clone(),
-                        value: if values.is_empty() {
-                            RequiredClaimValue::None
-                        } else if values.len() == 1 {
-                            RequiredClaimValue::Single(values[0].clone())
-                        } else {
-                            RequiredClaimValue::Multiple(values.clone())
-                        },
-                    },
-                    PartialRequiredClaim::Simple(name) => RequiredClaim {
-                        name: name.clone(),
-                        value: RequiredClaimValue::None,
-                    },
+            source,
+            enrich: partial.enrich.unwrap_or_default(),
+            authorities: match (partial.authority, partial.issuers) {
+                (Some(authority), issuers) if issuers.is_empty() => Authorities::Single(authority),
+                (None, issuers) if !issuers.is_empty() => Authorities::ByIssuer(issuers),
+                (None, _) => return Err(ValidationFileError::IsMissingAuthority(name.to_string())),
+                (Some(_), _) => {
+                    return Err(ValidationFileError::AuthorityAndIssuers(name.to_string()))
+                }
+            },
+            plan: Plan::new(
+                partial
+                    .required_claims
+                    .into_iter()
+                    .chain(partial.required_scopes.map(scope_requirement))
+                    .chain(
+                        partial
+                            .required_realm_roles
+                            .map(|roles| roles_requirement(REALM_ROLES_CLAIM.to_string(), roles)),
+                    )
+                    .chain(partial.required_client_roles.into_iter().flatten().map(
+                        |(client, roles)| roles_requirement(client_roles_claim(&client), roles),
+                    ))
+                    .map(|rc| compile_requirement(name, rc, false))
+                    .collect::<Result<Vec<_>, _>>()?,
+                partial
+                    .map_claims
+                    .into_iter()
+                    .map(|(k, v)| (k, v, false))
+                    .chain(
+                        partial
+                            .map_scopes
+                            .map(|header| (SCOPE_CLAIM.to_string(), header, true)),
+                    )
+                    .chain(
+                        partial
+                            .map_realm_roles
+                            .map(|header| (REALM_ROLES_CLAIM.to_string(), header, false)),
+                    )
+                    .chain(
+                        partial
+                            .map_client_roles
+                            .into_iter()
+                            .flatten()
+                            .map(|(client, header)| (client_roles_claim(&client), header, false)),
+                    )
+                    .map(|(k, v, space_delimited)| {
+                        let header = match HeaderName::from_str(&v) {
+                            Ok(header) => match &header_projection {
+                                Some(projection) => projection.name(header),
+                                None => header,
+                            },
+                            Err(_) => {
+                                return Err(ValidationFileError::InvalidHeaderName {
+                                    validator: name.to_string(),
+                                    claim: k,
+                                    header: v,
+                                })
+                            }
+                        };
+                        // Such headers are dropped by intermediaries or overwritten by the proxy
+                        if projection::is_reserved(&header) {
+                            return Err(ValidationFileError::ReservedHeaderName {
+                                validator: name.to_string(),
+                                claim: k,
+                                header: header.to_string(),
+                            });
+                        }
+                        Ok(ClaimMapping {
+                            claim: Claim::new(&k),
+                            header,
+                            space_delimited,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                partial
+                    .map_cookies
+                    .into_iter()
+                    .map(|(claim, cookie)| {
+                        CookieMapping::compile(&claim, cookie).map_err(|reason| {
+                            ValidationFileError::InvalidCookie {
+                                validator: name.to_string(),
+                                claim,
+                                reason,
+                            }
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            policy: partial
+                .policy
+                .map(|policy| {
+                    Policy::compile(&policy).map_err(|reason| ValidationFileError::InvalidPolicy {
+                        validator: name.to_string(),
+                        reason,
+                    })
                 })
-                .collect(),
-            map_claims: partial
-                .map_claims
+                .transpose()?,
+            opa: partial.opa.map(|opa| opa_config(name, opa)).transpose()?,
+            subjects: SubjectFilter::new(allowed_subjects, denied_subjects.unwrap_or_default()),
+            transport: partial
+                .transport
+                .map(|transport| TransportPolicy::from_config(name, transport))
+                .transpose()?,
+            outage: partial.outage_policy.unwrap_or_default(),
+            cache_max_age: partial.cache_max_age,
+            stale_while_revalidate: partial
+                .stale_while_revalidate_seconds
+                .map(Duration::from_secs),
+            header_limits: partial.header_limits.map(HeaderLimits::from_config),
+            header_projection,
+            compare: partial.compare_with.map(|compare| {
+                let claims = compare.claims.unwrap_or_else(|| vec!["sub".to_string()]);
+                (
+                    compare.authority,
+                    claims.iter().map(|claim| Claim::new(claim)).collect(),
+                )
+            }),
+            echo_headers: partial
+                .echo_headers
+                .unwrap_or_default()
                 .into_iter()
-                .map(|(k, v)| match HeaderName::from_str(&v) {
-                    Ok(v) => Ok((k, v)),
-                    Err(_) => Err(ValidationFileError::InvalidHeaderName {
-                        validator: name.to_string(),
-                        claim: k,
-                        header: v,
-                    }),
+                .map(|header| {
+                    HeaderName::from_str(&header).map_err(|_| {
+                        ValidationFileError::InvalidEchoHeader {
+                            validator: name.to_string(),
+                            header,
+                        }
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            success: partial
+                .success
+                .map(|success| {
+                    SuccessResponse::compile(success).map_err(|reason| {
+                        ValidationFileError::InvalidSuccessResponse {
+                            validator: name.to_string(),
+                            reason,
+                        }
+                    })
+                })
+                .transpose()?,
+            body_template: partial
+                .body_template
+                .map(|template| {
+                    BodyTemplate::compile(template).map_err(|reason| {
+                        ValidationFileError::InvalidBodyTemplate {
+                            validator: name.to_string(),
+                            reason,
+                        }
+                    })
+                })
+                .transpose()?,
+            realm: realm(name, partial.realm)?,
+            login_redirect: partial
+                .login_redirect
+                .map(|login| {
+                    LoginRedirect::compile(login).map_err(|reason| {
+                        ValidationFileError::InvalidLoginRedirect {
+                            validator: name.to_string(),
+                            reason,
+                        }
+                    })
                 })
-                .collect::<Result<HashMap<_, _>, _>>()?,
+                .transpose()?,
         })
     }
+
+    /// Reject options the proxy of the profile doesn't support
+    fn check_profile(&self, name: &str, profile: Profile) -> Result<(), ValidationFileError> {
+        let unsupported = |option| ValidationFileError::UnsupportedByProfile {
+            validator: name.to_string(),
+            profile: profile.as_str(),
+            option,
+        };
+
+        if self.login_redirect.is_some() && !profile.allows_redirects() {
+            return Err(unsupported("login_redirect"));
+        }
+        if let (Some(success), Some(status)) = (&self.success, profile.success_status()) {
+            if success.status() != status {
+                return Err(unsupported("success statuses other than 200"));
+            }
+        }
+        Ok(())
+    }
 }
 
-impl JWTAuthority {
-    pub fn to_validator(&self) -> CoreValidator {
-        let mut core_validator =
-            CoreValidator::default().with_leeway_secs(self.leeway_seconds.unwrap_or(0));
+impl DenyResponse {
+    fn from_partial(name: &str, partial: PartialJWTValidator) -> Result<Self, ValidationFileError> {
+        Ok(Self {
+            status: error_status(name, "deny_status", partial.deny_status)?,
+            message: partial
+                .deny_message
+                .unwrap_or_else(|| "Access denied".to_string()),
+        })
+    }
+}
+
+impl TransportPolicy {
+    fn from_config(name: &str, transport: TransportConfig) -> Result<Self, ValidationFileError> {
+        Ok(Self {
+            require_https: transport.require_https,
+            allowed_ports: transport.allowed_ports,
+            status: error_status(name, "transport status", transport.status)?,
+        })
+    }
+}
+
+/// The realm of the validator's challenges, defaults to the name of the validator
+fn realm(validator: &str, realm: Option<String>) -> Result<String, ValidationFileError> {
+    let realm = realm.unwrap_or_else(|| validator.to_string());
+    // Sent as a quoted string, without escapes
+    if realm
+        .chars()
+        .any(|c| c == '"' || c == '\\' || !(' '..='~').contains(&c))
+    {
+        return Err(ValidationFileError::InvalidRealm {
+            validator: validator.to_string(),
+            realm,
+        });
+    }
+    Ok(realm)
+}
+
+/// Rejection messages by language and reason
+type MessageCatalog = HashMap<String, HashMap<Reason, String>>;
+
+fn load_catalog(path: &Path) -> Result<MessageCatalog, ValidationFileError> {
+    fs::read(path)
+        .and_then(|content| {
+            serde_yaml::from_slice(&content)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .map_err(|error| ValidationFileError::MessageCatalog {
+            path: path.to_path_buf(),
+            error,
+        })
+}
+
+/// The messages of the validator's language, overridden by its own messages
+fn messages(
+    validator: &str,
+    partial: &PartialJWTValidator,
+    catalog: &MessageCatalog,
+) -> Result<Messages, ValidationFileError> {
+    let mut texts =
+        match &partial.language {
+            Some(language) => catalog.get(language).cloned().ok_or_else(|| {
+                ValidationFileError::UnknownLanguage {
+                    validator: validator.to_string(),
+                    language: language.clone(),
+                }
+            })?,
+            None => HashMap::new(),
+        };
+    // An explicit deny message is more specific than the catalog
+    if partial.deny_message.is_some() {
+        texts.remove(&Reason::Denied);
+    }
+    texts.extend(partial.messages.clone());
+
+    Ok(Messages::new(partial.language.as_deref(), texts))
+}
+
+/// Parse a configured rejection status, defaulting to 403
+fn error_status(
+    validator: &str,
+    option: &'static str,
+    status: Option<u16>,
+) -> Result<StatusCode, ValidationFileError> {
+    match status {
+        Some(status) => StatusCode::from_u16(status)
+            .ok()
+            // Anything else would let the request through the proxy
+            .filter(|s| s.is_client_error() || s.is_server_error())
+            .ok_or_else(|| ValidationFileError::InvalidStatus {
+                validator: validator.to_string(),
+                option,
+                status,
+            }),
+        None => Ok(StatusCode::FORBIDDEN),
+    }
+}
+
+/// One entry of `token_sources`, which has to name exactly one place to read the token from
+fn token_source(
+    validator: &str,
+    index: usize,
+    source: TokenSourceConfig,
+) -> Result<TokenSource, ValidationFileError> {
+    let invalid = |reason| ValidationFileError::InvalidTokenSource {
+        validator: validator.to_string(),
+        index,
+        reason,
+    };
+
+    if source.header.is_none() && (source.prefix.is_some() || source.require_prefix.is_some()) {
+        return Err(invalid("prefix and require_prefix only apply to headers"));
+    }
+
+    match (source.header, source.cookie, source.query_param) {
+        (Some(header), None, None) => {
+            token_header(validator, header, source.prefix, source.require_prefix)
+        }
+        (None, Some(cookie), None) => token_cookie(validator, cookie),
+        (None, None, Some(param)) => Ok(TokenSource::Query { param }),
+        _ => Err(invalid(
+            "needs exactly one of header, cookie and query_param",
+        )),
+    }
+}
+
+fn token_header(
+    validator: &str,
+    header: String,
+    prefixes: Option<Prefixes>,
+    require_prefix: Option<bool>,
+) -> Result<TokenSource, ValidationFileError> {
+    let name =
+        HeaderName::from_str(&header).map_err(|_| ValidationFileError::InvalidTokenHeader {
+            validator: validator.to_string(),
+            header,
+        })?;
+
+    Ok(TokenSource::Header {
+        name,
+        prefixes: prefixes
+            .map(Prefixes::into_vec)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| !s.trim().is_empty())
+            .collect(),
+        require_prefix: require_prefix.unwrap_or(false),
+    })
+}
+
+fn token_cookie(validator: &str, cookie: String) -> Result<TokenSource, ValidationFileError> {
+    if cookie.is_empty() || !cookie.bytes().all(cookies::is_token) {
+        return Err(ValidationFileError::InvalidTokenCookie {
+            validator: validator.to_string(),
+            cookie,
+        });
+    }
+
+    Ok(TokenSource::Cookie { name: cookie })
+}
+
+/// Check the URL of the policy, it has to be an `http` or `https` URL
+fn opa_config(validator: &str, opa: OpaConfig) -> Result<OpaConfig, ValidationFileError> {
+    match reqwest::Url::parse(&opa.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(opa),
+        _ => Err(ValidationFileError::InvalidOpaUrl {
+            validator: validator.to_string(),
+            url: opa.url,
+        }),
+    }
+}
+
+/// Combine a list of subjects with the subjects of a file, `None` if neither is given
+///
+/// The file contains one subject per line, empty lines and lines starting with `#` are ignored.
+fn load_subjects(
+    validator: &str,
+    subjects: Option<Vec<String>>,
+    file: Option<PathBuf>,
+) -> Result<Option<HashSet<String>>, ValidationFileError> {
+    if subjects.is_none() && file.is_none() {
+        return Ok(None);
+    }
+
+    let mut res = subjects.into_iter().flatten().collect::<HashSet<_>>();
+
+    if let Some(path) = file {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) => {
+                return Err(ValidationFileError::SubjectFile {
+                    validator: validator.to_string(),
+                    path,
+                    error,
+                })
+            }
+        };
+
+        res.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+    }
+
+    Ok(Some(res))
+}
+
+/// The requirement on the `scope` claim of `required_scopes`
+fn scope_requirement(scopes: Vec<String>) -> file::RequiredClaim {
+    file::RequiredClaim::Complex {
+        name: SCOPE_CLAIM.to_string(),
+        severity: None,
+        matchers: BTreeMap::from([(
+            "scopes".to_string(),
+            serde_yaml::Value::Sequence(scopes.into_iter().map(Into::into).collect()),
+        )]),
+    }
+}
+
+/// The claim Keycloak lists the roles of a token for a client in, as a JSON pointer since client
+/// ids may contain dots
+fn client_roles_claim(client: &str) -> String {
+    format!(
+        "/resource_access/{}/roles",
+        client.replace('~', "~0").replace('/', "~1")
+    )
+}
+
+/// The requirement on a Keycloak roles claim of `required_realm_roles` and `required_client_roles`
+fn roles_requirement(claim: String, roles: Vec<String>) -> file::RequiredClaim {
+    file::RequiredClaim::Complex {
+        name: claim,
+        severity: None,
+        matchers: BTreeMap::from([(
+            "contains_all".to_string(),
+            serde_yaml::Value::Sequence(roles.into_iter().map(Into::into).collect()),
+        )]),
+    }
+}
+
+/// Compile a required claim or group, `grouped` if it is part of a group
+fn compile_requirement(
+    validator: &str,
+    claim: file::RequiredClaim,
+    grouped: bool,
+) -> Result<Requirement, ValidationFileError> {
+    use file::RequiredClaim as PartialRequiredClaim;
 
-        if self.check_expiration.unwrap_or(true) {
-            core_validator = core_validator.check_expiration();
-        } else {
-            core_validator = core_validator.ignore_expiration();
+    let invalid_group = |reason| ValidationFileError::InvalidRequirementGroup {
+        validator: validator.to_string(),
+        reason,
+    };
+    let (name, severity, matchers) = match claim {
+        PartialRequiredClaim::Complex {
+            severity: Some(_), ..
+        } if grouped => {
+            return Err(invalid_group(
+                "only groups can have a severity, not the requirements in them",
+            ))
+        }
+        PartialRequiredClaim::Complex {
+            name,
+            severity,
+            matchers,
+        } => (name, severity.unwrap_or_default(), matchers),
+        PartialRequiredClaim::Simple(name) => (name, Severity::Reject, Default::default()),
+        PartialRequiredClaim::AnyOf { any_of, severity } => {
+            return compile_group(validator, Operator::AnyOf, any_of, severity, grouped)
         }
+        PartialRequiredClaim::AllOf { all_of, severity } => {
+            return compile_group(validator, Operator::AllOf, all_of, severity, grouped)
+        }
+    };
 
-        if self.check_not_before.unwrap_or(true) {
-            core_validator = core_validator.check_not_before();
-        } else {
-            core_validator = core_validator.ignore_not_before();
+    let mut checks = Vec::with_capacity(matchers.len());
+    for (matcher, args) in matchers {
+        match checks::compile(&matcher, args) {
+            Ok(Some(check)) => checks.push(check),
+            Ok(None) => {}
+            Err(CompileError::UnknownMatcher) => {
+                return Err(ValidationFileError::UnknownMatcher {
+                    validator: validator.to_string(),
+                    claim: name,
+                    matcher,
+                })
+            }
+            Err(CompileError::InvalidArguments(error)) => {
+                return Err(ValidationFileError::InvalidMatcher {
+                    validator: validator.to_string(),
+                    claim: name,
+                    matcher,
+                    error,
+                })
+            }
         }
+    }
 
-        core_validator =
-            core_validator.extend_approved_algorithms(self.approved_algorithms.iter().cloned());
+    Ok(Requirement::Claim(ClaimRequirement {
+        claim: Claim::new(&name),
+        checks,
+        severity,
+    }))
+}
 
-        core_validator
+fn compile_group(
+    validator: &str,
+    operator: Operator,
+    requirements: Vec<file::RequiredClaim>,
+    severity: Option<Severity>,
+    grouped: bool,
+) -> Result<Requirement, ValidationFileError> {
+    let invalid_group = |reason| ValidationFileError::InvalidRequirementGroup {
+        validator: validator.to_string(),
+        reason,
+    };
+    if requirements.is_empty() {
+        return Err(invalid_group("groups need at least one requirement"));
     }
+    if severity.is_some() && grouped {
+        return Err(invalid_group(
+            "only groups can have a severity, not the requirements in them",
+        ));
+    }
+
+    Ok(Requirement::Group(RequirementGroup {
+        operator,
+        requirements: requirements
+            .into_iter()
+            .map(|requirement| compile_requirement(validator, requirement, true))
+            .collect::<Result<_, _>>()?,
+        severity: severity.unwrap_or_default(),
+    }))
 }
 
-impl RequiredClaimValue {
-    pub fn matches(&self, value: &str) -> bool {
-        match self {
-            Self::None => true,
-            Self::Single(single) => single == value,
-            Self::Multiple(multiple) => multiple.iter().any(|v| v == value),
+impl HttpClientConfig {
+    /// Merge the settings with overrides, the overrides take precedence
+    pub fn merge(&self, overrides: &Self) -> Self {
+        Self {
+            timeout_seconds: overrides.timeout_seconds.or(self.timeout_seconds),
+            proxy: overrides.proxy.clone().or_else(|| self.proxy.clone()),
+            ca_file: overrides.ca_file.clone().or_else(|| self.ca_file.clone()),
+            accept_invalid_certs: overrides.accept_invalid_certs.or(self.accept_invalid_certs),
         }
     }
+
+    pub fn build(&self) -> Result<reqwest::Client, HttpClientError> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(timeout) = self.timeout_seconds {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+
+        if let Some(ca_file) = &self.ca_file {
+            let pem = fs::read(ca_file)?;
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if self.accept_invalid_certs.unwrap_or(false) {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+impl KeyFilter {
+    /// Loads every key regardless of its use
+    const ANY_USE: &'static str = "any";
+    const DEFAULT_MAX_DOCUMENT_BYTES: u64 = 1024 * 1024;
+    const DEFAULT_MAX_KEYS: usize = 100;
+
+    fn default_use() -> String {
+        "sig".to_string()
+    }
+
+    pub fn max_document_bytes(&self) -> u64 {
+        self.max_document_bytes
+            .unwrap_or(Self::DEFAULT_MAX_DOCUMENT_BYTES)
+    }
+
+    pub fn max_keys(&self) -> usize {
+        self.max_keys.unwrap_or(Self::DEFAULT_MAX_KEYS)
+    }
+
+    /// Whether a key (as it appears in the JWKS document) may be used to verify tokens
+    pub fn accepts(&self, key: &serde_json::Value) -> bool {
+        let key_use = key.get("use").and_then(|v| v.as_str());
+        let use_accepted =
+            self.key_use == Self::ANY_USE || key_use.is_none_or(|key_use| key_use == self.key_use);
+
+        let ops_accepted = match (&self.key_ops, key.get("key_ops").and_then(|v| v.as_array())) {
+            (Some(allowed), Some(ops)) => ops
+                .iter()
+                .filter_map(|op| op.as_str())
+                .any(|op| allowed.iter().any(|allowed| allowed == op)),
+            _ => true,
+        };
+
+        use_accepted && ops_accepted
+    }
 }
 
-impl fmt::Display for RequiredClaimValue {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Default for KeyFilter {
+    fn default() -> Self {
+        Self {
+            key_use: Self::default_use(),
+            key_ops: None,
+            max_document_bytes: None,
+            max_keys: None,
+            certificates: None,
+        }
+    }
+}
+
+impl OutagePolicy {
+    /// The name of the policy, as used by the metrics
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Self::None => write!(f, "None"),
-            Self::Single(single) => write!(f, "{}", single),
-            Self::Multiple(multiple) => {
-                write!(f, "[")?;
-                for (i, v) in multiple.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}", v)?;
-                }
-                write!(f, "]")
-            }
+            OutagePolicy::FailClosed => "fail-closed",
+            OutagePolicy::FailOpen => "fail-open",
+            OutagePolicy::DegradeToShadow => "degrade-to-shadow",
+        }
+    }
+}
+
+impl JWTAuthority {
+    /// The HTTP client settings of the authority, given the global settings
+    pub fn http_config(&self, global: &HttpClientConfig) -> HttpClientConfig {
+        match &self.http {
+            Some(overrides) => global.merge(overrides),
+            None => global.clone(),
+        }
+    }
+
+    pub fn key_filter(&self) -> KeyFilter {
+        self.keys.clone().unwrap_or_default()
+    }
+
+    pub fn to_validator(&self) -> Verifier {
+        // The lifetime is checked by the authority, against the clock of the service
+        let core_validator = CoreValidator::default()
+            .ignore_expiration()
+            .ignore_not_before()
+            .extend_approved_algorithms(self.approved_algorithms.iter().cloned());
+
+        Verifier {
+            core_validator,
+            lifetime: Lifetime {
+                check_expiration: self.check_expiration.unwrap_or(true),
+                check_not_before: self.check_not_before.unwrap_or(true),
+                leeway: self.leeway_seconds.unwrap_or(0),
+            },
         }
     }
 }
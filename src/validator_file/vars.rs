@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use serde_yaml::{Mapping, Value};
+
+use super::ValidationFileError;
+
+/// The top level key defining the variables of a configuration file
+const VARS_KEY: &str = "vars";
+
+/// Replace the `${name}` references of a configuration file with the values of its `vars`
+///
+/// References are replaced in every string and mapping key of the document, so they can be used
+/// in authority URLs, audiences and header names alike. Variables may reference other variables,
+/// `$${` is kept as a literal `${`. The `vars` section itself is removed.
+pub fn substitute(document: &mut Value) -> Result<(), ValidationFileError> {
+    let Some(root) = document.as_mapping_mut() else {
+        return Ok(());
+    };
+    let Some(vars) = root.remove(VARS_KEY) else {
+        return Ok(());
+    };
+
+    let definitions = definitions(vars)?;
+    let mut resolved = HashMap::new();
+    for name in definitions.keys() {
+        resolve(name, &definitions, &mut resolved, &mut Vec::new())?;
+    }
+
+    substitute_value(document, "", &resolved)
+}
+
+/// The raw values of the variables, scalars are used as they are written
+fn definitions(vars: Value) -> Result<HashMap<String, String>, ValidationFileError> {
+    let Value::Mapping(vars) = vars else {
+        return Err(ValidationFileError::InvalidVariable {
+            name: VARS_KEY.to_string(),
+            reason: "has to be a mapping of names to values",
+        });
+    };
+
+    vars.into_iter()
+        .map(|(name, value)| {
+            let Value::String(name) = name else {
+                return Err(ValidationFileError::InvalidVariable {
+                    name: serde_yaml::to_string(&name)
+                        .map(|s| s.trim().to_string())
+                        .unwrap_or_default(),
+                    reason: "the name has to be a string",
+                });
+            };
+            let value = match value {
+                Value::String(s) => s,
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => {
+                    return Err(ValidationFileError::InvalidVariable {
+                        name,
+                        reason: "the value has to be a string, number or boolean",
+                    })
+                }
+            };
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Resolve a variable, and the variables it references, depth-first
+fn resolve(
+    name: &str,
+    definitions: &HashMap<String, String>,
+    resolved: &mut HashMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<(), ValidationFileError> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+    if stack.iter().any(|n| n == name) {
+        return Err(ValidationFileError::CircularVariable(name.to_string()));
+    }
+
+    stack.push(name.to_string());
+    let value = &definitions[name];
+    for reference in references(value) {
+        if !definitions.contains_key(reference) {
+            return Err(ValidationFileError::UnknownVariable {
+                path: format!("{}.{}", VARS_KEY, name),
+                name: reference.to_string(),
+            });
+        }
+        resolve(reference, definitions, resolved, stack)?;
+    }
+    stack.pop();
+
+    let value = replace(value, &format!("{}.{}", VARS_KEY, name), resolved)?;
+    resolved.insert(name.to_string(), value);
+    Ok(())
+}
+
+fn substitute_value(
+    value: &mut Value,
+    path: &str,
+    vars: &HashMap<String, String>,
+) -> Result<(), ValidationFileError> {
+    match value {
+        Value::String(s) => *s = replace(s, path, vars)?,
+        Value::Sequence(sequence) => {
+            for (i, value) in sequence.iter_mut().enumerate() {
+                substitute_value(value, &format!("{}[{}]", path, i), vars)?;
+            }
+        }
+        Value::Mapping(mapping) => substitute_mapping(mapping, path, vars)?,
+        Value::Tagged(tagged) => substitute_value(&mut tagged.value, path, vars)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn substitute_mapping(
+    mapping: &mut Mapping,
+    path: &str,
+    vars: &HashMap<String, String>,
+) -> Result<(), ValidationFileError> {
+    let mut substituted = Mapping::with_capacity(mapping.len());
+    for (mut key, mut value) in std::mem::take(mapping) {
+        let key_path = match &key {
+            Value::String(s) if path.is_empty() => s.clone(),
+            Value::String(s) => format!("{}.{}", path, s),
+            _ => path.to_string(),
+        };
+        substitute_value(&mut key, &key_path, vars)?;
+        substitute_value(&mut value, &key_path, vars)?;
+        substituted.insert(key, value);
+    }
+    *mapping = substituted;
+    Ok(())
+}
+
+/// Replace the references of a string, failing on unknown variables
+fn replace(
+    s: &str,
+    path: &str,
+    vars: &HashMap<String, String>,
+) -> Result<String, ValidationFileError> {
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        // `$${` escapes a reference
+        if rest[..start].ends_with('$') {
+            res.push_str(&rest[..start - 1]);
+            res.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let value = vars
+            .get(name)
+            .ok_or_else(|| ValidationFileError::UnknownVariable {
+                path: path.to_string(),
+                name: name.to_string(),
+            })?;
+
+        res.push_str(&rest[..start]);
+        res.push_str(value);
+        rest = &rest[start + 2 + len + 1..];
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// The names of the variables a string references
+fn references(s: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let escaped = rest[..start].ends_with('$');
+        rest = &rest[start + 2..];
+        if escaped {
+            continue;
+        }
+        let Some(len) = rest.find('}') else {
+            break;
+        };
+        names.push(&rest[..len]);
+        rest = &rest[len + 1..];
+    }
+    names
+}
@@ -0,0 +1,290 @@
+use arc_swap::ArcSwapOption;
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::metrics;
+use crate::utils::clock;
+use crate::validator_file::{AlertRule, AlertsConfig, JWTValidator};
+use crate::validators::reason::Reason;
+use crate::validators::validator::Labels;
+
+/// The window is tracked in this many buckets, the oldest one is dropped as the window moves on
+const BUCKETS: u64 = 10;
+/// How often to look for a newly configured alert while alerts are disabled
+const IDLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Rolling-window statistics of the decisions per validator, with alerts on the share of
+/// requests rejected for a reason
+///
+/// Catches failures affecting many requests at once (e.g. an identity provider rotating its keys
+/// without publishing them) before users report them.
+#[derive(Debug, Clone)]
+pub struct Anomalies {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    settings: ArcSwapOption<Settings>,
+    windows: DashMap<String, Window>,
+    /// Whether the alert of a validator and reason is firing
+    firing: Mutex<HashMap<(String, Reason), bool>>,
+}
+
+#[derive(Debug)]
+pub struct Settings {
+    pub window: Duration,
+    pub min_requests: u64,
+    pub rules: Vec<AlertRule>,
+    /// The webhook and the client used to call it
+    pub webhook: Option<(String, reqwest::Client)>,
+    /// The labels of the validators, so alerts can be routed to the team owning the validator
+    pub labels: HashMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    buckets: [Bucket; BUCKETS as usize],
+}
+
+#[derive(Debug, Default, Clone)]
+struct Bucket {
+    /// The time slot the counts belong to, counts of older slots are stale
+    slot: u64,
+    total: u64,
+    reasons: HashMap<Reason, u64>,
+}
+
+/// A change of an alert state, as sent to the webhook
+#[derive(Debug, Serialize)]
+struct AlertEvent<'a> {
+    status: &'static str,
+    validator: &'a str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: &'a BTreeMap<String, String>,
+    reason: Reason,
+    ratio: f64,
+    threshold: f64,
+    requests: u64,
+    window_seconds: u64,
+}
+
+impl Settings {
+    pub fn from_config(
+        config: &AlertsConfig,
+        validators: &HashMap<String, JWTValidator>,
+        client: &reqwest::Client,
+    ) -> Self {
+        Self {
+            window: Duration::from_secs(config.window_seconds.unwrap_or(300).max(BUCKETS)),
+            min_requests: config.min_requests.unwrap_or(20),
+            rules: config.rules.clone(),
+            webhook: config
+                .webhook
+                .as_ref()
+                .map(|url| (url.clone(), client.clone())),
+            labels: validators
+                .iter()
+                .filter(|(_, validator)| !validator.labels.is_empty())
+                .map(|(name, validator)| (name.clone(), validator.labels.clone()))
+                .collect(),
+        }
+    }
+
+    fn labels(&self, validator: &str) -> &BTreeMap<String, String> {
+        static NO_LABELS: BTreeMap<String, String> = BTreeMap::new();
+        self.labels.get(validator).unwrap_or(&NO_LABELS)
+    }
+
+    fn bucket_length(&self) -> u64 {
+        self.window.as_secs() / BUCKETS
+    }
+}
+
+impl Anomalies {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                settings: ArcSwapOption::empty(),
+                windows: DashMap::new(),
+                firing: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn configure(&self, settings: Option<Settings>) {
+        let window = |s: &Option<Arc<Settings>>| s.as_ref().map(|s| s.window);
+        let previous = self.inner.settings.swap(settings.map(Arc::new));
+        if window(&previous) != window(&self.inner.settings.load_full()) {
+            // The buckets of another window length cannot be reused
+            self.inner.windows.clear();
+        }
+    }
+
+    /// The time until the alerts should be evaluated again
+    pub fn interval(&self) -> Duration {
+        self.inner
+            .settings
+            .load()
+            .as_ref()
+            .map_or(IDLE_INTERVAL, |settings| {
+                Duration::from_secs(settings.bucket_length())
+            })
+    }
+
+    /// Count a decision of a validator, if alerts are configured
+    pub fn record(&self, validator: &str, reason: Reason) {
+        let Some(settings) = self.inner.settings.load_full() else {
+            return;
+        };
+        // Any path can be requested, only track the validators that exist
+        if reason == Reason::UnknownValidator {
+            return;
+        }
+
+        let slot = now() / settings.bucket_length();
+        let mut window = match self.inner.windows.get_mut(validator) {
+            Some(window) => window,
+            None => self.inner.windows.entry(validator.to_string()).or_default(),
+        };
+
+        let bucket = &mut window.buckets[(slot % BUCKETS) as usize];
+        if bucket.slot != slot {
+            *bucket = Bucket {
+                slot,
+                ..Default::default()
+            };
+        }
+        bucket.total += 1;
+        *bucket.reasons.entry(reason).or_default() += 1;
+    }
+
+    /// Evaluate the rules against the current window of every validator
+    ///
+    /// Changes of an alert state are logged and sent to the webhook.
+    pub fn evaluate(&self) {
+        let Some(settings) = self.inner.settings.load_full() else {
+            return;
+        };
+
+        let oldest = (now() / settings.bucket_length()).saturating_sub(BUCKETS - 1);
+        let mut firing = self.inner.firing.lock().expect("lock poisoned");
+        let mut evaluated = HashMap::new();
+        for window in self.inner.windows.iter() {
+            let validator = window.key();
+            let buckets = window.buckets.iter().filter(|b| b.slot >= oldest);
+            let (mut total, mut reasons) = (0, HashMap::<Reason, u64>::new());
+            for bucket in buckets {
+                total += bucket.total;
+                for (reason, count) in &bucket.reasons {
+                    *reasons.entry(*reason).or_default() += count;
+                }
+            }
+
+            for rule in &settings.rules {
+                let count = reasons.get(&rule.reason).copied().unwrap_or(0);
+                let ratio = match total {
+                    0 => 0.0,
+                    total => count as f64 / total as f64,
+                };
+                let fires = total >= settings.min_requests && ratio > rule.threshold;
+
+                metrics::FAILURE_RATIO
+                    .with_label_values(&[validator, rule.reason.as_str()])
+                    .set(ratio);
+                metrics::ALERTS_FIRING
+                    .with_label_values(&[validator, rule.reason.as_str()])
+                    .set(fires as i64);
+
+                let key = (validator.clone(), rule.reason);
+                let was_firing = firing.get(&key).copied().unwrap_or(false);
+                if fires != was_firing {
+                    let event = AlertEvent {
+                        status: if fires { "firing" } else { "resolved" },
+                        validator,
+                        labels: settings.labels(validator),
+                        reason: rule.reason,
+                        ratio,
+                        threshold: rule.threshold,
+                        requests: total,
+                        window_seconds: settings.window.as_secs(),
+                    };
+                    match fires {
+                        true => warn!(
+                            labels = %Labels(event.labels),
+                            "Validator {} answered {:.0}% of {} requests with {} within {:?}",
+                            validator,
+                            ratio * 100.0,
+                            total,
+                            rule.reason.as_str(),
+                            settings.window
+                        ),
+                        false => info!(
+                            labels = %Labels(event.labels),
+                            "Validator {} recovered from answering requests with {}",
+                            validator,
+                            rule.reason.as_str()
+                        ),
+                    }
+                    notify(&settings, &event);
+                }
+                evaluated.insert(key, fires);
+            }
+        }
+
+        // Forget the rules that were removed, resolving their alerts
+        for ((validator, reason), was_firing) in firing.iter() {
+            if evaluated.contains_key(&(validator.clone(), *reason)) {
+                continue;
+            }
+
+            let _ = metrics::FAILURE_RATIO.remove_label_values(&[validator, reason.as_str()]);
+            let _ = metrics::ALERTS_FIRING.remove_label_values(&[validator, reason.as_str()]);
+            if *was_firing {
+                notify(
+                    &settings,
+                    &AlertEvent {
+                        status: "resolved",
+                        validator,
+                        labels: settings.labels(validator),
+                        reason: *reason,
+                        ratio: 0.0,
+                        threshold: 0.0,
+                        requests: 0,
+                        window_seconds: settings.window.as_secs(),
+                    },
+                );
+            }
+        }
+        *firing = evaluated;
+
+        // Idle validators have been evaluated as such, their windows can be dropped now
+        self.inner
+            .windows
+            .retain(|_, window| window.buckets.iter().any(|b| b.slot >= oldest));
+    }
+}
+
+/// Send a change of an alert state to the webhook in the background
+fn notify(settings: &Settings, event: &AlertEvent<'_>) {
+    let Some((url, client)) = &settings.webhook else {
+        return;
+    };
+
+    let request = client.post(url).json(event);
+    let url = url.clone();
+    tokio::spawn(async move {
+        match request.send().await.and_then(|res| res.error_for_status()) {
+            Ok(_) => debug!("Sent alert to {}", url),
+            Err(e) => warn!("Failed to send alert to {}: {}", url, e),
+        }
+    });
+}
+
+fn now() -> u64 {
+    clock::unix_now().as_secs()
+}
@@ -1,16 +1,21 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use aliri::error::JwtVerifyError;
 use aliri::jwa::Algorithm;
 use aliri::jwt::{CoreHeaders, CoreValidator, HasAlgorithm};
 use aliri::{jwt, JwtRef};
 use arc_swap::ArcSwap;
+use serde::Serialize;
 
+use crate::utils::clock;
 use crate::validators::claims::JWTClaims;
 use crate::validators::jwks::JwksState;
+use crate::validators::phases::{Phase, Phases};
+use crate::validators::reason::Reason;
+use crate::validators::tenant::Tenant;
 
 #[derive(Debug, Clone)]
 pub struct Authority {
@@ -19,11 +24,29 @@ pub struct Authority {
 
 #[derive(Debug)]
 struct AuthorityInner {
-    #[allow(dead_code)]
     name: String,
+    tenant: Option<Tenant>,
     jwks: JwksState,
-    core_validator: CoreValidator,
+    verifier: Verifier,
     update_interval: Duration,
+    blocking_refresh: bool,
+    max_staleness: Option<Duration>,
+}
+
+/// How the tokens of an authority are verified
+#[derive(Debug)]
+pub struct Verifier {
+    /// Verifies the signature and the approved algorithms, never the lifetime
+    pub core_validator: CoreValidator,
+    pub lifetime: Lifetime,
+}
+
+/// Which lifetime claims are checked, against the clock of the service
+#[derive(Debug, Clone, Copy)]
+pub struct Lifetime {
+    pub check_expiration: bool,
+    pub check_not_before: bool,
+    pub leeway: u64,
 }
 
 #[derive(Debug)]
@@ -31,6 +54,14 @@ pub struct AuthorityStore {
     states: ArcSwap<HashMap<String, Authority>>,
 }
 
+/// The key a token is verified with, as reported in explain mode
+#[derive(Debug, Serialize)]
+pub struct SelectedKey {
+    pub kid: Option<String>,
+    pub alg: String,
+    pub found: bool,
+}
+
 #[derive(Debug)]
 pub enum AuthorityError {
     MissingKey {
@@ -38,25 +69,51 @@ pub enum AuthorityError {
         alg: Algorithm,
     },
     JwtVerifyError(JwtVerifyError),
+    /// The token has no `exp` claim, although its expiration is checked
+    MissingExpiration,
+    Expired {
+        exp: u64,
+    },
+    /// The token has no `nbf` claim, although its start of validity is checked
+    MissingNotBefore,
+    NotYetValid {
+        nbf: u64,
+    },
 }
 
 impl Authority {
     pub fn new(
         name: String,
+        tenant: Option<Tenant>,
         jwks: JwksState,
-        core_validator: CoreValidator,
+        verifier: Verifier,
         update_interval: Duration,
+        blocking_refresh: bool,
+        max_staleness: Option<Duration>,
     ) -> Self {
         let inner = Arc::new(AuthorityInner {
             name,
+            tenant,
             jwks,
-            core_validator,
+            verifier,
             update_interval,
+            blocking_refresh,
+            max_staleness,
         });
 
         Self { inner }
     }
 
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    #[inline]
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.inner.tenant.as_ref()
+    }
+
     #[inline]
     pub fn jwks(&self) -> &JwksState {
         &self.inner.jwks
@@ -64,7 +121,7 @@ impl Authority {
 
     #[inline]
     pub fn core_validator(&self) -> &CoreValidator {
-        &self.inner.core_validator
+        &self.inner.verifier.core_validator
     }
 
     #[inline]
@@ -72,7 +129,30 @@ impl Authority {
         self.inner.update_interval
     }
 
-    pub fn validate(&self, token: &JwtRef) -> Result<JWTClaims, AuthorityError> {
+    /// Whether requests should wait for a stale JWKS to be refreshed before validating
+    #[inline]
+    pub fn blocking_refresh(&self) -> bool {
+        self.inner.blocking_refresh
+    }
+
+    /// Whether the identity provider is considered unavailable
+    ///
+    /// That is the case if the last refresh of the JWKS failed and the keys weren't refreshed
+    /// within the maximum staleness. Without a maximum staleness, stale keys are used forever.
+    pub fn outage(&self) -> bool {
+        let Some(max_staleness) = self.inner.max_staleness else {
+            return false;
+        };
+        if self.jwks().consecutive_failures() == 0 {
+            return false;
+        }
+
+        clock::elapsed(self.jwks().last_refresh())
+            .map_or(true, |elapsed| elapsed > max_staleness)
+    }
+
+    pub fn validate(&self, token: &JwtRef, phases: Phases) -> Result<JWTClaims, AuthorityError> {
+        let started = Instant::now();
         let decomposed = token.decompose()?;
 
         let validated: jwt::Validated<JWTClaims>;
@@ -89,14 +169,33 @@ impl Authority {
                         alg,
                     })?
             };
+            phases.observe(Phase::KeyLookup, started);
 
-            validated = decomposed.verify(key, self.core_validator())?;
+            // Rejected signatures take as long to check, so they're recorded as well
+            let started = Instant::now();
+            let verified = decomposed.verify(key, self.core_validator());
+            phases.observe(Phase::SignatureVerification, started);
+            validated = verified?;
         }
 
         let (_, validated_claims) = validated.extract();
+        self.inner.verifier.lifetime.check(&validated_claims)?;
 
         Ok(validated_claims)
     }
+
+    /// The key the token would be verified with, `None` if the token cannot be decomposed
+    pub fn selected_key(&self, token: &JwtRef) -> Option<SelectedKey> {
+        let decomposed: jwt::Decomposed = token.decompose().ok()?;
+        let kid = decomposed.kid();
+        let alg = decomposed.alg();
+
+        Some(SelectedKey {
+            kid: kid.map(|s| s.as_str().to_string()),
+            alg: alg.to_string(),
+            found: self.jwks().jwks().get_key_by_opt(kid, alg).is_some(),
+        })
+    }
 }
 
 impl AuthorityStore {
@@ -118,11 +217,64 @@ impl AuthorityStore {
         self.states.load().keys().cloned().collect()
     }
 
+    /// The JWKS of all authorities, authorities sharing a JWKS URL share its state
+    pub fn jwks(&self) -> Vec<JwksState> {
+        let mut jwks = HashMap::new();
+        for authority in self.states.load().values() {
+            jwks.entry(authority.jwks().uri().to_string())
+                .or_insert_with(|| authority.jwks().clone());
+        }
+
+        jwks.into_values().collect()
+    }
+
     pub fn clear(&self) {
         self.states.store(Arc::new(HashMap::new()));
     }
 }
 
+impl Lifetime {
+    /// Check the lifetime of a token
+    ///
+    /// Done here instead of by the core validator, so the time is read through the clock of the
+    /// service and the configured skew applies. Like the core validator did, tokens without an
+    /// `exp` or `nbf` are rejected if the claim is checked.
+    fn check(&self, claims: &JWTClaims) -> Result<(), AuthorityError> {
+        let now = clock::unix_now().as_secs();
+
+        if self.check_expiration {
+            let exp = claims.exp.ok_or(AuthorityError::MissingExpiration)?;
+            if exp.0.saturating_add(self.leeway) <= now {
+                return Err(AuthorityError::Expired { exp: exp.0 });
+            }
+        }
+        if self.check_not_before {
+            let nbf = claims.nbf.ok_or(AuthorityError::MissingNotBefore)?;
+            if nbf.0 > now.saturating_add(self.leeway) {
+                return Err(AuthorityError::NotYetValid { nbf: nbf.0 });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl AuthorityError {
+    /// The reason code a token is rejected with
+    pub fn reason(&self) -> Reason {
+        match self {
+            Self::MissingKey { .. } => Reason::UnknownKid,
+            Self::JwtVerifyError(JwtVerifyError::JwkVerifyError(_)) => Reason::Signature,
+            Self::JwtVerifyError(JwtVerifyError::ClaimsRejected(_)) => Reason::RegisteredClaims,
+            Self::JwtVerifyError(_) => Reason::Malformed,
+            Self::MissingExpiration
+            | Self::Expired { .. }
+            | Self::MissingNotBefore
+            | Self::NotYetValid { .. } => Reason::RegisteredClaims,
+        }
+    }
+}
+
 impl fmt::Display for AuthorityError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -146,6 +298,10 @@ impl fmt::Display for AuthorityError {
                 }
 
             }
+            Self::MissingExpiration => write!(f, "token has no expiration (exp)"),
+            Self::Expired { exp } => write!(f, "token expired at {}", exp),
+            Self::MissingNotBefore => write!(f, "token has no start of validity (nbf)"),
+            Self::NotYetValid { nbf } => write!(f, "token not valid before {}", nbf),
         }
     }
 }
@@ -155,6 +311,10 @@ impl std::error::Error for AuthorityError {
         match self {
             Self::MissingKey { .. } => None,
             Self::JwtVerifyError(err) => Some(err),
+            Self::MissingExpiration
+            | Self::Expired { .. }
+            | Self::MissingNotBefore
+            | Self::NotYetValid { .. } => None,
         }
     }
 }
@@ -164,3 +324,92 @@ impl From<JwtVerifyError> for AuthorityError {
         Self::JwtVerifyError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::FixedClock;
+    use serde_json::json;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn claims(claims: serde_json::Value) -> JWTClaims {
+        serde_json::from_value(claims).expect("claims should deserialize")
+    }
+
+    fn lifetime(leeway: u64) -> Lifetime {
+        Lifetime {
+            check_expiration: true,
+            check_not_before: true,
+            leeway,
+        }
+    }
+
+    #[test]
+    fn rejects_missing_expiration() {
+        clock::install(FixedClock::at(NOW));
+
+        let res = lifetime(0).check(&claims(json!({ "sub": "alice", "nbf": NOW })));
+        assert!(matches!(res, Err(AuthorityError::MissingExpiration)));
+    }
+
+    #[test]
+    fn accepts_missing_expiration_if_unchecked() {
+        clock::install(FixedClock::at(NOW));
+        let lifetime = Lifetime {
+            check_expiration: false,
+            ..lifetime(0)
+        };
+
+        assert!(lifetime.check(&claims(json!({ "sub": "alice", "nbf": NOW }))).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_not_before() {
+        clock::install(FixedClock::at(NOW));
+
+        let res = lifetime(0).check(&claims(json!({ "exp": NOW + 600 })));
+        assert!(matches!(res, Err(AuthorityError::MissingNotBefore)));
+    }
+
+    #[test]
+    fn accepts_missing_not_before_if_unchecked() {
+        clock::install(FixedClock::at(NOW));
+        let lifetime = Lifetime {
+            check_not_before: false,
+            ..lifetime(0)
+        };
+
+        assert!(lifetime.check(&claims(json!({ "exp": NOW + 600 }))).is_ok());
+    }
+
+    #[test]
+    fn rejects_expired() {
+        clock::install(FixedClock::at(NOW));
+
+        assert!(lifetime(0).check(&claims(json!({ "exp": NOW + 1, "nbf": NOW }))).is_ok());
+        let res = lifetime(0).check(&claims(json!({ "exp": NOW, "nbf": NOW })));
+        assert!(matches!(res, Err(AuthorityError::Expired { exp: NOW })));
+    }
+
+    #[test]
+    fn accepts_expired_within_leeway() {
+        clock::install(FixedClock::at(NOW));
+
+        assert!(lifetime(60).check(&claims(json!({ "exp": NOW - 59, "nbf": NOW - 600 }))).is_ok());
+        let res = lifetime(60).check(&claims(json!({ "exp": NOW - 60, "nbf": NOW - 600 })));
+        assert!(matches!(res, Err(AuthorityError::Expired { .. })));
+    }
+
+    #[test]
+    fn rejects_not_yet_valid() {
+        clock::install(FixedClock::at(NOW));
+
+        let token = claims(json!({ "exp": NOW + 600, "nbf": NOW + 30 }));
+        assert!(matches!(
+            lifetime(0).check(&token),
+            Err(AuthorityError::NotYetValid { nbf }) if nbf == NOW + 30
+        ));
+        assert!(lifetime(30).check(&token).is_ok());
+    }
+}
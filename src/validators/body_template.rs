@@ -0,0 +1,148 @@
+use axum::body::Body;
+use axum::response::Response;
+use handlebars::Handlebars;
+use http::{header, HeaderMap, HeaderValue};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::validator_file::BodyTemplateConfig;
+use crate::validators::claims::{Claim, JWTClaims};
+use crate::validators::reason::Reason;
+
+/// The name the template is registered with
+const TEMPLATE: &str = "body";
+
+/// The request header whose value is available to templates as `request_id`
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Renders the bodies of rejections, with the decision and selected claims of the token
+#[derive(Debug, Clone)]
+pub struct BodyTemplate {
+    registry: Arc<Handlebars<'static>>,
+    content_type: HeaderValue,
+    /// The claims available to the template, other claims never reach the body
+    claims: Vec<Claim>,
+}
+
+/// The selected claims of a rejected token, attached to the response for the template
+#[derive(Debug, Clone)]
+pub struct TemplateClaims(Map<String, Value>);
+
+/// What templates are rendered with
+#[derive(Debug, Serialize)]
+struct Context<'a> {
+    validator: &'a str,
+    status: u16,
+    reason: Option<&'static str>,
+    request_id: Option<&'a str>,
+    claims: Option<&'a Map<String, Value>>,
+}
+
+impl BodyTemplate {
+    /// Read and parse the template, the reason it is invalid otherwise
+    pub fn compile(config: BodyTemplateConfig) -> Result<Self, String> {
+        let template = match (config.template, config.template_file) {
+            (Some(template), None) => template,
+            (None, Some(path)) => fs::read_to_string(&path)
+                .map_err(|e| format!("the file {} can't be read: {}", path.display(), e))?,
+            (Some(_), Some(_)) => return Err("template and template_file are exclusive".into()),
+            (None, None) => return Err("either template or template_file is required".into()),
+        };
+        let content_type = config
+            .content_type
+            .as_deref()
+            .unwrap_or("text/html; charset=utf-8");
+        let json = content_type.contains("json");
+        let content_type = HeaderValue::from_str(content_type)
+            .map_err(|_| format!("{} isn't a valid content type", content_type))?;
+
+        let mut registry = Handlebars::new();
+        // Values are escaped for the content type, so claims can't inject markup
+        if json {
+            registry.register_escape_fn(json_escape);
+        }
+        registry
+            .register_template_string(TEMPLATE, template)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            registry: Arc::new(registry),
+            content_type,
+            claims: config
+                .claims
+                .iter()
+                .map(|claim| Claim::new(claim))
+                .collect(),
+        })
+    }
+
+    /// The selected claims of a token, `None` if the template uses none
+    ///
+    /// Claims with a single value are strings, others lists of strings.
+    pub fn claims(&self, claims: &JWTClaims) -> Option<TemplateClaims> {
+        if self.claims.is_empty() {
+            return None;
+        }
+
+        let selected = self
+            .claims
+            .iter()
+            .filter_map(|claim| {
+                let mut values = claims.values(claim).ok()?;
+                let value = match values.len() {
+                    1 => Value::from(values.remove(0).into_owned()),
+                    _ => values
+                        .into_iter()
+                        .map(|v| Value::from(v.into_owned()))
+                        .collect(),
+                };
+                Some((claim.to_string(), value))
+            })
+            .collect();
+        Some(TemplateClaims(selected))
+    }
+
+    /// Replace the body of a rejection with the rendered template
+    ///
+    /// If rendering fails, the response is left as it is.
+    pub fn apply(&self, res: Response, validator: &str, headers: &HeaderMap) -> Response {
+        if res.status().is_success() {
+            return res;
+        }
+
+        let context = Context {
+            validator,
+            status: res.status().as_u16(),
+            reason: res.extensions().get::<Reason>().map(Reason::as_str),
+            request_id: headers.get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()),
+            claims: res
+                .extensions()
+                .get::<TemplateClaims>()
+                .map(|claims| &claims.0),
+        };
+        let body = match self.registry.render(TEMPLATE, &context) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(validator, "Failed to render the body template: {}", e);
+                return res;
+            }
+        };
+
+        let (mut parts, _) = res.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(header::CONTENT_TYPE, self.content_type.clone());
+
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+/// Escape a value for a JSON string, templates quote the values themselves
+fn json_escape(value: &str) -> String {
+    let quoted = Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
@@ -0,0 +1,243 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde_json::Value;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::pem::Pem;
+use x509_parser::prelude::FromDer;
+use x509_parser::public_key::PublicKey;
+use x509_parser::time::ASN1Time;
+
+use crate::utils::clock;
+
+/// The certificate authorities the `x5c` chains of JWKS keys have to lead to
+///
+/// Some issuers distribute their keys as certificates, only keys whose chain is valid, unexpired
+/// and certifies the key itself are trusted.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrustAnchors {
+    /// The DER encoded certificates
+    certificates: Vec<Vec<u8>>,
+    /// Whether keys without a certificate chain are ignored
+    require: bool,
+}
+
+#[derive(Debug)]
+pub enum CertificateError {
+    Io(io::Error),
+    /// The CA file holds no certificates
+    NoCertificates,
+    /// A certificate isn't valid DER or base64
+    Invalid(String),
+    /// The key has no certificate chain, but one is required
+    MissingChain,
+    /// The certificate isn't valid at the current time
+    Expired {
+        subject: String,
+    },
+    /// The certificate isn't signed by the next one in the chain
+    Signature {
+        subject: String,
+    },
+    /// The chain doesn't end at one of the certificate authorities
+    Untrusted {
+        issuer: String,
+    },
+    /// The certificate isn't allowed to issue certificates
+    NotCa {
+        subject: String,
+    },
+    /// The certified key isn't the key of the JWK
+    KeyMismatch,
+}
+
+impl TrustAnchors {
+    /// Load the certificate authorities from a PEM file
+    pub fn load(path: &Path, require: bool) -> Result<Self, CertificateError> {
+        let pem = std::fs::read(path).map_err(CertificateError::Io)?;
+        let certificates = Pem::iter_from_buffer(&pem)
+            .map(|pem| {
+                let pem = pem.map_err(|e| CertificateError::Invalid(e.to_string()))?;
+                // Fail on certificates that can't be parsed while loading instead of on use
+                X509Certificate::from_der(&pem.contents)
+                    .map_err(|e| CertificateError::Invalid(e.to_string()))?;
+                Ok(pem.contents)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if certificates.is_empty() {
+            return Err(CertificateError::NoCertificates);
+        }
+
+        Ok(Self {
+            certificates,
+            require,
+        })
+    }
+
+    /// Trust no key at all, used if the certificate authorities can't be loaded anymore
+    pub fn none() -> Self {
+        Self {
+            certificates: Vec::new(),
+            require: true,
+        }
+    }
+
+    /// Check the certificate chain of a key (as it appears in the JWKS document)
+    ///
+    /// Keys without an `x5c` are accepted unless chains are required.
+    pub fn verify(&self, key: &Value) -> Result<(), CertificateError> {
+        let Some(chain) = key.get("x5c").and_then(Value::as_array) else {
+            // A chain that couldn't be fetched from its `x5u` can't be ignored
+            return match self.require || key.get("x5u").is_some() {
+                true => Err(CertificateError::MissingChain),
+                false => Ok(()),
+            };
+        };
+
+        let ders = chain
+            .iter()
+            .map(|cert| {
+                cert.as_str()
+                    .and_then(|cert| STANDARD.decode(cert).ok())
+                    .ok_or_else(|| CertificateError::Invalid("x5c entry isn't base64".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let chain = ders
+            .iter()
+            .map(|der| parse(der))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (Some(leaf), Some(last), Some(last_der)) = (chain.first(), chain.last(), ders.last())
+        else {
+            return Err(CertificateError::MissingChain);
+        };
+
+        let now = ASN1Time::from_timestamp(clock::unix_now().as_secs() as i64)
+            .map_err(|e| CertificateError::Invalid(e.to_string()))?;
+        for (i, cert) in chain.iter().enumerate() {
+            if !cert.validity().is_valid_at(now) {
+                return Err(CertificateError::Expired {
+                    subject: cert.subject().to_string(),
+                });
+            }
+
+            let Some(issuer) = chain.get(i + 1) else {
+                break;
+            };
+            if !issuer.is_ca() {
+                return Err(CertificateError::NotCa {
+                    subject: issuer.subject().to_string(),
+                });
+            }
+            cert.verify_signature(Some(issuer.public_key()))
+                .map_err(|_| CertificateError::Signature {
+                    subject: cert.subject().to_string(),
+                })?;
+        }
+
+        let trusted = self.certificates.iter().any(|anchor_der| {
+            let Ok(anchor) = parse(anchor_der) else {
+                return false;
+            };
+            anchor.validity().is_valid_at(now)
+                && (anchor_der == last_der
+                    || (anchor.subject() == last.issuer()
+                        && last.verify_signature(Some(anchor.public_key())).is_ok()))
+        });
+        if !trusted {
+            return Err(CertificateError::Untrusted {
+                issuer: last.issuer().to_string(),
+            });
+        }
+
+        match certifies(leaf, key) {
+            true => Ok(()),
+            false => Err(CertificateError::KeyMismatch),
+        }
+    }
+}
+
+fn parse(der: &[u8]) -> Result<X509Certificate<'_>, CertificateError> {
+    X509Certificate::from_der(der)
+        .map(|(_, cert)| cert)
+        .map_err(|e| CertificateError::Invalid(e.to_string()))
+}
+
+/// Whether the certificate certifies the public key of the JWK
+fn certifies(cert: &X509Certificate, key: &Value) -> bool {
+    let param = |name: &str| {
+        key.get(name)
+            .and_then(Value::as_str)
+            .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+    };
+    let spki = cert.public_key();
+
+    match key.get("kty").and_then(Value::as_str) {
+        Some("RSA") => match (spki.parsed(), param("n"), param("e")) {
+            (Ok(PublicKey::RSA(rsa)), Some(n), Some(e)) => {
+                strip_zeros(rsa.modulus) == strip_zeros(&n)
+                    && strip_zeros(rsa.exponent) == strip_zeros(&e)
+            }
+            _ => false,
+        },
+        // The uncompressed point of the certificate, the coordinates of the JWK
+        Some("EC") => match (param("x"), param("y")) {
+            (Some(x), Some(y)) => {
+                let point = &spki.subject_public_key.data;
+                point.len() == 1 + x.len() + y.len()
+                    && point[0] == 0x04
+                    && point[1..1 + x.len()] == x[..]
+                    && point[1 + x.len()..] == y[..]
+            }
+            _ => false,
+        },
+        Some("OKP") => param("x").is_some_and(|x| spki.subject_public_key.data[..] == x[..]),
+        _ => false,
+    }
+}
+
+fn strip_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read the certificate authorities: {}", e),
+            Self::NoCertificates => write!(f, "the file holds no certificates"),
+            Self::Invalid(e) => write!(f, "invalid certificate: {}", e),
+            Self::MissingChain => write!(f, "the key has no certificate chain"),
+            Self::Expired { subject } => {
+                write!(f, "the certificate of {} isn't valid now", subject)
+            }
+            Self::Signature { subject } => write!(
+                f,
+                "the certificate of {} isn't signed by the next certificate of the chain",
+                subject
+            ),
+            Self::Untrusted { issuer } => {
+                write!(
+                    f,
+                    "the chain ends at {}, which isn't a trusted authority",
+                    issuer
+                )
+            }
+            Self::NotCa { subject } => {
+                write!(f, "{} isn't allowed to issue certificates", subject)
+            }
+            Self::KeyMismatch => write!(f, "the certificate doesn't certify the key"),
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use regex::Regex;
+use serde::de::Error;
+use serde::Deserialize;
+
+use crate::validators::checks::ClaimCheck;
+use crate::validators::request::ForwardedRequest;
+
+/// Matches if any value of the claim equals the expected value
+#[derive(Debug)]
+pub struct Equals(String);
+
+/// Matches if any value of the claim equals one of the expected values
+#[derive(Debug)]
+pub struct OneOf(Vec<String>);
+
+/// Matches if any value of the claim is a number within the (inclusive) range
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+/// Matches if the claim contains all the expected values
+#[derive(Debug)]
+pub struct ContainsAll(Vec<String>);
+
+/// Matches if the claim has all the expected OAuth 2.0 scopes, its values are space-delimited
+/// lists of scopes (RFC 6749, section 3.3)
+#[derive(Debug)]
+pub struct Scopes(Vec<String>);
+
+/// Matches if any value of the claim is a path (e.g. `org/team/admins`) matching one of the patterns
+///
+/// In patterns `*` matches exactly one segment and `**` any number of segments.
+#[derive(Debug)]
+pub struct Hierarchy {
+    separator: String,
+    patterns: Vec<HierarchyPattern>,
+}
+
+#[derive(Debug)]
+struct HierarchyPattern {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    /// `*`
+    One,
+    /// `**`
+    Any,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum HierarchyArgs {
+    Full {
+        patterns: serde_yaml::Value,
+        separator: Option<String>,
+    },
+    /// Only the pattern(s), using the default separator
+    Patterns(serde_yaml::Value),
+}
+
+/// Matches if any value of the claim matches one of the regular expressions
+///
+/// The expressions aren't anchored, `^` and `$` have to be used to match whole values.
+#[derive(Debug)]
+pub struct Pattern(Vec<Regex>);
+
+/// Matches if any whole value of the claim matches one of the glob patterns
+///
+/// In patterns `*` matches any number of characters, `?` a single character and `[...]` one of
+/// the enclosed characters.
+#[derive(Debug)]
+pub struct Glob(Vec<glob::Pattern>);
+
+/// Matches if any value of the claim equals an attribute of the forwarded request
+#[derive(Debug)]
+pub struct EqualsRequest(RequestAttribute);
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "attribute", rename_all = "snake_case")]
+enum RequestAttribute {
+    Method,
+    Host,
+    /// The first label of the host
+    Subdomain,
+    Path,
+    PathSegment {
+        index: usize,
+    },
+    Query {
+        name: String,
+    },
+    Header {
+        name: String,
+    },
+}
+
+/// Convert a scalar from the configuration file into the string representation used for claims
+fn scalar(value: serde_yaml::Value) -> Result<String, serde_yaml::Error> {
+    match value {
+        serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+        serde_yaml::Value::Number(n) => Ok(n.to_string()),
+        serde_yaml::Value::String(s) => Ok(s),
+        _ => Err(serde_yaml::Error::custom(
+            "expected a string, number or boolean",
+        )),
+    }
+}
+
+fn scalars(value: serde_yaml::Value) -> Result<Vec<String>, serde_yaml::Error> {
+    match value {
+        serde_yaml::Value::Null => Ok(Vec::new()),
+        serde_yaml::Value::Sequence(seq) => seq.into_iter().map(scalar).collect(),
+        _ => Err(serde_yaml::Error::custom("expected a list of values")),
+    }
+}
+
+impl Equals {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        match args {
+            serde_yaml::Value::Null => Ok(None),
+            args => Ok(Some(Arc::new(Self(scalar(args)?)))),
+        }
+    }
+}
+
+impl ClaimCheck for Equals {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        values.iter().any(|v| v == &self.0)
+    }
+}
+
+impl fmt::Display for Equals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl OneOf {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let mut values = scalars(args)?;
+
+        match values.len() {
+            0 => Ok(None),
+            1 => Ok(Some(Arc::new(Equals(values.remove(0))))),
+            _ => Ok(Some(Arc::new(Self(values)))),
+        }
+    }
+}
+
+impl ClaimCheck for OneOf {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        values.iter().any(|v| self.0.iter().any(|e| v == e))
+    }
+}
+
+impl fmt::Display for OneOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "one of [{}]", self.0.join(", "))
+    }
+}
+
+impl Range {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let range: Self = serde_yaml::from_value(args)?;
+
+        match (range.min, range.max) {
+            (None, None) => Ok(None),
+            (Some(min), Some(max)) if min > max => Err(serde_yaml::Error::custom(
+                "the minimum of the range is larger than its maximum",
+            )),
+            _ => Ok(Some(Arc::new(range))),
+        }
+    }
+}
+
+impl ClaimCheck for Range {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        values
+            .iter()
+            .filter_map(|v| v.parse::<f64>().ok())
+            .any(|v| self.min.is_none_or(|min| v >= min) && self.max.is_none_or(|max| v <= max))
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "between {} and {}", min, max),
+            (Some(min), None) => write!(f, "at least {}", min),
+            (None, Some(max)) => write!(f, "at most {}", max),
+            (None, None) => write!(f, "any number"),
+        }
+    }
+}
+
+impl ContainsAll {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let values = scalars(args)?;
+
+        if values.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Arc::new(Self(values))))
+        }
+    }
+}
+
+impl ClaimCheck for ContainsAll {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        self.0.iter().all(|e| values.iter().any(|v| v == e))
+    }
+}
+
+impl fmt::Display for ContainsAll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all of [{}]", self.0.join(", "))
+    }
+}
+
+impl Scopes {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let scopes = scalars(args)?;
+        if scopes
+            .iter()
+            .any(|scope| scope.is_empty() || scope.contains(' '))
+        {
+            return Err(serde_yaml::Error::custom(
+                "scopes must not be empty or contain spaces",
+            ));
+        }
+
+        if scopes.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Arc::new(Self(scopes))))
+        }
+    }
+}
+
+impl ClaimCheck for Scopes {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        self.0
+            .iter()
+            .all(|e| values.iter().flat_map(|v| v.split(' ')).any(|v| v == e))
+    }
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "scopes [{}]", self.0.join(", "))
+    }
+}
+
+impl Hierarchy {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let (patterns, separator) = match serde_yaml::from_value(args)? {
+            HierarchyArgs::Full {
+                patterns,
+                separator,
+            } => (patterns, separator.unwrap_or_else(|| "/".to_string())),
+            HierarchyArgs::Patterns(patterns) => (patterns, "/".to_string()),
+        };
+
+        if separator.is_empty() {
+            return Err(serde_yaml::Error::custom("the separator must not be empty"));
+        }
+
+        let patterns = match patterns {
+            serde_yaml::Value::String(pattern) => vec![pattern],
+            patterns => scalars(patterns)?,
+        };
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| {
+                let segments = split(&pattern, &separator)
+                    .map(|segment| match segment {
+                        "*" => Segment::One,
+                        "**" => Segment::Any,
+                        literal => Segment::Literal(literal.to_string()),
+                    })
+                    .collect();
+
+                HierarchyPattern { pattern, segments }
+            })
+            .collect();
+
+        Ok(Some(Arc::new(Self {
+            separator,
+            patterns,
+        })))
+    }
+}
+
+/// Split a path into its segments, ignoring leading and trailing separators (e.g. `/org/team/`)
+fn split<'a>(path: &'a str, separator: &'a str) -> impl Iterator<Item = &'a str> {
+    path.trim_start_matches(separator)
+        .trim_end_matches(separator)
+        .split(separator)
+        .filter(|segment| !segment.is_empty())
+}
+
+fn matches_segments(pattern: &[Segment], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(Segment::Any), _) => {
+            // Either `**` matches no further segment, or it consumes one
+            matches_segments(&pattern[1..], path)
+                || (!path.is_empty() && matches_segments(pattern, &path[1..]))
+        }
+        (Some(Segment::One), Some(_)) => matches_segments(&pattern[1..], &path[1..]),
+        (Some(Segment::Literal(literal)), Some(segment)) if literal == segment => {
+            matches_segments(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+impl ClaimCheck for Hierarchy {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        values.iter().any(|value| {
+            let path = split(value, &self.separator).collect::<Vec<_>>();
+            self.patterns
+                .iter()
+                .any(|pattern| matches_segments(&pattern.segments, &path))
+        })
+    }
+}
+
+impl fmt::Display for Hierarchy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let patterns = self
+            .patterns
+            .iter()
+            .map(|p| p.pattern.as_str())
+            .collect::<Vec<_>>();
+        write!(f, "matching one of [{}]", patterns.join(", "))
+    }
+}
+
+impl Pattern {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let patterns = match args {
+            serde_yaml::Value::String(pattern) => vec![pattern],
+            patterns => scalars(patterns)?,
+        };
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let regexes = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    serde_yaml::Error::custom(format!("invalid regex {}: {}", pattern, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Arc::new(Self(regexes))))
+    }
+}
+
+impl ClaimCheck for Pattern {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        values
+            .iter()
+            .any(|value| self.0.iter().any(|regex| regex.is_match(value)))
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let patterns = self.0.iter().map(Regex::as_str).collect::<Vec<_>>();
+        write!(f, "matching one of [{}]", patterns.join(", "))
+    }
+}
+
+impl Glob {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        let patterns = match args {
+            serde_yaml::Value::String(pattern) => vec![pattern],
+            patterns => scalars(patterns)?,
+        };
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern).map_err(|e| {
+                    serde_yaml::Error::custom(format!("invalid glob {}: {}", pattern, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Arc::new(Self(patterns))))
+    }
+}
+
+impl ClaimCheck for Glob {
+    fn check(&self, values: &[Cow<'_, str>], _: &ForwardedRequest<'_>) -> bool {
+        values
+            .iter()
+            .any(|value| self.0.iter().any(|pattern| pattern.matches(value)))
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let patterns = self.0.iter().map(glob::Pattern::as_str).collect::<Vec<_>>();
+        write!(f, "matching one of [{}]", patterns.join(", "))
+    }
+}
+
+impl EqualsRequest {
+    pub fn compile(
+        args: serde_yaml::Value,
+    ) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error> {
+        match args {
+            serde_yaml::Value::Null => Ok(None),
+            args => Ok(Some(Arc::new(Self(serde_yaml::from_value(args)?)))),
+        }
+    }
+}
+
+impl RequestAttribute {
+    fn value<'a>(&self, request: &ForwardedRequest<'a>) -> Option<Cow<'a, str>> {
+        match self {
+            Self::Method => request.method().map(Cow::Borrowed),
+            Self::Host => request.host().map(Cow::Borrowed),
+            Self::Subdomain => request.subdomain().map(Cow::Borrowed),
+            Self::Path => request.path().map(Cow::Borrowed),
+            Self::PathSegment { index } => request.path_segment(*index),
+            Self::Query { name } => request.query_param(name),
+            Self::Header { name } => request.header(name).map(Cow::Borrowed),
+        }
+    }
+}
+
+impl ClaimCheck for EqualsRequest {
+    fn check(&self, values: &[Cow<'_, str>], request: &ForwardedRequest<'_>) -> bool {
+        // A missing attribute never matches, even an empty claim
+        match self.0.value(request) {
+            Some(expected) => values.contains(&expected),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for EqualsRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            RequestAttribute::Method => write!(f, "the request method"),
+            RequestAttribute::Host => write!(f, "the request host"),
+            RequestAttribute::Subdomain => write!(f, "the request subdomain"),
+            RequestAttribute::Path => write!(f, "the request path"),
+            RequestAttribute::PathSegment { index } => {
+                write!(f, "segment {} of the request path", index)
+            }
+            RequestAttribute::Query { name } => write!(f, "the request query parameter {}", name),
+            RequestAttribute::Header { name } => write!(f, "the request header {}", name),
+        }
+    }
+}
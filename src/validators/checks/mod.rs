@@ -0,0 +1,60 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::validators::request::ForwardedRequest;
+
+mod matchers;
+mod plan;
+mod subjects;
+
+pub use plan::{
+    ClaimEvaluation, ClaimMapping, ClaimRequirement, Operator, Plan, Requirement, RequirementGroup,
+};
+pub use subjects::SubjectFilter;
+
+/// A check that is run against the values of a claim
+pub trait ClaimCheck: fmt::Debug + fmt::Display + Send + Sync {
+    /// Whether the values of the claim satisfy the check
+    ///
+    /// Claims with a single value (e.g. `sub`) are passed as a slice with one element,
+    /// claims with multiple values (e.g. `aud`) with all of their values.
+    fn check(&self, values: &[Cow<'_, str>], request: &ForwardedRequest<'_>) -> bool;
+}
+
+/// Compiles the arguments of a matcher from the configuration file into a check
+///
+/// Returns `None` if the arguments don't restrict the claim (e.g. `value: null`).
+type CompileFn = fn(serde_yaml::Value) -> Result<Option<Arc<dyn ClaimCheck>>, serde_yaml::Error>;
+
+/// The registry of available matchers, keyed by their name in the configuration file
+const MATCHERS: &[(&str, CompileFn)] = &[
+    ("value", matchers::Equals::compile),
+    ("values", matchers::OneOf::compile),
+    ("range", matchers::Range::compile),
+    ("contains_all", matchers::ContainsAll::compile),
+    ("scopes", matchers::Scopes::compile),
+    ("hierarchy", matchers::Hierarchy::compile),
+    ("regex", matchers::Pattern::compile),
+    ("glob", matchers::Glob::compile),
+    ("request", matchers::EqualsRequest::compile),
+];
+
+#[derive(Debug)]
+pub enum CompileError {
+    UnknownMatcher,
+    InvalidArguments(serde_yaml::Error),
+}
+
+/// Compile a matcher from the configuration file into a check
+pub fn compile(
+    matcher: &str,
+    args: serde_yaml::Value,
+) -> Result<Option<Arc<dyn ClaimCheck>>, CompileError> {
+    let (_, compile) = MATCHERS
+        .iter()
+        .find(|(name, _)| *name == matcher)
+        .ok_or(CompileError::UnknownMatcher)?;
+
+    compile(args).map_err(CompileError::InvalidArguments)
+}
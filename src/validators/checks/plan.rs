@@ -0,0 +1,432 @@
+use http::{HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::utils::header_val::header_val_lossy_joined;
+use crate::validator_file::Severity;
+use crate::validators::checks::ClaimCheck;
+use crate::validators::claims::{Claim, ClaimError, JWTClaims};
+use crate::validators::cookies::CookieMapping;
+use crate::validators::privacy::Identity;
+use crate::validators::reason::Reason;
+use crate::validators::request::ForwardedRequest;
+
+/// A requirement of a validator on the claims of a token
+#[derive(Debug, Clone)]
+pub enum Requirement {
+    Claim(ClaimRequirement),
+    Group(RequirementGroup),
+}
+
+/// A claim that has to be present in the token and satisfy all of its checks
+#[derive(Debug, Clone)]
+pub struct ClaimRequirement {
+    pub claim: Claim,
+    pub checks: Vec<Arc<dyn ClaimCheck>>,
+    pub severity: Severity,
+}
+
+/// Requirements combined with `any_of` or `all_of`
+///
+/// Only the severity of the group applies, the requirements in it are plain conditions.
+#[derive(Debug, Clone)]
+pub struct RequirementGroup {
+    pub operator: Operator,
+    pub requirements: Vec<Requirement>,
+    pub severity: Severity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    AnyOf,
+    AllOf,
+}
+
+/// A claim forwarded to the upstream service as a header
+#[derive(Debug, Clone)]
+pub struct ClaimMapping {
+    pub claim: Claim,
+    pub header: HeaderName,
+    /// Whether the values are space-delimited lists (e.g. `scope`), mapped as separate values
+    pub space_delimited: bool,
+}
+
+/// The compiled requirements and mappings of a validator
+///
+/// The requirements are evaluated in order, the mappings of a token that satisfies all of them
+/// are emitted as headers and cookies. Cloning is cheap, so the plan can be shared between reloads.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    requirements: Arc<[Requirement]>,
+    mappings: Arc<[ClaimMapping]>,
+    cookies: Arc<[CookieMapping]>,
+}
+
+#[derive(Debug)]
+pub enum PlanError<'a> {
+    /// The claim isn't present in the token
+    Missing { claim: &'a str },
+    /// The claim has a value that cannot be checked (e.g. an object)
+    Unsupported { claim: &'a str },
+    /// The claim doesn't satisfy a check
+    Mismatch {
+        claim: &'a str,
+        values: String,
+        check: &'a dyn ClaimCheck,
+    },
+    /// None of the requirements of an `any_of` group is satisfied
+    NoneOf { errors: Vec<PlanError<'a>> },
+}
+
+/// The outcome of a single requirement, as reported in explain mode
+///
+/// Groups are reported with their operator as the claim and the outcomes of their requirements.
+#[derive(Debug, Serialize)]
+pub struct ClaimEvaluation {
+    pub claim: String,
+    pub severity: Severity,
+    pub outcome: &'static str,
+    pub values: Vec<String>,
+    pub checks: Vec<CheckEvaluation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub requirements: Vec<ClaimEvaluation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckEvaluation {
+    pub check: String,
+    pub passed: bool,
+}
+
+impl Plan {
+    /// The mappings are sorted by claim, so the headers are always emitted in the same order
+    pub fn new(
+        requirements: Vec<Requirement>,
+        mut mappings: Vec<ClaimMapping>,
+        mut cookies: Vec<CookieMapping>,
+    ) -> Self {
+        mappings.sort_unstable_by(|a, b| a.claim.cmp(&b.claim));
+        cookies.sort_unstable_by(|a, b| a.claim.cmp(&b.claim));
+
+        Self {
+            requirements: requirements.into(),
+            mappings: mappings.into(),
+            cookies: cookies.into(),
+        }
+    }
+
+    /// The number of requirements
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.requirements.len()
+    }
+
+    #[inline]
+    pub fn mappings(&self) -> &[ClaimMapping] {
+        &self.mappings
+    }
+
+    /// Evaluate the plan against the claims of a token, stopping at the first failure
+    ///
+    /// Failures of requirements with the `warn` severity don't stop the evaluation, they are
+    /// returned instead.
+    pub fn evaluate<'a>(
+        &'a self,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Result<Vec<PlanError<'a>>, PlanError<'a>> {
+        let mut warnings = Vec::new();
+        for requirement in self.requirements.iter() {
+            match requirement.evaluate(claims, request) {
+                Ok(()) => {}
+                Err(err) if requirement.severity() == Severity::Warn => warnings.push(err),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Evaluate every requirement and check, without stopping at the first failure
+    pub fn explain(
+        &self,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Vec<ClaimEvaluation> {
+        self.requirements
+            .iter()
+            .map(|requirement| requirement.explain(claims, request))
+            .collect()
+    }
+
+    /// The headers for the mapped claims of a token that satisfied the requirements
+    ///
+    /// Claims that are missing or cannot be represented as a header are skipped.
+    pub fn headers(&self, claims: &JWTClaims) -> HeaderMap {
+        let mut headers = HeaderMap::with_capacity(self.mappings.len());
+        for mapping in self.mappings.iter() {
+            let Ok(mut values) = claims.values(&mapping.claim) else {
+                continue;
+            };
+            if mapping.space_delimited {
+                values = values
+                    .iter()
+                    .flat_map(|value| value.split(' '))
+                    .filter(|value| !value.is_empty())
+                    .map(|value| Cow::Owned(value.to_string()))
+                    .collect();
+            }
+            headers.insert(&mapping.header, header_val_lossy_joined(&values, b','));
+        }
+
+        headers
+    }
+
+    /// The `Set-Cookie` headers for the cookie mappings of a token that satisfied the requirements,
+    /// keyed by the cookie name
+    ///
+    /// Claims that are missing or too large for their cookie are skipped.
+    pub fn cookies(
+        &self,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Vec<(String, HeaderValue)> {
+        let mut cookies = Vec::with_capacity(self.cookies.len());
+        for mapping in self.cookies.iter() {
+            let Ok(values) = claims.values(&mapping.claim) else {
+                continue;
+            };
+
+            match mapping.value(&values) {
+                Some(value) => cookies.extend(mapping.set_cookies(&value, request)),
+                None => info!(
+                    "Claim {} is too large for cookie {}, not setting it",
+                    mapping.claim, mapping.name
+                ),
+            }
+        }
+
+        cookies
+    }
+}
+
+impl Requirement {
+    fn severity(&self) -> Severity {
+        match self {
+            Self::Claim(requirement) => requirement.severity,
+            Self::Group(group) => group.severity,
+        }
+    }
+
+    fn evaluate(
+        &self,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Result<(), PlanError<'_>> {
+        match self {
+            Self::Claim(requirement) => requirement.evaluate(claims, request),
+            Self::Group(group) => group.evaluate(claims, request),
+        }
+    }
+
+    fn explain(&self, claims: &JWTClaims, request: &ForwardedRequest<'_>) -> ClaimEvaluation {
+        match self {
+            Self::Claim(requirement) => requirement.explain(claims, request),
+            Self::Group(group) => {
+                let requirements = group
+                    .requirements
+                    .iter()
+                    .map(|requirement| requirement.explain(claims, request))
+                    .collect::<Vec<_>>();
+                let satisfied = |evaluation: &ClaimEvaluation| evaluation.outcome == "satisfied";
+                let passed = match group.operator {
+                    Operator::AnyOf => requirements.iter().any(satisfied),
+                    Operator::AllOf => requirements.iter().all(satisfied),
+                };
+
+                ClaimEvaluation {
+                    claim: group.operator.as_str().to_string(),
+                    severity: group.severity,
+                    outcome: if passed { "satisfied" } else { "mismatch" },
+                    values: Vec::new(),
+                    checks: Vec::new(),
+                    requirements,
+                }
+            }
+        }
+    }
+}
+
+impl RequirementGroup {
+    /// Evaluate the requirements in order, `all_of` stops at the first failure and `any_of` at
+    /// the first success
+    fn evaluate(
+        &self,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Result<(), PlanError<'_>> {
+        let mut requirements = self.requirements.iter();
+        match self.operator {
+            Operator::AllOf => requirements.try_for_each(|r| r.evaluate(claims, request)),
+            Operator::AnyOf => {
+                let mut errors = Vec::with_capacity(self.requirements.len());
+                for requirement in requirements {
+                    match requirement.evaluate(claims, request) {
+                        Ok(()) => return Ok(()),
+                        Err(err) => errors.push(err),
+                    }
+                }
+                Err(PlanError::NoneOf { errors })
+            }
+        }
+    }
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AnyOf => "any_of",
+            Self::AllOf => "all_of",
+        }
+    }
+}
+
+impl ClaimRequirement {
+    fn explain(&self, claims: &JWTClaims, request: &ForwardedRequest<'_>) -> ClaimEvaluation {
+        let (outcome, values, checks) = match claims.values(&self.claim) {
+            Ok(values) => {
+                let checks = self
+                    .checks
+                    .iter()
+                    .map(|check| CheckEvaluation {
+                        check: check.to_string(),
+                        passed: check.check(&values, request),
+                    })
+                    .collect::<Vec<_>>();
+                let outcome = if checks.iter().all(|c| c.passed) {
+                    "satisfied"
+                } else {
+                    "mismatch"
+                };
+
+                (
+                    outcome,
+                    values.iter().map(|v| v.to_string()).collect(),
+                    checks,
+                )
+            }
+            Err(ClaimError::Missing) => ("missing", Vec::new(), Vec::new()),
+            Err(ClaimError::Unsupported) => ("unsupported", Vec::new(), Vec::new()),
+        };
+
+        ClaimEvaluation {
+            claim: self.claim.to_string(),
+            severity: self.severity,
+            outcome,
+            values,
+            checks,
+            requirements: Vec::new(),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Result<(), PlanError<'_>> {
+        let claim = self.claim.as_str();
+
+        let values = match claims.values(&self.claim) {
+            Ok(values) => values,
+            Err(ClaimError::Missing) => return Err(PlanError::Missing { claim }),
+            Err(ClaimError::Unsupported) => return Err(PlanError::Unsupported { claim }),
+        };
+
+        for check in &self.checks {
+            if !check.check(&values, request) {
+                return Err(PlanError::Mismatch {
+                    claim,
+                    values: values.join(","),
+                    check: check.as_ref(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PlanError<'_> {
+    /// The claim that failed, `any_of` for groups
+    pub fn claim(&self) -> &str {
+        match self {
+            Self::Missing { claim }
+            | Self::Unsupported { claim }
+            | Self::Mismatch { claim, .. } => claim,
+            Self::NoneOf { .. } => Operator::AnyOf.as_str(),
+        }
+    }
+
+    /// The reason code the request is rejected with
+    pub fn reason(&self) -> Reason {
+        match self {
+            Self::Missing { .. } => Reason::MissingClaim,
+            Self::Unsupported { .. } | Self::Mismatch { .. } => Reason::ClaimMismatch,
+            // Only missing if no requirement of the group had a claim to check
+            Self::NoneOf { errors } => {
+                if errors.iter().all(|e| e.reason() == Reason::MissingClaim) {
+                    Reason::MissingClaim
+                } else {
+                    Reason::ClaimMismatch
+                }
+            }
+        }
+    }
+
+    /// The message returned to the client
+    pub fn message(&self) -> String {
+        match self {
+            Self::Missing { claim } => format!("Token is missing required {} claim", claim),
+            Self::Unsupported { .. } => "Token contains invalid claim".to_string(),
+            Self::Mismatch { claim, .. } => format!("Token doesn't match required {} claim", claim),
+            Self::NoneOf { errors } => {
+                let claims = errors.iter().map(|e| e.claim()).collect::<Vec<_>>();
+                format!(
+                    "Token doesn't match any of the required claims {}",
+                    claims.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for PlanError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { claim } => write!(f, "Token is missing required {} claim", claim),
+            Self::Unsupported { claim } => write!(f, "Token contains invalid claim: {}", claim),
+            Self::Mismatch {
+                claim,
+                values,
+                check,
+            } => write!(
+                f,
+                "Token doesn't match required {} claim: [{}] != {}",
+                claim,
+                Identity::new(claim, values),
+                check
+            ),
+            Self::NoneOf { errors } => {
+                write!(f, "Token doesn't satisfy any of the requirements:")?;
+                for (i, error) in errors.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { "; " };
+                    write!(f, "{}{}", separator, error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::validators::privacy::Identity;
+
+/// The subjects a validator accepts, checked after the signature of the token
+#[derive(Debug, Clone, Default)]
+pub struct SubjectFilter {
+    /// If set, only these subjects are accepted
+    allowed: Option<HashSet<String>>,
+    denied: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub enum SubjectError<'a> {
+    /// The token has no subject, but only listed subjects are allowed
+    Missing,
+    NotAllowed(&'a str),
+    Denied(&'a str),
+}
+
+impl SubjectFilter {
+    pub fn new(allowed: Option<HashSet<String>>, denied: HashSet<String>) -> Self {
+        Self { allowed, denied }
+    }
+
+    /// Whether the filter accepts every subject
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_none() && self.denied.is_empty()
+    }
+
+    pub fn check<'a>(&self, subject: Option<&'a str>) -> Result<(), SubjectError<'a>> {
+        match subject {
+            Some(subject) if self.denied.contains(subject) => Err(SubjectError::Denied(subject)),
+            Some(subject) => match &self.allowed {
+                Some(allowed) if !allowed.contains(subject) => {
+                    Err(SubjectError::NotAllowed(subject))
+                }
+                _ => Ok(()),
+            },
+            None if self.allowed.is_some() => Err(SubjectError::Missing),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for SubjectError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(
+                f,
+                "Token has no subject, but only allowed subjects are accepted"
+            ),
+            Self::NotAllowed(subject) => write!(
+                f,
+                "Subject {} is not in the allowed subjects",
+                Identity::subject(subject)
+            ),
+            Self::Denied(subject) => write!(
+                f,
+                "Subject {} is in the denied subjects",
+                Identity::subject(subject)
+            ),
+        }
+    }
+}
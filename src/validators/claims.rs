@@ -1,7 +1,16 @@
 use aliri::jwt::{Audiences, CoreClaims, Issuer, IssuerRef, Subject, SubjectRef};
+use aliri::JwtRef;
 use aliri_clock::UnixTime;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use serde::Deserialize;
+use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::utils::clock;
 
 #[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct JWTClaims {
@@ -12,6 +21,67 @@ pub struct JWTClaims {
     pub nbf: Option<UnixTime>,
     #[serde(flatten)]
     pub other: HashMap<String, serde_json::Value>,
+    /// Values looked up by enrichments, they take precedence over claims of the token
+    #[serde(skip)]
+    pub enriched: HashMap<String, Arc<[String]>>,
+}
+
+/// The `iss` claim of a token whose signature wasn't verified yet, only to be used to select
+/// the authority verifying it
+pub fn unverified_issuer(token: &JwtRef) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Payload {
+        iss: String,
+    }
+
+    let payload = token.as_str().split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    serde_json::from_slice::<Payload>(&payload)
+        .ok()
+        .map(|payload| payload.iss)
+}
+
+impl JWTClaims {
+    /// The seconds until the token expires, `None` if it doesn't expire
+    pub fn expires_in(&self) -> Option<u64> {
+        let now = clock::unix_now().as_secs();
+        self.exp.map(|exp| exp.0.saturating_sub(now))
+    }
+
+    /// The claims as a JSON object, `aud` is always an array and enriched values replace the
+    /// claims of the token
+    pub fn to_json(&self) -> serde_json::Map<String, Value> {
+        let mut json = self
+            .other
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<serde_json::Map<_, _>>();
+        if let Some(aud) = &self.aud {
+            json.insert(
+                "aud".to_string(),
+                aud.iter().map(|aud| Value::from(aud.as_str())).collect(),
+            );
+        }
+        if let Some(iss) = &self.iss {
+            json.insert("iss".to_string(), iss.as_str().into());
+        }
+        if let Some(sub) = &self.sub {
+            json.insert("sub".to_string(), sub.as_str().into());
+        }
+        if let Some(exp) = self.exp {
+            json.insert("exp".to_string(), exp.0.into());
+        }
+        if let Some(nbf) = self.nbf {
+            json.insert("nbf".to_string(), nbf.0.into());
+        }
+        for (name, values) in &self.enriched {
+            json.insert(
+                name.clone(),
+                values.iter().map(|v| Value::from(v.as_str())).collect(),
+            );
+        }
+        json
+    }
 }
 
 impl CoreClaims for JWTClaims {
@@ -26,7 +96,7 @@ impl CoreClaims for JWTClaims {
     fn aud(&self) -> &Audiences {
         // The aud may not be present, but we need to return something
         // so we'll just return an empty audience
-        self.aud.as_ref().unwrap_or(&Audiences::EMPTY_AUD)
+        self.aud.as_ref().unwrap_or(Audiences::EMPTY_AUD)
     }
 
     fn iss(&self) -> Option<&IssuerRef> {
@@ -37,3 +107,198 @@ impl CoreClaims for JWTClaims {
         self.sub.as_ref().map(|s| s.as_ref())
     }
 }
+
+/// The name of a claim, resolved when the configuration is loaded
+///
+/// The registered claims are parsed into dedicated fields, so they are read without a lookup.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Claim {
+    Audience,
+    Issuer,
+    Subject,
+    Expiration,
+    NotBefore,
+    Other(Arc<str>),
+    /// A claim inside objects (and arrays) of the token, e.g. `realm_access.roles` or
+    /// `/resource_access/my-client/roles`
+    Nested {
+        name: Arc<str>,
+        path: Arc<[Box<str>]>,
+    },
+}
+
+/// The reason the values of a claim couldn't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimError {
+    /// The claim isn't present in the token
+    Missing,
+    /// The claim has a value that cannot be represented as strings (e.g. an object)
+    Unsupported,
+}
+
+impl Claim {
+    /// Resolve a claim name, a nested claim is named by a JSON pointer (RFC 6901, starting with
+    /// `/`) or a path of keys separated by dots
+    pub fn new(name: &str) -> Self {
+        match name {
+            "aud" => Self::Audience,
+            "iss" => Self::Issuer,
+            "sub" => Self::Subject,
+            "exp" => Self::Expiration,
+            "nbf" => Self::NotBefore,
+            pointer if pointer.starts_with('/') => Self::Nested {
+                name: pointer.into(),
+                path: pointer[1..]
+                    .split('/')
+                    .map(|key| key.replace("~1", "/").replace("~0", "~").into())
+                    .collect(),
+            },
+            path if path.contains('.') && path.split('.').all(|key| !key.is_empty()) => {
+                Self::Nested {
+                    name: path.into(),
+                    path: path.split('.').map(Into::into).collect(),
+                }
+            }
+            other => Self::Other(other.into()),
+        }
+    }
+
+    /// Whether the claim is one of the registered claims parsed by the verification
+    pub fn is_registered(&self) -> bool {
+        !matches!(self, Self::Other(_) | Self::Nested { .. })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Audience => "aud",
+            Self::Issuer => "iss",
+            Self::Subject => "sub",
+            Self::Expiration => "exp",
+            Self::NotBefore => "nbf",
+            Self::Other(name) | Self::Nested { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for Claim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl JWTClaims {
+    /// Get the values of a claim as strings
+    ///
+    /// Claims with multiple values (e.g. `aud`) return all of their values.
+    pub fn values(&self, claim: &Claim) -> Result<Vec<Cow<'_, str>>, ClaimError> {
+        match claim {
+            Claim::Audience => match &self.aud {
+                Some(aud) => Ok(aud.iter().map(|aud| Cow::Borrowed(aud.as_str())).collect()),
+                None => Err(ClaimError::Missing),
+            },
+            Claim::Issuer => match &self.iss {
+                Some(iss) => Ok(vec![Cow::Borrowed(iss.as_str())]),
+                None => Err(ClaimError::Missing),
+            },
+            Claim::Subject => match &self.sub {
+                Some(sub) => Ok(vec![Cow::Borrowed(sub.as_str())]),
+                None => Err(ClaimError::Missing),
+            },
+            Claim::Expiration => match &self.exp {
+                Some(exp) => Ok(vec![Cow::Owned(exp.to_string())]),
+                None => Err(ClaimError::Missing),
+            },
+            Claim::NotBefore => match &self.nbf {
+                Some(nbf) => Ok(vec![Cow::Owned(nbf.to_string())]),
+                None => Err(ClaimError::Missing),
+            },
+            Claim::Other(other) if self.enriched.contains_key(&**other) => Ok(self.enriched
+                [&**other]
+                .iter()
+                .map(|v| Cow::Borrowed(v.as_str()))
+                .collect()),
+            Claim::Other(other) => match self.other.get(&**other) {
+                Some(value) => scalar_values(value),
+                None => Err(ClaimError::Missing),
+            },
+            Claim::Nested { name, .. } if self.enriched.contains_key(&**name) => Ok(self.enriched
+                [&**name]
+                .iter()
+                .map(|v| Cow::Borrowed(v.as_str()))
+                .collect()),
+            // Claims whose name contains dots (e.g. `https://example.com/roles`) aren't nested
+            Claim::Nested { name, .. } if self.other.contains_key(&**name) => {
+                scalar_values(&self.other[&**name])
+            }
+            Claim::Nested { path, .. } => {
+                let (first, rest) = path.split_first().ok_or(ClaimError::Missing)?;
+                let mut found = Vec::new();
+                lookup(self.other.get(&**first), rest, &mut found);
+                if found.is_empty() {
+                    return Err(ClaimError::Missing);
+                }
+
+                let mut values = Vec::new();
+                for value in found {
+                    values.extend(scalar_values(value)?);
+                }
+                Ok(values)
+            }
+        }
+    }
+}
+
+/// The values of a claim, the elements of arrays are separate values
+///
+/// Objects and arrays inside arrays are skipped, so the other elements can still be matched.
+fn scalar_values(value: &Value) -> Result<Vec<Cow<'_, str>>, ClaimError> {
+    match value {
+        Value::Array(elements) => {
+            let values = elements
+                .iter()
+                .filter_map(|element| scalar(element).ok())
+                .collect::<Vec<_>>();
+            if values.is_empty() && !elements.is_empty() {
+                return Err(ClaimError::Unsupported);
+            }
+            Ok(values)
+        }
+        value => Ok(vec![scalar(value)?]),
+    }
+}
+
+fn scalar(value: &Value) -> Result<Cow<'_, str>, ClaimError> {
+    match value {
+        Value::Null => Ok(Cow::Borrowed("")),
+        Value::Bool(v) => Ok(Cow::Owned(v.to_string())),
+        Value::Number(v) => Ok(Cow::Owned(v.to_string())),
+        Value::String(v) => Ok(Cow::Borrowed(v.as_str())),
+        Value::Array(_) | Value::Object(_) => Err(ClaimError::Unsupported),
+    }
+}
+
+/// Collect the values at the path below `value`
+///
+/// A numeric key indexes an array, any other key is looked up in every element of an array.
+fn lookup<'a>(value: Option<&'a Value>, path: &[Box<str>], found: &mut Vec<&'a Value>) {
+    let Some(value) = value else {
+        return;
+    };
+    let Some((key, rest)) = path.split_first() else {
+        found.push(value);
+        return;
+    };
+
+    match value {
+        Value::Object(object) => lookup(object.get(&**key), rest, found),
+        Value::Array(elements) => match key.parse::<usize>() {
+            Ok(index) => lookup(elements.get(index), rest, found),
+            Err(_) => {
+                for element in elements {
+                    lookup(Some(element), path, found);
+                }
+            }
+        },
+        _ => {}
+    }
+}
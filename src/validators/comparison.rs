@@ -0,0 +1,82 @@
+use aliri::JwtRef;
+use serde::Serialize;
+use tracing::info;
+
+use crate::metrics;
+use crate::validators::authority::{Authority, AuthorityError};
+use crate::validators::claims::{Claim, JWTClaims};
+use crate::validators::phases::Phases;
+
+/// A second authority tokens are verified with alongside the enforced one, e.g. while migrating
+/// to a new issuer
+///
+/// Only the decision of the enforced authority counts. Differing decisions, and differing claims
+/// of tokens accepted by both, are logged and counted.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub authority: Authority,
+    /// The claims that have to match if both authorities accept the token
+    pub claims: Vec<Claim>,
+}
+
+/// The outcome of a comparison, as reported in explain mode
+#[derive(Debug, Serialize)]
+pub struct ComparisonOutcome {
+    pub authority: String,
+    pub valid: bool,
+    pub outcome: &'static str,
+    pub mismatched_claims: Vec<String>,
+}
+
+impl Comparison {
+    /// Verify the token with the compared authority and compare the result with the enforced one
+    pub fn compare(
+        &self,
+        validator: &str,
+        token: &JwtRef,
+        enforced: &Result<JWTClaims, AuthorityError>,
+    ) -> ComparisonOutcome {
+        let compared = self.authority.validate(token, Phases::disabled());
+
+        let mut mismatched_claims = Vec::new();
+        let outcome = match (enforced, &compared) {
+            (Ok(enforced), Ok(compared)) => {
+                mismatched_claims = self
+                    .claims
+                    .iter()
+                    .filter(|claim| enforced.values(claim).ok() != compared.values(claim).ok())
+                    .map(|claim| claim.to_string())
+                    .collect();
+                if mismatched_claims.is_empty() {
+                    "match"
+                } else {
+                    "claims_mismatch"
+                }
+            }
+            (Err(_), Err(_)) => "match",
+            _ => "decision_mismatch",
+        };
+
+        if outcome != "match" {
+            info!(
+                validator,
+                authority = self.authority.name(),
+                outcome,
+                "Compared authority decided differently (enforced valid: {}, compared valid: {}, mismatched claims: {:?})",
+                enforced.is_ok(),
+                compared.is_ok(),
+                mismatched_claims
+            );
+        }
+        metrics::AUTHORITY_COMPARISONS
+            .with_label_values(&[validator, self.authority.name(), outcome])
+            .inc();
+
+        ComparisonOutcome {
+            authority: self.authority.name().to_string(),
+            valid: compared.is_ok(),
+            outcome,
+            mismatched_claims,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use arc_swap::ArcSwapOption;
+use futures_util::future::join_all;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::metrics;
+use crate::validator_file::ConnectivityCheckConfig;
+use crate::validators::jwks::JwksState;
+
+/// How often to look for a newly configured check while checks are disabled
+const IDLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Periodic checks whether the JWKS hosts are reachable
+///
+/// Unlike the JWKS refreshes, which only happen when the keys are needed, the checks detect DNS
+/// or egress breakage before tokens start failing.
+#[derive(Debug, Clone)]
+pub struct Connectivity {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    settings: ArcSwapOption<Settings>,
+    /// Consecutive failed checks per JWKS URL
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+#[derive(Debug)]
+pub struct Settings {
+    pub interval: Duration,
+    pub failure_threshold: u32,
+}
+
+impl Settings {
+    pub fn from_config(config: &ConnectivityCheckConfig) -> Self {
+        Self {
+            interval: Duration::from_secs(config.interval.unwrap_or(30).max(1)),
+            failure_threshold: config.failure_threshold.unwrap_or(3).max(1),
+        }
+    }
+}
+
+impl Connectivity {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                settings: ArcSwapOption::empty(),
+                failures: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn configure(&self, settings: Option<Settings>) {
+        self.inner.settings.store(settings.map(Arc::new));
+    }
+
+    /// The time until the next check should run
+    pub fn interval(&self) -> Duration {
+        self.inner
+            .settings
+            .load()
+            .as_ref()
+            .map_or(IDLE_INTERVAL, |settings| settings.interval)
+    }
+
+    /// The JWKS URLs that failed at least as many consecutive checks as allowed
+    pub fn unreachable(&self) -> Vec<String> {
+        let Some(settings) = self.inner.settings.load_full() else {
+            return Vec::new();
+        };
+
+        let mut unreachable = self
+            .inner
+            .failures
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter(|(_, failures)| **failures >= settings.failure_threshold)
+            .map(|(uri, _)| uri.clone())
+            .collect::<Vec<_>>();
+        unreachable.sort_unstable();
+        unreachable
+    }
+
+    /// Check every JWKS host once, if checks are configured
+    pub async fn check(&self, jwks: Vec<JwksState>) {
+        let enabled = self.inner.settings.load().is_some();
+        // Static keys aren't fetched from a host
+        let checks =
+            jwks.iter()
+                .filter(|jwks| enabled && !jwks.is_static())
+                .map(|jwks| async move {
+                    let reachable = match jwks.check_connectivity().await {
+                        Ok(status) if status.is_server_error() => {
+                            warn!("JWKS host of {} answered with {}", jwks.uri(), status);
+                            false
+                        }
+                        Ok(_) => true,
+                        Err(e) => {
+                            warn!("JWKS host of {} is unreachable: {}", jwks.uri(), e);
+                            false
+                        }
+                    };
+
+                    (jwks.uri(), reachable)
+                });
+        let results = join_all(checks).await;
+
+        let mut failures = self.inner.failures.lock().expect("lock poisoned");
+        let mut checked = HashMap::new();
+        for (uri, reachable) in results {
+            let count = match reachable {
+                true => 0,
+                false => failures.get(uri).copied().unwrap_or(0) + 1,
+            };
+            if reachable && failures.get(uri).is_some_and(|count| *count > 0) {
+                info!("JWKS host of {} is reachable again", uri);
+            }
+            debug!(
+                "Connectivity check of {}: {} consecutive failures",
+                uri, count
+            );
+
+            metrics::IDP_REACHABLE
+                .with_label_values(&[uri])
+                .set(reachable as i64);
+            checked.insert(uri.to_string(), count);
+        }
+
+        // Forget the URLs that are no longer used or checked
+        for uri in failures.keys().filter(|uri| !checked.contains_key(*uri)) {
+            let _ = metrics::IDP_REACHABLE.remove_label_values(&[uri]);
+        }
+        *failures = checked;
+    }
+}
@@ -0,0 +1,171 @@
+use http::HeaderValue;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::validator_file::{CookieConfig, SameSite};
+use crate::validators::claims::Claim;
+use crate::validators::request::ForwardedRequest;
+
+const DEFAULT_MAX_SIZE: usize = 1024;
+/// Browsers accept cookies (name, value and attributes) of up to 4096 bytes, leave some headroom
+const MAX_COOKIE_SIZE: usize = 4000;
+/// Room for the suffix of a chunk's name and the `=`, e.g. `.12=`
+const CHUNK_SUFFIX_SIZE: usize = 5;
+
+/// Everything but the `cookie-octet`s of RFC 6265, section 4.1.1 (non-ASCII is always encoded)
+const COOKIE_VALUE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'%')
+    .add(b',')
+    .add(b';')
+    .add(b'\\');
+
+/// A claim set as a cookie of the response
+///
+/// Values too large for a single cookie are split into chunks named `name.0`, `name.1`, ...
+#[derive(Debug, Clone)]
+pub struct CookieMapping {
+    pub claim: Claim,
+    pub name: String,
+    /// The attributes following the value, e.g. `; Max-Age=3600; Path=/; Secure; SameSite=Lax`
+    attributes: String,
+    /// The attributes identifying the cookie, used to expire it
+    scope: String,
+    max_size: usize,
+}
+
+impl CookieMapping {
+    pub fn compile(claim: &str, config: CookieConfig) -> Result<Self, &'static str> {
+        if config.name.is_empty() || !config.name.bytes().all(is_token) {
+            return Err("the name has to be a token");
+        }
+
+        let secure = config.secure.unwrap_or(true);
+        let same_site = config.same_site.unwrap_or(SameSite::Lax);
+        if same_site == SameSite::None && !secure {
+            // Browsers reject such cookies
+            return Err("same_site none requires a secure cookie");
+        }
+
+        let mut scope = String::new();
+        let path = config.path.as_deref().unwrap_or("/");
+        if !path.bytes().all(is_attribute_value) {
+            return Err("the path contains invalid characters");
+        }
+        let _ = write!(scope, "; Path={}", path);
+
+        if let Some(domain) = &config.domain {
+            if domain.is_empty() || !domain.bytes().all(is_attribute_value) {
+                return Err("the domain contains invalid characters");
+            }
+            let _ = write!(scope, "; Domain={}", domain);
+        }
+        if secure {
+            scope.push_str("; Secure");
+        }
+        if config.http_only.unwrap_or(false) {
+            scope.push_str("; HttpOnly");
+        }
+        scope.push_str(match same_site {
+            SameSite::Strict => "; SameSite=Strict",
+            SameSite::Lax => "; SameSite=Lax",
+            SameSite::None => "; SameSite=None",
+        });
+
+        let attributes = match config.max_age {
+            Some(max_age) => format!("; Max-Age={}{}", max_age, scope),
+            None => scope.clone(),
+        };
+        if config.name.len() + CHUNK_SUFFIX_SIZE + attributes.len() >= MAX_COOKIE_SIZE {
+            return Err("the name and attributes leave no room for the value");
+        }
+
+        Ok(Self {
+            claim: Claim::new(claim),
+            name: config.name,
+            attributes,
+            scope,
+            max_size: config.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+        })
+    }
+
+    /// The encoded value of the cookie, `None` if it exceeds the maximum size
+    pub fn value(&self, values: &[Cow<'_, str>]) -> Option<String> {
+        let mut value = String::new();
+        for (i, v) in values.iter().enumerate() {
+            if i > 0 {
+                value.push_str("%2C");
+            }
+            value.extend(utf8_percent_encode(v, COOKIE_VALUE));
+        }
+
+        (value.len() <= self.max_size).then_some(value)
+    }
+
+    /// The `Set-Cookie` headers for an encoded value, keyed by the cookie name
+    ///
+    /// The value is split into chunks if it exceeds the size browsers accept. Cookies the request
+    /// still carries from a previous value (e.g. the chunks of a longer one) are expired.
+    pub fn set_cookies(
+        &self,
+        value: &str,
+        request: &ForwardedRequest<'_>,
+    ) -> Vec<(String, HeaderValue)> {
+        let mut cookies = Vec::new();
+        if self.name.len() + 1 + value.len() + self.attributes.len() <= MAX_COOKIE_SIZE {
+            cookies.push((self.name.clone(), self.header(&self.name, value)));
+        } else {
+            let chunk_size =
+                MAX_COOKIE_SIZE - self.name.len() - CHUNK_SUFFIX_SIZE - self.attributes.len();
+            // The value is percent-encoded, so it only contains ASCII
+            for (i, chunk) in value.as_bytes().chunks(chunk_size).enumerate() {
+                let name = format!("{}.{}", self.name, i);
+                let chunk = std::str::from_utf8(chunk).expect("the value is ASCII");
+                cookies.push((name.clone(), self.header(&name, chunk)));
+            }
+        }
+
+        let stale = request
+            .cookies()
+            .map(|(name, _)| name)
+            .filter(|name| self.is_own(name) && !cookies.iter().any(|(set, _)| set == name))
+            .collect::<HashSet<_>>();
+        for name in stale {
+            let expired = format!("{}=; Max-Age=0{}", name, self.scope);
+            cookies.push((
+                name.to_string(),
+                HeaderValue::try_from(expired).expect("the name and attributes are validated"),
+            ));
+        }
+
+        cookies
+    }
+
+    fn header(&self, name: &str, value: &str) -> HeaderValue {
+        HeaderValue::try_from(format!("{}={}{}", name, value, self.attributes))
+            .expect("the name, value and attributes are validated")
+    }
+
+    /// Whether the cookie is this one or one of its chunks
+    fn is_own(&self, name: &str) -> bool {
+        match name.strip_prefix(self.name.as_str()) {
+            Some("") => true,
+            Some(suffix) => suffix.strip_prefix('.').is_some_and(|index| {
+                !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit())
+            }),
+            None => false,
+        }
+    }
+}
+
+/// A `tchar` of RFC 9110, section 5.6.2
+pub fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn is_attribute_value(b: u8) -> bool {
+    b.is_ascii_graphic() && b != b';'
+}
@@ -0,0 +1,332 @@
+use arc_swap::ArcSwapOption;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metrics;
+use crate::utils::clock;
+use crate::validator_file::DecisionCacheConfig;
+use crate::validators::claims::JWTClaims;
+
+/// How many entries are looked at to choose the one evicted from a full cache
+const EVICTION_SAMPLE: usize = 16;
+
+/// The verified claims of tokens, so repeated requests with the same token skip the signature
+/// check, the subject check and the enrichments
+///
+/// Only accepted tokens are cached, rejected ones are checked again as the JWKS may have been
+/// refreshed in the meantime. The required claims are still evaluated for every request, as they
+/// may depend on the request. Entries are keyed by the configuration version, so a reload never
+/// answers from claims checked under the previous configuration.
+#[derive(Debug)]
+pub struct DecisionCache {
+    settings: ArcSwapOption<Settings>,
+    /// Incremented whenever a configuration is activated
+    version: AtomicU64,
+    entries: DashMap<Key, Cached>,
+}
+
+#[derive(Debug)]
+pub struct Settings {
+    pub ttl: Duration,
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    version: u64,
+    validator: String,
+    token: String,
+}
+
+#[derive(Debug)]
+struct Cached {
+    claims: JWTClaims,
+    /// Seconds since the epoch
+    inserted: u64,
+    /// Seconds since the epoch
+    expires: u64,
+}
+
+impl Settings {
+    pub fn from_config(config: &DecisionCacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_seconds.unwrap_or(60)),
+            max_entries: config.max_entries.unwrap_or(10_000),
+        }
+    }
+}
+
+impl DecisionCache {
+    pub fn new() -> Self {
+        Self {
+            settings: ArcSwapOption::empty(),
+            version: AtomicU64::new(0),
+            entries: DashMap::new(),
+        }
+    }
+
+    pub fn configure(&self, settings: Option<Settings>) {
+        self.settings.store(settings.map(Arc::new));
+    }
+
+    /// The current configuration version, to be read before the validator is looked up
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Forget every entry, called after a configuration was activated
+    ///
+    /// Requests still evaluating with the previous configuration insert their entries under the
+    /// previous version, so they are never used.
+    pub fn invalidate(&self) {
+        self.version.fetch_add(1, Ordering::AcqRel);
+        self.entries.clear();
+    }
+
+    /// The claims of a token accepted by the validator under the configuration version and their
+    /// age in seconds, if cached
+    pub fn get(&self, version: u64, validator: &str, token: &str) -> Option<(JWTClaims, u64)> {
+        self.settings.load().as_ref()?;
+
+        let key = Key {
+            version,
+            validator: validator.to_string(),
+            token: token.to_string(),
+        };
+        let now = now();
+        let claims = self
+            .entries
+            .get(&key)
+            .filter(|cached| cached.expires > now)
+            .map(|cached| (cached.claims.clone(), now.saturating_sub(cached.inserted)));
+
+        let outcome = if claims.is_some() { "hit" } else { "miss" };
+        metrics::DECISION_CACHE_LOOKUPS
+            .with_label_values(&[outcome])
+            .inc();
+        claims
+    }
+
+    /// Like [`get`](Self::get), but also the claims of entries that expired less than `grace` ago,
+    /// as long as the token itself hasn't expired
+    ///
+    /// Used while the keys are refreshed or unavailable, the lookup isn't counted as the regular
+    /// one follows if nothing is found.
+    pub fn get_stale(
+        &self,
+        version: u64,
+        validator: &str,
+        token: &str,
+        grace: Duration,
+    ) -> Option<(JWTClaims, u64)> {
+        self.settings.load().as_ref()?;
+
+        let key = Key {
+            version,
+            validator: validator.to_string(),
+            token: token.to_string(),
+        };
+        let now = now();
+        self.entries
+            .get(&key)
+            .filter(|cached| cached.expires + grace.as_secs() > now)
+            .filter(|cached| cached.claims.exp.is_none_or(|exp| exp.0 > now))
+            .map(|cached| (cached.claims.clone(), now.saturating_sub(cached.inserted)))
+    }
+
+    /// Cache the claims of an accepted token, at most until the token expires
+    pub fn insert(&self, version: u64, validator: &str, token: &str, claims: &JWTClaims) {
+        let Some(settings) = self.settings.load_full() else {
+            return;
+        };
+
+        let now = now();
+        let mut expires = now + settings.ttl.as_secs();
+        if let Some(exp) = claims.exp {
+            expires = expires.min(exp.0);
+        }
+        if expires <= now {
+            return;
+        }
+
+        let key = Key {
+            version,
+            validator: validator.to_string(),
+            token: token.to_string(),
+        };
+        while self.entries.len() >= settings.max_entries && !self.entries.contains_key(&key) {
+            if !self.evict(now) {
+                return;
+            }
+        }
+
+        self.entries.insert(
+            key,
+            Cached {
+                claims: claims.clone(),
+                inserted: now,
+                expires,
+            },
+        );
+    }
+
+    /// Make room in a full cache, removing the expired entries of a sample or else the one
+    /// expiring first, whether an entry was removed
+    ///
+    /// Sampling keeps inserting into a full cache cheap, instead of scanning every entry.
+    fn evict(&self, now: u64) -> bool {
+        let mut expired = Vec::new();
+        let mut first: Option<(Key, u64)> = None;
+        for entry in self.entries.iter().take(EVICTION_SAMPLE) {
+            let expires = entry.value().expires;
+            if expires <= now {
+                expired.push(entry.key().clone());
+            } else if first.as_ref().is_none_or(|(_, first)| expires < *first) {
+                first = Some((entry.key().clone(), expires));
+            }
+        }
+
+        // Removed after iterating, as the iterator holds the locks of the shards
+        if expired.is_empty() {
+            expired.extend(first.map(|(key, _)| key));
+        }
+        let mut evicted = false;
+        for key in expired {
+            evicted |= self.entries.remove(&key).is_some();
+        }
+        evicted
+    }
+}
+
+fn now() -> u64 {
+    clock::unix_now().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::FixedClock;
+    use serde_json::json;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn cache(ttl: u64, max_entries: usize) -> DecisionCache {
+        let cache = DecisionCache::new();
+        cache.configure(Some(Settings {
+            ttl: Duration::from_secs(ttl),
+            max_entries,
+        }));
+        cache
+    }
+
+    fn claims(exp: u64) -> JWTClaims {
+        serde_json::from_value(json!({ "sub": "alice", "exp": exp }))
+            .expect("claims should deserialize")
+    }
+
+    #[test]
+    fn expires_after_ttl() {
+        let time = FixedClock::at(NOW);
+        clock::install(time.clone());
+        let cache = cache(60, 10);
+        cache.insert(0, "api", "token", &claims(NOW + 3600));
+
+        time.advance(Duration::from_secs(59));
+        let (_, age) = cache
+            .get(0, "api", "token")
+            .expect("entry should be cached");
+        assert_eq!(age, 59);
+
+        time.advance(Duration::from_secs(1));
+        assert!(cache.get(0, "api", "token").is_none());
+    }
+
+    #[test]
+    fn expires_with_token() {
+        let time = FixedClock::at(NOW);
+        clock::install(time.clone());
+        let cache = cache(60, 10);
+        cache.insert(0, "api", "token", &claims(NOW + 10));
+
+        time.advance(Duration::from_secs(9));
+        assert!(cache.get(0, "api", "token").is_some());
+        time.advance(Duration::from_secs(1));
+        assert!(cache.get(0, "api", "token").is_none());
+    }
+
+    #[test]
+    fn serves_stale_within_grace() {
+        let time = FixedClock::at(NOW);
+        clock::install(time.clone());
+        let cache = cache(60, 10);
+        cache.insert(0, "api", "token", &claims(NOW + 90));
+        let grace = Duration::from_secs(60);
+
+        time.advance(Duration::from_secs(70));
+        assert!(cache.get(0, "api", "token").is_none());
+        assert!(cache.get_stale(0, "api", "token", grace).is_some());
+
+        // Never past the expiry of the token
+        time.advance(Duration::from_secs(20));
+        assert!(cache.get_stale(0, "api", "token", grace).is_none());
+    }
+
+    #[test]
+    fn evicts_when_full() {
+        let time = FixedClock::at(NOW);
+        clock::install(time.clone());
+        let cache = cache(60, 3);
+        for token in ["a", "b", "c"] {
+            cache.insert(0, "api", token, &claims(NOW + 3600));
+            time.advance(Duration::from_secs(1));
+        }
+
+        cache.insert(0, "api", "d", &claims(NOW + 3600));
+        assert_eq!(cache.entries.len(), 3);
+        assert!(cache.get(0, "api", "d").is_some());
+        // The entry expiring first makes room
+        assert!(cache.get(0, "api", "a").is_none());
+
+        // Replacing an entry evicts nothing
+        cache.insert(0, "api", "d", &claims(NOW + 3600));
+        for token in ["b", "c", "d"] {
+            assert!(cache.get(0, "api", token).is_some());
+        }
+    }
+
+    #[test]
+    fn evicts_expired_first() {
+        let time = FixedClock::at(NOW);
+        clock::install(time.clone());
+        let cache = cache(60, 3);
+        cache.insert(0, "api", "a", &claims(NOW + 3600));
+        cache.insert(0, "api", "b", &claims(NOW + 10));
+        cache.insert(0, "api", "c", &claims(NOW + 10));
+
+        time.advance(Duration::from_secs(10));
+        cache.insert(0, "api", "d", &claims(NOW + 3600));
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.get(0, "api", "a").is_some());
+        assert!(cache.get(0, "api", "d").is_some());
+    }
+
+    #[test]
+    fn invalidates_previous_version() {
+        clock::install(FixedClock::at(NOW));
+        let cache = cache(60, 10);
+        let version = cache.version();
+        cache.insert(version, "api", "token", &claims(NOW + 3600));
+
+        cache.invalidate();
+        assert_ne!(cache.version(), version);
+        assert!(cache.get(version, "api", "token").is_none());
+        assert!(cache.get(cache.version(), "api", "token").is_none());
+
+        // A request still evaluating under the previous version never answers the next ones
+        cache.insert(version, "api", "token", &claims(NOW + 3600));
+        assert!(cache.get(cache.version(), "api", "token").is_none());
+    }
+}
@@ -0,0 +1,189 @@
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http::{header, HeaderMap};
+use serde::Serialize;
+use tracing::info;
+
+use crate::validator_file::OutagePolicy;
+use crate::validators::authority::SelectedKey;
+use crate::validators::checks::ClaimEvaluation;
+use crate::validators::comparison::ComparisonOutcome;
+use crate::validators::ValidatorsState;
+
+/// The request header asking for a trace of the validation
+pub const EXPLAIN_HEADER: &str = "x-auth-explain";
+/// The request header carrying the admin token, as `Authorization` usually carries the JWT
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// A trace of the checks performed for a request
+///
+/// Steps are only built if the trace is enabled, so tracing costs nothing outside of explain mode.
+#[derive(Debug)]
+pub struct Trace {
+    steps: Option<Vec<Step>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    Maintenance,
+    Lookup {
+        found: bool,
+    },
+    Deny {
+        status: u16,
+    },
+    Transport {
+        proto: Option<String>,
+        port: Option<u16>,
+        accepted: bool,
+    },
+    Tenant {
+        tenant: String,
+        admitted: bool,
+    },
+    TokenSource {
+        header: String,
+        found: bool,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        prefixes: Vec<String>,
+        /// The prefix the header started with
+        prefix_stripped: Option<String>,
+    },
+    TokenQuery {
+        param: String,
+        found: bool,
+    },
+    TokenCookie {
+        cookie: String,
+        found: bool,
+    },
+    TokenLimits {
+        length: usize,
+        accepted: bool,
+    },
+    Encoding {
+        valid_utf8: bool,
+    },
+    JwksRefresh {
+        stale_for_seconds: u64,
+        blocking: bool,
+    },
+    Outage {
+        policy: OutagePolicy,
+        circuit_open: bool,
+    },
+    DecisionCache {
+        hit: bool,
+    },
+    /// Answered from the decision cache while the keys are refreshed or unavailable
+    StaleDecision {
+        age_seconds: u64,
+        refreshing: bool,
+    },
+    Key(SelectedKey),
+    Verification {
+        authority: String,
+        valid: bool,
+        error: Option<String>,
+    },
+    Issuer {
+        issuer: Option<String>,
+        authority: Option<String>,
+    },
+    Comparison(ComparisonOutcome),
+    Subject {
+        subject: Option<String>,
+        accepted: bool,
+    },
+    Enrichment {
+        enrichment: String,
+        outcome: &'static str,
+    },
+    Claim(ClaimEvaluation),
+    Policy {
+        expression: String,
+        accepted: bool,
+        error: Option<String>,
+    },
+    Opa {
+        url: String,
+        allowed: bool,
+        error: Option<String>,
+    },
+    MapClaims {
+        headers: Vec<String>,
+        cookies: Vec<String>,
+    },
+    Timeout,
+}
+
+#[derive(Debug, Serialize)]
+struct Explanation<'a> {
+    validator: &'a str,
+    status: u16,
+    steps: Vec<Step>,
+}
+
+impl Trace {
+    /// Enable the trace if the request asks for it and carries a valid admin token
+    pub fn for_request(validators: &ValidatorsState, headers: &HeaderMap) -> Self {
+        let requested = headers
+            .get(EXPLAIN_HEADER)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"true"));
+        if !requested {
+            return Self::disabled();
+        }
+
+        // Any scope grants the read access explain mode needs
+        let authorized = match (validators.admin_auth(), headers.get(ADMIN_TOKEN_HEADER)) {
+            (Some(auth), Some(provided)) => auth.verify(provided.as_bytes()).is_some(),
+            _ => false,
+        };
+        if !authorized {
+            info!("Ignoring explain request without a valid admin token");
+            return Self::disabled();
+        }
+
+        Self {
+            steps: Some(Vec::new()),
+        }
+    }
+
+    /// A trace that records nothing
+    pub fn disabled() -> Self {
+        Self { steps: None }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.steps.is_some()
+    }
+
+    /// Record a step, the step is only built if the trace is enabled
+    #[inline]
+    pub fn record(&mut self, step: impl FnOnce() -> Step) {
+        if let Some(steps) = &mut self.steps {
+            steps.push(step());
+        }
+    }
+
+    /// Replace the body of the response with the trace, keeping the status and headers
+    pub fn explain(self, validator: &str, response: Response) -> Response {
+        let Some(steps) = self.steps else {
+            return response;
+        };
+
+        let (mut parts, _) = response.into_parts();
+        parts.headers.remove(header::CONTENT_TYPE);
+        parts.headers.remove(header::CONTENT_LENGTH);
+
+        let explanation = Explanation {
+            validator,
+            status: parts.status.as_u16(),
+            steps,
+        };
+
+        (parts.status, parts.headers, Json(explanation)).into_response()
+    }
+}
@@ -0,0 +1,107 @@
+use http::{HeaderMap, HeaderValue};
+use std::fmt;
+
+use crate::validator_file::{HeaderLimitsConfig, LimitPolicy};
+
+/// Limits on the headers a validator maps claims to, for upstream services with small header
+/// limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// The size of a single header value in bytes
+    pub max_value_size: Option<usize>,
+    pub max_headers: Option<usize>,
+    pub policy: LimitPolicy,
+}
+
+/// A mapped header that exceeds the limits
+#[derive(Debug)]
+pub enum HeaderLimitError {
+    ValueSize {
+        header: String,
+        size: usize,
+        max: usize,
+    },
+    Count {
+        count: usize,
+        max: usize,
+    },
+}
+
+impl HeaderLimits {
+    pub fn from_config(config: HeaderLimitsConfig) -> Self {
+        Self {
+            max_value_size: config.max_value_size,
+            max_headers: config.max_headers,
+            policy: config.policy,
+        }
+    }
+
+    /// Apply the limits to the mapped headers
+    ///
+    /// With the truncate policy, values are cut to the maximum size and only the alphabetically
+    /// first headers are kept, the returned errors describe what was changed. With the reject
+    /// policy, the first exceeded limit is returned as the error.
+    pub fn apply(
+        &self,
+        headers: HeaderMap,
+    ) -> Result<(HeaderMap, Vec<HeaderLimitError>), HeaderLimitError> {
+        let mut exceeded = Vec::new();
+
+        let mut names = headers.keys().cloned().collect::<Vec<_>>();
+        names.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+        if let Some(max) = self.max_headers.filter(|max| names.len() > *max) {
+            let error = HeaderLimitError::Count {
+                count: names.len(),
+                max,
+            };
+            match self.policy {
+                LimitPolicy::Reject => return Err(error),
+                LimitPolicy::Truncate => exceeded.push(error),
+            }
+            names.truncate(max);
+        }
+
+        let mut limited = HeaderMap::with_capacity(names.len());
+        for name in names {
+            for value in headers.get_all(&name) {
+                let value = match self.max_value_size.filter(|max| value.len() > *max) {
+                    Some(max) => {
+                        let error = HeaderLimitError::ValueSize {
+                            header: name.to_string(),
+                            size: value.len(),
+                            max,
+                        };
+                        match self.policy {
+                            LimitPolicy::Reject => return Err(error),
+                            LimitPolicy::Truncate => exceeded.push(error),
+                        }
+                        // A prefix of a valid value only consists of valid bytes as well
+                        HeaderValue::from_bytes(&value.as_bytes()[..max])
+                            .expect("a prefix of a header value is a valid header value")
+                    }
+                    None => value.clone(),
+                };
+                limited.append(&name, value);
+            }
+        }
+
+        Ok((limited, exceeded))
+    }
+}
+
+impl fmt::Display for HeaderLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ValueSize { header, size, max } => write!(
+                f,
+                "header {} is {} bytes long, at most {} are allowed",
+                header, size, max
+            ),
+            Self::Count { count, max } => write!(
+                f,
+                "{} headers are mapped, at most {} are allowed",
+                count, max
+            ),
+        }
+    }
+}
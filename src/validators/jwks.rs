@@ -1,15 +1,42 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fmt;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::metrics;
 use crate::utils::atomic_instant::AtomicInstant;
+use crate::utils::clock;
+use crate::validator_file::{HttpClientConfig, KeyFilter, StaticKeys};
+use crate::validators::certificates::TrustAnchors;
+use crate::validators::jwks_cache::{CachedJwks, JwksCache};
+use crate::validators::static_keys::{self, StaticKeyError};
 use aliri::Jwks;
-use arc_swap::{ArcSwap, Guard};
+use arc_swap::{ArcSwap, ArcSwapOption, Guard};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use dashmap::DashMap;
-use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt};
 use http::{header, HeaderValue, StatusCode};
-use tracing::{debug, warn};
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use x509_parser::pem::Pem;
+
+/// The number of consecutive failed refreshes after which the circuit opens
+const CIRCUIT_THRESHOLD: u32 = 3;
+/// While the circuit is open, requests don't trigger refreshes until this long after the last
+/// failure, so an unavailable identity provider isn't called on every request
+const CIRCUIT_COOLDOWN: Duration = Duration::from_secs(30);
+/// The refreshes of a batch (on startup or after a reload) fetched at once, so configurations
+/// with hundreds of authorities don't call all identity providers in a burst
+const REFRESH_CONCURRENCY: usize = 16;
+/// States that served requests this recently are refreshed before the idle ones of a batch
+const ACTIVE_WINDOW: Duration = Duration::from_secs(600);
 
 #[derive(Debug, Clone)]
 pub struct JwksState {
@@ -19,9 +46,20 @@ pub struct JwksState {
 #[derive(Debug)]
 struct JwksStateInner {
     uri: String,
+    /// The keys of the document that are loaded
+    filter: KeyFilter,
+    /// The locally configured keys, loaded instead of fetching the URI
+    static_keys: Option<StaticKeys>,
     volatile: ArcSwap<Volatile>,
     last_refresh: AtomicInstant,
-    client: reqwest::Client,
+    last_failure: ArcSwapOption<Failure>,
+    consecutive_failures: AtomicU32,
+    /// The requests verified with the keys, to prioritize the refreshes of active states
+    requests: AtomicU64,
+    last_request: AtomicInstant,
+    refresh_lock: Mutex<()>,
+    client: ArcSwap<reqwest::Client>,
+    cache: Option<Arc<JwksCache>>,
 }
 
 #[derive(Debug)]
@@ -31,18 +69,67 @@ struct Volatile {
     last_modified: Option<HeaderValue>,
 }
 
+#[derive(Debug)]
+pub enum JwksError {
+    Http(reqwest::Error),
+    /// The document is not a valid JWKS, e.g. because it was truncated
+    Invalid(serde_json::Error),
+    /// The document exceeds the configured size
+    TooLarge {
+        limit: u64,
+    },
+    /// The document has more keys than configured
+    TooManyKeys {
+        keys: usize,
+        limit: usize,
+    },
+    Static(StaticKeyError),
+}
+
+/// The loaded keys and the outcome of the last refresh of a JWKS
+#[derive(Debug, Serialize)]
+pub struct JwksStatus {
+    pub url: String,
+    /// Authorities using the same URL with different filters have separate key sets
+    pub filter: KeyFilter,
+    pub keys: usize,
+    pub etag: Option<String>,
+    pub last_refresh: Option<String>,
+    /// The error of the last refresh, cleared by a successful refresh
+    pub last_error: Option<String>,
+    pub last_error_at: Option<String>,
+    pub consecutive_failures: u32,
+    pub circuit_open: bool,
+    /// The requests verified with the keys since the state was created
+    pub requests: u64,
+}
+
+#[derive(Debug)]
+struct Failure {
+    error: String,
+    at: SystemTime,
+}
+
 pub struct JwksGuard {
     inner: Guard<Arc<Volatile>>,
 }
 
 #[derive(Debug)]
 pub struct JwksStore {
-    states: DashMap<String, JwksState>,
-    client: reqwest::Client,
+    /// Authorities using the same URI with different key filters have separate states
+    states: DashMap<(String, KeyFilter), JwksState>,
+    cache: Option<Arc<JwksCache>>,
+    clients: ArcSwap<HashMap<HttpClientConfig, reqwest::Client>>,
 }
 
 impl JwksState {
-    fn new(uri: String, client: reqwest::Client) -> Self {
+    fn new(
+        uri: String,
+        filter: KeyFilter,
+        static_keys: Option<StaticKeys>,
+        client: reqwest::Client,
+        cache: Option<Arc<JwksCache>>,
+    ) -> Self {
         let volatile = Arc::new(Volatile {
             jwks: Jwks::default(),
             etag: None,
@@ -51,12 +138,57 @@ impl JwksState {
 
         let inner = Arc::new(JwksStateInner {
             uri,
+            filter,
+            static_keys,
             volatile: ArcSwap::from(volatile),
             last_refresh: AtomicInstant::empty(),
-            client,
+            last_failure: ArcSwapOption::empty(),
+            consecutive_failures: AtomicU32::new(0),
+            requests: AtomicU64::new(0),
+            last_request: AtomicInstant::empty(),
+            refresh_lock: Mutex::new(()),
+            client: ArcSwap::from_pointee(client),
+            cache,
         });
 
-        Self { inner }
+        let state = Self { inner };
+        if state.inner.static_keys.is_none() {
+            state.restore();
+        }
+        state
+    }
+
+    /// Load the keys cached by a previous run, until they are replaced by the first refresh
+    ///
+    /// The last refresh is left unset, so the state is still refreshed as a new one.
+    fn restore(&self) {
+        let Some(cached) = self
+            .inner
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.load(&self.inner.uri))
+        else {
+            return;
+        };
+
+        match self.parse(cached.document.as_bytes()) {
+            Ok(jwks) => {
+                info!(
+                    "Restored {} cached keys of JWKS {}",
+                    jwks.keys().len(),
+                    self.inner.uri
+                );
+                metrics::JWKS_KEYS
+                    .with_label_values(&[&self.inner.uri])
+                    .set(jwks.keys().len() as i64);
+                self.inner.volatile.store(Arc::new(Volatile {
+                    jwks,
+                    etag: cached.etag(),
+                    last_modified: cached.last_modified(),
+                }));
+            }
+            Err(e) => warn!("Ignoring the cached JWKS of {}: {}", self.inner.uri, e),
+        }
     }
 
     /// Get the URI of the JWKS
@@ -65,6 +197,12 @@ impl JwksState {
         &self.inner.uri
     }
 
+    /// Whether the keys are configured locally instead of fetched from the URI
+    #[inline]
+    pub fn is_static(&self) -> bool {
+        self.inner.static_keys.is_some()
+    }
+
     /// Get the JWKS
     #[inline]
     pub fn jwks(&self) -> JwksGuard {
@@ -79,26 +217,149 @@ impl JwksState {
         self.inner.last_refresh.to_system_time()
     }
 
-    /// Manually update the JWKS
-    pub fn update(&self, jwks: Jwks) {
-        let volatile = Arc::new(Volatile {
-            jwks,
-            etag: None,
-            last_modified: None,
-        });
+    /// The number of refreshes that failed since the last successful one
+    #[inline]
+    pub fn consecutive_failures(&self) -> u32 {
+        self.inner.consecutive_failures.load(Ordering::Relaxed)
+    }
 
-        self.inner.volatile.store(volatile);
-        self.inner.last_refresh.to_now();
+    /// Whether refreshes failed repeatedly and requests shouldn't trigger another one yet
+    ///
+    /// The circuit half-opens once the cooldown passed, the next refresh closes it again on success.
+    pub fn circuit_open(&self) -> bool {
+        if self.consecutive_failures() < CIRCUIT_THRESHOLD {
+            return false;
+        }
+
+        self.inner
+            .last_failure
+            .load()
+            .as_ref()
+            .is_some_and(|failure| {
+                clock::elapsed(failure.at).is_ok_and(|elapsed| elapsed < CIRCUIT_COOLDOWN)
+            })
+    }
+
+    /// Count a request verified with the keys
+    #[inline]
+    pub fn record_request(&self) {
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.last_request.to_now();
+    }
+
+    /// Whether the keys verified requests recently, idle states are refreshed last
+    fn is_active(&self) -> bool {
+        self.inner
+            .last_request
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed < ACTIVE_WINDOW)
+    }
+
+    /// Replace the HTTP client used to refresh the JWKS
+    pub fn set_client(&self, client: reqwest::Client) {
+        self.inner.client.store(Arc::new(client));
+    }
+
+    /// Check that the JWKS host answers, without fetching the JWKS
+    pub async fn check_connectivity(&self) -> Result<StatusCode, reqwest::Error> {
+        let res = self
+            .inner
+            .client
+            .load()
+            .head(&self.inner.uri)
+            .send()
+            .await?;
+        Ok(res.status())
+    }
+
+    /// Refresh the JWKS unless it has been refreshed within `max_age`
+    ///
+    /// Concurrent callers wait for a single refresh instead of each fetching the JWKS
+    pub async fn refresh_stale(&self, max_age: Duration) -> Result<(), JwksError> {
+        let _guard = self.inner.refresh_lock.lock().await;
+
+        let fresh = clock::elapsed(self.last_refresh()).is_ok_and(|elapsed| elapsed <= max_age);
+        if fresh {
+            debug!("JWKS was refreshed while waiting");
+            return Ok(());
+        }
+
+        self.refresh().await
     }
 
     /// Automatically refresh the JWKS from the remote URI
+    ///
+    /// The previous keys are kept if the refresh fails, the failure is recorded for the status.
     #[tracing::instrument(skip(self), fields(jwks.url = tracing::field::Empty))]
-    pub async fn refresh(&self) -> Result<(), reqwest::Error> {
+    pub async fn refresh(&self) -> Result<(), JwksError> {
         let span = tracing::Span::current();
-        span.record("jwks.url", &self.inner.uri.as_str());
+        span.record("jwks.url", self.inner.uri.as_str());
         debug!("refreshing JWKS");
 
-        let mut req = self.inner.client.get(&self.inner.uri);
+        let outcome = match self.fetch().await {
+            Ok(true) => {
+                let keys = self.jwks().keys().len();
+                metrics::JWKS_KEYS
+                    .with_label_values(&[&self.inner.uri])
+                    .set(keys as i64);
+                debug!("JWKS refreshed successfully, loaded {} keys", keys);
+                Ok("updated")
+            }
+            Ok(false) => {
+                debug!("JWKS not modified");
+                Ok("not_modified")
+            }
+            Err(err) => Err(err),
+        };
+
+        let reason = match &outcome {
+            Ok(outcome) => *outcome,
+            Err(err) => err.reason(),
+        };
+        metrics::JWKS_REFRESHES
+            .with_label_values(&[&self.inner.uri, reason])
+            .inc();
+
+        match outcome {
+            Ok(_) => {
+                self.inner.last_failure.store(None);
+                self.inner.consecutive_failures.store(0, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(err) => {
+                let error: &dyn std::error::Error = &err;
+                warn!(
+                    error,
+                    "JWKS refresh failed; keeping the previous {} keys",
+                    self.jwks().keys().len()
+                );
+                self.inner.last_failure.store(Some(Arc::new(Failure {
+                    error: err.to_string(),
+                    at: clock::now(),
+                })));
+                let failures = self
+                    .inner
+                    .consecutive_failures
+                    .fetch_add(1, Ordering::Relaxed)
+                    + 1;
+                if failures == CIRCUIT_THRESHOLD {
+                    warn!(
+                        "JWKS refresh failed {} times in a row, opening the circuit",
+                        failures
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Fetch the JWKS, returns whether it changed
+    async fn fetch(&self) -> Result<bool, JwksError> {
+        if let Some(keys) = &self.inner.static_keys {
+            return self.load_static(keys);
+        }
+
+        let mut req = self.inner.client.load().get(&self.inner.uri);
 
         {
             let volatile = self.inner.volatile.load();
@@ -111,20 +372,13 @@ impl JwksState {
             }
         }
 
-        let res = req.send().await?;
+        let mut res = req.send().await?;
 
         if res.status() == StatusCode::NOT_MODIFIED {
-            debug!("JWKS not modified");
-            return Ok(());
-        } else if let Err(err) = res.error_for_status_ref() {
-            let error: &dyn std::error::Error = &err;
-            warn!(
-                error,
-                http.status_code = res.status().as_u16(),
-                "JWKS refresh failed; unexpected status code"
-            );
-            return Err(err);
+            self.inner.last_refresh.to_now();
+            return Ok(false);
         }
+        res.error_for_status_ref()?;
 
         let etag = res.headers().get(header::ETAG).map(ToOwned::to_owned);
         let last_modified = res
@@ -132,27 +386,275 @@ impl JwksState {
             .get(header::LAST_MODIFIED)
             .map(ToOwned::to_owned);
 
-        match res.json::<Jwks>().await {
-            Ok(jwks) => {
-                let volatile = Arc::new(Volatile {
-                    jwks,
-                    etag,
-                    last_modified,
-                });
+        // Check the announced length first, but don't rely on it, it may be missing or wrong
+        let limit = self.inner.filter.max_document_bytes();
+        if res.content_length().is_some_and(|length| length > limit) {
+            return Err(JwksError::TooLarge { limit });
+        }
 
-                self.inner.volatile.store(volatile);
-                self.inner.last_refresh.to_now();
+        let mut document = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            if (document.len() + chunk.len()) as u64 > limit {
+                return Err(JwksError::TooLarge { limit });
             }
-            Err(err) => {
-                let error: &dyn std::error::Error = &err;
-                warn!(error, "JWKS refresh failed; invalid JWKS");
-                return Err(err);
+            document.extend_from_slice(&chunk);
+        }
+
+        let document = self.resolve_x5u(document).await;
+        let jwks = self.parse(&document)?;
+        if let Some(cache) = &self.inner.cache {
+            // The document is valid JSON at this point, which is always valid UTF-8
+            if let Ok(document) = String::from_utf8(document) {
+                let header = |value: &Option<HeaderValue>| {
+                    value
+                        .as_ref()
+                        .and_then(|v| v.to_str().ok())
+                        .map(ToOwned::to_owned)
+                };
+                cache.save(&CachedJwks {
+                    uri: self.inner.uri.clone(),
+                    etag: header(&etag),
+                    last_modified: header(&last_modified),
+                    document,
+                });
             }
+        }
+
+        let volatile = Arc::new(Volatile {
+            jwks,
+            etag,
+            last_modified,
+        });
+
+        self.inner.volatile.store(volatile);
+        self.inner.last_refresh.to_now();
+
+        Ok(true)
+    }
+
+    /// Load the locally configured keys, PEM files are read again on every refresh
+    fn load_static(&self, keys: &StaticKeys) -> Result<bool, JwksError> {
+        let document = static_keys::document(keys).map_err(JwksError::Static)?;
+        let jwks = self.parse(&document)?;
+
+        self.inner.volatile.store(Arc::new(Volatile {
+            jwks,
+            etag: None,
+            last_modified: None,
+        }));
+        self.inner.last_refresh.to_now();
+
+        Ok(true)
+    }
+
+    /// Fetch the certificate chains keys only reference by their `x5u`, adding them as `x5c`
+    ///
+    /// Only done if certificate chains are validated. Keys whose chain can't be fetched are left
+    /// as they are and ignored when the document is parsed.
+    async fn resolve_x5u(&self, document: Vec<u8>) -> Vec<u8> {
+        if self.inner.filter.certificates.is_none() {
+            return document;
+        }
+        let Ok(mut parsed) = serde_json::from_slice::<serde_json::Value>(&document) else {
+            return document;
+        };
+        let Some(keys) = parsed.get_mut("keys").and_then(|keys| keys.as_array_mut()) else {
+            return document;
         };
 
-        debug!("JWKS refreshed successfully");
+        let mut resolved = false;
+        let limit = self.inner.filter.max_keys();
+        for key in keys.iter_mut().take(limit) {
+            let Some(url) = key.get("x5u").and_then(|url| url.as_str()) else {
+                continue;
+            };
+            if key.get("x5c").is_some() {
+                continue;
+            }
 
-        Ok(())
+            match self.fetch_chain(url).await {
+                Ok(chain) => {
+                    key["x5c"] = serde_json::Value::from(chain);
+                    resolved = true;
+                }
+                Err(err) => warn!("Failed to fetch the certificate chain {}: {}", url, err),
+            }
+        }
+
+        match resolved {
+            true => serde_json::to_vec(&parsed).unwrap_or(document),
+            false => document,
+        }
+    }
+
+    /// Fetch a PEM certificate chain, returning the certificates as they appear in `x5c`
+    async fn fetch_chain(&self, url: &str) -> Result<Vec<String>, String> {
+        // The chain vouches for the key, so it has to be fetched over an authenticated channel
+        if !url.starts_with("https://") {
+            return Err("only https URLs are allowed".to_string());
+        }
+
+        let client = self.inner.client.load_full();
+        let mut res = client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let limit = self.inner.filter.max_document_bytes();
+        if res.content_length().is_some_and(|length| length > limit) {
+            return Err("the chain exceeds the maximum document size".to_string());
+        }
+        let mut pem = Vec::new();
+        while let Some(chunk) = res.chunk().await.map_err(|e| e.to_string())? {
+            if (pem.len() + chunk.len()) as u64 > limit {
+                return Err("the chain exceeds the maximum document size".to_string());
+            }
+            pem.extend_from_slice(&chunk);
+        }
+
+        Pem::iter_from_buffer(&pem)
+            .map(|pem| {
+                pem.map(|pem| STANDARD.encode(pem.contents))
+                    .map_err(|e| e.to_string())
+            })
+            .collect()
+    }
+
+    /// Parse a JWKS document, keeping only the keys accepted by the filter
+    fn parse(&self, document: &[u8]) -> Result<Jwks, JwksError> {
+        let mut document = serde_json::from_slice::<serde_json::Value>(document)?;
+
+        if let Some(keys) = document
+            .get_mut("keys")
+            .and_then(|keys| keys.as_array_mut())
+        {
+            let total = keys.len();
+            let limit = self.inner.filter.max_keys();
+            if total > limit {
+                return Err(JwksError::TooManyKeys { keys: total, limit });
+            }
+
+            keys.retain(|key| self.inner.filter.accepts(key));
+            if keys.len() < total {
+                debug!(
+                    "Ignoring {} of {} keys not usable for verification",
+                    total - keys.len(),
+                    total
+                );
+            }
+
+            if let Some(certificates) = &self.inner.filter.certificates {
+                // Read on every refresh, so changes to the file apply with the next refresh
+                let anchors = TrustAnchors::load(&certificates.ca_file, certificates.require)
+                    .unwrap_or_else(|err| {
+                        warn!(
+                            "Failed to load the certificate authorities of {}, trusting no key: {}",
+                            self.inner.uri, err
+                        );
+                        TrustAnchors::none()
+                    });
+                keys.retain(|key| match anchors.verify(key) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        warn!(
+                            kid = key.get("kid").and_then(|kid| kid.as_str()),
+                            "Ignoring key of {} with an untrusted certificate chain: {}",
+                            self.inner.uri,
+                            err
+                        );
+                        false
+                    }
+                });
+            }
+        }
+
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// The loaded keys and the outcome of the last refresh
+    pub fn status(&self) -> JwksStatus {
+        let last_refresh = self.last_refresh();
+        let failure = self.inner.last_failure.load();
+        let volatile = self.inner.volatile.load();
+
+        JwksStatus {
+            url: self.inner.uri.clone(),
+            filter: self.inner.filter.clone(),
+            keys: volatile.jwks.keys().len(),
+            etag: volatile
+                .etag
+                .as_ref()
+                .and_then(|etag| etag.to_str().ok())
+                .map(ToOwned::to_owned),
+            last_refresh: (last_refresh > UNIX_EPOCH).then(|| format_time(last_refresh)),
+            last_error: failure.as_ref().map(|failure| failure.error.clone()),
+            last_error_at: failure.as_ref().map(|failure| format_time(failure.at)),
+            consecutive_failures: self.consecutive_failures(),
+            circuit_open: self.circuit_open(),
+            requests: self.inner.requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+impl JwksError {
+    /// The reason of the failure, as used by the metrics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            JwksError::Http(e) if e.is_status() => "http_status",
+            JwksError::Http(_) => "http",
+            JwksError::Invalid(_) => "invalid",
+            JwksError::TooLarge { .. } => "too_large",
+            JwksError::TooManyKeys { .. } => "too_many_keys",
+            JwksError::Static(_) => "static_keys",
+        }
+    }
+}
+
+impl From<reqwest::Error> for JwksError {
+    fn from(e: reqwest::Error) -> Self {
+        JwksError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for JwksError {
+    fn from(e: serde_json::Error) -> Self {
+        JwksError::Invalid(e)
+    }
+}
+
+impl fmt::Display for JwksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwksError::Http(e) => write!(f, "failed to fetch the JWKS: {}", e),
+            JwksError::Invalid(e) => write!(f, "invalid JWKS: {}", e),
+            JwksError::TooLarge { limit } => {
+                write!(f, "the JWKS document exceeds the limit of {} bytes", limit)
+            }
+            JwksError::TooManyKeys { keys, limit } => write!(
+                f,
+                "the JWKS document has {} keys, more than the limit of {}",
+                keys, limit
+            ),
+            JwksError::Static(e) => write!(f, "failed to load the static keys: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JwksError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JwksError::Http(e) => Some(e),
+            JwksError::Invalid(e) => Some(e),
+            JwksError::Static(e) => Some(e),
+            JwksError::TooLarge { .. } | JwksError::TooManyKeys { .. } => None,
+        }
     }
 }
 
@@ -171,64 +673,115 @@ impl fmt::Debug for JwksGuard {
 }
 
 impl JwksStore {
-    /// Create a new JWKS store
-    ///
-    /// The store will use the provided `client` to fetch JWKS from remote URIs
-    pub fn new(client: reqwest::Client) -> Self {
+    /// Create a new JWKS store, fetched documents are persisted to the cache if given
+    pub fn new(cache: Option<JwksCache>) -> Self {
         Self {
             states: DashMap::new(),
-            client,
+            cache: cache.map(Arc::new),
+            clients: ArcSwap::from_pointee(HashMap::new()),
         }
     }
 
-    /// Ensure that a JWKS state exists for the given URI
-    pub fn ensure(&self, uri: &str) {
-        if self.states.get(uri).is_none() {
-            self.states
-                .entry(uri.to_string())
-                .or_insert_with(|| JwksState::new(uri.to_string(), self.client.clone()));
-        }
+    /// Update the HTTP clients, keyed by their settings
+    ///
+    /// Clients whose settings didn't change are kept, so their connection pools survive
+    /// a reload. Returns the clients that are in use after the update.
+    pub fn update_clients(
+        &self,
+        clients: &HashMap<HttpClientConfig, reqwest::Client>,
+    ) -> Arc<HashMap<HttpClientConfig, reqwest::Client>> {
+        let current = self.clients.load();
+        let updated = clients
+            .iter()
+            .map(|(config, client)| {
+                let client = current.get(config).unwrap_or(client).clone();
+                (config.clone(), client)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let updated = Arc::new(updated);
+        self.clients.store(updated.clone());
+        updated
     }
 
-    /// Get the JWKS state for the given URI
-    pub fn get(&self, uri: &str) -> JwksState {
-        if let Some(state) = self.states.get(uri) {
+    /// Get the JWKS state for the given URI and key filter, making sure it uses the given client
+    pub fn get(&self, uri: &str, filter: &KeyFilter, client: &reqwest::Client) -> JwksState {
+        let key = (uri.to_string(), filter.clone());
+        if let Some(state) = self.states.get(&key) {
+            state.value().set_client(client.clone());
             state.value().clone()
         } else {
             self.states
-                .entry(uri.to_string())
-                .or_insert_with(|| JwksState::new(uri.to_string(), self.client.clone()))
+                .entry(key)
+                .or_insert_with(|| {
+                    JwksState::new(
+                        uri.to_string(),
+                        filter.clone(),
+                        None,
+                        client.clone(),
+                        self.cache.clone(),
+                    )
+                })
                 .value()
                 .clone()
         }
     }
 
-    /// Get the URIs of all JWKS states
-    pub fn uris(&self) -> Vec<String> {
-        self.states
-            .iter()
-            .map(|entry| entry.key().clone())
-            .collect()
+    /// Get the JWKS state of an authority with static keys
+    ///
+    /// The state is replaced if the keys changed. It is identified as `static:<authority>` in
+    /// the status and the metrics.
+    pub fn get_static(
+        &self,
+        authority: &str,
+        keys: &StaticKeys,
+        filter: &KeyFilter,
+        client: &reqwest::Client,
+    ) -> JwksState {
+        let key = (format!("static:{}", authority), filter.clone());
+        if let Some(state) = self.states.get(&key) {
+            if state.value().inner.static_keys.as_ref() == Some(keys) {
+                state.value().set_client(client.clone());
+                return state.value().clone();
+            }
+        }
+
+        let state = JwksState::new(
+            key.0.clone(),
+            filter.clone(),
+            Some(keys.clone()),
+            client.clone(),
+            None,
+        );
+        self.states.insert(key, state.clone());
+        state
     }
 
-    /// Remove the JWKS state for the given URI
-    pub fn remove(&self, uri: &str) {
-        self.states.remove(uri);
+    /// The status of all JWKS states, sorted by their URI
+    pub fn statuses(&self) -> Vec<JwksStatus> {
+        let mut statuses = self
+            .states
+            .iter()
+            .map(|state| state.value().status())
+            .collect::<Vec<_>>();
+        statuses.sort_unstable_by(|a, b| a.url.cmp(&b.url));
+        statuses
     }
 
     /// Refresh all JWKS states
-    pub async fn refresh_all(&self) -> impl Iterator<Item = Result<(), reqwest::Error>> + '_ {
-        let futures = self.states.iter().map(|state| {
-            let state = state.value().clone();
-            async move { state.refresh().await }
-        });
+    pub async fn refresh_all(&self) -> impl Iterator<Item = Result<(), JwksError>> + '_ {
+        let states = self
+            .states
+            .iter()
+            .map(|state| state.value().clone())
+            .collect();
 
-        join_all(futures).await.into_iter()
+        refresh_prioritized(states).await.into_iter()
     }
 
     /// Refresh new JWKS states
-    pub async fn refresh_new(&self) -> impl Iterator<Item = Result<(), reqwest::Error>> + '_ {
-        let futures = self
+    pub async fn refresh_new(&self) -> impl Iterator<Item = Result<(), JwksError>> + '_ {
+        let states = self
             .states
             .iter()
             .filter(|state| {
@@ -239,11 +792,74 @@ impl JwksStore {
                     Err(_) => true,
                 }
             })
-            .map(|state| {
-                let state = state.value().clone();
-                async move { state.refresh().await }
-            });
+            .map(|state| state.value().clone())
+            .collect();
+
+        refresh_prioritized(states).await.into_iter()
+    }
+}
+
+/// Refresh a batch of states, at most [`REFRESH_CONCURRENCY`] at once
+///
+/// States that verified requests recently are refreshed first, the busiest of them before the
+/// others, so idle states don't delay the keys requests are waiting for.
+async fn refresh_prioritized(mut states: Vec<JwksState>) -> Vec<Result<(), JwksError>> {
+    states.sort_by_cached_key(|state| {
+        (
+            !state.is_active(),
+            Reverse(state.inner.requests.load(Ordering::Relaxed)),
+        )
+    });
+    let deferred = states.iter().filter(|state| !state.is_active()).count();
+    if deferred > 0 && deferred < states.len() {
+        debug!(
+            "Refreshing {} active JWKS before {} idle ones",
+            states.len() - deferred,
+            deferred
+        );
+    }
+
+    stream::iter(states)
+        .map(|state| async move { state.refresh().await })
+        .buffered(REFRESH_CONCURRENCY)
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::FixedClock;
+
+    /// Refused right away, so every refresh fails without waiting for a timeout
+    const UNREACHABLE: &str = "http://127.0.0.1:1/jwks.json";
+
+    #[tokio::test]
+    async fn circuit_half_opens_after_cooldown() {
+        let time = FixedClock::at(1_700_000_000);
+        clock::install(time.clone());
+        let state = JwksState::new(
+            UNREACHABLE.to_string(),
+            KeyFilter::default(),
+            None,
+            reqwest::Client::new(),
+            None,
+        );
+
+        for _ in 1..CIRCUIT_THRESHOLD {
+            assert!(state.refresh().await.is_err());
+            assert!(!state.circuit_open());
+        }
+        assert!(state.refresh().await.is_err());
+        assert!(state.circuit_open());
+
+        time.advance(CIRCUIT_COOLDOWN - Duration::from_secs(1));
+        assert!(state.circuit_open());
+        time.advance(Duration::from_secs(1));
+        assert!(!state.circuit_open());
 
-        join_all(futures).await.into_iter()
+        // The half-open refresh failing again opens the circuit for another cooldown
+        assert!(state.refresh().await.is_err());
+        assert!(state.circuit_open());
     }
 }
@@ -0,0 +1,106 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// Longer URIs are truncated, leaving room for the extension of temporary files
+const MAX_FILE_NAME_LEN: usize = 200;
+
+/// Fetched JWKS documents persisted to a directory, so they are available right after a restart
+///
+/// Together with the validators of the document (`ETag` and `Last-Modified`), this makes the first
+/// refresh after a restart a conditional request, instead of downloading every JWKS again.
+#[derive(Debug)]
+pub struct JwksCache {
+    dir: PathBuf,
+}
+
+/// A cached JWKS document, as returned by the server (before filtering its keys)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedJwks {
+    /// The URI of the JWKS, file names are only derived from it and may collide
+    pub uri: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub document: String,
+}
+
+impl JwksCache {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// The cached document of the URI, if any
+    pub fn load(&self, uri: &str) -> Option<CachedJwks> {
+        let path = self.path(uri);
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!("Failed to read cached JWKS {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        match serde_json::from_slice::<CachedJwks>(&content) {
+            Ok(cached) if cached.uri == uri => {
+                debug!("Loaded cached JWKS of {}", uri);
+                Some(cached)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Ignoring invalid cached JWKS {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Persist a fetched document, replacing the file atomically
+    pub fn save(&self, cached: &CachedJwks) {
+        let path = self.path(&cached.uri);
+        // Authorities using different key filters share the file and may save it concurrently
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+
+        let res = serde_json::to_vec(cached)
+            .map_err(io::Error::from)
+            .and_then(|content| fs::write(&tmp, content))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            warn!("Failed to cache JWKS in {}: {}", path.display(), e);
+            let _ = fs::remove_file(Path::new(&tmp));
+        }
+    }
+
+    fn path(&self, uri: &str) -> PathBuf {
+        let name = uri
+            .chars()
+            .take(MAX_FILE_NAME_LEN)
+            .map(
+                |c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    true => c,
+                    false => '_',
+                },
+            )
+            .collect::<String>();
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+impl CachedJwks {
+    pub fn etag(&self) -> Option<HeaderValue> {
+        self.etag
+            .as_deref()
+            .and_then(|v| HeaderValue::from_str(v).ok())
+    }
+
+    pub fn last_modified(&self) -> Option<HeaderValue> {
+        self.last_modified
+            .as_deref()
+            .and_then(|v| HeaderValue::from_str(v).ok())
+    }
+}
@@ -0,0 +1,242 @@
+use axum::body::Body;
+use axum::response::Response;
+use http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use tracing::debug;
+
+use crate::validator_file::LoginRedirectConfig;
+use crate::validators::request::ForwardedRequest;
+
+/// Redirects browsers to a login page instead of answering rejected requests with 401
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginRedirect {
+    url: String,
+    /// The query parameter carrying the original URL
+    param: String,
+    /// The lowercase hosts the original URL may have, a leading `*.` matches any subdomain
+    ///
+    /// Without them only the path of the original URL is passed.
+    allowed_hosts: Option<Vec<String>>,
+}
+
+impl LoginRedirect {
+    /// Check the login URL, the reason it is invalid otherwise
+    pub fn compile(config: LoginRedirectConfig) -> Result<Self, &'static str> {
+        let uri = config
+            .url
+            .parse::<Uri>()
+            .map_err(|_| "the url isn't a valid URL")?;
+        match uri.scheme_str() {
+            Some("http" | "https") if uri.host().is_some() => {}
+            Some(_) => return Err("the url has to be an http or https URL"),
+            None if config.url.starts_with('/') => {}
+            None => return Err("the url has to be absolute or start with /"),
+        }
+        if config.url.contains('#') {
+            return Err("the url must not have a fragment");
+        }
+
+        let param = config.param.unwrap_or_else(|| "rd".to_string());
+        if param.is_empty() {
+            return Err("the param must not be empty");
+        }
+
+        let allowed_hosts = config.allowed_return_hosts.map(|hosts| {
+            hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        });
+        if allowed_hosts
+            .iter()
+            .flatten()
+            .any(|host| !is_host_pattern(host))
+        {
+            return Err(
+                "the allowed_return_hosts have to be host names, optionally starting with *.",
+            );
+        }
+
+        Ok(Self {
+            url: config.url,
+            param,
+            allowed_hosts,
+        })
+    }
+
+    /// Turn a 401 for a request accepting HTML into a redirect to the login page
+    ///
+    /// The original URL (from `X-Forwarded-Proto`, `X-Forwarded-Host` and `X-Forwarded-Uri`, or
+    /// only its path without allowed hosts) is passed in the query parameter, so the login page can
+    /// send the user back. The reason and the other headers of the rejection are kept.
+    pub fn apply(&self, res: Response, headers: &HeaderMap) -> Response {
+        if res.status() != StatusCode::UNAUTHORIZED || !accepts_html(headers) {
+            return res;
+        }
+        let Ok(location) = HeaderValue::try_from(self.location(headers)) else {
+            return res;
+        };
+
+        let (mut parts, _) = res.into_parts();
+        parts.status = StatusCode::FOUND;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_TYPE);
+        parts.headers.remove(header::CONTENT_LANGUAGE);
+        parts.headers.remove(header::WWW_AUTHENTICATE);
+        parts.headers.insert(header::LOCATION, location);
+        // The redirect depends on the credentials of the request
+        parts
+            .headers
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+        Response::from_parts(parts, Body::empty())
+    }
+
+    fn location(&self, headers: &HeaderMap) -> String {
+        let request = ForwardedRequest::new(headers);
+        let original = match (
+            // The forwarded host can only be trusted if it is checked against the allowed hosts
+            request.proto().filter(|_| self.allowed_hosts.is_some()),
+            request.header("x-forwarded-host"),
+            request.uri(),
+        ) {
+            (Some(proto), Some(host), uri) if uri.is_none_or(|uri| uri.starts_with('/')) => {
+                Some(format!("{}://{}{}", proto, host, uri.unwrap_or("/")))
+            }
+            (_, _, Some(uri)) => Some(uri.to_string()),
+            _ => None,
+        };
+        let Some(original) = original.filter(|original| self.is_allowed(original)) else {
+            return self.url.clone();
+        };
+
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair(&self.param, &original)
+            .finish();
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", self.url, separator, query)
+    }
+
+    /// Whether the original URL may be passed to the login page, which sends the user there
+    ///
+    /// Paths have to stay on the host (`//host` would leave it), absolute URLs need an `http` or
+    /// `https` scheme, no user info and an explicitly allowed host.
+    fn is_allowed(&self, original: &str) -> bool {
+        if original.starts_with('/') {
+            return !original.starts_with("//") && !original.starts_with("/\\");
+        }
+
+        let Ok(uri) = original.parse::<Uri>() else {
+            debug!(
+                "Not passing the invalid original URL {} to the login page",
+                original
+            );
+            return false;
+        };
+        let Some(host) = uri
+            .authority()
+            .filter(|authority| !authority.as_str().contains('@'))
+            .map(|authority| authority.host().to_ascii_lowercase())
+        else {
+            return false;
+        };
+        if !matches!(uri.scheme_str(), Some("http" | "https")) {
+            return false;
+        }
+
+        let allowed = self.allowed_hosts.as_ref().is_some_and(|allowed| {
+            allowed
+                .iter()
+                .any(|allowed| match allowed.strip_prefix('*') {
+                    Some(suffix) => host.ends_with(suffix),
+                    None => host == *allowed,
+                })
+        });
+        if !allowed {
+            debug!(
+                "Not passing the original URL to the login page, its host {} isn't allowed",
+                host
+            );
+        }
+        allowed
+    }
+}
+
+/// A host name, or `*.` followed by a host name
+fn is_host_pattern(host: &str) -> bool {
+    let name = host.strip_prefix("*.").unwrap_or(host);
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| {
+            let media = media.split(';').next().unwrap_or_default().trim();
+            media.eq_ignore_ascii_case("text/html")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(allowed_return_hosts: Option<&[&str]>) -> LoginRedirect {
+        LoginRedirect::compile(LoginRedirectConfig {
+            url: "https://login.example.com/start".to_string(),
+            param: None,
+            allowed_return_hosts: allowed_return_hosts
+                .map(|hosts| hosts.iter().map(ToString::to_string).collect()),
+        })
+        .expect("the login redirect should be valid")
+    }
+
+    fn forwarded(host: &str, uri: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        headers.insert("x-forwarded-host", HeaderValue::from_str(host).unwrap());
+        headers.insert("x-forwarded-uri", HeaderValue::from_str(uri).unwrap());
+        headers
+    }
+
+    #[test]
+    fn passes_only_paths_without_allowed_hosts() {
+        let login = login(None);
+
+        assert_eq!(
+            login.location(&forwarded("evil.example.net", "/orders?page=2")),
+            "https://login.example.com/start?rd=%2Forders%3Fpage%3D2"
+        );
+        assert_eq!(
+            login.location(&forwarded("app.example.com", "https://evil.example.net/")),
+            "https://login.example.com/start"
+        );
+        assert_eq!(
+            login.location(&forwarded("app.example.com", "//evil.example.net/")),
+            "https://login.example.com/start"
+        );
+    }
+
+    #[test]
+    fn passes_urls_of_allowed_hosts() {
+        let login = login(Some(&["app.example.com", "*.apps.example.com"]));
+
+        assert_eq!(
+            login.location(&forwarded("app.example.com", "/orders")),
+            "https://login.example.com/start?rd=https%3A%2F%2Fapp.example.com%2Forders"
+        );
+        assert_eq!(
+            login.location(&forwarded("shop.apps.example.com", "/")),
+            "https://login.example.com/start?rd=https%3A%2F%2Fshop.apps.example.com%2F"
+        );
+        assert_eq!(
+            login.location(&forwarded("evil.example.net", "/orders")),
+            "https://login.example.com/start"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Maintenance mode of the service and its validators
+///
+/// The configuration file provides the defaults, which can be overridden at runtime through the
+/// admin API. Overrides survive configuration reloads.
+#[derive(Debug)]
+pub struct Maintenance {
+    configured: ArcSwap<Settings>,
+    overrides: RwLock<Overrides>,
+}
+
+#[derive(Debug, Default)]
+pub struct Settings {
+    pub service: bool,
+    pub validators: HashSet<String>,
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Overrides {
+    service: Option<bool>,
+    validators: HashMap<String, bool>,
+}
+
+/// The effective maintenance mode, as exposed by the admin API
+#[derive(Debug, Serialize)]
+pub struct MaintenanceStatus {
+    pub service: bool,
+    pub validators: Vec<String>,
+    pub retry_after: u64,
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Self {
+            configured: ArcSwap::new(Arc::new(Settings::default())),
+            overrides: RwLock::new(Overrides::default()),
+        }
+    }
+
+    pub fn configure(&self, settings: Settings) {
+        self.configured.store(Arc::new(settings));
+    }
+
+    /// Whether requests for the validator should be answered with 503
+    pub fn active(&self, validator: &str) -> bool {
+        let configured = self.configured.load();
+        let overrides = self.overrides.read().expect("lock poisoned");
+
+        overrides.service.unwrap_or(configured.service)
+            || overrides
+                .validators
+                .get(validator)
+                .copied()
+                .unwrap_or_else(|| configured.validators.contains(validator))
+    }
+
+    pub fn retry_after(&self) -> Duration {
+        self.configured.load().retry_after
+    }
+
+    pub fn set_service(&self, enabled: bool) {
+        self.overrides.write().expect("lock poisoned").service = Some(enabled);
+    }
+
+    pub fn set_validator(&self, validator: &str, enabled: bool) {
+        self.overrides
+            .write()
+            .expect("lock poisoned")
+            .validators
+            .insert(validator.to_string(), enabled);
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        let configured = self.configured.load();
+        let overrides = self.overrides.read().expect("lock poisoned");
+
+        let mut validators = configured
+            .validators
+            .iter()
+            .filter(|name| overrides.validators.get(*name) != Some(&false))
+            .chain(
+                overrides
+                    .validators
+                    .iter()
+                    .filter(|(_, enabled)| **enabled)
+                    .map(|(name, _)| name),
+            )
+            .cloned()
+            .collect::<Vec<_>>();
+        validators.sort_unstable();
+        validators.dedup();
+
+        MaintenanceStatus {
+            service: overrides.service.unwrap_or(configured.service),
+            validators,
+            retry_after: configured.retry_after.as_secs(),
+        }
+    }
+}
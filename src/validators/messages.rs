@@ -0,0 +1,51 @@
+use axum::body::Body;
+use axum::response::Response;
+use http::{header, HeaderValue};
+use std::collections::HashMap;
+
+use crate::validators::reason::Reason;
+
+/// The rejection messages of a validator, replacing the built-in English bodies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Messages {
+    /// Sent as `Content-Language` with a replaced body
+    language: Option<HeaderValue>,
+    texts: HashMap<Reason, String>,
+}
+
+impl Messages {
+    pub fn new(language: Option<&str>, texts: HashMap<Reason, String>) -> Self {
+        Self {
+            language: language.and_then(|language| HeaderValue::from_str(language).ok()),
+            texts,
+        }
+    }
+
+    /// Replace the body of a rejection with the message for its reason, if there is one
+    pub fn apply(&self, res: Response) -> Response {
+        if res.status().is_success() {
+            return res;
+        }
+        let Some(text) = res
+            .extensions()
+            .get::<Reason>()
+            .and_then(|reason| self.texts.get(reason))
+        else {
+            return res;
+        };
+
+        let (mut parts, _) = res.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        if let Some(language) = &self.language {
+            parts
+                .headers
+                .insert(header::CONTENT_LANGUAGE, language.clone());
+        }
+
+        Response::from_parts(parts, Body::from(text.clone()))
+    }
+}
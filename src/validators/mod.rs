@@ -1,387 +1,969 @@
 use aliri::JwtRef;
-use axum::extract::{Path, State};
-use axum::response::IntoResponse;
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{any, get};
-use axum::Json;
-use http::{header, HeaderMap, StatusCode};
-use serde_json::Value;
+use axum::{Extension, Json};
+use http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use futures_util::FutureExt;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
 use std::str::from_utf8;
-use tracing::info;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, info_span, warn, Instrument};
 
+pub mod anomalies;
 pub mod authority;
+pub mod body_template;
+pub mod certificates;
+pub mod checks;
 pub mod claims;
+pub mod comparison;
+pub mod connectivity;
+pub mod cookies;
+pub mod decision_cache;
+pub mod explain;
+pub mod header_limits;
 pub mod jwks;
+pub mod jwks_cache;
+pub mod login;
+pub mod maintenance;
+pub mod messages;
+pub mod opa;
+pub mod phases;
+pub mod policy;
+pub mod privacy;
+pub mod profile;
+pub mod projection;
+pub mod registry;
+pub mod reason;
+pub mod request;
+pub mod slots;
+pub mod static_keys;
 mod store;
+pub mod success;
+pub mod tenant;
+pub mod token_limits;
+pub mod token_source;
 pub mod validator;
 
 pub use store::Store;
 pub use store::ValidatorsState;
 
-use crate::utils::header_val::header_val_lossy;
+use crate::events::DecisionEvent;
+use crate::metrics;
+use crate::utils::{clock, panic};
+use crate::validator_file::OutagePolicy;
+use crate::validators::authority::Authority;
+use crate::validators::claims::{unverified_issuer, JWTClaims};
+use crate::validators::decision_cache::DecisionCache;
+use crate::validators::explain::{Step, Trace};
+use crate::validators::opa::OpaDecision;
+use crate::validators::phases::{Phase, Phases};
+use crate::validators::reason::{challenge, Reason, TokenSubject, REASON_HEADER};
+use crate::validators::request::ForwardedRequest;
+use crate::validators::slots::Staged;
+use crate::validators::validator::{
+    Action, Authorities, LabelSelector, Labels, Validation, Validator,
+};
+
+/// The expiry of the token as a Unix timestamp, sent with successful responses
+pub const EXPIRES_AT_HEADER: HeaderName = HeaderName::from_static("x-auth-expires-at");
+/// The seconds until the token expires, sent with successful responses
+pub const EXPIRES_IN_HEADER: HeaderName = HeaderName::from_static("x-auth-expires-in");
+
+/// The largest page of the validator listing
+const MAX_PAGE_SIZE: usize = 1000;
+/// The number of validators matching the filters of the listing, across all pages
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+/// The filters and the page of the validator listing
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    /// Only validators whose name starts with the prefix
+    prefix: Option<String>,
+    /// Only validators with the labels, e.g. `team=payments,env=prod`
+    selector: Option<String>,
+    /// Only validators whose name sorts after this one, the cursor of the next page
+    after: Option<String>,
+    /// The size of the page, every matching validator if not set
+    limit: Option<usize>,
+}
 
 async fn available_validators(
     State(validators): State<ValidatorsState>,
+    Query(query): Query<ListQuery>,
     headers: HeaderMap,
-) -> impl IntoResponse {
+) -> Response {
     info!("Fetching available validators");
-    let validators = validators.list();
 
-    match headers.get(header::ACCEPT).map(|v| v.as_bytes()) {
-        Some(b"application/json") => Json(validators).into_response(),
+    let selector = match query.selector.as_deref().map(str::parse::<LabelSelector>) {
+        Some(Ok(selector)) => Some(selector),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        None => None,
+    };
+    if query.limit.is_some_and(|limit| limit == 0 || limit > MAX_PAGE_SIZE) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("The limit has to be between 1 and {}", MAX_PAGE_SIZE),
+        )
+            .into_response();
+    }
+
+    // The validators are sorted by name, so the cursor is the last name of the previous page
+    let validators = validators.list_validators();
+    let matches = |v: &&Validator| {
+        query.prefix.as_deref().is_none_or(|prefix| v.name().starts_with(prefix))
+            && selector.as_ref().is_none_or(|selector| selector.matches(v.labels()))
+    };
+    let start = query
+        .after
+        .as_deref()
+        .map_or(0, |after| validators.partition_point(|v| v.name() <= after));
+    let mut remaining = validators[start..].iter().filter(matches);
+    let page = remaining
+        .by_ref()
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+    let more = remaining.next().is_some();
+    let total = validators.iter().filter(matches).count();
+
+    let mut res = match headers.get(header::ACCEPT).map(|v| v.as_bytes()) {
+        Some(b"application/json") => {
+            let infos = page.iter().map(|v| v.info()).collect::<Vec<_>>();
+            Json(infos).into_response()
+        }
         _ => {
-            let string = if validators.is_empty() {
+            let string = if page.is_empty() {
                 "No validators available".to_string()
             } else {
-                validators.join("\n")
+                let names = page.iter().map(|v| v.name()).collect::<Vec<_>>();
+                names.join("\n")
             };
 
             string.into_response()
         }
+    };
+
+    res.headers_mut()
+        .insert(TOTAL_COUNT_HEADER, HeaderValue::from(total));
+    if let (Some(last), true) = (page.last(), more) {
+        res.headers_mut().insert(header::LINK, next_page(&query, last.name()));
+    }
+    res
+}
+
+/// The `Link` to the next page of the listing, relative to the listing itself
+fn next_page(query: &ListQuery, last: &str) -> HeaderValue {
+    let mut next = form_urlencoded::Serializer::new(String::new());
+    if let Some(prefix) = &query.prefix {
+        next.append_pair("prefix", prefix);
+    }
+    if let Some(selector) = &query.selector {
+        next.append_pair("selector", selector);
+    }
+    next.append_pair("after", last);
+    if let Some(limit) = query.limit {
+        next.append_pair("limit", &limit.to_string());
     }
+
+    HeaderValue::try_from(format!("<?{}>; rel=\"next\"", next.finish()))
+        .expect("the query is percent-encoded")
 }
 
 async fn handler(
     State(validators): State<ValidatorsState>,
     Path(template): Path<String>,
     headers: HeaderMap,
-) -> impl IntoResponse {
-    let validator = match validators.get(&template) {
-        Some(validator) => validator,
+) -> Response {
+    let mut trace = Trace::for_request(&validators, &headers);
+
+    let timeout = validators.request_timeout();
+    let validating = validate(&validators, &template, &headers, &mut trace);
+    let validated = match tokio::time::timeout(timeout, validating).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!(
+                "Validation for template {} did not finish within {:?}",
+                template, timeout
+            );
+            trace.record(|| Step::Timeout);
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                Extension(Reason::Timeout),
+                "Token validation timed out",
+            )
+                .into_response()
+        }
+    };
+    let validator = validators.get(&template);
+    let no_labels = BTreeMap::new();
+    let labels = validator.as_ref().map_or(&no_labels, Validator::labels);
+    let mut res = match &validator {
+        Some(validator) => respond(validator, validated, &headers),
+        None => validated,
+    };
+
+    if let Some(reason) = res.extensions().get::<Reason>().copied() {
+        info!(
+            validator = template,
+            labels = %Labels(labels),
+            status = res.status().as_u16(),
+            reason = reason.as_str(),
+            "Answered validation request"
+        );
+        // Requests for unknown validators are counted together, as any path can be requested
+        metrics::DECISIONS
+            .with_label_values(&[validator.as_ref().map_or("", Validator::name), reason.as_str()])
+            .inc();
+        validators.anomalies().record(&template, reason);
+        res.headers_mut()
+            .insert(REASON_HEADER, HeaderValue::from_static(reason.as_str()));
+    }
+    validators
+        .events()
+        .record(|| DecisionEvent::new(&template, labels, &headers, &res));
+    let source = match validator.as_ref().map(Validator::action) {
+        Some(Action::Validate(validation)) => Some(&validation.source),
+        _ => None,
+    };
+    validators.mirror().record(&template, &headers, res.status(), source);
+
+    trace.explain(&template, res)
+}
+
+async fn validate(
+    validators: &ValidatorsState,
+    template: &str,
+    headers: &HeaderMap,
+    trace: &mut Trace,
+) -> Response {
+    let maintenance = validators.maintenance();
+    if maintenance.active(template) {
+        info!("Validator {} is in maintenance mode", template);
+        trace.record(|| Step::Maintenance);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, maintenance.retry_after().as_secs().to_string())],
+            Extension(Reason::Maintenance),
+            "Service is under maintenance",
+        )
+            .into_response();
+    }
+
+    // Read before the validator, so it is never older than the configuration of the validator
+    let version = validators.decisions().version();
+    let validator = validators.get(template);
+    trace.record(|| Step::Lookup { found: validator.is_some() });
+    let res = match &validator {
+        Some(validator) => {
+            let cache = Some((validators.decisions(), version));
+            let limits = validators.token_limits().settings();
+            evaluate(validator, template, headers, limits, cache, trace).await
+        }
         None => {
             info!("Validator not found: {}", template);
-            return (
+            (
                 StatusCode::UNAUTHORIZED,
+                Extension(Reason::UnknownValidator),
                 "Token could not be validated",
             )
-                .into_response();
+                .into_response()
         }
     };
 
-    info!("Validating token for template: {}", template);
+    if let Some(staged) = validators.staged() {
+        // Disabled validators stay disabled after a promotion
+        let disabled = validator.is_none() && validators.validators().contains(template);
+        let shadow = shadow(
+            staged,
+            template.to_string(),
+            headers.clone(),
+            validators.token_limits().settings(),
+            res.status(),
+            disabled,
+            validators.request_timeout(),
+        );
+        tokio::spawn(shadow.instrument(info_span!("shadow")));
+    }
 
-    let token = match headers.get(validator.header()) {
-        Some(token) => {
-            if let Some(prefix) = validator.strip_prefix() {
-                token
-                    .as_bytes()
-                    .strip_prefix(prefix.as_bytes())
-                    .unwrap_or(token.as_bytes())
-            } else {
-                token.as_bytes()
+    res
+}
+
+/// Whether a validator accepts the request, used to authenticate admin requests with tokens of
+/// the identity provider
+///
+/// Maintenance mode and the decision cache don't apply, and the decision isn't recorded.
+pub async fn accepts(validators: &ValidatorsState, name: &str, headers: &HeaderMap) -> bool {
+    let Some(validator) = validators.get(name) else {
+        return false;
+    };
+
+    let limits = validators.token_limits().settings();
+    let mut trace = Trace::disabled();
+    let evaluating = evaluate(&validator, name, headers, limits, None, &mut trace);
+    match tokio::time::timeout(validators.request_timeout(), evaluating).await {
+        Ok(res) => res.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Send a token through every validator a number of times before the service accepts traffic
+///
+/// Warms up what is only set up on first use (connections to enrichment backends, caches of the
+/// token decoding, lazily registered metrics...), so the first requests after a deployment aren't
+/// slower than the rest. Like for [`accepts`], the decisions aren't recorded.
+pub async fn warm_up(validators: &ValidatorsState, requests: usize, token: &str) {
+    let started = Instant::now();
+    let names = validators.list();
+
+    let mut warmed = 0;
+    for name in &names {
+        let Some(validator) = validators.get(name) else {
+            continue;
+        };
+        let Action::Validate(validation) = validator.action() else {
+            continue;
+        };
+
+        warmed += 1;
+        let mut headers = HeaderMap::new();
+        validation.source.insert_token(&mut headers, token);
+        let mut accepted = 0;
+        for _ in 0..requests {
+            // Like the requests of the service, a panicking validation only fails itself
+            let accepting = AssertUnwindSafe(accepts(validators, name, &headers));
+            match accepting.catch_unwind().await {
+                Ok(true) => accepted += 1,
+                Ok(false) => {}
+                Err(err) => panic::report(err),
             }
         }
-        None => {
-            info!("Token not found in header: {}", validator.header());
+        if accepted < requests {
+            warn!(
+                "Validator {} rejected {} of {} warm-up requests",
+                name,
+                requests - accepted,
+                requests
+            );
+        }
+    }
 
+    info!(
+        "Warmed up {} validators with {} requests each in {:?}",
+        warmed,
+        requests,
+        started.elapsed()
+    );
+}
+
+/// Evaluate the request with the staged configuration and compare the decision with the active one
+async fn shadow(
+    staged: Arc<Staged>,
+    template: String,
+    headers: HeaderMap,
+    limits: token_limits::Settings,
+    active: StatusCode,
+    disabled: bool,
+    timeout: Duration,
+) {
+    let status = match staged.validator(&template) {
+        Some(validator) if !disabled => {
+            let mut trace = Trace::disabled();
+            // The decision cache belongs to the active configuration
+            let evaluating = evaluate(validator, &template, &headers, limits, None, &mut trace);
+            match tokio::time::timeout(timeout, evaluating).await {
+                Ok(res) => res.status(),
+                Err(_) => StatusCode::GATEWAY_TIMEOUT,
+            }
+        }
+        _ => StatusCode::UNAUTHORIZED,
+    };
+
+    staged.record(&template, active, status);
+}
+
+/// Evaluate a request with a validator, the checks shared by the active and staged configuration
+async fn evaluate(
+    validator: &Validator,
+    template: &str,
+    headers: &HeaderMap,
+    limits: token_limits::Settings,
+    cache: Option<(&DecisionCache, u64)>,
+    trace: &mut Trace,
+) -> Response {
+    let validation = match validator.action() {
+        Action::Validate(validation) => validation,
+        Action::Deny(deny) => {
+            info!("Validator {} denies all requests", template);
+            trace.record(|| Step::Deny { status: deny.status.as_u16() });
+            return (deny.status, Extension(Reason::Denied), deny.message.clone()).into_response();
+        }
+    };
+
+    let request = ForwardedRequest::new(headers);
+    if let Some(transport) = &validation.transport {
+        let proto = request.proto();
+        let port = request.port();
+
+        let https = !transport.require_https
+            || proto.is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+        let port_allowed = match &transport.allowed_ports {
+            Some(allowed) => port.is_some_and(|port| allowed.contains(&port)),
+            None => true,
+        };
+        trace.record(|| Step::Transport {
+            proto: proto.map(str::to_string),
+            port,
+            accepted: https && port_allowed,
+        });
+
+        if !(https && port_allowed) {
+            info!(
+                "Rejecting request forwarded with protocol {:?} and port {:?}",
+                proto, port
+            );
+            return (
+                transport.status,
+                Extension(Reason::Transport),
+                "Request was not received over an allowed transport",
+            )
+                .into_response();
+        }
+    }
+
+    // Validators selecting the authority by issuer are admitted once the token was inspected
+    if let Authorities::Single(authority) = &validation.authorities {
+        if let Some(res) = admit(authority, trace) {
+            return res;
+        }
+    }
+
+    info!("Validating token for template: {}", template);
+
+    let phases = Phases::new(template);
+    let started = Instant::now();
+    let token = match validation.source.extract(headers, limits.max_length, trace) {
+        Ok(token) => token,
+        Err(err) => {
+            info!("{}", err);
             return (
                 StatusCode::UNAUTHORIZED,
-                format!("Header {} not found", validator.header()),
+                Extension(Reason::MissingToken),
+                err.to_string(),
             )
                 .into_response();
         }
     };
-    let token = match from_utf8(token) {
+    let limited = limits.check(&token);
+    trace.record(|| Step::TokenLimits {
+        length: token.len(),
+        accepted: limited.is_ok(),
+    });
+    if let Err(err) = limited {
+        info!("Rejecting oversized token: {}", err);
+        return (
+            StatusCode::UNAUTHORIZED,
+            Extension(Reason::Oversized),
+            "Token exceeds the size limits",
+        )
+            .into_response();
+    }
+
+    let token = from_utf8(&token);
+    trace.record(|| Step::Encoding { valid_utf8: token.is_ok() });
+    let token = match token {
         Ok(token) => JwtRef::from_str(token),
         Err(_) => {
             info!("Token is not valid UTF-8");
             return (
                 StatusCode::UNAUTHORIZED,
+                Extension(Reason::InvalidUtf8),
                 "Token is not valid UTF-8",
             )
                 .into_response();
         }
     };
+    phases.observe(Phase::TokenExtraction, started);
 
-    let duration_since_last_update = validator
-        .authority()
-        .jwks()
-        .last_refresh()
-        .elapsed()
-        .expect("time went backwards");
-
-    if duration_since_last_update > validator.authority().update_interval() {
-        let jwks = validator.authority().jwks().clone();
-        tokio::spawn(async move {
-            let _ = jwks.refresh().await;
+    let authority = match &validation.authorities {
+        Authorities::Single(authority) => authority,
+        Authorities::ByIssuer(issuers) => {
+            let issuer = unverified_issuer(token);
+            let authority = issuer.as_deref().and_then(|issuer| issuers.get(issuer));
+            trace.record(|| Step::Issuer {
+                issuer: issuer.clone(),
+                authority: authority.map(|authority| authority.name().to_string()),
+            });
+
+            let Some(authority) = authority else {
+                info!("Rejecting token of issuer {:?}, which isn't accepted", issuer);
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Extension(Reason::UnknownIssuer),
+                    "Token issuer is not accepted",
+                )
+                    .into_response();
+            };
+            if let Some(res) = admit(authority, trace) {
+                return res;
+            }
+            authority
+        }
+    };
+
+    authority.jwks().record_request();
+    let duration_since_last_update = clock::elapsed(authority.jwks().last_refresh())
+        // The clock went backwards since the refresh, so it just happened
+        .unwrap_or_default();
+
+    // While the circuit is open, requests use the stale keys instead of calling the identity provider
+    let jwks = authority.jwks();
+    let refresh_due =
+        duration_since_last_update > authority.update_interval() && !jwks.circuit_open();
+
+    // Tokens accepted shortly before are answered from the decision cache instead of waiting for
+    // the refresh or applying the outage policy, the keys are refreshed in the background
+    if let (Some(grace), Some((cache, version))) = (validation.stale_while_revalidate, cache) {
+        let stale = (refresh_due || authority.outage())
+            .then(|| cache.get_stale(version, template, token.as_str(), grace))
+            .flatten();
+        if let Some((claims, age)) = stale {
+            trace.record(|| Step::StaleDecision {
+                age_seconds: age,
+                refreshing: refresh_due,
+            });
+            metrics::STALE_DECISIONS.with_label_values(&[template]).inc();
+
+            if refresh_due {
+                // Concurrent requests wait on the refresh lock and find the keys fresh
+                let jwks = jwks.clone();
+                let max_age = authority.update_interval();
+                tokio::spawn(async move {
+                    let _ = jwks.refresh_stale(max_age).await;
+                });
+            }
+            return authorize(validation, template, claims, age, &request, trace).await;
+        }
+    }
+
+    if refresh_due {
+        trace.record(|| Step::JwksRefresh {
+            stale_for_seconds: duration_since_last_update.as_secs(),
+            blocking: authority.blocking_refresh(),
         });
+
+        let jwks = jwks.clone();
+        if authority.blocking_refresh() {
+            // The request deadline bounds how long we wait for the refresh
+            let _ = jwks
+                .refresh_stale(authority.update_interval())
+                .await;
+        } else {
+            let max_age = authority.update_interval();
+            tokio::spawn(async move {
+                let _ = jwks.refresh_stale(max_age).await;
+            });
+        }
     }
 
-    let claims = match validator.authority().validate(&token) {
-        Ok(claims) => claims,
-        Err(e) => {
-            info!("Failed to validate token: {}", e);
-            return (
-                StatusCode::UNAUTHORIZED,
-                "Token isn't valid",
-            ).into_response();
+    if authority.outage() {
+        trace.record(|| Step::Outage {
+            policy: validation.outage,
+            circuit_open: jwks.circuit_open(),
+        });
+        metrics::OUTAGE_DECISIONS
+            .with_label_values(&[template, validation.outage.as_str()])
+            .inc();
+
+        match validation.outage {
+            OutagePolicy::FailClosed => {
+                warn!(
+                    "Identity provider of authority {} is unavailable, rejecting the request",
+                    authority.name()
+                );
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Extension(Reason::StaleKeys),
+                    "Identity provider is unavailable",
+                )
+                    .into_response();
+            }
+            OutagePolicy::FailOpen => {
+                warn!(
+                    "Identity provider of authority {} is unavailable, allowing the request",
+                    authority.name()
+                );
+                return (StatusCode::OK, Extension(Reason::StaleKeys)).into_response();
+            }
+            OutagePolicy::DegradeToShadow => {
+                let res =
+                    verify(validation, authority, template, token, &request, cache, trace).await;
+                if res.status().is_success() {
+                    return res;
+                }
+
+                warn!(
+                    "Identity provider of authority {} is unavailable, allowing a request rejected with {}",
+                    authority.name(),
+                    res.status()
+                );
+                return (StatusCode::OK, Extension(Reason::StaleKeys)).into_response();
+            }
+        }
+    }
+
+    verify(validation, authority, template, token, &request, cache, trace).await
+}
+
+/// Shape the response of a validator: its messages, body template, success response, challenge,
+/// login redirect and the adjustments of its profile
+fn respond(validator: &Validator, res: Response, headers: &HeaderMap) -> Response {
+    let res = validator.messages().apply(res);
+    let res = match validator.action() {
+        Action::Validate(validation) => {
+            let res = match &validation.body_template {
+                Some(template) => template.apply(res, validator.name(), headers),
+                None => res,
+            };
+            let mut res = match &validation.success {
+                Some(success) => success.apply(res),
+                None => res,
+            };
+            echo(res.headers_mut(), headers, &validation.echo_headers);
+            let res = challenge(res, &validation.realm);
+            match &validation.login_redirect {
+                Some(login) => login.apply(res, headers),
+                None => res,
+            }
         }
+        Action::Deny(_) => res,
     };
 
-    let mut headers = HeaderMap::new();
-    let mut already_inserted = Vec::new();
-
-    for claim in validator.required_claims() {
-        match claim.name.as_str() {
-            "aud" => match &claims.aud {
-                Some(aud) => {
-                    if !aud.iter().any(|aud| claim.value.matches(aud.as_str())) {
-                        let val = aud
-                            .iter()
-                            .map(|aud| aud.as_str())
-                            .collect::<Vec<&str>>()
-                            .join(",");
-
-                        info!(
-                            "No audience in token matches required audience: [{}] != {}",
-                            val, claim.value
-                        );
-                        return (
-                            StatusCode::UNAUTHORIZED,
-                            "Token doesn't match required audience",
-                        )
-                            .into_response();
-                    }
-
-                    if let Some(key) = validator.map_claims().get("aud") {
-                        let val = aud
-                            .iter()
-                            .map(|aud| aud.as_str())
-                            .collect::<Vec<&str>>()
-                            .join(",");
-
-                        headers.insert(key, header_val_lossy(val));
-                        already_inserted.push("aud");
-                    }
-                }
-                None => {
-                    info!("Token is missing required audience claim");
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        "Token is missing audience claim",
-                    )
-                        .into_response();
-                }
-            },
-            "iss" => match &claims.iss {
-                Some(iss) => {
-                    if !claim.value.matches(iss.as_str()) {
-                        info!(
-                            "Token issuer doesn't match required issuer: {} != {}",
-                            iss, claim.value
-                        );
-                        return (
-                            StatusCode::UNAUTHORIZED,
-                            "Token doesn't match required issuer",
-                        )
-                            .into_response();
-                    }
-
-                    if let Some(key) = validator.map_claims().get("iss") {
-                        headers.insert(key, header_val_lossy(iss.as_str()));
-                        already_inserted.push("iss");
-                    }
-                }
-                None => {
-                    info!("Token is missing issuer claim");
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        "Token is missing issuer claim",
-                    )
-                        .into_response();
-                }
-            },
-            "sub" => match &claims.sub {
-                Some(sub) => {
-                    if !claim.value.matches(sub.as_str()) {
-                        info!(
-                            "Token subject doesn't match required subject: {} != {}",
-                            sub, claim.value
-                        );
-                        return (
-                            StatusCode::UNAUTHORIZED,
-                            "Token doesn't match required subject",
-                        )
-                            .into_response();
-                    }
-
-                    if let Some(key) = validator.map_claims().get("sub") {
-                        headers.insert(key, header_val_lossy(sub.as_str()));
-                        already_inserted.push("sub");
-                    }
-                }
-                None => {
-                    info!("Token is missing subject claim");
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        "Token is missing subject claim",
-                    )
-                        .into_response();
-                }
-            },
-            "exp" => match &claims.exp {
-                Some(exp) => {
-                    if !claim.value.matches(&exp.to_string()) {
-                        info!(
-                            "Token expiration doesn't match required expiration: {} != {}",
-                            exp, claim.value
-                        );
-                        return (
-                            StatusCode::UNAUTHORIZED,
-                            "Token doesn't match required expiration",
-                        )
-                            .into_response();
-                    }
-
-                    if let Some(key) = validator.map_claims().get("exp") {
-                        headers.insert(key, header_val_lossy(exp.to_string()));
-                        already_inserted.push("exp");
-                    }
-                }
-                None => {
-                    info!("Token is missing expiration claim");
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        "Token is missing expiration claim",
-                    )
-                        .into_response();
-                }
-            },
-            "nbf" => match &claims.nbf {
-                Some(nbf) => {
-                    if !claim.value.matches(&nbf.to_string()) {
-                        info!(
-                            "Token not before doesn't match required not before: {} != {}",
-                            nbf, claim.value
-                        );
-                        return (
-                            StatusCode::UNAUTHORIZED,
-                            "Token doesn't match required not before",
-                        )
-                            .into_response();
-                    }
-
-                    if let Some(key) = validator.map_claims().get("nbf") {
-                        headers.insert(key, header_val_lossy(nbf.to_string()));
-                        already_inserted.push("nbf");
-                    }
-                }
-                None => {
-                    info!("Token is missing not before claim");
-                    return (
-                        StatusCode::UNAUTHORIZED,
-                        "Token is missing not before claim",
-                    )
-                        .into_response();
-                }
-            },
-            other => {
-                match claims.other.get(other) {
-                    Some(v) => {
-                        let matcher = match v {
-                            Value::Null => String::new(),
-                            Value::Bool(v) => v.to_string(),
-                            Value::Number(v) => v.to_string(),
-                            Value::String(v) => v.clone(),
-                            // Arrays and objects shouldn't be present in the claims
-                            Value::Array(_) | Value::Object(_) => {
-                                info!("Token contains invalid claim: {}", other);
-                                return (
-                                    StatusCode::UNAUTHORIZED,
-                                    "Token contains invalid claim",
-                                )
-                                    .into_response();
-                            }
-                        };
-
-                        if !claim.value.matches(&matcher) {
-                            info!(
-                                "Token doesn't match required {} claim: {} != {}",
-                                other, matcher, claim.value
-                            );
-                            return (
-                                StatusCode::UNAUTHORIZED,
-                                format!("Token doesn't match required {} claim", other),
-                            )
-                                .into_response();
-                        }
-
-                        if let Some(key) = validator.map_claims().get(other) {
-                            headers.insert(key, header_val_lossy(matcher));
-                            already_inserted.push(other);
-                        }
-                    }
-                    None => {
-                        info!("Token is missing required {} claim", other);
-                        return (
-                            StatusCode::UNAUTHORIZED,
-                            format!("Token is missing required {} claim", other),
-                        )
-                            .into_response();
-                    }
-                }
+    validator.profile().apply(res)
+}
+
+/// Copy the request headers to the response, so proxies only propagating the headers of the
+/// response pass them upstream
+///
+/// Headers the response already has (e.g. from the mapped claims) are kept.
+fn echo(res_headers: &mut HeaderMap, headers: &HeaderMap, echoed: &[HeaderName]) {
+    for name in echoed {
+        if res_headers.contains_key(name) {
+            continue;
+        }
+        for value in headers.get_all(name) {
+            res_headers.append(name, value.clone());
+        }
+    }
+}
+
+/// Count the request against the rate of the authority's tenant, the rejection if it exceeded it
+fn admit(authority: &Authority, trace: &mut Trace) -> Option<Response> {
+    let tenant = authority.tenant()?;
+
+    let admitted = tenant.admit();
+    trace.record(|| Step::Tenant {
+        tenant: tenant.name().to_string(),
+        admitted,
+    });
+    if !admitted {
+        info!("Tenant {} exceeded its request rate", tenant.name());
+        return Some(
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                Extension(Reason::RateLimited),
+                "Too many requests",
+            )
+                .into_response(),
+        );
+    }
+
+    None
+}
+
+/// Verify the token (unless its claims are cached) and check its claims, mapping them to the response
+async fn verify(
+    validation: &Validation,
+    authority: &Authority,
+    template: &str,
+    token: &JwtRef,
+    request: &ForwardedRequest<'_>,
+    cache: Option<(&DecisionCache, u64)>,
+    trace: &mut Trace,
+) -> Response {
+    let cached = cache.and_then(|(cache, version)| cache.get(version, template, token.as_str()));
+    if cache.is_some() {
+        trace.record(|| Step::DecisionCache { hit: cached.is_some() });
+    }
+    let (claims, age) = match cached {
+        Some(cached) => cached,
+        None => {
+            let claims = match check_token(validation, authority, template, token, trace).await {
+                Ok(claims) => claims,
+                Err(res) => return res,
+            };
+            if let Some((cache, version)) = cache {
+                cache.insert(version, template, token.as_str(), &claims);
             }
+            (claims, 0)
+        }
+    };
+    authorize(validation, template, claims, age, request, trace).await
+}
+
+/// Check the claims of a verified token, mapping them to the response
+async fn authorize(
+    validation: &Validation,
+    template: &str,
+    claims: JWTClaims,
+    age: u64,
+    request: &ForwardedRequest<'_>,
+    trace: &mut Trace,
+) -> Response {
+    let token_subject = claims.sub.as_ref().map(|s| Extension(TokenSubject(s.to_string())));
+    let template_claims = validation
+        .body_template
+        .as_ref()
+        .and_then(|template| template.claims(&claims))
+        .map(Extension);
+
+    if trace.is_enabled() {
+        for evaluation in validation.plan.explain(&claims, request) {
+            trace.record(|| Step::Claim(evaluation));
         }
     }
 
-    for (claim, header) in validator
-        .map_claims()
-        .iter()
-        .filter(|(k, _)| already_inserted.contains(&k.as_str()))
-    {
-        match claim.as_str() {
-            "aud" => {
-                if let Some(aud) = &claims.aud {
-                    let val = aud
-                        .iter()
-                        .map(|aud| aud.as_str())
-                        .collect::<Vec<&str>>()
-                        .join(",");
-
-                    headers.insert(header, header_val_lossy(val));
-                }
+    let phases = Phases::new(template);
+    let evaluated = phases.time(Phase::Claims, || validation.plan.evaluate(&claims, request));
+    match evaluated {
+        Ok(warnings) => {
+            for warning in warnings {
+                warn!(
+                    validator = template,
+                    claim = warning.claim(),
+                    reason = warning.reason().as_str(),
+                    "Accepting token with failed claim of warn severity: {}",
+                    warning
+                );
+                metrics::CLAIM_WARNINGS
+                    .with_label_values(&[template, warning.claim(), warning.reason().as_str()])
+                    .inc();
             }
-            "iss" => {
-                if let Some(iss) = &claims.iss {
-                    headers.insert(header, header_val_lossy(iss.as_str()));
-                }
+        }
+        Err(err) => {
+            info!("{}", err);
+            return (
+                StatusCode::UNAUTHORIZED,
+                Extension(err.reason()),
+                token_subject,
+                template_claims,
+                err.message(),
+            )
+                .into_response();
+        }
+    }
+
+    if let Some(policy) = &validation.policy {
+        let evaluated = phases.time(Phase::Claims, || policy.evaluate(&claims));
+        trace.record(|| Step::Policy {
+            expression: policy.to_string(),
+            accepted: evaluated == Ok(true),
+            error: evaluated.as_ref().err().cloned(),
+        });
+        if evaluated != Ok(true) {
+            match evaluated {
+                Err(err) => info!("Rejecting token, the policy of {} failed: {}", template, err),
+                _ => info!("Rejecting token not satisfying the policy of {}", template),
             }
-            "sub" => {
-                if let Some(sub) = &claims.sub {
-                    headers.insert(header, header_val_lossy(sub.as_str()));
-                }
+            return (
+                StatusCode::UNAUTHORIZED,
+                Extension(Reason::Policy),
+                token_subject,
+                template_claims,
+                "Token claims do not satisfy the policy",
+            )
+                .into_response();
+        }
+    }
+
+    let mut opa_headers = HeaderMap::new();
+    if let Some(opa) = &validation.opa {
+        let decision = opa.decide(template, &claims, request).await;
+        trace.record(|| Step::Opa {
+            url: opa.url().to_string(),
+            allowed: matches!(decision, Ok(OpaDecision::Allow(_))),
+            error: decision.as_ref().err().map(|e| e.to_string()),
+        });
+        match decision {
+            Ok(OpaDecision::Allow(headers)) => opa_headers = headers,
+            Ok(OpaDecision::Deny) => {
+                info!("Open Policy Agent of {} denied the token", template);
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Extension(Reason::Policy),
+                    token_subject,
+                    template_claims,
+                    "Token claims do not satisfy the policy",
+                )
+                    .into_response();
             }
-            "exp" => {
-                if let Some(exp) = &claims.exp {
-                    headers.insert(header, header_val_lossy(exp.to_string()));
-                }
+            Err(err) => {
+                warn!("Open Policy Agent of {} couldn't decide: {}", template, err);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Extension(Reason::PolicyUnavailable),
+                    token_subject,
+                    "Policy decision is unavailable",
+                )
+                    .into_response();
             }
-            "nbf" => {
-                if let Some(nbf) = &claims.nbf {
-                    headers.insert(header, header_val_lossy(nbf.to_string()));
+        }
+    }
+
+    let started = Instant::now();
+    let mut res_headers = validation.plan.headers(&claims);
+    if let Some(projection) = &validation.header_projection {
+        let (projected, dropped) = projection.apply(res_headers);
+        for header in dropped {
+            warn!(
+                validator = template,
+                "Dropping mapped header {}, its encoded value is too large for the projection",
+                header
+            );
+        }
+        res_headers = projected;
+    }
+    if let Some(limits) = &validation.header_limits {
+        res_headers = match limits.apply(res_headers) {
+            Ok((limited, exceeded)) => {
+                for exceeded in exceeded {
+                    warn!(validator = template, "Truncating mapped headers: {}", exceeded);
                 }
+                limited
             }
-            _ => {
-                if let Some(v) = claims.other.get(claim) {
-                    let val = match v {
-                        Value::Null => String::new(),
-                        Value::Bool(v) => v.to_string(),
-                        Value::Number(v) => v.to_string(),
-                        Value::String(v) => v.clone(),
-                        // Arrays and objects shouldn't be present in the claims
-                        Value::Array(_) | Value::Object(_) => continue,
-                    };
-
-                    headers.insert(header, header_val_lossy(val));
-                }
+            Err(err) => {
+                info!("Rejecting token whose mapped headers exceed the limits: {}", err);
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Extension(Reason::HeaderLimits),
+                    token_subject,
+                    template_claims,
+                    "Token claims exceed the header limits",
+                )
+                    .into_response();
             }
+        };
+    }
+    // The headers of the policy decision, the mapped claims take precedence
+    for (name, value) in &opa_headers {
+        if !res_headers.contains_key(name) {
+            res_headers.insert(name, value.clone());
         }
     }
+    let cookies = validation.plan.cookies(&claims, request);
+
+    trace.record(|| Step::MapClaims {
+        headers: res_headers.keys().map(|h| h.to_string()).collect(),
+        cookies: cookies.iter().map(|(name, _)| name.clone()).collect(),
+    });
+    for (_, cookie) in cookies {
+        res_headers.append(header::SET_COOKIE, cookie);
+    }
+    // Lets the proxy and upstream services refresh the token or cache the decision in time
+    if let (Some(exp), Some(expires_in)) = (claims.exp, claims.expires_in()) {
+        res_headers.insert(EXPIRES_AT_HEADER, HeaderValue::from(exp.0));
+        res_headers.insert(EXPIRES_IN_HEADER, HeaderValue::from(expires_in));
+    }
+    if let Some(max_age) = validation.cache_max_age {
+        let max_age = claims.expires_in().map_or(max_age, |e| e.min(max_age));
+        res_headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::try_from(format!("max-age={}", max_age)).expect("the value is ASCII"),
+        );
+        // Claims from the decision cache were checked that long ago
+        res_headers.insert(header::AGE, HeaderValue::from(age));
+    }
+    phases.observe(Phase::Headers, started);
 
     info!("Token is valid and matches all required claims");
-    if !headers.is_empty() {
-        info!("Returning headers: {:?}", headers);
+    if !res_headers.is_empty() {
+        info!("Returning headers: {:?}", res_headers);
+    }
+    (StatusCode::OK, Extension(Reason::Valid), token_subject, res_headers).into_response()
+}
+
+/// Verify the signature and subject of the token and apply the enrichments
+async fn check_token(
+    validation: &Validation,
+    authority: &Authority,
+    template: &str,
+    token: &JwtRef,
+    trace: &mut Trace,
+) -> Result<JWTClaims, Response> {
+    if trace.is_enabled() {
+        if let Some(key) = authority.selected_key(token) {
+            trace.record(|| Step::Key(key));
+        }
+    }
+
+    let claims = authority.validate(token, Phases::new(template));
+    trace.record(|| Step::Verification {
+        authority: authority.name().to_string(),
+        valid: claims.is_ok(),
+        error: claims.as_ref().err().map(|e| e.to_string()),
+    });
+    if let Some(comparison) = &validation.compare {
+        let outcome = comparison.compare(template, token, &claims);
+        trace.record(|| Step::Comparison(outcome));
     }
-    (StatusCode::OK, headers).into_response()
+    let mut claims = match claims {
+        Ok(claims) => claims,
+        Err(e) => {
+            info!("Failed to validate token: {}", e);
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Extension(e.reason()),
+                "Token isn't valid",
+            ).into_response());
+        }
+    };
+
+    if !validation.subjects.is_empty() {
+        let subject = claims.sub.as_ref().map(|s| s.as_str());
+        let checked = validation.subjects.check(subject);
+        trace.record(|| Step::Subject {
+            subject: subject.map(str::to_string),
+            accepted: checked.is_ok(),
+        });
+
+        if let Err(err) = checked {
+            info!("{}", err);
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Extension(Reason::Subject),
+                subject.map(|s| Extension(TokenSubject(s.to_string()))),
+                validation
+                    .body_template
+                    .as_ref()
+                    .and_then(|template| template.claims(&claims))
+                    .map(Extension),
+                "Subject is not allowed",
+            )
+                .into_response());
+        }
+    }
+
+    for enrichment in &validation.enrichments {
+        let outcome = enrichment.apply(&mut claims).await;
+        trace.record(|| Step::Enrichment {
+            enrichment: enrichment.name().to_string(),
+            outcome: outcome.as_str(),
+        });
+    }
+
+    Ok(claims)
 }
 
 pub fn routes<S>(store: ValidatorsState) -> axum::Router<S> {
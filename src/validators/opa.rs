@@ -0,0 +1,180 @@
+use http::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::validator_file::OpaConfig;
+use crate::validators::claims::JWTClaims;
+use crate::validators::projection;
+use crate::validators::request::ForwardedRequest;
+
+/// The time a decision may take, unless configured otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An Open Policy Agent deciding about verified tokens, for authorization logic centralized in Rego
+///
+/// The claims and the forwarded request are posted to the Data API of the policy, which answers
+/// with a boolean or an object with `allow` and the `headers` to add to accepted requests.
+#[derive(Debug, Clone)]
+pub struct OpaPolicy {
+    url: String,
+    client: reqwest::Client,
+    timeout: Duration,
+}
+
+/// The decision of the policy
+#[derive(Debug)]
+pub enum OpaDecision {
+    /// Accept the token and add the headers to the response
+    Allow(HeaderMap),
+    Deny,
+}
+
+#[derive(Debug)]
+pub enum OpaError {
+    /// The policy couldn't be reached, timed out or answered with an error status
+    Request(reqwest::Error),
+    /// The answer isn't a boolean or an object with a boolean `allow`
+    InvalidResult,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    input: Input<'a>,
+}
+
+/// What the policy decides about, `input` in Rego
+#[derive(Serialize)]
+struct Input<'a> {
+    validator: &'a str,
+    claims: Map<String, Value>,
+    request: Request<'a>,
+}
+
+/// The original request, as forwarded by the reverse proxy
+#[derive(Serialize)]
+struct Request<'a> {
+    method: Option<&'a str>,
+    proto: Option<&'a str>,
+    host: Option<&'a str>,
+    uri: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Answer {
+    /// Undefined if no rule of the policy matched, which denies the request
+    result: Option<Value>,
+}
+
+impl OpaPolicy {
+    pub fn new(config: &OpaConfig, client: &reqwest::Client) -> Self {
+        Self {
+            url: config.url.clone(),
+            client: client.clone(),
+            timeout: config
+                .timeout_seconds
+                .map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Ask the policy whether the token is accepted
+    pub async fn decide(
+        &self,
+        validator: &str,
+        claims: &JWTClaims,
+        request: &ForwardedRequest<'_>,
+    ) -> Result<OpaDecision, OpaError> {
+        let body = Body {
+            input: Input {
+                validator,
+                claims: claims.to_json(),
+                request: Request {
+                    method: request.method(),
+                    proto: request.proto(),
+                    host: request.host(),
+                    uri: request.uri(),
+                },
+            },
+        };
+        let answer = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(OpaError::Request)?
+            .json::<Answer>()
+            .await
+            .map_err(OpaError::Request)?;
+
+        match answer.result {
+            None | Some(Value::Bool(false)) => Ok(OpaDecision::Deny),
+            Some(Value::Bool(true)) => Ok(OpaDecision::Allow(HeaderMap::new())),
+            Some(Value::Object(result)) => match result.get("allow") {
+                Some(Value::Bool(true)) => Ok(OpaDecision::Allow(
+                    result.get("headers").map(headers).unwrap_or_default(),
+                )),
+                Some(Value::Bool(false)) | None => Ok(OpaDecision::Deny),
+                Some(_) => Err(OpaError::InvalidResult),
+            },
+            Some(_) => Err(OpaError::InvalidResult),
+        }
+    }
+}
+
+/// The headers of an allowing decision, invalid and reserved headers are skipped
+fn headers(headers: &Value) -> HeaderMap {
+    let mut res = HeaderMap::new();
+    let Some(headers) = headers.as_object() else {
+        warn!("Ignoring the headers of the policy decision, they aren't an object");
+        return res;
+    };
+
+    for (name, value) in headers {
+        let header = HeaderName::try_from(name)
+            .ok()
+            .filter(|header| !projection::is_reserved(header));
+        let value = value
+            .as_str()
+            .and_then(|value| HeaderValue::try_from(value).ok());
+        match (header, value) {
+            (Some(header), Some(value)) => {
+                res.insert(header, value);
+            }
+            _ => warn!(
+                "Ignoring invalid or reserved header {} of the policy decision",
+                name
+            ),
+        }
+    }
+    res
+}
+
+impl fmt::Display for OpaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpaError::Request(e) => write!(f, "the policy request failed: {}", e),
+            OpaError::InvalidResult => write!(
+                f,
+                "the policy result isn't a boolean or an object with a boolean allow"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for OpaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OpaError::Request(e) => Some(e),
+            OpaError::InvalidResult => None,
+        }
+    }
+}
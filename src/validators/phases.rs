@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+use crate::metrics;
+
+/// A phase of evaluating a validation request, timed in `jwt_fwa_validation_phase_duration_seconds`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading the token from the request, checking its size and encoding
+    TokenExtraction,
+    /// Decomposing the token and finding its key in the JWKS
+    KeyLookup,
+    /// Verifying the signature and lifetime of the token
+    SignatureVerification,
+    /// Checking the required claims
+    Claims,
+    /// Mapping the claims to the response headers and cookies
+    Headers,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::TokenExtraction => "token_extraction",
+            Phase::KeyLookup => "key_lookup",
+            Phase::SignatureVerification => "signature_verification",
+            Phase::Claims => "claims",
+            Phase::Headers => "headers",
+        }
+    }
+}
+
+/// Records the phase durations of the requests of a validator
+///
+/// Disabled for verifications that aren't part of answering the request (e.g. the compared
+/// authority), so they don't skew the durations of the validator.
+#[derive(Debug, Copy, Clone)]
+pub struct Phases<'a> {
+    validator: Option<&'a str>,
+}
+
+impl<'a> Phases<'a> {
+    pub fn new(validator: &'a str) -> Self {
+        Self {
+            validator: Some(validator),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { validator: None }
+    }
+
+    /// Record the time since `started` as the duration of the phase
+    pub fn observe(&self, phase: Phase, started: Instant) {
+        if let Some(validator) = self.validator {
+            metrics::VALIDATION_PHASE_DURATION
+                .with_label_values(&[validator, phase.as_str()])
+                .observe(started.elapsed().as_secs_f64());
+        }
+    }
+
+    /// Run `f`, recording its duration as the duration of the phase
+    pub fn time<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let res = f();
+        self.observe(phase, started);
+        res
+    }
+}
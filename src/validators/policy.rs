@@ -0,0 +1,65 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::validators::claims::JWTClaims;
+
+/// A CEL expression deciding whether the claims of a token are accepted
+///
+/// The expression is evaluated with the claims (including enriched values) as the map `claims`
+/// and has to evaluate to a boolean, e.g. `"admin" in claims.groups || claims.sub == "ci"`.
+#[derive(Clone)]
+pub struct Policy {
+    expression: Arc<str>,
+    #[cfg(feature = "cel")]
+    program: Arc<cel_interpreter::Program>,
+}
+
+impl Policy {
+    #[cfg(feature = "cel")]
+    pub fn compile(expression: &str) -> Result<Self, String> {
+        let program = cel_interpreter::Program::compile(expression).map_err(|e| e.to_string())?;
+        Ok(Self {
+            expression: expression.into(),
+            program: Arc::new(program),
+        })
+    }
+
+    #[cfg(not(feature = "cel"))]
+    pub fn compile(_expression: &str) -> Result<Self, String> {
+        Err("CEL policies require building with the `cel` feature".to_string())
+    }
+
+    /// Whether the policy accepts the claims, errors (e.g. a missing claim the expression doesn't
+    /// guard with `has()`) reject them
+    #[cfg(feature = "cel")]
+    pub fn evaluate(&self, claims: &JWTClaims) -> Result<bool, String> {
+        use cel_interpreter::{Context, Value};
+
+        let mut context = Context::default();
+        context
+            .add_variable("claims", claims.to_json())
+            .map_err(|e| e.to_string())?;
+        match self.program.execute(&context) {
+            Ok(Value::Bool(accepted)) => Ok(accepted),
+            Ok(value) => Err(format!("evaluated to {:?} instead of a boolean", value)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    #[cfg(not(feature = "cel"))]
+    pub fn evaluate(&self, _claims: &JWTClaims) -> Result<bool, String> {
+        unreachable!("policies can't be compiled without the `cel` feature")
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Policy").field(&self.expression).finish()
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
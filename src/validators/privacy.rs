@@ -0,0 +1,113 @@
+use arc_swap::ArcSwapOption;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::validator_file::IdentityHashingConfig;
+
+/// The bytes of the HMAC kept in a pseudonym, plenty to tell identities apart
+const PSEUDONYM_SIZE: usize = 16;
+
+/// The policy of the loaded configuration, applied wherever identities are logged or published
+static POLICY: ArcSwapOption<IdentityHashing> = ArcSwapOption::const_empty();
+
+/// Replaces the values of identity claims with keyed hashes (pseudonyms) in the logs and the
+/// decision events
+///
+/// The same value always has the same pseudonym under one key, so the requests of an identity can
+/// still be correlated, but the value can't be recovered without the key.
+#[derive(Clone)]
+pub struct IdentityHashing {
+    key: Arc<[u8]>,
+    /// Prefixed to the pseudonyms, so they can be told apart across key rotations
+    key_id: Option<String>,
+    claims: HashSet<String>,
+}
+
+/// A value of a claim, displayed as its pseudonym if the policy covers the claim
+pub struct Identity<'a> {
+    claim: &'a str,
+    value: &'a str,
+}
+
+impl IdentityHashing {
+    pub fn compile(config: &IdentityHashingConfig) -> Result<Self, &'static str> {
+        if config.key.is_empty() {
+            return Err("the key must not be empty");
+        }
+        if config
+            .key_id
+            .as_ref()
+            .is_some_and(|id| id.is_empty() || id.contains(char::is_whitespace))
+        {
+            return Err("the key_id must be a non-empty word");
+        }
+
+        Ok(Self {
+            key: config.key.as_bytes().into(),
+            key_id: config.key_id.clone(),
+            claims: match &config.claims {
+                Some(claims) => claims.iter().cloned().collect(),
+                None => HashSet::from(["sub".to_string()]),
+            },
+        })
+    }
+
+    /// Replace the policy, `None` to log identities as they are
+    pub fn install(policy: Option<Self>) {
+        POLICY.store(policy.map(Arc::new));
+    }
+
+    /// The pseudonym of a value, e.g. `hmac:2024-10:3f2a...`
+    fn pseudonym(&self, value: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(value.as_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        let mut pseudonym = String::from("hmac:");
+        if let Some(key_id) = &self.key_id {
+            pseudonym.push_str(key_id);
+            pseudonym.push(':');
+        }
+        for byte in &hash[..PSEUDONYM_SIZE] {
+            let _ = write!(pseudonym, "{:02x}", byte);
+        }
+        pseudonym
+    }
+}
+
+impl<'a> Identity<'a> {
+    pub fn new(claim: &'a str, value: &'a str) -> Self {
+        Self { claim, value }
+    }
+
+    /// The subject of a token
+    pub fn subject(value: &'a str) -> Self {
+        Self::new("sub", value)
+    }
+}
+
+impl fmt::Debug for IdentityHashing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityHashing")
+            .field("key", &"<redacted>")
+            .field("key_id", &self.key_id)
+            .field("claims", &self.claims)
+            .finish()
+    }
+}
+
+impl fmt::Display for Identity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*POLICY.load() {
+            Some(policy) if policy.claims.contains(self.claim) => {
+                f.write_str(&policy.pseudonym(self.value))
+            }
+            _ => f.write_str(self.value),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use axum::body::Body;
+use axum::response::Response;
+use http::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+/// The reverse proxy a validator answers, adjusting the responses to its forward auth semantics
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Responses are sent as they are
+    #[default]
+    Generic,
+    /// Traefik's `forwardAuth`, which returns rejections (including redirects) to the client
+    Traefik,
+    /// Caddy's `forward_auth`, which returns rejections (including redirects) to the client
+    Caddy,
+    /// nginx's `auth_request`, which only understands 2xx, 401 and 403 and ignores the body
+    Nginx,
+    /// Envoy's HTTP `ext_authz` service, which only allows requests answered with 200
+    EnvoyHttp,
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Generic => "generic",
+            Profile::Traefik => "traefik",
+            Profile::Caddy => "caddy",
+            Profile::Nginx => "nginx",
+            Profile::EnvoyHttp => "envoy-http",
+        }
+    }
+
+    /// Whether the proxy follows redirects of the auth response, the `login_redirect` requires it
+    pub fn allows_redirects(&self) -> bool {
+        !matches!(self, Profile::Nginx)
+    }
+
+    /// The only success status the proxy allows requests with, if it doesn't take any 2xx
+    pub fn success_status(&self) -> Option<StatusCode> {
+        match self {
+            Profile::EnvoyHttp => Some(StatusCode::OK),
+            Profile::Generic | Profile::Traefik | Profile::Caddy | Profile::Nginx => None,
+        }
+    }
+
+    /// Adjust a response to what the proxy expects
+    ///
+    /// nginx treats every status but 2xx, 401 and 403 as an error of the auth service and answers
+    /// the client with 500, so the other rejections (e.g. 429 or 503) are sent as 403. The reason
+    /// is kept in `X-Auth-Reason` for `auth_request_set`.
+    pub fn apply(&self, res: Response) -> Response {
+        match self {
+            Profile::Nginx => {
+                let status = res.status();
+                if status.is_success()
+                    || status == StatusCode::UNAUTHORIZED
+                    || status == StatusCode::FORBIDDEN
+                {
+                    return res;
+                }
+
+                let (mut parts, _) = res.into_parts();
+                parts.status = StatusCode::FORBIDDEN;
+                parts.headers.remove(header::CONTENT_LENGTH);
+                parts.headers.remove(header::CONTENT_TYPE);
+                Response::from_parts(parts, Body::empty())
+            }
+            Profile::Generic | Profile::Traefik | Profile::Caddy | Profile::EnvoyHttp => res,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use http::{HeaderMap, HeaderName, HeaderValue};
+use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
+use std::str::FromStr;
+
+use crate::validator_file::HeaderProjectionConfig;
+
+/// The CORS-safelisted value size of the Fetch standard
+const DEFAULT_MAX_VALUE_SIZE: usize = 128;
+
+/// The CORS-unsafe request-header bytes of the Fetch standard, and `%` so encoded values can be
+/// decoded unambiguously (non-ASCII is always encoded)
+const CORS_UNSAFE: &AsciiSet = &CONTROLS
+    .add(b'"')
+    .add(b'%')
+    .add(b'(')
+    .add(b')')
+    .add(b':')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'@')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'{')
+    .add(b'}');
+
+/// Headers that are removed by intermediaries (hop-by-hop headers, RFC 9110, section 7.6.1) or
+/// set by the reverse proxy itself, claims mapped to them would never reach the upstream service
+const RESERVED: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+    "content-type",
+    "content-encoding",
+    "authorization",
+    "cookie",
+    "set-cookie",
+    "forwarded",
+    "via",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-method",
+    "x-forwarded-port",
+    "x-forwarded-proto",
+    "x-forwarded-uri",
+    "x-real-ip",
+];
+
+/// Whether claims can't be mapped to the header, see `RESERVED`
+///
+/// The `grpc-` headers are reserved by gRPC and gRPC-Web.
+pub fn is_reserved(header: &HeaderName) -> bool {
+    RESERVED.contains(&header.as_str()) || header.as_str().starts_with("grpc-")
+}
+
+/// Restricts the headers claims are mapped to, so they pass browsers and gRPC-Web proxies
+///
+/// Mapped headers are named with a common prefix, their values are percent-encoded to consist of
+/// CORS-safe bytes only, and values exceeding the maximum size are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderProjection {
+    /// The lowercase prefix of the mapped headers
+    prefix: String,
+    max_value_size: usize,
+}
+
+impl HeaderProjection {
+    pub fn compile(config: HeaderProjectionConfig) -> Result<Self, &'static str> {
+        if config.prefix.is_empty() || HeaderName::from_str(&config.prefix).is_err() {
+            return Err("the prefix has to be a valid header name");
+        }
+        let max_value_size = config.max_value_size.unwrap_or(DEFAULT_MAX_VALUE_SIZE);
+        if max_value_size == 0 {
+            return Err("max_value_size has to be positive");
+        }
+
+        Ok(Self {
+            prefix: config.prefix.to_ascii_lowercase(),
+            max_value_size,
+        })
+    }
+
+    /// The name a header is mapped to, names already starting with the prefix are kept
+    pub fn name(&self, header: HeaderName) -> HeaderName {
+        if header.as_str().starts_with(&self.prefix) {
+            return header;
+        }
+        HeaderName::from_str(&format!("{}{}", self.prefix, header))
+            .expect("the prefix and the name are valid header names")
+    }
+
+    /// Encode the values of the mapped headers, returns the names of the dropped headers
+    pub fn apply(&self, headers: HeaderMap) -> (HeaderMap, Vec<HeaderName>) {
+        let mut projected = HeaderMap::with_capacity(headers.len());
+        let mut dropped = Vec::new();
+        for (name, value) in &headers {
+            let encoded = percent_encode(value.as_bytes(), CORS_UNSAFE).to_string();
+            if encoded.len() > self.max_value_size {
+                dropped.push(name.clone());
+                continue;
+            }
+            projected.append(
+                name,
+                HeaderValue::try_from(encoded).expect("the value is percent-encoded"),
+            );
+        }
+
+        (projected, dropped)
+    }
+}
@@ -0,0 +1,139 @@
+use axum::response::Response;
+use http::{header, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+
+/// The response header carrying the reason code of the decision
+pub const REASON_HEADER: &str = "x-auth-reason";
+
+/// Why a validation request was answered the way it was
+///
+/// Attached to the response as an extension, so it can be audited without parsing the response.
+/// The codes are stable: they are sent in the `X-Auth-Reason` header and used in the logs, the
+/// metric labels and the decision events, so they must not be renamed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    Valid,
+    Maintenance,
+    UnknownValidator,
+    Denied,
+    Transport,
+    RateLimited,
+    MissingToken,
+    /// The token exceeds the size limits and wasn't decoded
+    Oversized,
+    InvalidUtf8,
+    /// The token isn't a well-formed JWT
+    Malformed,
+    /// The validator selects the authority by issuer and doesn't accept the issuer of the token
+    UnknownIssuer,
+    /// The JWKS has no key for the key ID and algorithm of the token
+    UnknownKid,
+    /// The signature doesn't match the key
+    Signature,
+    /// The issuer, audience, expiry or not-before claim was rejected
+    RegisteredClaims,
+    Subject,
+    /// A required claim isn't present in the token
+    MissingClaim,
+    /// A required claim doesn't satisfy its checks
+    ClaimMismatch,
+    /// The claims don't satisfy the CEL policy or the Open Policy Agent denied the request
+    Policy,
+    /// The Open Policy Agent of the validator couldn't decide
+    PolicyUnavailable,
+    /// The headers the claims are mapped to exceed the limits of the validator
+    HeaderLimits,
+    Timeout,
+    /// The keys of the authority are beyond their maximum staleness and the outage policy decided
+    StaleKeys,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Valid => "valid",
+            Reason::Maintenance => "maintenance",
+            Reason::UnknownValidator => "unknown_validator",
+            Reason::Denied => "denied",
+            Reason::Transport => "transport",
+            Reason::RateLimited => "rate_limited",
+            Reason::MissingToken => "missing_token",
+            Reason::Oversized => "oversized",
+            Reason::InvalidUtf8 => "invalid_utf8",
+            Reason::Malformed => "malformed",
+            Reason::UnknownIssuer => "unknown_issuer",
+            Reason::UnknownKid => "unknown_kid",
+            Reason::Signature => "signature",
+            Reason::RegisteredClaims => "registered_claims",
+            Reason::Subject => "subject",
+            Reason::MissingClaim => "missing_claim",
+            Reason::ClaimMismatch => "claim_mismatch",
+            Reason::Policy => "policy",
+            Reason::PolicyUnavailable => "policy_unavailable",
+            Reason::HeaderLimits => "header_limits",
+            Reason::Timeout => "timeout",
+            Reason::StaleKeys => "stale_keys",
+        }
+    }
+
+    /// The RFC 6750 error code and description of a rejection for this reason
+    ///
+    /// `None` if the request carried no token, in which case the challenge has no error.
+    pub fn bearer_error(&self) -> Option<(&'static str, &'static str)> {
+        let error = match self {
+            Reason::Oversized => ("invalid_token", "The token exceeds the size limits"),
+            Reason::InvalidUtf8 => ("invalid_token", "The token is not valid UTF-8"),
+            Reason::Malformed => ("invalid_token", "The token is malformed"),
+            Reason::UnknownIssuer => ("invalid_token", "The token issuer is not accepted"),
+            Reason::UnknownKid => ("invalid_token", "The token is signed with an unknown key"),
+            Reason::Signature => ("invalid_token", "The token signature is invalid"),
+            Reason::RegisteredClaims => (
+                "invalid_token",
+                "The token is expired or its issuer or audience is not accepted",
+            ),
+            Reason::HeaderLimits => ("invalid_token", "The token claims exceed the header limits"),
+            // A new token of the same client won't do, it needs other privileges
+            Reason::Subject => ("insufficient_scope", "The token subject is not allowed"),
+            Reason::MissingClaim => ("insufficient_scope", "The token lacks a required claim"),
+            Reason::ClaimMismatch => (
+                "insufficient_scope",
+                "The token claims do not satisfy the requirements",
+            ),
+            Reason::Policy => (
+                "insufficient_scope",
+                "The token claims do not satisfy the policy",
+            ),
+            _ => return None,
+        };
+        Some(error)
+    }
+}
+
+/// Add the RFC 6750 `WWW-Authenticate` challenge to a 401, with the error of its reason
+pub fn challenge(mut res: Response, realm: &str) -> Response {
+    if res.status() != StatusCode::UNAUTHORIZED {
+        return res;
+    }
+    let Some(reason) = res.extensions().get::<Reason>() else {
+        return res;
+    };
+
+    // The realm was checked to be a valid quoted string when the configuration was loaded
+    let challenge = match reason.bearer_error() {
+        Some((error, description)) => format!(
+            "Bearer realm=\"{}\", error=\"{}\", error_description=\"{}\"",
+            realm, error, description
+        ),
+        None => format!("Bearer realm=\"{}\"", realm),
+    };
+    if let Ok(challenge) = HeaderValue::try_from(challenge) {
+        res.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, challenge);
+    }
+    res
+}
+
+/// The subject of the verified token, attached to the response as an extension
+#[derive(Debug, Clone)]
+pub struct TokenSubject(pub String);
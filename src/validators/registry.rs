@@ -0,0 +1,184 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::validator_file::{Config, ConfigFile, DynamicConfig, Lint, ValidationFileError};
+
+/// The configuration file and the authorities and validators registered through the admin API
+///
+/// The effective configuration is the configuration file with the dynamic entries added, entries of
+/// the configuration file take precedence.
+#[derive(Debug)]
+pub struct Registry {
+    file: Mutex<Option<Arc<ConfigFile>>>,
+    dynamic: Mutex<DynamicConfig>,
+    /// The file the dynamic entries are persisted to, if any
+    state_file: Option<PathBuf>,
+    /// Whether lint findings make a configuration invalid
+    strict: bool,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    /// No valid configuration file is loaded
+    NoConfig,
+    /// The name is already used by the configuration file
+    Conflict(String),
+    NotFound(String),
+    Invalid(ValidationFileError),
+    /// The change could not be written to the state file
+    Persist(ValidationFileError),
+}
+
+impl Registry {
+    pub fn new(state_file: Option<PathBuf>, strict: bool) -> Self {
+        Self {
+            file: Mutex::new(None),
+            dynamic: Mutex::new(DynamicConfig::default()),
+            state_file,
+            strict,
+        }
+    }
+
+    /// Build the configuration, logging its lint findings or rejecting it in strict mode
+    pub fn compile(&self, file: ConfigFile) -> Result<Config, ValidationFileError> {
+        let cfg = Config::from_file(file)?;
+        if self.strict && !cfg.lints.is_empty() {
+            return Err(ValidationFileError::Lint(cfg.lints));
+        }
+
+        for lint in &cfg.lints {
+            match lint {
+                Lint::DeprecatedKey(used) => warn!(
+                    key = used.deprecation.key,
+                    path = used.path,
+                    replacement = used.deprecation.replacement,
+                    removed_in = used.deprecation.removed_in,
+                    "{}",
+                    lint
+                ),
+                lint => warn!("{}", lint),
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Load the dynamic entries persisted by a previous run
+    pub fn restore(&self) -> Result<(), ValidationFileError> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+
+        info!(
+            "Loading registered authorities and validators from: {}",
+            path.display()
+        );
+        let dynamic = DynamicConfig::load(path)?;
+        info!(
+            "Restored {} authorities and {} validators",
+            dynamic.authorities.len(),
+            dynamic.validators.len()
+        );
+        *self.dynamic.lock().expect("lock poisoned") = dynamic;
+
+        Ok(())
+    }
+
+    /// Replace the configuration file and apply the resulting configuration
+    ///
+    /// If the dynamic entries are incompatible with the new file (e.g. they reference an authority
+    /// that was removed), only the file is applied until the entries are fixed through the admin API.
+    /// Nothing is changed if the file itself is invalid.
+    pub fn set_file<F>(&self, file: Arc<ConfigFile>, apply: F) -> Result<(), ValidationFileError>
+    where
+        F: FnOnce(&Config),
+    {
+        let dynamic = self.dynamic.lock().expect("lock poisoned");
+
+        let cfg = match self.compile(file.with_dynamic(&dynamic)) {
+            Ok(cfg) => Ok(cfg),
+            Err(err) if !dynamic.is_empty() => {
+                warn!(
+                    "Dynamic authorities and validators are incompatible with the configuration file, ignoring them: {}",
+                    err
+                );
+                self.compile(ConfigFile::clone(&file))
+            }
+            Err(err) => Err(err),
+        }?;
+
+        *self.file.lock().expect("lock poisoned") = Some(file);
+        apply(&cfg);
+        Ok(())
+    }
+
+    /// The configuration file the active configuration is based on
+    pub fn file(&self) -> Option<Arc<ConfigFile>> {
+        self.file.lock().expect("lock poisoned").clone()
+    }
+
+    /// Forget the configuration file, e.g. because it cannot be parsed anymore
+    pub fn clear_file(&self) {
+        *self.file.lock().expect("lock poisoned") = None;
+    }
+
+    pub fn dynamic(&self) -> DynamicConfig {
+        self.dynamic.lock().expect("lock poisoned").clone()
+    }
+
+    /// Change the dynamic entries and apply the resulting configuration
+    ///
+    /// The change is discarded if the resulting configuration is invalid or cannot be persisted.
+    pub fn update<C, F>(&self, change: C, apply: F) -> Result<(), RegistryError>
+    where
+        C: FnOnce(&ConfigFile, &mut DynamicConfig) -> Result<(), RegistryError>,
+        F: FnOnce(&Config),
+    {
+        let file = self
+            .file
+            .lock()
+            .expect("lock poisoned")
+            .clone()
+            .ok_or(RegistryError::NoConfig)?;
+        // Holding the lock while applying keeps concurrent changes in order
+        let mut dynamic = self.dynamic.lock().expect("lock poisoned");
+
+        let mut candidate = dynamic.clone();
+        change(&file, &mut candidate)?;
+
+        let cfg = self
+            .compile(file.with_dynamic(&candidate))
+            .map_err(RegistryError::Invalid)?;
+        if let Some(path) = &self.state_file {
+            candidate.save(path).map_err(RegistryError::Persist)?;
+        }
+        *dynamic = candidate;
+        apply(&cfg);
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::NoConfig => write!(f, "No valid configuration file is loaded"),
+            RegistryError::Conflict(name) => {
+                write!(f, "{} is defined by the configuration file", name)
+            }
+            RegistryError::NotFound(name) => write!(f, "{} is not registered", name),
+            RegistryError::Invalid(err) => write!(f, "Invalid configuration: {}", err),
+            RegistryError::Persist(err) => write!(f, "Failed to persist the change: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RegistryError::Invalid(err) | RegistryError::Persist(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use http::HeaderMap;
+use percent_encoding::percent_decode_str;
+use std::borrow::Cow;
+
+/// The original request as forwarded by the reverse proxy through the `X-Forwarded-*` headers
+#[derive(Debug, Clone, Copy)]
+pub struct ForwardedRequest<'a> {
+    headers: &'a HeaderMap,
+}
+
+impl<'a> ForwardedRequest<'a> {
+    pub fn new(headers: &'a HeaderMap) -> Self {
+        Self { headers }
+    }
+
+    /// The value of a request header, if it is valid UTF-8
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// The cookies sent with the request, as name and (still encoded) value
+    pub fn cookies(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.headers
+            .get_all(http::header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+    }
+
+    /// The value of a cookie, reassembled from its chunks (`name.0`, `name.1`, ...) if it was
+    /// split because of its size
+    ///
+    /// The chunks are collected in a single pass. Reassembly stops once the value exceeds
+    /// `max_length`, so an oversized value is still rejected by the length check without
+    /// assembling all of it.
+    pub fn cookie(&self, name: &str, max_length: usize) -> Option<Cow<'a, str>> {
+        let mut chunks = Vec::new();
+        for (cookie, value) in self.cookies() {
+            let Some(suffix) = cookie.strip_prefix(name) else {
+                continue;
+            };
+            if suffix.is_empty() {
+                return Some(Cow::Borrowed(unquote(value)));
+            }
+
+            // Every chunk before carries a part of the value, so chunks past the length limit
+            // can't belong to a value within it
+            let index = suffix
+                .strip_prefix('.')
+                .filter(|index| index.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index <= max_length);
+            if let Some(index) = index {
+                chunks.push((index, unquote(value)));
+            }
+        }
+        // Stable, so the first of repeated chunks is used
+        chunks.sort_by_key(|(index, _)| *index);
+
+        let mut value = String::new();
+        let mut next = 0;
+        for (index, part) in chunks {
+            if index < next {
+                continue;
+            }
+            // The value ends at the first missing or empty chunk
+            if index > next || part.is_empty() {
+                break;
+            }
+            next += 1;
+            value.push_str(part);
+            if value.len() > max_length {
+                break;
+            }
+        }
+        (!value.is_empty()).then_some(Cow::Owned(value))
+    }
+
+    pub fn method(&self) -> Option<&'a str> {
+        self.header("x-forwarded-method")
+    }
+
+    /// The protocol of the original request, the first one if there are multiple proxies
+    pub fn proto(&self) -> Option<&'a str> {
+        self.header("x-forwarded-proto")
+            .and_then(|proto| proto.split(',').next())
+            .map(str::trim)
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.header("x-forwarded-port")?.trim().parse().ok()
+    }
+
+    /// The forwarded host, without the port
+    pub fn host(&self) -> Option<&'a str> {
+        let host = self.header("x-forwarded-host")?;
+        match host.rsplit_once(':') {
+            // Don't split IPv6 addresses without a port (e.g. `[::1]`)
+            Some((name, port)) if !port.ends_with(']') => Some(name),
+            _ => Some(host),
+        }
+    }
+
+    /// The first label of the forwarded host (e.g. `acme` for `acme.example.com`)
+    pub fn subdomain(&self) -> Option<&'a str> {
+        self.host()?.split('.').next().filter(|s| !s.is_empty())
+    }
+
+    /// The forwarded URI, including the query
+    pub fn uri(&self) -> Option<&'a str> {
+        self.header("x-forwarded-uri")
+    }
+
+    pub fn path(&self) -> Option<&'a str> {
+        let uri = self.uri()?;
+        Some(uri.split_once('?').map_or(uri, |(path, _)| path))
+    }
+
+    /// A (percent-decoded) segment of the forwarded path, counting from zero
+    pub fn path_segment(&self, index: usize) -> Option<Cow<'a, str>> {
+        let segment = self
+            .path()?
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .nth(index)?;
+
+        percent_decode_str(segment).decode_utf8().ok()
+    }
+
+    /// The first value of a (decoded) query parameter of the forwarded URI
+    pub fn query_param(&self, name: &str) -> Option<Cow<'a, str>> {
+        let (_, query) = self.uri()?.split_once('?')?;
+
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Cookie values may be enclosed in double quotes, which aren't part of the value
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn cookies(cookies: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_str(cookies).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn reassembles_chunks_in_order() {
+        let headers = cookies("session.1=def; theme=dark; session.0=abc; session.2=\"gh\"");
+        let request = ForwardedRequest::new(&headers);
+
+        assert_eq!(request.cookie("session", 1024).as_deref(), Some("abcdefgh"));
+        assert_eq!(request.cookie("theme", 1024).as_deref(), Some("dark"));
+        assert_eq!(request.cookie("missing", 1024), None);
+    }
+
+    #[test]
+    fn prefers_the_whole_cookie() {
+        let headers = cookies("session.0=abc; session=whole");
+        let request = ForwardedRequest::new(&headers);
+
+        assert_eq!(request.cookie("session", 1024).as_deref(), Some("whole"));
+    }
+
+    #[test]
+    fn stops_at_missing_chunks() {
+        let headers = cookies("session.0=abc; session.2=ghi; session.0=xyz");
+        let request = ForwardedRequest::new(&headers);
+
+        assert_eq!(request.cookie("session", 1024).as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn stops_past_the_length_limit() {
+        let chunks = (0..100)
+            .map(|i| format!("session.{}=abcd", i))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let headers = cookies(&chunks);
+        let request = ForwardedRequest::new(&headers);
+
+        let value = request
+            .cookie("session", 10)
+            .expect("the chunks should be reassembled");
+        assert_eq!(value.len(), 12);
+        assert_eq!(request.cookie("session", 1024).map(|v| v.len()), Some(400));
+    }
+}
@@ -0,0 +1,160 @@
+use arc_swap::ArcSwapOption;
+use http::StatusCode;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::info;
+
+use crate::metrics;
+use crate::validator_file::{ConfigFile, ValidationFileError};
+use crate::validators::validator::Validator;
+
+/// The staged configuration and the configuration that was active before the last promotion
+///
+/// The staged configuration evaluates every request in shadow mode, its decisions are only compared
+/// with the decisions of the active configuration.
+#[derive(Debug)]
+pub struct Slots {
+    staged: ArcSwapOption<Staged>,
+    /// The configuration file to roll back to, the lock also serializes promotions and rollbacks
+    previous: Mutex<Option<Arc<ConfigFile>>>,
+}
+
+#[derive(Debug)]
+pub struct Staged {
+    file: Arc<ConfigFile>,
+    validators: HashMap<String, Validator>,
+    matches: AtomicU64,
+    mismatches: AtomicU64,
+}
+
+#[derive(Debug)]
+pub enum SlotError {
+    NothingStaged,
+    NothingToRollBack,
+    Invalid(ValidationFileError),
+}
+
+/// The state of the slots, as exposed by the admin API
+#[derive(Debug, Serialize)]
+pub struct SlotStatus {
+    pub staged: Option<StagedStatus>,
+    pub rollback_available: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StagedStatus {
+    pub validators: Vec<String>,
+    /// Requests the staged configuration decided like the active one
+    pub matches: u64,
+    pub mismatches: u64,
+}
+
+impl Slots {
+    pub fn new() -> Self {
+        Self {
+            staged: ArcSwapOption::empty(),
+            previous: Mutex::new(None),
+        }
+    }
+
+    pub fn staged(&self) -> Option<Arc<Staged>> {
+        self.staged.load_full()
+    }
+
+    /// Stage a configuration, replacing the currently staged one
+    pub fn stage(&self, staged: Staged) {
+        self.staged.store(Some(Arc::new(staged)));
+    }
+
+    /// Discard the staged configuration, returns whether one was staged
+    pub fn discard(&self) -> bool {
+        self.staged.swap(None).is_some()
+    }
+
+    /// Lock the configuration to roll back to
+    pub fn previous(&self) -> MutexGuard<'_, Option<Arc<ConfigFile>>> {
+        self.previous.lock().expect("lock poisoned")
+    }
+
+    pub fn clear_previous(&self) {
+        *self.previous() = None;
+    }
+
+    pub fn status(&self) -> SlotStatus {
+        SlotStatus {
+            staged: self.staged().map(|staged| staged.status()),
+            rollback_available: self.previous().is_some(),
+        }
+    }
+}
+
+impl Staged {
+    pub fn new(file: Arc<ConfigFile>, validators: HashMap<String, Validator>) -> Self {
+        Self {
+            file,
+            validators,
+            matches: AtomicU64::new(0),
+            mismatches: AtomicU64::new(0),
+        }
+    }
+
+    pub fn file(&self) -> &Arc<ConfigFile> {
+        &self.file
+    }
+
+    pub fn validator(&self, name: &str) -> Option<&Validator> {
+        self.validators.get(name)
+    }
+
+    /// Compare the decision of the staged configuration with the decision of the active one
+    pub fn record(&self, validator: &str, active: StatusCode, staged: StatusCode) {
+        let outcome = if active == staged {
+            self.matches.fetch_add(1, Ordering::Relaxed);
+            "match"
+        } else {
+            info!(
+                "Staged configuration answers {} instead of {} for validator {}",
+                staged, active, validator
+            );
+            self.mismatches.fetch_add(1, Ordering::Relaxed);
+            "mismatch"
+        };
+
+        metrics::SHADOW_DECISIONS
+            .with_label_values(&[outcome])
+            .inc();
+    }
+
+    fn status(&self) -> StagedStatus {
+        let mut validators = self.validators.keys().cloned().collect::<Vec<_>>();
+        validators.sort_unstable();
+
+        StagedStatus {
+            validators,
+            matches: self.matches.load(Ordering::Relaxed),
+            mismatches: self.mismatches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotError::NothingStaged => write!(f, "No configuration is staged"),
+            SlotError::NothingToRollBack => write!(f, "No previous configuration to roll back to"),
+            SlotError::Invalid(err) => write!(f, "Invalid configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SlotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SlotError::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
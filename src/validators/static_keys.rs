@@ -0,0 +1,184 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::pem::Pem;
+use x509_parser::prelude::FromDer;
+use x509_parser::public_key::PublicKey;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+use crate::validator_file::{KeyMaterial, StaticKeys};
+
+#[derive(Debug)]
+pub enum StaticKeyError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The file holds no public key or certificate
+    NoKey {
+        path: PathBuf,
+    },
+    Invalid(String),
+    /// The key isn't an RSA or EC key
+    Unsupported,
+    /// PEM keys don't say which algorithm they are used with
+    MissingAlgorithm,
+    /// The environment variable holding the secret isn't set
+    MissingEnv(String),
+    EmptySecret,
+}
+
+/// The JWKS document of keys configured locally, in the form a fetched document has
+///
+/// PEM files are read again on every call, so a replaced key is picked up by the next refresh.
+pub fn document(keys: &StaticKeys) -> Result<Vec<u8>, StaticKeyError> {
+    let mut jwk = match &keys.material {
+        // A single key is accepted in place of a whole JWKS
+        KeyMaterial::Jwks(jwk) if jwk.get("kty").is_some() => jwk.clone(),
+        KeyMaterial::Jwks(jwks) => {
+            return serde_json::to_vec(jwks).map_err(|e| StaticKeyError::Invalid(e.to_string()))
+        }
+        KeyMaterial::PemFile(path) => {
+            let mut jwk = pem_jwk(path)?;
+            let algorithm = keys.algorithm.ok_or(StaticKeyError::MissingAlgorithm)?;
+            jwk["alg"] = json!(algorithm);
+            jwk
+        }
+        KeyMaterial::HmacSecret(secret) => hmac_jwk(secret.as_bytes(), keys)?,
+        KeyMaterial::SecretFile(path) => {
+            let secret = std::fs::read(path).map_err(|error| StaticKeyError::Io {
+                path: path.clone(),
+                error,
+            })?;
+            // Files written with `echo` end with a newline that isn't part of the secret
+            let secret = secret
+                .strip_suffix(b"\n")
+                .map(|secret| secret.strip_suffix(b"\r").unwrap_or(secret))
+                .unwrap_or(&secret);
+            hmac_jwk(secret, keys)?
+        }
+        KeyMaterial::SecretEnv(name) => {
+            let secret =
+                std::env::var_os(name).ok_or_else(|| StaticKeyError::MissingEnv(name.clone()))?;
+            hmac_jwk(secret.as_encoded_bytes(), keys)?
+        }
+    };
+
+    if !matches!(keys.material, KeyMaterial::Jwks(_)) {
+        jwk["use"] = json!("sig");
+        if let Some(kid) = &keys.kid {
+            jwk["kid"] = json!(kid);
+        }
+    }
+
+    serde_json::to_vec(&json!({ "keys": [jwk] }))
+        .map_err(|e| StaticKeyError::Invalid(e.to_string()))
+}
+
+fn hmac_jwk(secret: &[u8], keys: &StaticKeys) -> Result<Value, StaticKeyError> {
+    if secret.is_empty() {
+        return Err(StaticKeyError::EmptySecret);
+    }
+
+    Ok(json!({
+        "kty": "oct",
+        "k": URL_SAFE_NO_PAD.encode(secret),
+        "alg": keys.algorithm.map_or(json!("HS256"), |alg| json!(alg)),
+    }))
+}
+
+/// The public key of a PEM file (a `PUBLIC KEY` or a `CERTIFICATE`) as a JWK without metadata
+fn pem_jwk(path: &Path) -> Result<Value, StaticKeyError> {
+    let content = std::fs::read(path).map_err(|error| StaticKeyError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    for pem in Pem::iter_from_buffer(&content) {
+        let pem = pem.map_err(|e| StaticKeyError::Invalid(e.to_string()))?;
+        match pem.label.as_str() {
+            "PUBLIC KEY" => {
+                let (_, spki) = SubjectPublicKeyInfo::from_der(&pem.contents)
+                    .map_err(|e| StaticKeyError::Invalid(e.to_string()))?;
+                return spki_jwk(&spki);
+            }
+            "CERTIFICATE" => {
+                let (_, cert) = X509Certificate::from_der(&pem.contents)
+                    .map_err(|e| StaticKeyError::Invalid(e.to_string()))?;
+                return spki_jwk(cert.public_key());
+            }
+            _ => continue,
+        }
+    }
+
+    Err(StaticKeyError::NoKey {
+        path: path.to_path_buf(),
+    })
+}
+
+fn spki_jwk(spki: &SubjectPublicKeyInfo) -> Result<Value, StaticKeyError> {
+    let encode = |bytes: &[u8]| {
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+        URL_SAFE_NO_PAD.encode(&bytes[start..])
+    };
+
+    match spki.parsed() {
+        Ok(PublicKey::RSA(rsa)) => Ok(json!({
+            "kty": "RSA",
+            "n": encode(rsa.modulus),
+            "e": encode(rsa.exponent),
+        })),
+        Ok(PublicKey::EC(point)) => {
+            // Uncompressed points only, the curve follows from their length
+            let point = point.data();
+            let crv = match point.len() {
+                65 => "P-256",
+                97 => "P-384",
+                133 => "P-521",
+                _ => return Err(StaticKeyError::Unsupported),
+            };
+            if point[0] != 0x04 {
+                return Err(StaticKeyError::Unsupported);
+            }
+
+            let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+            Ok(json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": URL_SAFE_NO_PAD.encode(x),
+                "y": URL_SAFE_NO_PAD.encode(y),
+            }))
+        }
+        Ok(_) => Err(StaticKeyError::Unsupported),
+        Err(e) => Err(StaticKeyError::Invalid(e.to_string())),
+    }
+}
+
+impl fmt::Display for StaticKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "failed to read {}: {}", path.display(), error),
+            Self::NoKey { path } => {
+                write!(f, "{} holds no public key or certificate", path.display())
+            }
+            Self::Invalid(e) => write!(f, "invalid key: {}", e),
+            Self::Unsupported => write!(f, "only RSA and EC keys are supported"),
+            Self::MissingAlgorithm => write!(f, "the algorithm of the PEM key is missing"),
+            Self::MissingEnv(name) => write!(f, "environment variable {} isn't set", name),
+            Self::EmptySecret => write!(f, "the secret is empty"),
+        }
+    }
+}
+
+impl std::error::Error for StaticKeyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
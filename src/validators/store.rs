@@ -1,12 +1,33 @@
+use crate::admin::AdminAuth;
+use crate::enrichment::Enrichment;
+use crate::events::Events;
+use crate::metrics;
+use crate::mirror::Mirror;
+use crate::utils::rate_limiter::RateLimiter;
 use crate::utils::reloadable::Reloadable;
-use crate::validator_file::Config;
+use crate::validator_file::{
+    Config, ConfigFile, DynamicConfig, HttpClientConfig, JWTAuthority, Lint, PartialJWTValidator,
+    TenantConfig, ValidationFileError, ValidatorAction,
+};
+use crate::validators::anomalies::{self, Anomalies};
 use crate::validators::authority::{Authority, AuthorityStore};
-use crate::validators::jwks::JwksStore;
-use crate::validators::validator::{Validator, ValidatorStore};
+use crate::validators::comparison::Comparison;
+use crate::validators::connectivity::{self, Connectivity};
+use crate::validators::decision_cache::{self, DecisionCache};
+use crate::validators::jwks::{JwksStatus, JwksStore};
+use crate::validators::jwks_cache::JwksCache;
+use crate::validators::maintenance::{Maintenance, Settings};
+use crate::validators::opa::OpaPolicy;
+use crate::validators::privacy::IdentityHashing;
+use crate::validators::registry::{Registry, RegistryError};
+use crate::validators::slots::{SlotError, SlotStatus, Slots, Staged};
+use crate::validators::tenant::Tenant;
+use crate::validators::token_limits::{self, TokenLimits};
+use crate::validators::validator::{Action, Validation, Validator, ValidatorStore};
 use crate::{Shutdown, State, States};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::{info, warn};
 
@@ -28,11 +49,33 @@ struct Inner {
     jwks: JwksStore,
     authorities: AuthorityStore,
     validators: ValidatorStore,
+    maintenance: Maintenance,
+    connectivity: Connectivity,
+    anomalies: Anomalies,
+    decisions: DecisionCache,
+    token_limits: TokenLimits,
+    registry: Registry,
+    slots: Slots,
+    built: Mutex<Built>,
+    request_timeout: Duration,
+    admin_auth: Option<AdminAuth>,
+    events: Events,
+    mirror: Mirror,
 }
 
 impl Store {
-    pub fn new(state: State, client: reqwest::Client) -> Self {
-        let jwks = JwksStore::new(client);
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        state: State,
+        request_timeout: Duration,
+        admin_auth: Option<AdminAuth>,
+        state_file: Option<PathBuf>,
+        strict_config: bool,
+        events: Events,
+        mirror: Mirror,
+        jwks_cache: Option<JwksCache>,
+    ) -> Self {
+        let jwks = JwksStore::new(jwks_cache);
         let authorities = AuthorityStore::new();
         let validators = ValidatorStore::new();
 
@@ -41,6 +84,18 @@ impl Store {
             jwks,
             authorities,
             validators,
+            maintenance: Maintenance::new(),
+            connectivity: Connectivity::new(),
+            anomalies: Anomalies::new(),
+            decisions: DecisionCache::new(),
+            token_limits: TokenLimits::new(),
+            registry: Registry::new(state_file, strict_config),
+            slots: Slots::new(),
+            built: Mutex::new(Built::default()),
+            request_timeout,
+            admin_auth,
+            events,
+            mirror,
         };
 
         Self {
@@ -48,75 +103,22 @@ impl Store {
         }
     }
 
-    fn load(&self, cfg: &Config) {
-        let this = &self.inner;
-
-        let authorities = cfg
-            .authorities
-            .iter()
-            .map(|(name, authority)| {
-                (
-                    name.clone(),
-                    Authority::new(
-                        name.clone(),
-                        this.jwks.get(&authority.jwks_url),
-                        authority.to_validator(),
-                        authority
-                            .update_interval
-                            .map(Duration::from_secs)
-                            .unwrap_or_else(|| Duration::from_secs(3600)),
-                    ),
-                )
-            })
-            .collect::<HashMap<_, _>>();
-
-        let validators = cfg
-            .validators
-            .iter()
-            .map(|(name, validator)| {
-                let authority = authorities
-                    .get(&validator.authority)
-                    .cloned()
-                    .expect("Authority should exist");
-
-                (
-                    name.clone(),
-                    Validator::new(
-                        name.clone(),
-                        authority,
-                        validator.header.clone(),
-                        validator.header_prefix.clone(),
-                        validator.required_claims.clone(),
-                        validator.map_claims.clone(),
-                    ),
-                )
-            })
-            .collect::<HashMap<_, _>>();
-
-        this.authorities.update(authorities);
-        this.validators.update(validators);
-    }
-
-    fn clear(&self) {
-        self.inner.authorities.clear();
-        self.inner.validators.clear();
-    }
-
     pub async fn start_file_watcher(&self, path: PathBuf) -> Result<(), Shutdown> {
+        self.inner
+            .registry
+            .restore()
+            .with_context(|| "Failed to load the registered authorities and validators")?;
+
         info!("Loading configuration from: {}", path.display());
 
-        let reloadable = Reloadable::new(
-            path.clone(),
-            |path| Config::load(path),
-            |e| {
-                warn!("Notify error: {}", e);
-            },
-        )
+        let reloadable = Reloadable::new(path.clone(), ConfigFile::load, |e| {
+            warn!("Notify error: {}", e);
+        })
         .with_context(|| format!("Failed to load configuration from: {}", path.display()))?;
+        watch_included(&reloadable);
 
-        match &*reloadable.get() {
-            Ok(cfg) => {
-                self.load(cfg);
+        match self.inner.apply_file(&reloadable.get()) {
+            Ok(()) => {
                 let _ = self.inner.jwks.refresh_all().await;
                 self.inner.states.set(States::Running);
             }
@@ -126,14 +128,31 @@ impl Store {
             }
         }
 
+        let connectivity = self.inner.connectivity.clone();
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(connectivity.interval()).await;
+                connectivity.check(this.inner.authorities.jwks()).await;
+            }
+        });
+
+        let anomalies = self.inner.anomalies.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(anomalies.interval()).await;
+                anomalies.evaluate();
+            }
+        });
+
         let this = self.clone();
         tokio::spawn(async move {
             loop {
                 reloadable.wait().await;
-                match &*reloadable.get() {
-                    Ok(cfg) => {
-                        info!("Reloading configuration");
-                        this.load(cfg);
+                info!("Reloading configuration");
+                watch_included(&reloadable);
+                match this.inner.apply_file(&reloadable.get()) {
+                    Ok(()) => {
                         let _ = this.inner.jwks.refresh_new().await;
                     }
                     Err(e) => {
@@ -142,7 +161,7 @@ impl Store {
                         // cannot serve any requests
                         this.inner.states.set(States::FaultyConfig);
                         // Clear the validators to prevent any further validation
-                        this.clear();
+                        this.inner.clear();
                     }
                 }
             }
@@ -158,12 +177,544 @@ impl Store {
     }
 }
 
+/// Watch the files included by the configuration file, unchanged if it can't be loaded
+fn watch_included(reloadable: &Reloadable<Result<ConfigFile, ValidationFileError>>) {
+    let Ok(file) = &*reloadable.get() else {
+        return;
+    };
+
+    for (path, e) in reloadable.watch_also(&file.watched) {
+        warn!("Failed to watch included path {}: {}", path.display(), e);
+    }
+}
+
+/// The active tenants, authorities and validators, with what they were built from
+#[derive(Debug, Default)]
+struct Built {
+    tenants: HashMap<String, (TenantConfig, Tenant)>,
+    authorities: HashMap<String, (AuthoritySource, Authority)>,
+    enrichments: HashMap<String, Enrichment>,
+    validators: HashMap<String, (Option<PartialJWTValidator>, Validator)>,
+    /// The settings of the client validators query their Open Policy Agent with
+    http: HttpClientConfig,
+}
+
+/// Everything an authority is built from
+#[derive(Debug, PartialEq)]
+struct AuthoritySource {
+    config: JWTAuthority,
+    tenant: Option<TenantConfig>,
+    http: HttpClientConfig,
+    update_interval: Duration,
+}
+
+impl Built {
+    fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+            && self.authorities.is_empty()
+            && self.enrichments.is_empty()
+            && self.validators.is_empty()
+    }
+
+    fn authorities(&self) -> HashMap<String, Authority> {
+        self.authorities
+            .iter()
+            .map(|(name, (_, authority))| (name.clone(), authority.clone()))
+            .collect()
+    }
+
+    fn validators(&self) -> HashMap<String, Validator> {
+        self.validators
+            .iter()
+            .map(|(name, (_, validator))| (name.clone(), validator.clone()))
+            .collect()
+    }
+}
+
+impl Inner {
+    fn load(&self, cfg: &Config) {
+        let this = self;
+
+        metrics::TENANT_AUTHORITIES.reset();
+        for name in cfg.tenants.keys() {
+            let count = cfg
+                .authorities
+                .values()
+                .filter(|authority| authority.tenant.as_ref() == Some(name))
+                .count();
+            metrics::TENANT_AUTHORITIES
+                .with_label_values(&[name])
+                .set(count as i64);
+        }
+
+        metrics::VALIDATOR_LABELS.reset();
+        for (name, validator) in &cfg.validators {
+            for (label, value) in &validator.labels {
+                metrics::VALIDATOR_LABELS
+                    .with_label_values(&[name, label, value])
+                    .set(1);
+            }
+        }
+
+        metrics::CONFIG_DEPRECATED_KEYS.reset();
+        for lint in &cfg.lints {
+            if let Lint::DeprecatedKey(used) = lint {
+                metrics::CONFIG_DEPRECATED_KEYS
+                    .with_label_values(&[used.deprecation.key])
+                    .inc();
+            }
+        }
+
+        let mut built = this.built.lock().expect("lock poisoned");
+        *built = this.build(cfg, &built);
+
+        this.maintenance.configure(Settings {
+            service: cfg.maintenance.enabled,
+            validators: cfg
+                .validators
+                .iter()
+                .filter(|(_, validator)| validator.maintenance)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            retry_after: Duration::from_secs(cfg.maintenance.retry_after.unwrap_or(60)),
+        });
+
+        this.connectivity.configure(
+            cfg.connectivity_check
+                .as_ref()
+                .map(connectivity::Settings::from_config),
+        );
+
+        this.anomalies.configure(cfg.alerts.as_ref().map(|alerts| {
+            anomalies::Settings::from_config(alerts, &cfg.validators, &cfg.http_clients[&cfg.http])
+        }));
+
+        this.decisions.configure(
+            cfg.decision_cache
+                .as_ref()
+                .map(decision_cache::Settings::from_config),
+        );
+
+        this.token_limits
+            .configure(token_limits::Settings::from_config(&cfg.token_limits));
+        IdentityHashing::install(cfg.identity_hashing.clone());
+
+        this.authorities.update(built.authorities());
+        this.validators.update(built.validators());
+        // Only after the validators were replaced, so requests reading the new version use them
+        this.decisions.invalidate();
+    }
+
+    /// Build the tenants, authorities and validators of a configuration without activating them
+    ///
+    /// Entries built from an unchanged definition are taken from `previous`, so they keep their
+    /// rate limits, JWKS state and lookup caches.
+    fn build(&self, cfg: &Config, previous: &Built) -> Built {
+        let this = self;
+
+        let clients = this.jwks.update_clients(&cfg.http_clients);
+
+        let tenants = cfg
+            .tenants
+            .iter()
+            .map(|(name, config)| {
+                let tenant = match previous.tenants.get(name) {
+                    Some((previous, tenant)) if previous == config => tenant.clone(),
+                    _ => {
+                        let limiter = config.requests_per_second.map(|rate| {
+                            RateLimiter::new(rate, config.burst.unwrap_or(rate).max(1.0))
+                        });
+                        Tenant::new(name.clone(), limiter)
+                    }
+                };
+
+                (name.clone(), (config.clone(), tenant))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut reused_authorities = HashSet::new();
+        let authorities = cfg
+            .authorities
+            .iter()
+            .map(|(name, authority)| {
+                let http = authority.http_config(&cfg.http);
+
+                let mut update_interval = authority
+                    .update_interval
+                    .map(Duration::from_secs)
+                    .unwrap_or_else(|| Duration::from_secs(3600));
+
+                // Clamp the refresh frequency to the tenant's quota
+                let min_interval = authority
+                    .tenant
+                    .as_ref()
+                    .and_then(|tenant| cfg.tenants[tenant].min_update_interval)
+                    .map(Duration::from_secs);
+                if let Some(min_interval) = min_interval {
+                    if update_interval < min_interval {
+                        warn!(
+                            "Authority {} refreshes more often than tenant {} allows, using an update interval of {:?}",
+                            name,
+                            authority.tenant.as_deref().unwrap_or_default(),
+                            min_interval
+                        );
+                        update_interval = min_interval;
+                    }
+                }
+
+                let source = AuthoritySource {
+                    config: authority.clone(),
+                    tenant: authority
+                        .tenant
+                        .as_ref()
+                        .map(|tenant| cfg.tenants[tenant].clone()),
+                    http,
+                    update_interval,
+                };
+
+                let built = match previous.authorities.get(name) {
+                    Some((previous, built)) if *previous == source => {
+                        reused_authorities.insert(name.as_str());
+                        built.clone()
+                    }
+                    _ => Authority::new(
+                        name.clone(),
+                        authority
+                            .tenant
+                            .as_ref()
+                            .map(|tenant| tenants[tenant].1.clone()),
+                        match (&authority.static_keys, &authority.jwks_url) {
+                            (Some(keys), _) => this.jwks.get_static(
+                                name,
+                                keys,
+                                &authority.key_filter(),
+                                &clients[&source.http],
+                            ),
+                            (None, url) => this.jwks.get(
+                                url.as_deref().unwrap_or_default(),
+                                &authority.key_filter(),
+                                &clients[&source.http],
+                            ),
+                        },
+                        authority.to_validator(),
+                        update_interval,
+                        authority.blocking_refresh.unwrap_or(false),
+                        authority.max_staleness.map(Duration::from_secs),
+                    ),
+                };
+
+                (name.clone(), (source, built))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut reused_enrichments = HashSet::new();
+        let enrichments = cfg
+            .enrichments
+            .iter()
+            .map(|(name, enrichment)| match previous.enrichments.get(name) {
+                Some(previous) if previous.config() == enrichment.config() => {
+                    reused_enrichments.insert(name.as_str());
+                    (name.clone(), previous.clone())
+                }
+                _ => (name.clone(), enrichment.clone()),
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut reused_validators = 0;
+        let validators =
+            cfg.validators
+                .iter()
+                .map(|(name, validator)| {
+                    let unchanged = match (previous.validators.get(name), &validator.definition) {
+                        // The message catalog may have changed even if the definition didn't
+                        (Some((Some(previous), built)), Some(definition)) => {
+                            previous == definition && *built.messages() == validator.messages
+                        }
+                        _ => false,
+                    };
+                    // A validator has to be rebuilt to use a rebuilt authority, enrichment or client
+                    let dependencies_reused =
+                        match &validator.action {
+                            ValidatorAction::Validate(validation) => {
+                                validation.authorities.iter().all(|authority| {
+                                    reused_authorities.contains(authority.as_str())
+                                }) && validation.compare.as_ref().is_none_or(|(authority, _)| {
+                                    reused_authorities.contains(authority.as_str())
+                                }) && validation
+                                    .enrich
+                                    .iter()
+                                    .all(|name| reused_enrichments.contains(name.as_str()))
+                                    && (validation.opa.is_none() || previous.http == cfg.http)
+                            }
+                            ValidatorAction::Deny(_) => true,
+                        };
+                    if unchanged && dependencies_reused {
+                        reused_validators += 1;
+                        let (definition, built) = &previous.validators[name];
+                        return (name.clone(), (definition.clone(), built.clone()));
+                    }
+
+                    let action = match &validator.action {
+                        ValidatorAction::Validate(validation) => {
+                            Action::Validate(Box::new(Validation {
+                                authorities: validation
+                                    .authorities
+                                    .map(|name| authorities[name].1.clone()),
+                                source: validation.source.clone(),
+                                enrichments: validation
+                                    .enrich
+                                    .iter()
+                                    .map(|name| enrichments[name].clone())
+                                    .collect(),
+                                plan: validation.plan.clone(),
+                                policy: validation.policy.clone(),
+                                opa: validation
+                                    .opa
+                                    .as_ref()
+                                    .map(|opa| OpaPolicy::new(opa, &cfg.http_clients[&cfg.http])),
+                                subjects: validation.subjects.clone(),
+                                transport: validation.transport.clone(),
+                                outage: validation.outage,
+                                cache_max_age: validation.cache_max_age,
+                                stale_while_revalidate: validation.stale_while_revalidate,
+                                header_limits: validation.header_limits.clone(),
+                                header_projection: validation.header_projection.clone(),
+                                compare: validation.compare.as_ref().map(|(name, claims)| {
+                                    Comparison {
+                                        authority: authorities[name].1.clone(),
+                                        claims: claims.clone(),
+                                    }
+                                }),
+                                echo_headers: validation.echo_headers.clone(),
+                                success: validation.success.clone(),
+                                body_template: validation.body_template.clone(),
+                                realm: validation.realm.clone(),
+                                login_redirect: validation.login_redirect.clone(),
+                            }))
+                        }
+                        ValidatorAction::Deny(deny) => Action::Deny(deny.clone()),
+                    };
+
+                    (
+                        name.clone(),
+                        (
+                            validator.definition.clone(),
+                            Validator::new(
+                                name.clone(),
+                                validator.labels.clone(),
+                                action,
+                                validator.messages.clone(),
+                                validator.profile,
+                            ),
+                        ),
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+
+        if !previous.is_empty() {
+            info!(
+                "Kept {} of {} authorities and {} of {} validators unchanged",
+                reused_authorities.len(),
+                authorities.len(),
+                reused_validators,
+                validators.len()
+            );
+        }
+
+        Built {
+            tenants,
+            authorities,
+            enrichments,
+            validators,
+            http: cfg.http.clone(),
+        }
+    }
+
+    fn clear(&self) {
+        self.authorities.clear();
+        self.validators.clear();
+        self.decisions.invalidate();
+    }
+
+    /// Apply a (re)loaded configuration file together with the dynamic entries
+    fn apply_file(&self, file: &Result<ConfigFile, ValidationFileError>) -> Result<(), String> {
+        match file {
+            Ok(file) => {
+                // A new file replaces whatever was promoted, so there is nothing to roll back to
+                self.slots.clear_previous();
+                self.registry
+                    .set_file(Arc::new(file.clone()), |cfg| self.load(cfg))
+                    .map_err(|e| {
+                        self.registry.clear_file();
+                        e.to_string()
+                    })
+            }
+            Err(e) => {
+                self.registry.clear_file();
+                Err(e.to_string())
+            }
+        }
+    }
+}
+
 impl ValidatorsState {
+    /// The names of the enabled validators, sorted
     pub fn list(&self) -> Vec<String> {
         self.inner.validators.keys()
     }
 
+    /// The names of the active authorities
+    pub fn authorities(&self) -> Vec<String> {
+        self.inner.authorities.keys()
+    }
+
+    /// Get an active authority
+    pub fn authority(&self, name: &str) -> Option<Authority> {
+        self.inner.authorities.get(name)
+    }
+
+    /// The loaded keys and last refresh of every JWKS
+    pub fn jwks(&self) -> Vec<JwksStatus> {
+        self.inner.jwks.statuses()
+    }
+
+    /// The enabled validators, sorted by name
+    pub fn list_validators(&self) -> Arc<[Validator]> {
+        self.inner.validators.values()
+    }
+
     pub fn get(&self, name: &str) -> Option<Validator> {
         self.inner.validators.get(name)
     }
+
+    /// The validators, including disabled ones
+    pub fn validators(&self) -> &ValidatorStore {
+        &self.inner.validators
+    }
+
+    pub fn maintenance(&self) -> &Maintenance {
+        &self.inner.maintenance
+    }
+
+    pub fn connectivity(&self) -> &Connectivity {
+        &self.inner.connectivity
+    }
+
+    pub fn anomalies(&self) -> &Anomalies {
+        &self.inner.anomalies
+    }
+
+    pub fn decisions(&self) -> &DecisionCache {
+        &self.inner.decisions
+    }
+
+    pub fn token_limits(&self) -> &TokenLimits {
+        &self.inner.token_limits
+    }
+
+    /// The authorities and validators registered through the admin API
+    pub fn dynamic(&self) -> DynamicConfig {
+        self.inner.registry.dynamic()
+    }
+
+    /// Change the dynamically registered authorities and validators and apply the result
+    pub async fn update_dynamic<C>(&self, change: C) -> Result<(), RegistryError>
+    where
+        C: FnOnce(&ConfigFile, &mut DynamicConfig) -> Result<(), RegistryError>,
+    {
+        self.inner
+            .registry
+            .update(change, |cfg| self.inner.load(cfg))?;
+        let _ = self.inner.jwks.refresh_new().await;
+
+        Ok(())
+    }
+
+    /// The configuration evaluated in shadow mode, if any
+    pub fn staged(&self) -> Option<Arc<Staged>> {
+        self.inner.slots.staged()
+    }
+
+    pub fn slots(&self) -> SlotStatus {
+        self.inner.slots.status()
+    }
+
+    /// Stage a configuration file, combined with the dynamically registered entries
+    pub async fn stage(&self, file: ConfigFile) -> Result<(), SlotError> {
+        let dynamic = self.inner.registry.dynamic();
+        let cfg = self
+            .inner
+            .registry
+            .compile(file.with_dynamic(&dynamic))
+            .map_err(SlotError::Invalid)?;
+
+        // Staged entries are built from scratch, so shadow evaluations don't count against the
+        // rate limits of the active tenants
+        let validators = self.inner.build(&cfg, &Built::default()).validators();
+        self.inner
+            .slots
+            .stage(Staged::new(Arc::new(file), validators));
+        let _ = self.inner.jwks.refresh_new().await;
+
+        Ok(())
+    }
+
+    pub fn discard_staged(&self) -> Result<(), SlotError> {
+        match self.inner.slots.discard() {
+            true => Ok(()),
+            false => Err(SlotError::NothingStaged),
+        }
+    }
+
+    /// Activate the staged configuration, keeping the active one to roll back to
+    pub fn promote(&self) -> Result<(), SlotError> {
+        let mut previous = self.inner.slots.previous();
+        let staged = self.inner.slots.staged().ok_or(SlotError::NothingStaged)?;
+
+        // The staged configuration is kept if it cannot be activated, so it can be replaced or
+        // discarded
+        let active = self.inner.registry.file();
+        self.inner
+            .registry
+            .set_file(staged.file().clone(), |cfg| self.inner.load(cfg))
+            .map_err(SlotError::Invalid)?;
+
+        self.inner.slots.discard();
+        *previous = active;
+        Ok(())
+    }
+
+    /// Reactivate the configuration that was active before the last promotion
+    ///
+    /// The replaced configuration becomes the one to roll back to, so a rollback can be undone.
+    pub fn rollback(&self) -> Result<(), SlotError> {
+        let mut previous = self.inner.slots.previous();
+        let target = previous.clone().ok_or(SlotError::NothingToRollBack)?;
+
+        let active = self.inner.registry.file();
+        self.inner
+            .registry
+            .set_file(target, |cfg| self.inner.load(cfg))
+            .map_err(SlotError::Invalid)?;
+
+        *previous = active;
+        Ok(())
+    }
+
+    /// The deadline for validating a single request
+    pub fn request_timeout(&self) -> Duration {
+        self.inner.request_timeout
+    }
+
+    pub fn admin_auth(&self) -> Option<&AdminAuth> {
+        self.inner.admin_auth.as_ref()
+    }
+
+    pub fn events(&self) -> &Events {
+        &self.inner.events
+    }
+
+    pub fn mirror(&self) -> &Mirror {
+        &self.inner.mirror
+    }
 }
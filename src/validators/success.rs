@@ -0,0 +1,85 @@
+use axum::body::Body;
+use axum::response::Response;
+use http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+
+use crate::validator_file::SuccessResponseConfig;
+
+/// What a validator answers successful validations with, instead of an empty 200
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuccessResponse {
+    status: StatusCode,
+    /// Added unless the response already has them, e.g. from the mapped claims
+    headers: HeaderMap,
+    body: Option<String>,
+}
+
+impl SuccessResponse {
+    /// Check the status and headers, the reason the response is invalid otherwise
+    pub fn compile(config: SuccessResponseConfig) -> Result<Self, String> {
+        let status = match config.status {
+            Some(status) => StatusCode::from_u16(status)
+                .ok()
+                // Anything else would be taken as a rejection by the proxy
+                .filter(StatusCode::is_success)
+                .ok_or_else(|| format!("status {} isn't a 2xx status", status))?,
+            None => StatusCode::OK,
+        };
+        if config.body.is_some() && status == StatusCode::NO_CONTENT {
+            return Err("a 204 response can't have a body".to_string());
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers {
+            let name = HeaderName::try_from(&name)
+                .map_err(|_| format!("{} isn't a valid header name", name))?;
+            let value = HeaderValue::try_from(&value)
+                .map_err(|_| format!("the value of header {} isn't a valid header value", name))?;
+            headers.insert(name, value);
+        }
+
+        Ok(Self {
+            status,
+            headers,
+            body: config.body,
+        })
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Replace the status and body of a successful response and add the headers
+    pub fn apply(&self, res: Response) -> Response {
+        if !res.status().is_success() {
+            return res;
+        }
+
+        let (mut parts, body) = res.into_parts();
+        parts.status = self.status;
+        for (name, value) in &self.headers {
+            if !parts.headers.contains_key(name) {
+                parts.headers.insert(name, value.clone());
+            }
+        }
+
+        let body = match &self.body {
+            Some(text) => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                if !parts.headers.contains_key(header::CONTENT_TYPE) {
+                    parts.headers.insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("text/plain; charset=utf-8"),
+                    );
+                }
+                Body::from(text.clone())
+            }
+            None if self.status == StatusCode::NO_CONTENT => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                parts.headers.remove(header::CONTENT_TYPE);
+                Body::empty()
+            }
+            None => body,
+        };
+        Response::from_parts(parts, body)
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::metrics;
+use crate::utils::rate_limiter::RateLimiter;
+
+/// A tenant owning a set of authorities and the validators using them
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    inner: Arc<TenantInner>,
+}
+
+#[derive(Debug)]
+struct TenantInner {
+    name: String,
+    limiter: Option<RateLimiter>,
+}
+
+impl Tenant {
+    pub fn new(name: String, limiter: Option<RateLimiter>) -> Self {
+        let inner = Arc::new(TenantInner { name, limiter });
+
+        Self { inner }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Account for a request of the tenant, returns false if the tenant exceeded its request rate
+    pub fn admit(&self) -> bool {
+        let allowed = self
+            .inner
+            .limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.try_acquire());
+
+        let outcome = if allowed { "allowed" } else { "rate_limited" };
+        metrics::TENANT_REQUESTS
+            .with_label_values(&[self.name(), outcome])
+            .inc();
+
+        allowed
+    }
+}
@@ -0,0 +1,133 @@
+use arc_swap::ArcSwap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::validator_file::TokenLimitsConfig;
+
+/// Limits on the size of tokens, checked before they are decoded
+///
+/// Anything larger than a sensible JWT is rejected without touching the base64 and JSON parsers,
+/// so multi-megabyte "tokens" cost no more than a length check.
+#[derive(Debug)]
+pub struct TokenLimits {
+    settings: ArcSwap<Settings>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub max_length: usize,
+    pub max_header_length: usize,
+    pub max_payload_length: usize,
+    pub max_signature_length: usize,
+}
+
+/// Why a token exceeds the limits
+#[derive(Debug)]
+pub enum LimitError {
+    Length {
+        length: usize,
+        max: usize,
+    },
+    Segment {
+        segment: &'static str,
+        length: usize,
+        max: usize,
+    },
+    /// A JWS has three segments, anything with more isn't worth decoding
+    Segments {
+        count: usize,
+    },
+}
+
+impl Settings {
+    pub fn from_config(config: &TokenLimitsConfig) -> Self {
+        Self {
+            max_length: config.max_length.unwrap_or(16 * 1024),
+            max_header_length: config.max_header_length.unwrap_or(1024),
+            max_payload_length: config.max_payload_length.unwrap_or(12 * 1024),
+            max_signature_length: config.max_signature_length.unwrap_or(1024),
+        }
+    }
+
+    /// Check the token, as extracted from the request, against the limits
+    pub fn check(&self, token: &[u8]) -> Result<(), LimitError> {
+        if token.len() > self.max_length {
+            return Err(LimitError::Length {
+                length: token.len(),
+                max: self.max_length,
+            });
+        }
+
+        let count = token.split(|b| *b == b'.').count();
+        if count > 3 {
+            return Err(LimitError::Segments { count });
+        }
+
+        let segments = ["header", "payload", "signature"];
+        let maxima = [
+            self.max_header_length,
+            self.max_payload_length,
+            self.max_signature_length,
+        ];
+        for ((segment, max), part) in segments
+            .into_iter()
+            .zip(maxima)
+            .zip(token.split(|b| *b == b'.'))
+        {
+            if part.len() > max {
+                return Err(LimitError::Segment {
+                    segment,
+                    length: part.len(),
+                    max,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TokenLimits {
+    pub fn new() -> Self {
+        Self {
+            settings: ArcSwap::new(Arc::new(Settings::from_config(
+                &TokenLimitsConfig::default(),
+            ))),
+        }
+    }
+
+    pub fn configure(&self, settings: Settings) {
+        self.settings.store(Arc::new(settings));
+    }
+
+    /// The current limits, the staged configuration is checked with them as well
+    pub fn settings(&self) -> Settings {
+        **self.settings.load()
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length { length, max } => {
+                write!(
+                    f,
+                    "token is {} bytes long, at most {} are allowed",
+                    length, max
+                )
+            }
+            Self::Segment {
+                segment,
+                length,
+                max,
+            } => write!(
+                f,
+                "token {} is {} bytes long, at most {} are allowed",
+                segment, length, max
+            ),
+            Self::Segments { count } => {
+                write!(f, "token has {} segments, at most 3 are allowed", count)
+            }
+        }
+    }
+}
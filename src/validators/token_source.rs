@@ -0,0 +1,417 @@
+use http::{header, HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::validators::explain::{Step, Trace};
+use crate::validators::request::ForwardedRequest;
+
+/// Where a validator reads the token from
+#[derive(Debug, Clone)]
+pub enum TokenSource {
+    /// A header of the request, optionally with one of several prefixes like `Bearer `
+    Header {
+        name: HeaderName,
+        /// The accepted prefixes, the first one the header starts with is stripped
+        prefixes: Vec<String>,
+        /// Whether tokens without a prefix are rejected
+        require_prefix: bool,
+    },
+    /// A query parameter of the original request, as forwarded in `X-Forwarded-Uri`
+    ///
+    /// Used for signed URLs and clients that cannot set headers (e.g. `EventSource`).
+    Query { param: String },
+    /// A cookie of the request, reassembled if it was split into chunks
+    ///
+    /// Used by single page applications keeping the token in an `HttpOnly` cookie.
+    Cookie { name: String },
+    /// Sources tried in order, the first one yielding a token is used
+    Ordered(Vec<TokenSource>),
+}
+
+#[derive(Debug)]
+pub enum TokenError<'a> {
+    Missing(&'a TokenSource),
+    /// The header doesn't start with the required prefix
+    MissingPrefix(&'a HeaderName),
+}
+
+/// The token source, as exposed by the validator listing
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum TokenSourceInfo<'a> {
+    Header {
+        header: &'a str,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        prefixes: Vec<&'a str>,
+        require_prefix: bool,
+    },
+    Query {
+        query: &'a str,
+    },
+    Cookie {
+        cookie: &'a str,
+    },
+    Ordered {
+        sources: Vec<TokenSourceInfo<'a>>,
+    },
+}
+
+impl TokenSource {
+    /// Read the (undecoded) token from the request
+    ///
+    /// Cookies split into chunks are only reassembled until they exceed `max_length`.
+    pub fn extract<'a>(
+        &'a self,
+        headers: &'a HeaderMap,
+        max_length: usize,
+        trace: &mut Trace,
+    ) -> Result<Cow<'a, [u8]>, TokenError<'a>> {
+        match self {
+            Self::Header {
+                name,
+                prefixes,
+                require_prefix,
+            } => {
+                let Some(value) = headers.get(name) else {
+                    trace.record(|| Step::TokenSource {
+                        header: name.to_string(),
+                        found: false,
+                        prefixes: prefixes.clone(),
+                        prefix_stripped: None,
+                    });
+                    return Err(TokenError::Missing(self));
+                };
+
+                let stripped = strip_any_prefix(value.as_bytes(), prefixes);
+                trace.record(|| Step::TokenSource {
+                    header: name.to_string(),
+                    found: true,
+                    prefixes: prefixes.clone(),
+                    prefix_stripped: stripped.map(|(prefix, _)| prefix.to_string()),
+                });
+
+                match stripped {
+                    Some((_, token)) => Ok(Cow::Borrowed(token)),
+                    None if *require_prefix && !prefixes.is_empty() => {
+                        Err(TokenError::MissingPrefix(name))
+                    }
+                    None => Ok(Cow::Borrowed(value.as_bytes())),
+                }
+            }
+            Self::Query { param } => {
+                let token = ForwardedRequest::new(headers).query_param(param);
+                trace.record(|| Step::TokenQuery {
+                    param: param.clone(),
+                    found: token.is_some(),
+                });
+
+                match token {
+                    Some(Cow::Borrowed(token)) => Ok(Cow::Borrowed(token.as_bytes())),
+                    Some(Cow::Owned(token)) => Ok(Cow::Owned(token.into_bytes())),
+                    None => Err(TokenError::Missing(self)),
+                }
+            }
+            Self::Cookie { name } => {
+                let token = ForwardedRequest::new(headers).cookie(name, max_length);
+                trace.record(|| Step::TokenCookie {
+                    cookie: name.clone(),
+                    found: token.is_some(),
+                });
+
+                match token {
+                    Some(Cow::Borrowed(token)) => Ok(Cow::Borrowed(token.as_bytes())),
+                    Some(Cow::Owned(token)) => Ok(Cow::Owned(token.into_bytes())),
+                    None => Err(TokenError::Missing(self)),
+                }
+            }
+            Self::Ordered(sources) => {
+                // A header without its required prefix is reported if no other source has a token
+                let mut error = TokenError::Missing(self);
+                for source in sources {
+                    match source.extract(headers, max_length, trace) {
+                        Ok(token) => return Ok(token),
+                        Err(TokenError::Missing(_)) => {}
+                        Err(e) => error = e,
+                    }
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Replace the token of the request, keeping the prefix of the header
+    ///
+    /// Used to pass requests on without their token, requests without a token are left unchanged.
+    /// Of ordered sources, the token is replaced in every source, not only in the one it is read
+    /// from.
+    pub fn replace_token(&self, headers: &mut HeaderMap, replace: impl Fn(&[u8]) -> String) {
+        self.replace_with(headers, &replace)
+    }
+
+    fn replace_with(&self, headers: &mut HeaderMap, replace: &dyn Fn(&[u8]) -> String) {
+        match self {
+            Self::Header { name, prefixes, .. } => {
+                let Some(value) = headers.get(name) else {
+                    return;
+                };
+
+                let replaced = match strip_any_prefix(value.as_bytes(), prefixes) {
+                    Some((prefix, token)) => format!("{} {}", prefix.trim(), replace(token)),
+                    None => replace(value.as_bytes()),
+                };
+                if let Ok(replaced) = HeaderValue::try_from(replaced) {
+                    headers.insert(name, replaced);
+                }
+            }
+            Self::Query { param } => {
+                let Some((path, query)) = ForwardedRequest::new(headers)
+                    .uri()
+                    .and_then(|uri| uri.split_once('?'))
+                else {
+                    return;
+                };
+
+                let mut serializer = form_urlencoded::Serializer::new(String::new());
+                for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                    if key == param.as_str() {
+                        serializer.append_pair(&key, &replace(value.as_bytes()));
+                    } else {
+                        serializer.append_pair(&key, &value);
+                    }
+                }
+
+                let uri = format!("{}?{}", path, serializer.finish());
+                if let Ok(uri) = HeaderValue::try_from(uri) {
+                    headers.insert("x-forwarded-uri", uri);
+                }
+            }
+            Self::Cookie { name } => {
+                let request = ForwardedRequest::new(headers);
+                // Bounded by the size of the Cookie header, the whole token is hashed
+                let Some(token) = request.cookie(name, usize::MAX) else {
+                    return;
+                };
+
+                // The chunks of the token are replaced by a single cookie
+                let mut replaced = false;
+                let mut cookies = Vec::new();
+                for (cookie, value) in request.cookies() {
+                    if !is_cookie_or_chunk(cookie, name) {
+                        cookies.push(format!("{}={}", cookie, value));
+                    } else if !replaced {
+                        replaced = true;
+                        cookies.push(format!("{}={}", name, replace(token.as_bytes())));
+                    }
+                }
+
+                if let Ok(cookies) = HeaderValue::try_from(cookies.join("; ")) {
+                    headers.insert(header::COOKIE, cookies);
+                }
+            }
+            Self::Ordered(sources) => {
+                // The sources not used may still carry tokens, e.g. a cookie next to the header
+                for source in sources {
+                    source.replace_with(headers, replace);
+                }
+            }
+        }
+    }
+
+    /// Add a token to a request where it is read from, e.g. for synthetic requests
+    ///
+    /// Of ordered sources, the first one is used.
+    pub fn insert_token(&self, headers: &mut HeaderMap, token: &str) {
+        let (name, value) = match self {
+            Self::Header { name, prefixes, .. } => match prefixes.first() {
+                Some(prefix) => (name.clone(), format!("{} {}", prefix.trim(), token)),
+                None => (name.clone(), token.to_string()),
+            },
+            Self::Query { param } => {
+                let query = form_urlencoded::Serializer::new(String::new())
+                    .append_pair(param, token)
+                    .finish();
+                (
+                    HeaderName::from_static("x-forwarded-uri"),
+                    format!("/?{}", query),
+                )
+            }
+            Self::Cookie { name } => (header::COOKIE, format!("{}={}", name, token)),
+            Self::Ordered(sources) => {
+                if let Some(source) = sources.first() {
+                    source.insert_token(headers, token);
+                }
+                return;
+            }
+        };
+
+        if let Ok(value) = HeaderValue::try_from(value) {
+            headers.insert(name, value);
+        }
+    }
+
+    /// Whether the token may be read from the header
+    pub fn reads_header(&self, header: &HeaderName) -> bool {
+        match self {
+            Self::Header { name, .. } => name == header,
+            Self::Query { .. } | Self::Cookie { .. } => false,
+            Self::Ordered(sources) => sources.iter().any(|source| source.reads_header(header)),
+        }
+    }
+
+    pub fn info(&self) -> TokenSourceInfo<'_> {
+        match self {
+            Self::Header {
+                name,
+                prefixes,
+                require_prefix,
+            } => TokenSourceInfo::Header {
+                header: name.as_str(),
+                prefixes: prefixes.iter().map(String::as_str).collect(),
+                require_prefix: *require_prefix,
+            },
+            Self::Query { param } => TokenSourceInfo::Query { query: param },
+            Self::Cookie { name } => TokenSourceInfo::Cookie { cookie: name },
+            Self::Ordered(sources) => TokenSourceInfo::Ordered {
+                sources: sources.iter().map(TokenSource::info).collect(),
+            },
+        }
+    }
+}
+
+/// Strip the first of the prefixes the header value starts with, returning it with the token
+fn strip_any_prefix<'a, 'p>(
+    value: &'a [u8],
+    prefixes: &'p [String],
+) -> Option<(&'p str, &'a [u8])> {
+    prefixes
+        .iter()
+        .find_map(|prefix| strip_prefix(value, prefix).map(|token| (prefix.as_str(), token)))
+}
+
+/// Strip the prefix (e.g. `Bearer `) from a header value
+///
+/// The prefix is matched case-insensitively and any whitespace around it is skipped, so
+/// `bearer  <token>` is accepted as well. Returns `None` if the value doesn't start with the prefix.
+fn strip_prefix<'a>(value: &'a [u8], prefix: &str) -> Option<&'a [u8]> {
+    let separated = prefix.ends_with(|c: char| c.is_ascii_whitespace());
+    let prefix = prefix.trim().as_bytes();
+
+    let value = value.trim_ascii_start();
+    if value.len() < prefix.len() || !value[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+
+    let rest = &value[prefix.len()..];
+    // Don't accept `Bearerabc` for a `Bearer ` prefix
+    if separated && !rest.first().is_some_and(u8::is_ascii_whitespace) {
+        return None;
+    }
+
+    Some(rest.trim_ascii())
+}
+
+/// Whether a cookie is the named one or one of its chunks (`name.0`, `name.1`, ...)
+fn is_cookie_or_chunk(cookie: &str, name: &str) -> bool {
+    match cookie.strip_prefix(name) {
+        Some("") => true,
+        Some(suffix) => suffix
+            .strip_prefix('.')
+            .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit())),
+        None => false,
+    }
+}
+
+impl fmt::Display for TokenError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(TokenSource::Header { name, .. }) => {
+                write!(f, "Header {} not found", name)
+            }
+            Self::Missing(TokenSource::Query { param }) => {
+                write!(f, "Query parameter {} not found", param)
+            }
+            Self::Missing(TokenSource::Cookie { name }) => {
+                write!(f, "Cookie {} not found", name)
+            }
+            Self::Missing(TokenSource::Ordered(sources)) => {
+                write!(
+                    f,
+                    "No token found in any of {} token sources",
+                    sources.len()
+                )
+            }
+            Self::MissingPrefix(name) => {
+                write!(f, "Header {} doesn't start with an accepted prefix", name)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(value).expect("valid header value"),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replaces_token_in_every_ordered_source() {
+        let source = TokenSource::Ordered(vec![
+            TokenSource::Header {
+                name: header::AUTHORIZATION,
+                prefixes: vec!["Bearer ".to_string()],
+                require_prefix: false,
+            },
+            TokenSource::Query {
+                param: "access_token".to_string(),
+            },
+            TokenSource::Cookie {
+                name: "session".to_string(),
+            },
+        ]);
+        let mut headers = headers(&[
+            ("authorization", "Bearer header-token"),
+            ("x-forwarded-uri", "/events?access_token=query-token&page=2"),
+            ("cookie", "theme=dark; session.0=cookie-; session.1=token"),
+        ]);
+
+        source.replace_token(&mut headers, |token| format!("<{}>", token.len()));
+
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer <12>");
+        assert_eq!(
+            headers["x-forwarded-uri"],
+            "/events?access_token=%3C11%3E&page=2"
+        );
+        assert_eq!(headers[header::COOKIE], "theme=dark; session=<12>");
+    }
+
+    #[test]
+    fn leaves_requests_without_token_unchanged() {
+        let source = TokenSource::Ordered(vec![
+            TokenSource::Header {
+                name: header::AUTHORIZATION,
+                prefixes: Vec::new(),
+                require_prefix: false,
+            },
+            TokenSource::Cookie {
+                name: "session".to_string(),
+            },
+        ]);
+        let mut headers = headers(&[("cookie", "theme=dark")]);
+
+        source.replace_token(&mut headers, |_| unreachable!("there is no token"));
+
+        assert!(!headers.contains_key(header::AUTHORIZATION));
+        assert_eq!(headers[header::COOKIE], "theme=dark");
+    }
+}
@@ -1,10 +1,27 @@
 use arc_swap::ArcSwap;
-use axum::http::HeaderName;
-use std::collections::HashMap;
+use http::HeaderName;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::validator_file::RequiredClaim;
+use crate::enrichment::Enrichment;
+use crate::validator_file::{DenyResponse, OutagePolicy, TransportPolicy};
 use crate::validators::authority::Authority;
+use crate::validators::body_template::BodyTemplate;
+use crate::validators::checks::{Plan, SubjectFilter};
+use crate::validators::comparison::Comparison;
+use crate::validators::header_limits::HeaderLimits;
+use crate::validators::login::LoginRedirect;
+use crate::validators::messages::Messages;
+use crate::validators::opa::OpaPolicy;
+use crate::validators::policy::Policy;
+use crate::validators::profile::Profile;
+use crate::validators::projection::HeaderProjection;
+use crate::validators::success::SuccessResponse;
+use crate::validators::token_source::{TokenSource, TokenSourceInfo};
 
 #[derive(Debug, Clone)]
 pub struct Validator {
@@ -13,89 +30,384 @@ pub struct Validator {
 
 #[derive(Debug)]
 struct ValidatorInner {
-    #[allow(dead_code)]
     name: String,
-    authority: Authority,
+    labels: BTreeMap<String, String>,
+    action: Action,
+    messages: Messages,
+    profile: Profile,
+}
+
+#[derive(Debug)]
+pub enum Action {
+    /// Validate the token of the request
+    Validate(Box<Validation>),
+    /// Reject every request with a fixed response
+    Deny(DenyResponse),
+}
+
+#[derive(Debug)]
+pub struct Validation {
+    pub authorities: Authorities<Authority>,
+
+    pub source: TokenSource,
+
+    pub enrichments: Vec<Enrichment>,
+    pub plan: Plan,
+    /// The CEL expression the claims have to satisfy after the required claims
+    pub policy: Option<Policy>,
+    /// The Open Policy Agent deciding about tokens after the policy
+    pub opa: Option<OpaPolicy>,
+    pub subjects: SubjectFilter,
+    pub transport: Option<TransportPolicy>,
+    /// How requests are answered while the authority's identity provider is unavailable
+    pub outage: OutagePolicy,
+    /// How long the proxy may cache successful responses, bounded by the expiry of the token
+    pub cache_max_age: Option<u64>,
+    /// How long past its ttl a cached decision is served while the keys are refreshed or
+    /// unavailable
+    pub stale_while_revalidate: Option<Duration>,
+    /// Limits on the headers claims are mapped to
+    pub header_limits: Option<HeaderLimits>,
+    pub header_projection: Option<HeaderProjection>,
+    /// The authority whose decisions are compared with the enforced one
+    pub compare: Option<Comparison>,
+    /// Request headers copied to the response
+    pub echo_headers: Vec<HeaderName>,
+    /// What successful validations are answered with, instead of an empty 200
+    pub success: Option<SuccessResponse>,
+    /// Renders the bodies of rejections
+    pub body_template: Option<BodyTemplate>,
+    /// The realm of the `WWW-Authenticate` challenge of rejections
+    pub realm: String,
+    /// Where browsers are redirected to instead of answering with 401
+    pub login_redirect: Option<LoginRedirect>,
+}
 
-    header: String,
-    strip_prefix: Option<String>,
+/// The authorities a validator verifies tokens with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authorities<A> {
+    Single(A),
+    /// Selected by the unverified `iss` claim of the token, tokens of other issuers are rejected
+    ByIssuer(HashMap<String, A>),
+}
 
-    required_claims: Vec<RequiredClaim>,
-    map_claims: HashMap<String, HeaderName>, // TODO: Add some sort of html template to provide a nice error page
+/// Metadata about a validator, as exposed by the validator listing
+#[derive(Debug, Serialize)]
+pub struct ValidatorInfo<'a> {
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: &'a BTreeMap<String, String>,
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority: Option<&'a str>,
+    /// The issuers accepted by a validator selecting the authority by issuer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuers: Option<Vec<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_source: Option<TokenSourceInfo<'a>>,
+    pub required_claims: usize,
+    pub mapped_claims: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny_status: Option<u16>,
 }
 
+/// Selects validators by their labels, e.g. `team=payments,env=prod`
+///
+/// A validator matches if it has every listed label with the listed value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSelector(Vec<(String, String)>);
+
+#[derive(Debug)]
+pub struct InvalidSelector(String);
+
+/// Displays labels in the selector syntax, e.g. `env=prod,team=payments`
+pub struct Labels<'a>(pub &'a BTreeMap<String, String>);
+
+/// The validators, looked up by name on every request
+///
+/// The index is replaced as a whole whenever a validator changes, so lookups and listings only
+/// load it once and never have to sort or filter thousands of validators.
 #[derive(Debug)]
 pub struct ValidatorStore {
-    states: ArcSwap<HashMap<String, Validator>>,
+    index: ArcSwap<ValidatorIndex>,
+}
+
+#[derive(Debug, Default)]
+struct ValidatorIndex {
+    validators: HashMap<String, Validator>,
+    /// Validators disabled through the admin API, they are kept across configuration reloads
+    disabled: HashSet<String>,
+    /// The enabled validators, sorted by name
+    enabled: Arc<[Validator]>,
+}
+
+impl<A> Authorities<A> {
+    /// Every authority tokens may be verified with
+    pub fn iter(&self) -> impl Iterator<Item = &A> {
+        let authorities = match self {
+            Self::Single(authority) => vec![authority],
+            Self::ByIssuer(issuers) => issuers.values().collect(),
+        };
+        authorities.into_iter()
+    }
+
+    pub fn map<B>(&self, mut f: impl FnMut(&A) -> B) -> Authorities<B> {
+        match self {
+            Self::Single(authority) => Authorities::Single(f(authority)),
+            Self::ByIssuer(issuers) => Authorities::ByIssuer(
+                issuers
+                    .iter()
+                    .map(|(issuer, authority)| (issuer.clone(), f(authority)))
+                    .collect(),
+            ),
+        }
+    }
 }
 
 impl Validator {
     pub fn new(
         name: String,
-        authority: Authority,
-        header: String,
-        strip_prefix: Option<String>,
-        required_claims: Vec<RequiredClaim>,
-        map_claims: HashMap<String, HeaderName>,
+        labels: BTreeMap<String, String>,
+        action: Action,
+        messages: Messages,
+        profile: Profile,
     ) -> Self {
         let inner = Arc::new(ValidatorInner {
             name,
-            authority,
-            header,
-            strip_prefix,
-            required_claims,
-            map_claims,
+            labels,
+            action,
+            messages,
+            profile,
         });
 
         Self { inner }
     }
 
     #[inline]
-    pub fn authority(&self) -> &Authority {
-        &self.inner.authority
+    pub fn name(&self) -> &str {
+        &self.inner.name
     }
 
     #[inline]
-    pub fn header(&self) -> &str {
-        &self.inner.header
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.inner.labels
     }
 
     #[inline]
-    pub fn strip_prefix(&self) -> Option<&str> {
-        self.inner.strip_prefix.as_deref()
+    pub fn action(&self) -> &Action {
+        &self.inner.action
     }
 
+    /// The rejection messages replacing the built-in ones
     #[inline]
-    pub fn required_claims(&self) -> &[RequiredClaim] {
-        &self.inner.required_claims
+    pub fn messages(&self) -> &Messages {
+        &self.inner.messages
     }
 
+    /// The reverse proxy the responses are adjusted to
     #[inline]
-    pub fn map_claims(&self) -> &HashMap<String, HeaderName> {
-        &self.inner.map_claims
+    pub fn profile(&self) -> Profile {
+        self.inner.profile
+    }
+
+    pub fn info(&self) -> ValidatorInfo<'_> {
+        match self.action() {
+            Action::Validate(validation) => ValidatorInfo {
+                name: self.name(),
+                labels: self.labels(),
+                action: "validate",
+                authority: match &validation.authorities {
+                    Authorities::Single(authority) => Some(authority.name()),
+                    Authorities::ByIssuer(_) => None,
+                },
+                issuers: match &validation.authorities {
+                    Authorities::Single(_) => None,
+                    Authorities::ByIssuer(issuers) => {
+                        let mut issuers = issuers.keys().map(String::as_str).collect::<Vec<_>>();
+                        issuers.sort_unstable();
+                        Some(issuers)
+                    }
+                },
+                token_source: Some(validation.source.info()),
+                required_claims: validation.plan.len(),
+                mapped_claims: validation.plan.mappings().len(),
+                deny_status: None,
+            },
+            Action::Deny(deny) => ValidatorInfo {
+                name: self.name(),
+                labels: self.labels(),
+                action: "deny",
+                authority: None,
+                issuers: None,
+                token_source: None,
+                required_claims: 0,
+                mapped_claims: 0,
+                deny_status: Some(deny.status.as_u16()),
+            },
+        }
     }
 }
 
 impl ValidatorStore {
     pub fn new() -> Self {
         Self {
-            states: ArcSwap::new(Arc::new(HashMap::new())),
+            index: ArcSwap::new(Arc::new(ValidatorIndex::default())),
         }
     }
 
     pub fn update(&self, map: HashMap<String, Validator>) {
-        self.states.store(Arc::new(map));
+        self.index
+            .rcu(|index| ValidatorIndex::new(map.clone(), index.disabled.clone()));
     }
 
+    /// Get an enabled validator
     pub fn get(&self, name: &str) -> Option<Validator> {
-        self.states.load().get(name).cloned()
+        let index = self.index.load();
+        if index.disabled.contains(name) {
+            return None;
+        }
+
+        index.validators.get(name).cloned()
     }
 
+    /// The names of the enabled validators, sorted
     pub fn keys(&self) -> Vec<String> {
-        self.states.load().keys().cloned().collect()
+        self.index
+            .load()
+            .enabled
+            .iter()
+            .map(|validator| validator.name().to_string())
+            .collect()
+    }
+
+    /// The enabled validators, sorted by name
+    pub fn values(&self) -> Arc<[Validator]> {
+        self.index.load().enabled.clone()
+    }
+
+    /// Get a validator, regardless of whether it is disabled
+    pub fn get_configured(&self, name: &str) -> Option<Validator> {
+        self.index.load().validators.get(name).cloned()
+    }
+
+    /// Whether the validator is configured, regardless of whether it is disabled
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.load().validators.contains_key(name)
+    }
+
+    /// All configured validators and whether they are enabled
+    pub fn statuses(&self) -> Vec<(Validator, bool)> {
+        let index = self.index.load();
+        index
+            .validators
+            .values()
+            .map(|validator| {
+                (
+                    validator.clone(),
+                    !index.disabled.contains(validator.name()),
+                )
+            })
+            .collect()
+    }
+
+    /// The names of the configured validators matching the selector, regardless of whether they
+    /// are disabled
+    pub fn select(&self, selector: &LabelSelector) -> Vec<String> {
+        self.index
+            .load()
+            .validators
+            .values()
+            .filter(|validator| selector.matches(validator.labels()))
+            .map(|validator| validator.name().to_string())
+            .collect()
+    }
+
+    pub fn set_enabled(&self, name: &str, enabled: bool) {
+        self.set_enabled_all(&[name.to_string()], enabled);
+    }
+
+    /// Enable or disable several validators at once
+    pub fn set_enabled_all(&self, names: &[String], enabled: bool) {
+        self.index.rcu(|index| {
+            let mut disabled = index.disabled.clone();
+            for name in names {
+                if enabled {
+                    disabled.remove(name);
+                } else {
+                    disabled.insert(name.clone());
+                }
+            }
+            ValidatorIndex::new(index.validators.clone(), disabled)
+        });
     }
 
     pub fn clear(&self) {
-        self.states.store(Arc::new(HashMap::new()));
+        self.index
+            .rcu(|index| ValidatorIndex::new(HashMap::new(), index.disabled.clone()));
+    }
+}
+
+impl ValidatorIndex {
+    fn new(validators: HashMap<String, Validator>, disabled: HashSet<String>) -> Self {
+        let mut enabled = validators
+            .values()
+            .filter(|validator| !disabled.contains(validator.name()))
+            .cloned()
+            .collect::<Vec<_>>();
+        enabled.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+
+        Self {
+            validators,
+            disabled,
+            enabled: enabled.into(),
+        }
+    }
+}
+
+impl LabelSelector {
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.0
+            .iter()
+            .all(|(label, value)| labels.get(label) == Some(value))
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = InvalidSelector;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let requirements = s
+            .split(',')
+            .map(|requirement| match requirement.split_once('=') {
+                Some((label, value)) if !label.trim().is_empty() => {
+                    Ok((label.trim().to_string(), value.trim().to_string()))
+                }
+                _ => Err(InvalidSelector(requirement.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(requirements))
+    }
+}
+
+impl fmt::Display for Labels<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (label, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", label, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for InvalidSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid label requirement {:?}, expected label=value",
+            self.0
+        )
     }
 }